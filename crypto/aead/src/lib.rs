@@ -22,12 +22,18 @@ pub fn get_random_nonce() -> ring::aead::Nonce {
 /// Encrypt `plaintext` using `key`.
 ///
 /// Prefixes the ciphertext with a nonce.
-pub fn encrypt(mut plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
+pub fn encrypt(plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
+    encrypt_with_aad(plaintext, &[], key)
+}
+
+/// Like [`encrypt`], but additionally authenticates `aad`, which has to be
+/// passed to [`decrypt_with_aad`] again.
+pub fn encrypt_with_aad(mut plaintext: Vec<u8>, aad: &[u8], key: &LessSafeKey) -> Result<Vec<u8>> {
     let nonce = get_random_nonce();
     // prefix ciphertext with nonce
     let mut ciphertext: Vec<u8> = nonce.as_ref().to_vec();
 
-    key.seal_in_place_append_tag(nonce, Aad::empty(), &mut plaintext)
+    key.seal_in_place_append_tag(nonce, Aad::from(aad), &mut plaintext)
         .map_err(|_| anyhow::format_err!("Encryption failed due to unspecified aead error"))?;
 
     ciphertext.append(&mut plaintext);
@@ -39,6 +45,16 @@ pub fn encrypt(mut plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
 ///
 /// Expect nonce in the prefix, like [`encrypt`] produces.
 pub fn decrypt<'c>(ciphertext: &'c mut [u8], key: &LessSafeKey) -> Result<&'c [u8]> {
+    decrypt_with_aad(ciphertext, &[], key)
+}
+
+/// Decrypts a `ciphertext` produced by [`encrypt_with_aad`] with the same
+/// `aad`.
+pub fn decrypt_with_aad<'c>(
+    ciphertext: &'c mut [u8],
+    aad: &[u8],
+    key: &LessSafeKey,
+) -> Result<&'c [u8]> {
     if ciphertext.len() < NONCE_LEN {
         bail!("Ciphertext too short: {}", ciphertext.len());
     }
@@ -47,7 +63,7 @@ pub fn decrypt<'c>(ciphertext: &'c mut [u8], key: &LessSafeKey) -> Result<&'c [u
 
     key.open_in_place(
         Nonce::assume_unique_for_key(nonce_bytes.try_into().expect("nonce size known")),
-        Aad::empty(),
+        Aad::from(aad),
         encrypted_bytes,
     )
     .map_err(|_| format_err!("Decryption failed due to unspecified aead error"))?;
@@ -92,12 +108,27 @@ pub fn encrypted_read(key: &LessSafeKey, file: PathBuf) -> Result<Vec<u8>> {
 /// * `password` - Strong user-created password
 /// * `salt` - Nonce >8 bytes to discourage rainbow attacks
 pub fn get_encryption_key(password: &str, salt: &str) -> Result<LessSafeKey> {
+    get_encryption_key_from_bytes(&stretch_password(password, salt)?)
+}
+
+/// Stretches `password` into key material using Argon2, see
+/// [`get_encryption_key`]
+pub fn stretch_password(
+    password: &str,
+    salt: &str,
+) -> Result<[u8; ring::digest::SHA256_OUTPUT_LEN]> {
     let mut key = [0u8; ring::digest::SHA256_OUTPUT_LEN];
 
     argon2()
         .hash_password_into(password.as_bytes(), salt.as_bytes(), &mut key)
         .map_err(|e| format_err!("could not hash password").context(e))?;
-    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, &key)
+    Ok(key)
+}
+
+/// Creates a ChaCha20-Poly1305 key from uniformly random key material, e.g.
+/// derived from the output of [`stretch_password`]
+pub fn get_encryption_key_from_bytes(key: &[u8]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, key)
         .map_err(|_| anyhow::Error::msg("Unable to create key"))?;
     Ok(LessSafeKey::new(key))
 }
@@ -138,6 +138,9 @@ pub const FM_GWCLI_CLN_ENV: &str = "FM_GWCLI_CLN";
 // Env variable to define command for the LND client
 pub const FM_GWCLI_LND_ENV: &str = "FM_GWCLI_LND";
 
+// Env variable to define command for the fake lightning node client
+pub const FM_GWCLI_FAKE_ENV: &str = "FM_GWCLI_FAKE";
+
 /// Make `devimint` print stderr of called commands directly on its own stderr
 pub const FM_DEVIMINT_CMD_INHERIT_STDERR_ENV: &str = "FM_DEVIMINT_CMD_INHERIT_STDERR";
 
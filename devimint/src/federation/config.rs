@@ -58,6 +58,10 @@ pub fn attach_default_module_init_params(
                         bitcoin29_to_bitcoin30_network(network),
                     ),
                     fee_consensus: Default::default(),
+                    peg_out_batching: None,
+                    peg_in_descriptor_kind: Default::default(),
+                    utxo_consolidation: None,
+                    cpfp: None,
                 },
             },
         );
@@ -24,12 +24,35 @@ pub struct Gatewayd {
 
 impl Gatewayd {
     pub async fn new(process_mgr: &ProcessManager, ln: LightningNode) -> Result<Self> {
-        let ln_name = ln.name();
-        let test_dir = &process_mgr.globals.FM_TEST_DIR;
+        let ln_name = ln.name().to_string();
         let port = match ln {
             LightningNode::Cln(_) => process_mgr.globals.FM_PORT_GW_CLN,
             LightningNode::Lnd(_) => process_mgr.globals.FM_PORT_GW_LND,
         };
+        Self::start(process_mgr, Some(ln), &ln_name, port).await
+    }
+
+    /// Starts a gateway that simulates its lightning node, see
+    /// `gateway-cli lightning simulate-payment`.
+    pub async fn new_fake(process_mgr: &ProcessManager) -> Result<Self> {
+        // Unlike for real lightning nodes, no daemon creates the data dir for us
+        tokio::fs::create_dir_all(process_mgr.globals.FM_TEST_DIR.join("fake")).await?;
+        Self::start(
+            process_mgr,
+            None,
+            "fake",
+            process_mgr.globals.FM_PORT_GW_FAKE,
+        )
+        .await
+    }
+
+    async fn start(
+        process_mgr: &ProcessManager,
+        ln: Option<LightningNode>,
+        ln_name: &str,
+        port: u16,
+    ) -> Result<Self> {
+        let test_dir = &process_mgr.globals.FM_TEST_DIR;
         let addr = format!("http://127.0.0.1:{port}/{V1_API_ENDPOINT}");
         let gateway_env: HashMap<String, String> = HashMap::from_iter([
             (
@@ -50,7 +73,7 @@ impl Gatewayd {
             .await?;
 
         let gatewayd = Self {
-            ln: Some(ln),
+            ln,
             _process: process,
             addr,
         };
@@ -86,12 +109,10 @@ impl Gatewayd {
     ) -> Result<()> {
         self._process.terminate().await?;
         std::env::set_var("FM_GATEWAYD_BASE_EXECUTABLE", bin_path);
-        let ln = self
-            .ln
-            .as_ref()
-            .expect("gateway already had an associated ln node")
-            .clone();
-        let new_gw = Self::new(process_mgr, ln).await?;
+        let new_gw = match self.ln.clone() {
+            Some(ln) => Self::new(process_mgr, ln).await?,
+            None => Self::new_fake(process_mgr).await?,
+        };
         self._process = new_gw._process;
         let gatewayd_version = crate::util::Gatewayd::version_or_default().await;
         info!("upgraded gatewayd to version: {}", gatewayd_version);
@@ -204,6 +225,19 @@ impl Gatewayd {
         Ok(())
     }
 
+    /// Simulates an incoming payment of `invoice` through a gateway started
+    /// with [`Gatewayd::new_fake`] and returns the preimage it was settled
+    /// with.
+    pub async fn simulate_payment(&self, invoice: String) -> Result<String> {
+        let preimage = cmd!(self, "lightning", "simulate-payment", "--invoice", invoice)
+            .out_json()
+            .await?
+            .as_str()
+            .context("preimage must be a string")?
+            .to_owned();
+        Ok(preimage)
+    }
+
     pub async fn wait_for_chain_sync(&self, bitcoind: &Bitcoind) -> Result<()> {
         poll("lightning node block processing", || async {
             let block_height = bitcoind
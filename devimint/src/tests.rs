@@ -2280,6 +2280,70 @@ pub async fn guardian_backup_test(dev_fed: DevFed, process_mgr: &ProcessManager)
     Ok(())
 }
 
+pub async fn fake_gateway_test(dev_fed: DevFed, process_mgr: &ProcessManager) -> Result<()> {
+    log_binary_versions().await?;
+    let DevFed { fed, .. } = dev_fed;
+
+    let gw_fake = Gatewayd::new_fake(process_mgr).await?;
+    gw_fake.connect_fed(&fed).await?;
+    fed.pegin_gateway(10_000, &gw_fake).await?;
+    let gw_fake_id = gw_fake.gateway_id().await?;
+
+    let client = fed.new_joined_client("fake-gateway-client").await?;
+    fed.pegin_client(10_000, &client).await?;
+
+    info!("Testing simulated incoming payment through the fake gateway");
+    let initial_client_balance = client.balance().await?;
+    let ln_invoice_response = ln_invoice(
+        &client,
+        Amount::from_msats(1_000_000),
+        "incoming-over-fake-gw".to_string(),
+        gw_fake_id,
+    )
+    .await?;
+    gw_fake
+        .simulate_payment(ln_invoice_response.invoice)
+        .await?;
+    cmd!(client, "await-invoice", ln_invoice_response.operation_id)
+        .run()
+        .await?;
+    let final_client_balance = client.balance().await?;
+    anyhow::ensure!(
+        final_client_balance - initial_client_balance == 1_000_000,
+        "Client balance changed by {} on fake incoming payment, expected 1000000",
+        final_client_balance - initial_client_balance
+    );
+
+    info!("Testing outgoing payments through the fake gateway");
+    let data_dir = env::var(FM_DATA_DIR_ENV)?;
+    let load_test_temp = PathBuf::from(data_dir).join("fake-gateway-load-test-temp");
+    fed.pegin_client(10_000, fed.internal_client().await?)
+        .await?;
+    let output = cmd!(
+        LoadTestTool,
+        "--archive-dir",
+        load_test_temp.display(),
+        "--users",
+        "1",
+        "load-test",
+        "--notes-per-user",
+        "1",
+        "--generate-invoice-with",
+        "fake",
+        "--invite-code",
+        fed.invite_code()?
+    )
+    .out_string()
+    .await?;
+    println!("{output}");
+    anyhow::ensure!(
+        output.contains("1 gateway_pay_invoice"),
+        "paid different number of invoices than expected"
+    );
+
+    Ok(())
+}
+
 pub async fn cannot_replay_tx_test(dev_fed: DevFed) -> Result<()> {
     log_binary_versions().await?;
     let fedimint_cli_version = crate::util::FedimintCli::version_or_default().await;
@@ -2430,6 +2494,9 @@ pub enum TestCmd {
     GuardianBackup,
     /// `devfed` then tests that spent ecash cannot be double spent
     CannotReplayTransaction,
+    /// `devfed` then starts a gateway with a fake lightning node and tests
+    /// payments through it
+    FakeGatewayTest,
     /// Test upgrade paths for a given binary
     UpgradeTests {
         #[clap(subcommand)]
@@ -2550,6 +2617,11 @@ pub async fn handle_command(cmd: TestCmd, common_args: CommonArgs) -> Result<()>
             let dev_fed = dev_fed(&process_mgr).await?;
             cannot_replay_tx_test(dev_fed).await?;
         }
+        TestCmd::FakeGatewayTest => {
+            let (process_mgr, _) = setup(common_args).await?;
+            let dev_fed = dev_fed(&process_mgr).await?;
+            fake_gateway_test(dev_fed, &process_mgr).await?;
+        }
         TestCmd::UpgradeTests { binary } => {
             let (process_mgr, _) = setup(common_args).await?;
             upgrade_tests(&process_mgr, binary).await?;
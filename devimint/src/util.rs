@@ -32,7 +32,7 @@ use crate::envs::{
     FM_ELECTRS_BASE_EXECUTABLE_ENV, FM_ESPLORA_BASE_EXECUTABLE_ENV, FM_FAUCET_BASE_EXECUTABLE_ENV,
     FM_FEDIMINTD_BASE_EXECUTABLE_ENV, FM_FEDIMINT_CLI_BASE_EXECUTABLE_ENV,
     FM_FEDIMINT_DBTOOL_BASE_EXECUTABLE_ENV, FM_GATEWAYD_BASE_EXECUTABLE_ENV,
-    FM_GATEWAY_CLI_BASE_EXECUTABLE_ENV, FM_GWCLI_CLN_ENV, FM_GWCLI_LND_ENV,
+    FM_GATEWAY_CLI_BASE_EXECUTABLE_ENV, FM_GWCLI_CLN_ENV, FM_GWCLI_FAKE_ENV, FM_GWCLI_LND_ENV,
     FM_LIGHTNINGD_BASE_EXECUTABLE_ENV, FM_LIGHTNING_CLI_BASE_EXECUTABLE_ENV, FM_LIGHTNING_CLI_ENV,
     FM_LNCLI_BASE_EXECUTABLE_ENV, FM_LNCLI_ENV, FM_LND_BASE_EXECUTABLE_ENV,
     FM_LOAD_TEST_TOOL_BASE_EXECUTABLE_ENV, FM_LOGS_DIR_ENV, FM_MINT_CLIENT_ENV,
@@ -938,6 +938,16 @@ impl GatewayLndCli {
     }
 }
 
+pub struct GatewayFakeCli;
+impl GatewayFakeCli {
+    pub async fn cmd(self) -> Command {
+        to_command(get_command_str_for_alias(
+            &[FM_GWCLI_FAKE_ENV],
+            &["gateway-fake"],
+        ))
+    }
+}
+
 pub struct LnCli;
 impl LnCli {
     pub async fn cmd(self) -> Command {
@@ -18,15 +18,17 @@ use fedimint_core::backup::ClientBackupSnapshot;
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::backup::SignedBackupRequest;
 use fedimint_core::core::{Decoder, DynOutputOutcome, ModuleInstanceId, OutputOutcome};
+use fedimint_core::db::snapshot::SnapshotSummary;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::endpoint_constants::{
     ADD_CONFIG_GEN_PEER_ENDPOINT, AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT,
     AWAIT_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_ENDPOINT,
     CONFIG_GEN_PEERS_ENDPOINT, CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT,
-    DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT, RECOVER_ENDPOINT,
-    RESTART_FEDERATION_SETUP_ENDPOINT, RUN_DKG_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT,
-    SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
-    SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
+    DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT,
+    GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT, RECOVER_ENDPOINT, RESTART_FEDERATION_SETUP_ENDPOINT,
+    RUN_DKG_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT,
+    SESSION_STATUS_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT, SET_CONFIG_GEN_PARAMS_ENDPOINT,
+    SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT, START_GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT,
     STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
     VERIFY_CONFIG_HASH_ENDPOINT, VERSION_ENDPOINT,
 };
@@ -732,6 +734,24 @@ pub trait IGlobalFederationApi: IRawFederationApi {
     async fn guardian_config_backup(&self, auth: ApiAuth)
         -> FederationResult<GuardianConfigBackup>;
 
+    /// Create a consistent snapshot of the guardian database to back it up,
+    /// which can then be downloaded with
+    /// [`Self::guardian_database_snapshot_chunk`]
+    async fn start_guardian_database_snapshot(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianDatabaseSnapshot>;
+
+    /// Download the chunk of the guardian database snapshot `id` starting at
+    /// `offset`. The guardian deletes the snapshot once its last chunk was
+    /// downloaded.
+    async fn guardian_database_snapshot_chunk(
+        &self,
+        id: u64,
+        offset: u64,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianDatabaseSnapshotChunk>;
+
     /// Check auth credentials
     async fn auth(&self, auth: ApiAuth) -> FederationResult<()>;
 
@@ -1118,6 +1138,32 @@ where
         .await
     }
 
+    async fn start_guardian_database_snapshot(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianDatabaseSnapshot> {
+        self.request_admin(
+            START_GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT,
+            ApiRequestErased::default(),
+            auth,
+        )
+        .await
+    }
+
+    async fn guardian_database_snapshot_chunk(
+        &self,
+        id: u64,
+        offset: u64,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianDatabaseSnapshotChunk> {
+        self.request_admin(
+            GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT,
+            ApiRequestErased::new((id, offset)),
+            auth,
+        )
+        .await
+    }
+
     async fn auth(&self, auth: ApiAuth) -> FederationResult<()> {
         self.request_admin(AUTH_ENDPOINT, ApiRequestErased::default(), auth)
             .await
@@ -1492,6 +1538,26 @@ pub struct GuardianConfigBackup {
     pub tar_archive_bytes: Vec<u8>,
 }
 
+/// Snapshot of the guardian database as produced by
+/// [`fedimint_core::db::Database::export_snapshot`] that is ready to be
+/// downloaded in chunks. The concatenated chunks can be restored into an empty
+/// database using `fedimint-dbtool import-snapshot`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GuardianDatabaseSnapshot {
+    /// Id the chunks of the snapshot are requested with
+    pub id: u64,
+    pub summary: SnapshotSummary,
+    /// Size of the whole snapshot
+    pub total_bytes: u64,
+}
+
+/// Chunk of a [`GuardianDatabaseSnapshot`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GuardianDatabaseSnapshotChunk {
+    #[serde(with = "fedimint_core::hex::serde")]
+    pub chunk_bytes: Vec<u8>,
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr as _;
@@ -34,6 +34,14 @@ impl IBitcoindRpcFactory for BitcoindFactory {
     ) -> anyhow::Result<DynBitcoindRpc> {
         Ok(RetryClient::new(BitcoinClient::new(url)?, handle).into())
     }
+
+    fn create_connection_without_retries(
+        &self,
+        url: &SafeUrl,
+        _handle: TaskHandle,
+    ) -> anyhow::Result<DynBitcoindRpc> {
+        Ok(BitcoinClient::new(url)?.into())
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,1463 @@
+//! Light client backend using compact block filters ([BIP 157]/[BIP 158])
+//! downloaded from peers of the Bitcoin P2P network.
+//!
+//! Scripts are matched against the filters locally and only the blocks that
+//! match are downloaded, so the peers never learn which scripts we are
+//! interested in. Connect to nodes run with `-blockfilterindex=1
+//! -peerblockfilters=1`, e.g. `bip157://127.0.0.1:18444?network=regtest`.
+//!
+//! More peers are added with the `peer` query parameter, e.g.
+//! `bip157://node1.example.com?network=bitcoin&peer=node2.example.com:8333`,
+//! peers without a port use the port of the first one. We follow the valid
+//! chain with the most work any of the peers knows about, so a peer can't hide
+//! blocks from us as long as another peer is honest. Filters are only used if
+//! all peers on that chain agree on them.
+//!
+//! Headers are validated as far as possible without the blocks: they have to
+//! connect, meet the difficulty required by the retargeting rules and have
+//! timestamps after the median of the previous blocks. They are persisted to
+//! the file given by the `headers_file` query parameter, which defaults to
+//! `fedimint/bip157-headers-<network>` in the user's cache directory, so only
+//! the new headers are downloaded after a restart. Regtest headers are only
+//! persisted if `headers_file` is set, since regtest chains are usually thrown
+//! away with their node.
+//!
+//! Limitations compared to the other backends:
+//! * only confirmed transactions are found
+//! * filters are kept in memory and synced again after a restart, starting
+//!   `start_height` (query parameter) or [`DEFAULT_SCAN_DEPTH`] blocks below
+//!   the tip
+//! * all headers since genesis are kept in memory, about 150 bytes per block
+//! * fee rates are estimated from the median of the average fee rates of the
+//!   last 6 blocks, bounded by 1 sat/vB and Bitcoin Core's default
+//!   `-maxfeerate` of 10,000 sat/vB. Each estimate downloads at most one of
+//!   these blocks, so no estimate is returned until all of them were
+//!   downloaded and callers fall back to the federation's consensus fee rate
+//!
+//! [BIP 157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+//! [BIP 158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, ensure, format_err, Context};
+use bitcoin::bip158::BlockFilter;
+use bitcoin::block::Header;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::{deserialize, serialize, Params};
+use bitcoin::hash_types::{FilterHash, FilterHeader};
+use bitcoin::hashes::Hash;
+use bitcoin::merkle_tree::PartialMerkleTree;
+use bitcoin::network::constants::ServiceFlags;
+use bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::network::message_blockdata::{GetHeadersMessage, Inventory};
+use bitcoin::network::message_filter::{CFHeaders, GetCFHeaders, GetCFilters};
+use bitcoin::network::message_network::VersionMessage;
+use bitcoin::pow::{CompactTarget, Target, Work};
+use bitcoin::{Block, BlockHash, Network, OutPoint, ScriptBuf, Transaction, Txid};
+use fedimint_core::bitcoin_migration::{
+    bitcoin30_to_bitcoin29_block_header, bitcoin30_to_bitcoin29_partial_merkle_tree,
+};
+use fedimint_core::task::TaskHandle;
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::txoproof::TxOutProof;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{apply, async_trait_maybe_send, runtime, Feerate};
+use fedimint_logging::LOG_BLOCKCHAIN;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{debug, info, warn};
+
+use crate::{DynBitcoindRpc, IBitcoindRpc, IBitcoindRpcFactory, RetryClient};
+
+/// Number of blocks below the tip we start syncing filters from if no
+/// `start_height` is configured
+pub const DEFAULT_SCAN_DEPTH: u64 = 1000;
+
+/// Filter type of the basic filters defined in BIP 158
+const BASIC_FILTER_TYPE: u8 = 0;
+
+/// Maximum number of headers in a `headers` message
+const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+
+/// Maximum number of filters that can be requested with a single `getcfilters`
+/// message
+const MAX_FILTERS_PER_REQUEST: u64 = 1000;
+
+/// Size of a serialized block header
+const HEADER_SIZE: usize = 80;
+
+/// Number of previous blocks whose median timestamp a header's timestamp has
+/// to exceed
+const MEDIAN_TIME_SPAN: u64 = 11;
+
+/// How far the timestamp of a header may be ahead of our clock
+const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+/// Number of recent blocks whose fee rates are used for estimates
+const FEE_ESTIMATE_BLOCKS: u64 = 6;
+
+/// Fee rate returned if recent blocks contain no transactions
+const MIN_RELAY_FEE_RATE: Feerate = Feerate { sats_per_kvb: 1000 };
+
+/// Upper bound of our estimates, a miner can inflate the average fee rate of
+/// its own blocks by paying fees to itself. Matches Bitcoin Core's default
+/// `-maxfeerate`.
+const MAX_FEE_RATE: Feerate = Feerate {
+    sats_per_kvb: 10_000_000,
+};
+
+/// Maximum size of a P2P message we accept, enough for any valid block
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const PROTOCOL_VERSION: u32 = 70016;
+
+const USER_AGENT: &str = "/fedimint-bitcoind:0.4.0/";
+
+#[derive(Debug)]
+pub struct CompactFiltersFactory;
+
+impl IBitcoindRpcFactory for CompactFiltersFactory {
+    fn create_connection(
+        &self,
+        url: &SafeUrl,
+        handle: TaskHandle,
+    ) -> anyhow::Result<DynBitcoindRpc> {
+        Ok(RetryClient::new(CompactFiltersClient::new(url)?, handle).into())
+    }
+
+    fn create_connection_without_retries(
+        &self,
+        url: &SafeUrl,
+        _handle: TaskHandle,
+    ) -> anyhow::Result<DynBitcoindRpc> {
+        Ok(CompactFiltersClient::new(url)?.into())
+    }
+}
+
+#[derive(Debug)]
+pub struct CompactFiltersClient {
+    peer_addrs: Vec<String>,
+    network: Network,
+    start_height: Option<u64>,
+    headers_file: Option<PathBuf>,
+    inner: Mutex<CompactFiltersInner>,
+}
+
+#[derive(Debug)]
+struct CompactFiltersInner {
+    /// Connections to the peers, indexed like
+    /// [`CompactFiltersClient::peer_addrs`]
+    peers: Vec<Option<Peer>>,
+    chain: ChainState,
+    /// Whether the headers file was loaded into [`Self::chain`]
+    headers_loaded: bool,
+}
+
+/// What we know about the best chain of the peers
+#[derive(Debug)]
+struct ChainState {
+    network: Network,
+    /// Headers of the best chain, indexed by height
+    headers: Vec<Header>,
+    /// Hashes of [`Self::headers`]
+    hashes: Vec<BlockHash>,
+    /// Total work of the chain up to each height
+    chainwork: Vec<Work>,
+    /// Number of [`Self::headers`] that are stored in the headers file
+    persisted_headers: usize,
+    /// Verified filters and their filter headers, indexed by height
+    filters: BTreeMap<u64, (FilterHeader, Vec<u8>)>,
+    /// Transactions relevant to a script and the height up to which the
+    /// script was scanned
+    script_history: HashMap<ScriptBuf, ScriptHistory>,
+    /// Heights of all transactions in [`Self::script_history`]
+    tx_heights: HashMap<Txid, u64>,
+    /// Fee rates of recent blocks by height, see [`block_fee_rate`]
+    block_fee_rates: BTreeMap<u64, Option<Feerate>>,
+}
+
+#[derive(Debug, Default)]
+struct ScriptHistory {
+    scanned_height: Option<u64>,
+    transactions: Vec<(u64, Transaction)>,
+}
+
+impl ChainState {
+    fn new(network: Network) -> Self {
+        let genesis = genesis_block(network).header;
+        ChainState {
+            network,
+            headers: vec![genesis],
+            hashes: vec![genesis.block_hash()],
+            chainwork: vec![genesis.work()],
+            persisted_headers: 0,
+            filters: BTreeMap::new(),
+            script_history: HashMap::new(),
+            tx_heights: HashMap::new(),
+            block_fee_rates: BTreeMap::new(),
+        }
+    }
+
+    fn tip_height(&self) -> u64 {
+        self.headers.len() as u64 - 1
+    }
+
+    fn tip_hash(&self) -> BlockHash {
+        self.hashes[self.hashes.len() - 1]
+    }
+
+    /// Block locator as described for the `getheaders` message, starting at
+    /// `height`
+    fn locator(&self, mut height: u64) -> Vec<BlockHash> {
+        let mut locator = vec![];
+        let mut step = 1;
+        loop {
+            locator.push(self.hashes[height as usize]);
+            if height == 0 {
+                break;
+            }
+            if 10 <= locator.len() {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Switches to the chain ending with `branch`, which has to fork from
+    /// this one
+    fn extend(&mut self, branch: Branch) {
+        if branch.fork_height != self.tip_height() {
+            info!(target: LOG_BLOCKCHAIN, fork_height = branch.fork_height, "Chain reorganization");
+            self.truncate(branch.fork_height);
+        }
+
+        for (header, hash) in branch.headers.into_iter().zip(branch.hashes) {
+            let chainwork = self.chainwork[self.chainwork.len() - 1] + header.work();
+            self.headers.push(header);
+            self.hashes.push(hash);
+            self.chainwork.push(chainwork);
+        }
+    }
+
+    /// Drops everything above `height` after the chain was reorganized
+    fn truncate(&mut self, height: u64) {
+        let len = height as usize + 1;
+        self.headers.truncate(len);
+        self.hashes.truncate(len);
+        self.chainwork.truncate(len);
+        self.persisted_headers = self.persisted_headers.min(len);
+        self.filters.split_off(&(height + 1));
+        self.block_fee_rates.split_off(&(height + 1));
+        self.tx_heights.retain(|_, tx_height| *tx_height <= height);
+        for history in self.script_history.values_mut() {
+            history
+                .transactions
+                .retain(|(tx_height, _)| *tx_height <= height);
+            history.scanned_height = history.scanned_height.map(|scanned| scanned.min(height));
+        }
+    }
+
+    /// Loads the headers persisted to `path`, which doesn't need to exist yet.
+    /// Invalid headers and everything following them are dropped and
+    /// overwritten when persisting the next time.
+    async fn load_headers(&mut self, path: &Path) -> anyhow::Result<()> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Couldn't read headers file {}", path.display()))
+            }
+        };
+
+        let mut chunks = bytes.chunks_exact(HEADER_SIZE);
+        let Some(genesis) = chunks.next() else {
+            return Ok(());
+        };
+        ensure!(
+            deserialize::<Header>(genesis)? == self.headers[0],
+            "Headers file {} belongs to another network",
+            path.display()
+        );
+
+        let mut branch = Branch::new(0);
+        for chunk in chunks {
+            let result = deserialize(chunk)
+                .map_err(anyhow::Error::from)
+                .and_then(|header| branch.push(self, header));
+            if let Err(error) = result {
+                warn!(target: LOG_BLOCKCHAIN, height = branch.tip_height() + 1, ?error, "Dropping invalid headers from headers file");
+                break;
+            }
+        }
+
+        self.extend(branch);
+        self.persisted_headers = self.headers.len();
+        info!(target: LOG_BLOCKCHAIN, height = self.tip_height(), "Loaded headers from headers file");
+        Ok(())
+    }
+
+    /// Writes the headers that aren't persisted to `path` yet, replacing the
+    /// ones dropped by reorganizations
+    async fn persist_headers(&mut self, path: &Path) -> anyhow::Result<()> {
+        if self.persisted_headers == self.headers.len() {
+            return Ok(());
+        }
+
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .await?;
+        file.set_len((self.persisted_headers * HEADER_SIZE) as u64)
+            .await?;
+        file.seek(SeekFrom::End(0)).await?;
+
+        let headers = self.headers[self.persisted_headers..]
+            .iter()
+            .flat_map(serialize)
+            .collect::<Vec<_>>();
+        file.write_all(&headers).await?;
+        file.flush().await?;
+        file.sync_data().await?;
+
+        self.persisted_headers = self.headers.len();
+        Ok(())
+    }
+}
+
+/// Headers of a peer's chain we don't have, following our chain at
+/// `fork_height`
+#[derive(Debug)]
+struct Branch {
+    fork_height: u64,
+    headers: Vec<Header>,
+    hashes: Vec<BlockHash>,
+}
+
+impl Branch {
+    fn new(fork_height: u64) -> Self {
+        Branch {
+            fork_height,
+            headers: vec![],
+            hashes: vec![],
+        }
+    }
+
+    fn tip_height(&self) -> u64 {
+        self.fork_height + self.headers.len() as u64
+    }
+
+    fn tip_hash(&self, chain: &ChainState) -> BlockHash {
+        self.hashes
+            .last()
+            .copied()
+            .unwrap_or(chain.hashes[self.fork_height as usize])
+    }
+
+    /// Header at `height` of the chain ending with this branch
+    fn header(&self, chain: &ChainState, height: u64) -> Header {
+        match height.checked_sub(self.fork_height + 1) {
+            Some(index) => self.headers[index as usize],
+            None => chain.headers[height as usize],
+        }
+    }
+
+    /// Total work of the chain ending with this branch
+    fn chainwork(&self, chain: &ChainState) -> Work {
+        self.headers.iter().fold(
+            chain.chainwork[self.fork_height as usize],
+            |work, header| work + header.work(),
+        )
+    }
+
+    /// Validates `header` and appends it to the branch
+    fn push(&mut self, chain: &ChainState, header: Header) -> anyhow::Result<()> {
+        let height = self.tip_height() + 1;
+
+        // Peers behind us send headers we already have
+        if self.headers.is_empty()
+            && chain.hashes.get(height as usize) == Some(&header.block_hash())
+        {
+            self.fork_height = height;
+            return Ok(());
+        }
+
+        let hash = validate_header(
+            chain.network,
+            height,
+            &header,
+            self.tip_hash(chain),
+            |ancestor_height| self.header(chain, ancestor_height),
+        )?;
+        self.headers.push(header);
+        self.hashes.push(hash);
+        Ok(())
+    }
+}
+
+/// Checks that `header` can follow the block `previous_hash` at `height`
+/// under the consensus rules that don't need the blocks, `ancestor` returns
+/// the headers below it. Returns the hash of `header`.
+fn validate_header(
+    network: Network,
+    height: u64,
+    header: &Header,
+    previous_hash: BlockHash,
+    ancestor: impl Fn(u64) -> Header,
+) -> anyhow::Result<BlockHash> {
+    ensure!(
+        header.prev_blockhash == previous_hash,
+        "Headers don't form a chain"
+    );
+
+    let required_bits = required_bits(&Params::new(network), height, header.time, &ancestor);
+    ensure!(
+        header.bits == required_bits,
+        "Header at height {height} has difficulty bits {:#x} instead of {:#x}",
+        header.bits.to_consensus(),
+        required_bits.to_consensus()
+    );
+    let block_hash = header
+        .validate_pow(header.target())
+        .map_err(|e| format_err!("Invalid proof of work: {e}"))?;
+
+    let mut times = (height.saturating_sub(MEDIAN_TIME_SPAN)..height)
+        .map(|ancestor_height| ancestor(ancestor_height).time)
+        .collect::<Vec<_>>();
+    times.sort_unstable();
+    ensure!(
+        times[times.len() / 2] < header.time,
+        "Header at height {height} isn't newer than the median time of the previous blocks"
+    );
+    ensure!(
+        u64::from(header.time) <= duration_since_epoch().as_secs() + MAX_FUTURE_BLOCK_TIME,
+        "Header at height {height} is too far in the future"
+    );
+
+    Ok(block_hash)
+}
+
+/// Difficulty bits the block at `height` with timestamp `time` needs, as
+/// computed by Bitcoin Core's `GetNextWorkRequired`
+fn required_bits(
+    params: &Params,
+    height: u64,
+    time: u32,
+    ancestor: &impl Fn(u64) -> Header,
+) -> CompactTarget {
+    let interval = params.difficulty_adjustment_interval();
+    let previous = ancestor(height - 1);
+    let pow_limit_bits = pow_limit(params).to_compact_lossy();
+
+    if height % interval != 0 {
+        if !params.allow_min_difficulty_blocks {
+            return previous.bits;
+        }
+
+        // Blocks more than two target spacings after the previous one may have
+        // the minimum difficulty
+        if u64::from(previous.time) + 2 * params.pow_target_spacing < u64::from(time) {
+            return pow_limit_bits;
+        }
+
+        // Otherwise they need the difficulty of the last block that didn't
+        let mut ancestor_height = height - 1;
+        let mut ancestor_header = previous;
+        while ancestor_height % interval != 0 && ancestor_header.bits == pow_limit_bits {
+            ancestor_height -= 1;
+            ancestor_header = ancestor(ancestor_height);
+        }
+        return ancestor_header.bits;
+    }
+
+    if params.no_pow_retargeting {
+        return previous.bits;
+    }
+
+    retarget(
+        params,
+        previous.bits,
+        ancestor(height - interval).time,
+        previous.time,
+    )
+}
+
+/// Adjusts the difficulty `bits` of a period whose first block has the
+/// timestamp `first_time` and last block `last_time`
+fn retarget(
+    params: &Params,
+    bits: CompactTarget,
+    first_time: u32,
+    last_time: u32,
+) -> CompactTarget {
+    let timespan = u64::from(last_time)
+        .saturating_sub(u64::from(first_time))
+        .clamp(
+            params.pow_target_timespan / 4,
+            params.pow_target_timespan * 4,
+        );
+
+    // The target is multiplied by the timespan before dividing like Bitcoin Core
+    // does, which needs more than 256 bits in between
+    let bytes = Target::from_compact(bits).to_le_bytes();
+    let mut limbs = [0u64; 5];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+        *limb = u64::from_le_bytes(chunk.try_into().expect("8 bytes"));
+    }
+    let mut carry = 0u128;
+    for limb in &mut limbs {
+        let product = u128::from(*limb) * u128::from(timespan) + carry;
+        *limb = product as u64;
+        carry = product >> 64;
+    }
+    let mut remainder = 0u128;
+    for limb in limbs.iter_mut().rev() {
+        let dividend = (remainder << 64) | u128::from(*limb);
+        *limb = (dividend / u128::from(params.pow_target_timespan)) as u64;
+        remainder = dividend % u128::from(params.pow_target_timespan);
+    }
+
+    if limbs[4] != 0 {
+        return pow_limit(params).to_compact_lossy();
+    }
+    let mut bytes = [0; 32];
+    for (chunk, limb) in bytes.chunks_exact_mut(8).zip(limbs) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    Target::from_le_bytes(bytes)
+        .min(pow_limit(params))
+        .to_compact_lossy()
+}
+
+/// Easiest target allowed on the network of `params`
+fn pow_limit(params: &Params) -> Target {
+    // The limit is stored as `Work` but is a target
+    Target::from_le_bytes(params.pow_limit.to_le_bytes())
+}
+
+impl CompactFiltersInner {
+    /// Drops the connection to the peer at `index` if `result` is an error,
+    /// since it may have sent an unexpected message or the stream may be left
+    /// mid-message
+    fn reset_on_err<T>(&mut self, index: usize, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        if result.is_err() {
+            self.peers[index] = None;
+        }
+        result
+    }
+
+    fn peer(&mut self, index: usize) -> &mut Peer {
+        self.peers[index].as_mut().expect("peer is connected")
+    }
+
+    /// Indices of the peers whose best block is our tip
+    fn synced_peers(&self) -> Vec<usize> {
+        let tip = (self.chain.tip_height(), self.chain.tip_hash());
+        self.peers
+            .iter()
+            .enumerate()
+            .filter(|(_, peer)| {
+                peer.as_ref()
+                    .is_some_and(|peer| peer.best_block == Some(tip))
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Index of a peer whose best block is our tip
+    fn synced_peer(&self) -> anyhow::Result<usize> {
+        self.synced_peers()
+            .first()
+            .copied()
+            .context("No peer is synced to our best block")
+    }
+
+    /// Downloads the headers of all peers, connecting to them if necessary, and
+    /// switches to the valid chain with the most work
+    async fn sync_headers(
+        &mut self,
+        peer_addrs: &[String],
+        network: Network,
+    ) -> anyhow::Result<()> {
+        let CompactFiltersInner { peers, chain, .. } = self;
+        let chain = &*chain;
+
+        let branches = futures::future::join_all(peers.iter_mut().zip(peer_addrs).map(
+            |(peer, addr)| async move {
+                let result = async {
+                    if peer.is_none() {
+                        *peer = Some(Peer::connect(addr, network).await?);
+                    }
+                    peer.as_mut()
+                        .expect("connected above")
+                        .get_branch(chain)
+                        .await
+                }
+                .await;
+
+                match result {
+                    Ok(branch) => Some(branch),
+                    Err(error) => {
+                        warn!(target: LOG_BLOCKCHAIN, %addr, ?error, "Error syncing headers from peer");
+                        *peer = None;
+                        None
+                    }
+                }
+            },
+        ))
+        .await;
+        ensure!(
+            branches.iter().any(Option::is_some),
+            "Couldn't sync headers from any peer"
+        );
+
+        let best_branch = branches
+            .into_iter()
+            .flatten()
+            .filter(|branch| !branch.headers.is_empty())
+            .map(|branch| (branch.chainwork(&self.chain), branch))
+            .max_by_key(|(chainwork, _)| *chainwork);
+        if let Some((chainwork, branch)) = best_branch {
+            if self.chain.chainwork[self.chain.chainwork.len() - 1] < chainwork {
+                self.chain.extend(branch);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Downloads and verifies the filters of all blocks from `start_height`
+    /// to the tip we don't have yet
+    async fn sync_filters(&mut self, start_height: u64) -> anyhow::Result<()> {
+        let synced_peers = self.synced_peers();
+        let first_peer = *synced_peers
+            .first()
+            .context("No peer is synced to our best block")?;
+
+        let mut height = self
+            .chain
+            .filters
+            .last_key_value()
+            .map_or(start_height, |(height, _)| height + 1);
+
+        while height <= self.chain.tip_height() {
+            let stop_height = (height + MAX_FILTERS_PER_REQUEST - 1).min(self.chain.tip_height());
+            let stop_hash = self.chain.hashes[stop_height as usize];
+
+            // Only a single peer has to be honest to notice wrong filters
+            let mut cf_headers: Option<CFHeaders> = None;
+            for &index in &synced_peers {
+                let result = self
+                    .peer(index)
+                    .get_filter_headers(height, stop_height, stop_hash)
+                    .await;
+                let peer_cf_headers = self.reset_on_err(index, result)?;
+                if let Some(cf_headers) = &cf_headers {
+                    ensure!(
+                        *cf_headers == peer_cf_headers,
+                        "Peers disagree on the filters of the blocks {height} to {stop_height}"
+                    );
+                }
+                cf_headers = Some(peer_cf_headers);
+            }
+            let cf_headers = cf_headers.expect("there is a synced peer");
+
+            if let Some((previous_header, _)) = height
+                .checked_sub(1)
+                .and_then(|previous_height| self.chain.filters.get(&previous_height))
+            {
+                ensure!(
+                    *previous_header == cf_headers.previous_filter_header,
+                    "Filter headers don't connect"
+                );
+            }
+
+            let CompactFiltersInner { peers, chain, .. } = self;
+            let result = peers[first_peer]
+                .as_mut()
+                .expect("peer is connected")
+                .get_filters(chain, height, cf_headers)
+                .await;
+            self.reset_on_err(first_peer, result)?;
+            height = stop_height + 1;
+
+            debug!(target: LOG_BLOCKCHAIN, height, "Synced compact filters");
+        }
+
+        Ok(())
+    }
+}
+
+impl CompactFiltersClient {
+    fn new(url: &SafeUrl) -> anyhow::Result<Self> {
+        let host = url.host_str().context("Missing peer host")?;
+
+        let mut network = None;
+        let mut start_height = None;
+        let mut headers_file = None;
+        let mut other_peers = vec![];
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "network" => network = Some(value.parse::<Network>()?),
+                "start_height" => start_height = Some(value.parse::<u64>()?),
+                "headers_file" => headers_file = Some(PathBuf::from(value.as_ref())),
+                "peer" => other_peers.push(value.into_owned()),
+                _ => bail!("Unknown query parameter {key}"),
+            }
+        }
+
+        let (network, port) = match (network, url.port()) {
+            (Some(network), Some(port)) => (network, port),
+            (Some(Network::Bitcoin), None) | (None, Some(8333)) => (Network::Bitcoin, 8333),
+            (Some(Network::Testnet), None) | (None, Some(18333)) => (Network::Testnet, 18333),
+            (Some(Network::Signet), None) | (None, Some(38333)) => (Network::Signet, 38333),
+            (Some(Network::Regtest), None) | (None, Some(18444)) => (Network::Regtest, 18444),
+            _ => bail!("Unknown network, set the network query parameter and port"),
+        };
+
+        let mut peer_addrs = vec![format!("{host}:{port}")];
+        for peer in other_peers {
+            let has_port = peer
+                .rsplit_once(':')
+                .is_some_and(|(_, peer_port)| peer_port.parse::<u16>().is_ok());
+            peer_addrs.push(if has_port {
+                peer
+            } else {
+                format!("{peer}:{port}")
+            });
+        }
+
+        let headers_file = headers_file.or_else(|| default_headers_file(network));
+
+        Ok(Self {
+            inner: Mutex::new(CompactFiltersInner {
+                peers: peer_addrs.iter().map(|_| None).collect(),
+                chain: ChainState::new(network),
+                headers_loaded: false,
+            }),
+            peer_addrs,
+            network,
+            start_height,
+            headers_file,
+        })
+    }
+
+    /// Locks the client with the chain synced to the peers' best tip,
+    /// connecting to the peers if necessary
+    async fn lock_synced(
+        &self,
+        sync_filters: bool,
+    ) -> anyhow::Result<MutexGuard<'_, CompactFiltersInner>> {
+        let mut inner = self.inner.lock().await;
+
+        if let Some(headers_file) = &self.headers_file {
+            if !inner.headers_loaded {
+                inner.chain.load_headers(headers_file).await?;
+                inner.headers_loaded = true;
+            }
+        }
+
+        inner.sync_headers(&self.peer_addrs, self.network).await?;
+
+        if let Some(headers_file) = &self.headers_file {
+            // The headers are still in memory, so we can keep going
+            if let Err(error) = inner.chain.persist_headers(headers_file).await {
+                warn!(target: LOG_BLOCKCHAIN, ?error, "Error persisting headers");
+            }
+        }
+
+        if sync_filters {
+            let start_height = self
+                .start_height
+                .unwrap_or_else(|| inner.chain.tip_height().saturating_sub(DEFAULT_SCAN_DEPTH));
+            inner.sync_filters(start_height).await?;
+        }
+
+        Ok(inner)
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IBitcoindRpc for CompactFiltersClient {
+    async fn get_network(&self) -> anyhow::Result<Network> {
+        Ok(self.network)
+    }
+
+    async fn get_block_count(&self) -> anyhow::Result<u64> {
+        Ok(self.lock_synced(false).await?.chain.tip_height() + 1)
+    }
+
+    async fn get_block_hash(&self, height: u64) -> anyhow::Result<BlockHash> {
+        self.lock_synced(false)
+            .await?
+            .chain
+            .hashes
+            .get(height as usize)
+            .copied()
+            .ok_or_else(|| format_err!("No block at height {height}"))
+    }
+
+    async fn get_fee_rate(&self, _confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+        let mut inner = self.lock_synced(false).await?;
+
+        let tip_height = inner.chain.tip_height();
+        let first_height = tip_height.saturating_sub(FEE_ESTIMATE_BLOCKS - 1).max(1);
+        inner.chain.block_fee_rates = inner.chain.block_fee_rates.split_off(&first_height);
+
+        // Downloading the newest missing block only keeps estimates cheap, once
+        // we are running they cost a block download per mined block
+        let missing_heights = (first_height..=tip_height)
+            .rev()
+            .filter(|height| !inner.chain.block_fee_rates.contains_key(height))
+            .collect::<Vec<_>>();
+        if let Some(&height) = missing_heights.first() {
+            let index = inner.synced_peer()?;
+            let block_hash = inner.chain.hashes[height as usize];
+            let result = inner.peer(index).get_block(block_hash).await;
+            let block = inner.reset_on_err(index, result)?;
+            inner
+                .chain
+                .block_fee_rates
+                .insert(height, block_fee_rate(&block, height, self.network));
+        }
+        if 1 < missing_heights.len() {
+            debug!(target: LOG_BLOCKCHAIN, "Not enough blocks downloaded to estimate fee rate yet");
+            return Ok(None);
+        }
+
+        Ok(Some(estimate_fee_rate(&inner.chain.block_fee_rates)))
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) {
+        let txid = transaction.txid();
+        let mut inner = self.inner.lock().await;
+
+        for (index, addr) in self.peer_addrs.iter().enumerate() {
+            let result = async {
+                if inner.peers[index].is_none() {
+                    inner.peers[index] = Some(Peer::connect(addr, self.network).await?);
+                }
+                // Nodes accept transactions without announcing them with `inv` first
+                inner
+                    .peer(index)
+                    .send(NetworkMessage::Tx(transaction.clone()))
+                    .await
+            }
+            .await;
+
+            if let Err(error) = inner.reset_on_err(index, result) {
+                info!(target: LOG_BLOCKCHAIN, %txid, %addr, ?error, "Error broadcasting transaction");
+            }
+        }
+    }
+
+    async fn get_tx_block_height(&self, txid: &Txid) -> anyhow::Result<Option<u64>> {
+        // We can only look up transactions we found scanning for scripts
+        Ok(self
+            .lock_synced(false)
+            .await?
+            .chain
+            .tx_heights
+            .get(txid)
+            .copied())
+    }
+
+    async fn watch_script_history(&self, script: &ScriptBuf) -> anyhow::Result<()> {
+        self.inner
+            .lock()
+            .await
+            .chain
+            .script_history
+            .entry(script.clone())
+            .or_default();
+        Ok(())
+    }
+
+    async fn get_script_history(&self, script: &ScriptBuf) -> anyhow::Result<Vec<Transaction>> {
+        let mut inner = self.lock_synced(true).await?;
+        let index = inner.synced_peer()?;
+        let CompactFiltersInner { peers, chain, .. } = &mut *inner;
+        let peer = peers[index].as_mut().expect("peer is connected");
+
+        let mut history = chain.script_history.remove(script).unwrap_or_default();
+        let result = peer.scan_script(chain, script, &mut history).await;
+        let transactions = history
+            .transactions
+            .iter()
+            .map(|(_, tx)| tx.clone())
+            .collect();
+        chain.script_history.insert(script.clone(), history);
+
+        inner.reset_on_err(index, result).map(|()| transactions)
+    }
+
+    async fn get_txout_proof(&self, txid: Txid) -> anyhow::Result<TxOutProof> {
+        let mut inner = self.lock_synced(false).await?;
+
+        let height = inner
+            .chain
+            .tx_heights
+            .get(&txid)
+            .copied()
+            .context("Transaction is unknown or not confirmed")?;
+        let index = inner.synced_peer()?;
+        let block_hash = inner.chain.hashes[height as usize];
+        let result = inner.peer(index).get_block(block_hash).await;
+        let block = inner.reset_on_err(index, result)?;
+
+        let txids = block
+            .txdata
+            .iter()
+            .map(Transaction::txid)
+            .collect::<Vec<_>>();
+        let matches = txids
+            .iter()
+            .map(|block_txid| *block_txid == txid)
+            .collect::<Vec<_>>();
+
+        Ok(TxOutProof {
+            block_header: bitcoin30_to_bitcoin29_block_header(block.header),
+            merkle_proof: bitcoin30_to_bitcoin29_partial_merkle_tree(
+                PartialMerkleTree::from_txids(&txids, &matches),
+            ),
+        })
+    }
+}
+
+/// Headers file used if the `headers_file` query parameter isn't set
+fn default_headers_file(network: Network) -> Option<PathBuf> {
+    if network == Network::Regtest {
+        return None;
+    }
+    let Some(cache_dir) = dirs::cache_dir() else {
+        warn!(target: LOG_BLOCKCHAIN, "No cache directory, headers will be downloaded again after a restart");
+        return None;
+    };
+    Some(
+        cache_dir
+            .join("fedimint")
+            .join(format!("bip157-headers-{network}")),
+    )
+}
+
+/// Median of the average fee rates of `block_fee_rates`, bounded by
+/// [`MIN_RELAY_FEE_RATE`] and [`MAX_FEE_RATE`]
+fn estimate_fee_rate(block_fee_rates: &BTreeMap<u64, Option<Feerate>>) -> Feerate {
+    let mut fee_rates = block_fee_rates
+        .values()
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+    fee_rates.sort_unstable();
+    fee_rates
+        .get(fee_rates.len() / 2)
+        .copied()
+        .unwrap_or(MIN_RELAY_FEE_RATE)
+        .clamp(MIN_RELAY_FEE_RATE, MAX_FEE_RATE)
+}
+
+/// Average fee rate paid by the transactions of `block`, `None` if it only
+/// contains the coinbase transaction
+fn block_fee_rate(block: &Block, height: u64, network: Network) -> Option<Feerate> {
+    let halving_interval = match network {
+        Network::Regtest => 150,
+        _ => 210_000,
+    };
+    let subsidy = (50 * 100_000_000u64)
+        .checked_shr((height / halving_interval) as u32)
+        .unwrap_or(0);
+
+    let coinbase_value = block
+        .txdata
+        .first()?
+        .output
+        .iter()
+        .map(|output| output.value)
+        .sum::<u64>();
+    let fees = coinbase_value.saturating_sub(subsidy);
+
+    let vsize = block
+        .txdata
+        .iter()
+        .skip(1)
+        .map(|tx| tx.vsize() as u64)
+        .sum::<u64>();
+    if vsize == 0 {
+        return None;
+    }
+
+    Some(Feerate {
+        sats_per_kvb: fees * 1000 / vsize,
+    })
+}
+
+/// Connection to a single peer
+#[derive(Debug)]
+struct Peer {
+    stream: TcpStream,
+    network: Network,
+    /// Height and hash of the peer's best block, if known from the last time
+    /// we synced its headers
+    best_block: Option<(u64, BlockHash)>,
+}
+
+impl Peer {
+    async fn connect(addr: &str, network: Network) -> anyhow::Result<Self> {
+        let stream = runtime::timeout(MESSAGE_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| format_err!("Timed out connecting to {addr}"))??;
+        let peer_addr = stream.peer_addr()?;
+        let local_addr = stream.local_addr()?;
+        let mut peer = Peer {
+            stream,
+            network,
+            best_block: None,
+        };
+
+        peer.send(NetworkMessage::Version(VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: ServiceFlags::NONE,
+            timestamp: duration_since_epoch().as_secs() as i64,
+            receiver: bitcoin::network::Address::new(&peer_addr, ServiceFlags::NONE),
+            sender: bitcoin::network::Address::new(&local_addr, ServiceFlags::NONE),
+            nonce: rand::random(),
+            user_agent: USER_AGENT.to_string(),
+            start_height: 0,
+            relay: false,
+        }))
+        .await?;
+
+        let mut version_received = false;
+        let mut verack_received = false;
+        while !(version_received && verack_received) {
+            match peer.recv().await? {
+                NetworkMessage::Version(version) => {
+                    ensure!(
+                        version.services.has(ServiceFlags::COMPACT_FILTERS),
+                        "Peer {addr} doesn't serve compact filters, it needs to run with -blockfilterindex=1 -peerblockfilters=1"
+                    );
+                    peer.send(NetworkMessage::Verack).await?;
+                    version_received = true;
+                }
+                NetworkMessage::Verack => verack_received = true,
+                _ => {}
+            }
+        }
+
+        debug!(target: LOG_BLOCKCHAIN, %addr, "Connected to compact filters peer");
+        Ok(peer)
+    }
+
+    async fn send(&mut self, payload: NetworkMessage) -> anyhow::Result<()> {
+        let message = RawNetworkMessage {
+            magic: self.network.magic(),
+            payload,
+        };
+        self.stream.write_all(&serialize(&message)).await?;
+        Ok(())
+    }
+
+    /// Receives the next message that isn't a `ping`, which are answered
+    async fn recv(&mut self) -> anyhow::Result<NetworkMessage> {
+        loop {
+            let message = runtime::timeout(MESSAGE_TIMEOUT, self.recv_raw())
+                .await
+                .map_err(|_| format_err!("Timed out waiting for peer"))??;
+            ensure!(
+                message.magic == self.network.magic(),
+                "Peer is on another network"
+            );
+
+            match message.payload {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(nonce)).await?,
+                payload => return Ok(payload),
+            }
+        }
+    }
+
+    async fn recv_raw(&mut self) -> anyhow::Result<RawNetworkMessage> {
+        // Magic, command, payload length and checksum
+        let mut message = vec![0; 24];
+        self.stream.read_exact(&mut message).await?;
+
+        let payload_len = u32::from_le_bytes(message[16..20].try_into().expect("4 bytes")) as usize;
+        ensure!(payload_len <= MAX_MESSAGE_SIZE, "Message is too large");
+
+        message.resize(24 + payload_len, 0);
+        self.stream.read_exact(&mut message[24..]).await?;
+
+        Ok(deserialize(&message)?)
+    }
+
+    /// Downloads and validates the headers of the peer's best chain that
+    /// aren't in `chain`, which may fork from it, and remembers the peer's
+    /// best block
+    async fn get_branch(&mut self, chain: &ChainState) -> anyhow::Result<Branch> {
+        // Starting below our tip, a peer that has it sends at least its header
+        let mut locator = chain.locator(chain.tip_height().saturating_sub(1));
+        let mut branch: Option<Branch> = None;
+        self.best_block = None;
+
+        loop {
+            self.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+                locator.clone(),
+                BlockHash::all_zeros(),
+            )))
+            .await?;
+
+            let headers = loop {
+                if let NetworkMessage::Headers(headers) = self.recv().await? {
+                    break headers;
+                }
+            };
+
+            let Some(first) = headers.first() else {
+                // The peer's best block is the block of the locator if there is
+                // only one, otherwise any of them
+                if locator.len() == 1 {
+                    let branch = branch.get_or_insert_with(|| Branch::new(0));
+                    self.best_block = Some((branch.tip_height(), branch.tip_hash(chain)));
+                }
+                break;
+            };
+
+            let branch = match &mut branch {
+                Some(branch) => branch,
+                None => {
+                    // The headers connect to the last block of the locator we share with the
+                    // peer
+                    let fork_height = chain
+                        .hashes
+                        .iter()
+                        .rposition(|hash| *hash == first.prev_blockhash)
+                        .context("Headers don't connect to our chain")?;
+                    branch.insert(Branch::new(fork_height as u64))
+                }
+            };
+
+            for header in &headers {
+                branch.push(chain, *header)?;
+            }
+            self.best_block = Some((branch.tip_height(), branch.tip_hash(chain)));
+
+            if headers.len() < MAX_HEADERS_PER_MESSAGE {
+                break;
+            }
+            locator = vec![branch.tip_hash(chain)];
+        }
+
+        Ok(branch.unwrap_or_else(|| Branch::new(chain.tip_height())))
+    }
+
+    /// Downloads the filter headers of the blocks from `start_height` to
+    /// `stop_height`
+    async fn get_filter_headers(
+        &mut self,
+        start_height: u64,
+        stop_height: u64,
+        stop_hash: BlockHash,
+    ) -> anyhow::Result<CFHeaders> {
+        self.send(NetworkMessage::GetCFHeaders(GetCFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height: start_height as u32,
+            stop_hash,
+        }))
+        .await?;
+
+        let cf_headers = loop {
+            if let NetworkMessage::CFHeaders(cf_headers) = self.recv().await? {
+                break cf_headers;
+            }
+        };
+        ensure!(
+            cf_headers.stop_hash == stop_hash
+                && cf_headers.filter_hashes.len() as u64 == stop_height - start_height + 1,
+            "Unexpected filter headers"
+        );
+
+        Ok(cf_headers)
+    }
+
+    /// Downloads the filters of the blocks from `start_height` to the stop
+    /// hash of `cf_headers` and adds them to `chain` after checking them
+    /// against the filter headers
+    async fn get_filters(
+        &mut self,
+        chain: &mut ChainState,
+        start_height: u64,
+        cf_headers: CFHeaders,
+    ) -> anyhow::Result<()> {
+        self.send(NetworkMessage::GetCFilters(GetCFilters {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height: start_height as u32,
+            stop_hash: cf_headers.stop_hash,
+        }))
+        .await?;
+
+        let mut filter_header = cf_headers.previous_filter_header;
+        for (height, filter_hash) in (start_height..).zip(cf_headers.filter_hashes) {
+            let filter = loop {
+                if let NetworkMessage::CFilter(filter) = self.recv().await? {
+                    break filter;
+                }
+            };
+            ensure!(
+                filter.block_hash == chain.hashes[height as usize],
+                "Filter for unexpected block"
+            );
+            ensure!(
+                FilterHash::hash(&filter.filter) == filter_hash,
+                "Filter doesn't match its header"
+            );
+
+            filter_header = filter_hash.filter_header(&filter_header);
+            chain.filters.insert(height, (filter_header, filter.filter));
+        }
+
+        Ok(())
+    }
+
+    async fn get_block(&mut self, block_hash: BlockHash) -> anyhow::Result<Block> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(
+            block_hash,
+        )]))
+        .await?;
+
+        loop {
+            match self.recv().await? {
+                NetworkMessage::Block(block) if block.block_hash() == block_hash => {
+                    ensure!(block.check_merkle_root(), "Invalid block {block_hash}");
+                    return Ok(block);
+                }
+                NetworkMessage::NotFound(_) => bail!("Peer doesn't have block {block_hash}"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds the transactions paying to or spending from `script` in the blocks
+    /// that weren't scanned for it yet to its `history`
+    async fn scan_script(
+        &mut self,
+        chain: &mut ChainState,
+        script: &ScriptBuf,
+        history: &mut ScriptHistory,
+    ) -> anyhow::Result<()> {
+        let mut outpoints = history
+            .transactions
+            .iter()
+            .flat_map(|(_, tx)| {
+                let txid = tx.txid();
+                tx.output
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, output)| output.script_pubkey == *script)
+                    .map(move |(vout, _)| OutPoint::new(txid, vout as u32))
+            })
+            .collect::<BTreeSet<_>>();
+
+        let first_height = history.scanned_height.map_or(0, |height| height + 1);
+        let matching_heights = chain
+            .filters
+            .range(first_height..)
+            .filter_map(|(height, (_, filter))| {
+                BlockFilter::new(filter)
+                    .match_any(
+                        &chain.hashes[*height as usize],
+                        &mut std::iter::once(script.as_bytes()),
+                    )
+                    .map(|matches| matches.then_some(*height))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for height in matching_heights {
+            let block = self.get_block(chain.hashes[height as usize]).await?;
+
+            for tx in block.txdata {
+                let txid = tx.txid();
+                let spends = tx
+                    .input
+                    .iter()
+                    .any(|input| outpoints.contains(&input.previous_output));
+                let mut pays = false;
+                for (vout, output) in tx.output.iter().enumerate() {
+                    if output.script_pubkey == *script {
+                        outpoints.insert(OutPoint::new(txid, vout as u32));
+                        pays = true;
+                    }
+                }
+
+                if spends || pays {
+                    chain.tx_heights.insert(txid, height);
+                    history.transactions.push((height, tx));
+                }
+            }
+
+            // Blocks that matched are only downloaded again after a reorg
+            history.scanned_height = Some(height);
+        }
+
+        history.scanned_height = chain
+            .filters
+            .last_key_value()
+            .map(|(height, _)| *height)
+            .or(history.scanned_height);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::block::Version;
+    use bitcoin::hash_types::TxMerkleNode;
+
+    use super::*;
+
+    /// Mines a regtest header following `previous`
+    fn mine(previous: &Header, time: u32) -> Header {
+        let mut header = Header {
+            version: Version::TWO,
+            prev_blockhash: previous.block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: previous.bits,
+            nonce: 0,
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    /// Mines `blocks` headers `spacing` seconds apart on top of `fork_height`
+    fn mine_branch(chain: &ChainState, fork_height: u64, blocks: u32, spacing: u32) -> Branch {
+        let mut branch = Branch::new(fork_height);
+        for _ in 0..blocks {
+            let previous = branch.header(chain, branch.tip_height());
+            branch
+                .push(chain, mine(&previous, previous.time + spacing))
+                .expect("mined header is valid");
+        }
+        branch
+    }
+
+    #[test]
+    fn retarget_matches_bitcoin_core() {
+        // Test vectors of Bitcoin Core's `pow_tests`
+        let params = Params::new(Network::Bitcoin);
+        for (bits, first_time, last_time, expected_bits) in [
+            (0x1d00_ffff, 1_261_130_161, 1_262_152_739, 0x1d00_d86a),
+            (0x1d00_ffff, 1_231_006_505, 1_233_061_996, 0x1d00_ffff),
+            (0x1c05_a3f4, 1_279_008_237, 1_279_297_671, 0x1c01_68fd),
+            (0x1c38_7f6f, 1_263_163_443, 1_269_211_443, 0x1d00_e1fd),
+        ] {
+            assert_eq!(
+                retarget(
+                    &params,
+                    CompactTarget::from_consensus(bits),
+                    first_time,
+                    last_time
+                ),
+                CompactTarget::from_consensus(expected_bits)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let chain = ChainState::new(Network::Regtest);
+        let genesis = chain.headers[0];
+        let mut branch = Branch::new(0);
+
+        let mut wrong_bits = mine(&genesis, genesis.time + 600);
+        wrong_bits.bits = CompactTarget::from_consensus(0x1d00_ffff);
+        assert!(branch.push(&chain, wrong_bits).is_err());
+
+        assert!(branch.push(&chain, mine(&genesis, genesis.time)).is_err());
+
+        let header = mine(&genesis, genesis.time + 600);
+        assert!(branch
+            .push(&chain, mine(&header, header.time + 600))
+            .is_err());
+
+        branch.push(&chain, header).expect("header is valid");
+        assert_eq!(branch.hashes, vec![header.block_hash()]);
+    }
+
+    #[test]
+    fn reorganizations_replace_the_chain() {
+        let mut chain = ChainState::new(Network::Regtest);
+        chain.extend(mine_branch(&chain, 0, 10, 600));
+        chain.persisted_headers = chain.headers.len();
+
+        let branch = mine_branch(&chain, 5, 6, 601);
+        assert!(chain.chainwork[10] < branch.chainwork(&chain));
+        let branch_hashes = branch.hashes.clone();
+        chain.extend(branch);
+
+        assert_eq!(chain.tip_height(), 11);
+        assert_eq!(chain.hashes[6..], branch_hashes[..]);
+        assert_eq!(chain.persisted_headers, 6);
+    }
+
+    #[test]
+    fn fee_rate_estimates_are_bounded() {
+        let estimate = |rates: &[Option<u64>]| {
+            let block_fee_rates = rates
+                .iter()
+                .enumerate()
+                .map(|(height, rate)| {
+                    let rate = rate.map(|sats_per_kvb| Feerate { sats_per_kvb });
+                    (height as u64, rate)
+                })
+                .collect();
+            estimate_fee_rate(&block_fee_rates).sats_per_kvb
+        };
+
+        assert_eq!(estimate(&[None, None]), 1000);
+        assert_eq!(estimate(&[Some(5000), None, Some(3000), Some(4000)]), 4000);
+        assert_eq!(estimate(&[Some(10), Some(20), Some(30)]), 1000);
+        assert_eq!(estimate(&[Some(u64::MAX), Some(u64::MAX)]), 10_000_000);
+    }
+
+    #[tokio::test]
+    async fn headers_are_persisted() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("fedimint").join("headers");
+
+        let mut chain = ChainState::new(Network::Regtest);
+        chain.load_headers(&path).await?;
+        assert_eq!(chain.tip_height(), 0);
+
+        chain.extend(mine_branch(&chain, 0, 10, 600));
+        chain.persist_headers(&path).await?;
+        chain.extend(mine_branch(&chain, 5, 6, 601));
+        chain.persist_headers(&path).await?;
+
+        let mut loaded = ChainState::new(Network::Regtest);
+        loaded.load_headers(&path).await?;
+        assert_eq!(loaded.hashes, chain.hashes);
+        assert_eq!(loaded.chainwork, chain.chainwork);
+        assert_eq!(loaded.persisted_headers, loaded.headers.len());
+
+        let mut other_network = ChainState::new(Network::Testnet);
+        assert!(other_network.load_headers(&path).await.is_err());
+
+        Ok(())
+    }
+}
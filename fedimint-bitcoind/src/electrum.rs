@@ -26,6 +26,14 @@ impl IBitcoindRpcFactory for ElectrumFactory {
     ) -> anyhow::Result<DynBitcoindRpc> {
         Ok(RetryClient::new(ElectrumClient::new(url)?, handle).into())
     }
+
+    fn create_connection_without_retries(
+        &self,
+        url: &SafeUrl,
+        _handle: TaskHandle,
+    ) -> anyhow::Result<DynBitcoindRpc> {
+        Ok(ElectrumClient::new(url)?.into())
+    }
 }
 
 pub struct ElectrumClient(electrum_client::Client);
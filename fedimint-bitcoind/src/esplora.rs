@@ -25,6 +25,14 @@ impl IBitcoindRpcFactory for EsploraFactory {
     ) -> anyhow::Result<DynBitcoindRpc> {
         Ok(RetryClient::new(EsploraClient::new(url)?, handle).into())
     }
+
+    fn create_connection_without_retries(
+        &self,
+        url: &SafeUrl,
+        _handle: TaskHandle,
+    ) -> anyhow::Result<DynBitcoindRpc> {
+        Ok(EsploraClient::new(url)?.into())
+    }
 }
 
 #[derive(Debug)]
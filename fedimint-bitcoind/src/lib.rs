@@ -23,10 +23,15 @@ use tracing::{debug, info};
 
 #[cfg(feature = "bitcoincore-rpc")]
 pub mod bitcoincore;
+#[cfg(feature = "compact-filters")]
+pub mod compact_filters;
 #[cfg(feature = "electrum-client")]
 mod electrum;
 #[cfg(feature = "esplora-client")]
 mod esplora;
+#[cfg(feature = "metrics")]
+mod metrics;
+pub mod quorum;
 
 // <https://blockstream.info/api/block-height/0>
 const MAINNET_GENESIS_BLOCK_HASH: &str =
@@ -48,13 +53,14 @@ lazy_static! {
             ("electrum".to_string(), electrum::ElectrumFactory.into()),
             #[cfg(feature = "bitcoincore-rpc")]
             ("bitcoind".to_string(), bitcoincore::BitcoindFactory.into()),
+            #[cfg(feature = "compact-filters")]
+            ("bip157".to_string(), compact_filters::CompactFiltersFactory.into()),
+            ("quorum".to_string(), quorum::QuorumFactory.into()),
         ]));
 }
 
 /// Create a bitcoin RPC of a given kind
 pub fn create_bitcoind(config: &BitcoinRpcConfig, handle: TaskHandle) -> Result<DynBitcoindRpc> {
-    let registry = BITCOIN_RPC_REGISTRY.lock().expect("lock poisoned");
-
     let kind = env::var(FM_FORCE_BITCOIN_RPC_KIND_ENV)
         .ok()
         .unwrap_or_else(|| config.kind.clone());
@@ -64,15 +70,20 @@ pub fn create_bitcoind(config: &BitcoinRpcConfig, handle: TaskHandle) -> Result<
         .transpose()?
         .unwrap_or_else(|| config.url.clone());
     debug!(target: LOG_CORE, %kind, %url, "Starting bitcoin rpc");
-    let maybe_factory = registry.get(&kind);
-    let factory = maybe_factory.with_context(|| {
+    // The registry must not be locked while creating the connection, since
+    // factories like the quorum one create further connections
+    get_bitcoind_factory(&kind)?.create_connection(&url, handle)
+}
+
+/// Returns the registered factory for bitcoin RPCs of a given kind
+fn get_bitcoind_factory(kind: &str) -> Result<DynBitcoindRpcFactory> {
+    let registry = BITCOIN_RPC_REGISTRY.lock().expect("lock poisoned");
+    registry.get(kind).cloned().with_context(|| {
         anyhow::anyhow!(
-            "{} rpc not registered, available options: {:?}",
-            config.kind,
+            "{kind} rpc not registered, available options: {:?}",
             registry.keys()
         )
-    })?;
-    factory.create_connection(&url, handle)
+    })
 }
 
 /// Register a new factory for creating bitcoin RPCs
@@ -85,6 +96,19 @@ pub fn register_bitcoind(kind: String, factory: DynBitcoindRpcFactory) {
 pub trait IBitcoindRpcFactory: Debug + Send + Sync {
     /// Creates a new bitcoin RPC client connection
     fn create_connection(&self, url: &SafeUrl, handle: TaskHandle) -> Result<DynBitcoindRpc>;
+
+    /// Creates a new bitcoin RPC client connection that returns errors right
+    /// away instead of retrying failed calls like [`RetryClient`] does, for
+    /// RPCs that handle failures themselves like
+    /// [`quorum::QuorumBitcoindRpc`]. Defaults to
+    /// [`IBitcoindRpcFactory::create_connection`].
+    fn create_connection_without_retries(
+        &self,
+        url: &SafeUrl,
+        handle: TaskHandle,
+    ) -> Result<DynBitcoindRpc> {
+        self.create_connection(url, handle)
+    }
 }
 
 dyn_newtype_define! {
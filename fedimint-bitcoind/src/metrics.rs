@@ -0,0 +1,26 @@
+use fedimint_metrics::{
+    lazy_static, opts, register_int_counter_vec_with_registry, IntCounterVec, REGISTRY,
+};
+
+lazy_static! {
+    pub(crate) static ref BITCOIN_RPC_QUORUM_DISAGREEMENTS_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "bitcoin_rpc_quorum_disagreements_total",
+                "Number of queries the backends of a bitcoin rpc quorum answered differently",
+            ),
+            &["method"],
+            REGISTRY
+        )
+        .unwrap();
+    pub(crate) static ref BITCOIN_RPC_QUORUM_FAILURES_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            opts!(
+                "bitcoin_rpc_quorum_failures_total",
+                "Number of queries for which not enough backends of a bitcoin rpc quorum agreed",
+            ),
+            &["method"],
+            REGISTRY
+        )
+        .unwrap();
+}
@@ -0,0 +1,590 @@
+//! Bitcoin RPC that queries several backends and only trusts answers enough of
+//! them agree on, so a single misbehaving server can't mislead us.
+//!
+//! Configured with the `quorum` kind and the backends as query parameters of
+//! the url, named by their kind, e.g.
+//! `quorum:?threshold=2&esplora=https://mempool.space/api/&esplora=https://blockstream.info/api/&electrum=ssl://electrum.blockstream.info:50002`.
+//! Urls containing `&` or `#` have to be percent-encoded. The threshold
+//! defaults to a majority of the backends.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, format_err, Context};
+use bitcoin::{BlockHash, Network, ScriptBuf, Transaction, Txid};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::envs::BitcoinRpcConfig;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::TaskHandle;
+use fedimint_core::time::now;
+use fedimint_core::txoproof::TxOutProof;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{apply, async_trait_maybe_send, runtime, Feerate};
+use fedimint_logging::LOG_BLOCKCHAIN;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use tracing::{debug, warn};
+
+use crate::{get_bitcoind_factory, DynBitcoindRpc, IBitcoindRpc, IBitcoindRpcFactory, RetryClient};
+
+/// Maximum time we wait for the backends to answer a query
+const QUORUM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time we keep waiting for the remaining backends once enough of them
+/// answered, so one unresponsive backend doesn't slow down every query
+const QUORUM_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct QuorumFactory;
+
+impl IBitcoindRpcFactory for QuorumFactory {
+    fn create_connection(
+        &self,
+        url: &SafeUrl,
+        handle: TaskHandle,
+    ) -> anyhow::Result<DynBitcoindRpc> {
+        let mut threshold = None;
+        let mut backends = vec![];
+        for (key, value) in url.query_pairs() {
+            if key == "threshold" {
+                threshold = Some(value.parse::<usize>().context("Invalid threshold")?);
+                continue;
+            }
+
+            let config = BitcoinRpcConfig {
+                url: SafeUrl::parse(&value)
+                    .with_context(|| format!("Invalid url of {key} backend"))?,
+                kind: key.into_owned(),
+            };
+            ensure!(config.kind != "quorum", "Quorums can't be nested");
+            debug!(target: LOG_BLOCKCHAIN, backend = backends.len(), kind = %config.kind, url = %config.url, "Adding quorum backend");
+
+            // Failed calls are retried for the whole quorum, a backend retrying
+            // on its own would only delay the other backends' answers
+            backends.push(
+                get_bitcoind_factory(&config.kind)?
+                    .create_connection_without_retries(&config.url, handle.clone())?,
+            );
+        }
+
+        let threshold = threshold.unwrap_or(backends.len() / 2 + 1);
+        Ok(RetryClient::new(QuorumBitcoindRpc::new(backends, threshold)?, handle).into())
+    }
+}
+
+/// Wraps several [`DynBitcoindRpc`]s and only returns answers at least
+/// `threshold` of them agree on
+#[derive(Debug)]
+pub struct QuorumBitcoindRpc {
+    backends: Vec<DynBitcoindRpc>,
+    threshold: usize,
+}
+
+impl QuorumBitcoindRpc {
+    pub fn new(backends: Vec<DynBitcoindRpc>, threshold: usize) -> anyhow::Result<Self> {
+        ensure!(
+            1 <= threshold && threshold <= backends.len(),
+            "Threshold of {threshold} is not possible with {} backends",
+            backends.len()
+        );
+
+        Ok(Self {
+            backends,
+            threshold,
+        })
+    }
+
+    /// Sends `query` to all backends and collects the successful answers
+    /// until either all backends answered, `enough` answers were collected
+    /// or we timed out. Answers are tagged with the index of their backend.
+    async fn query_all<T, F, Fut>(
+        &self,
+        method: &'static str,
+        query: F,
+        enough: impl Fn(&[(usize, T)]) -> bool,
+    ) -> Vec<(usize, T)>
+    where
+        F: Fn(DynBitcoindRpc) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut pending = self
+            .backends
+            .iter()
+            .enumerate()
+            .map(|(backend, rpc)| {
+                let response = query(rpc.clone());
+                async move { (backend, response.await) }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut deadline = now() + QUORUM_TIMEOUT;
+        let mut answers = vec![];
+        loop {
+            let remaining = deadline.duration_since(now()).unwrap_or_default();
+            let Ok(Some((backend, response))) = runtime::timeout(remaining, pending.next()).await
+            else {
+                break;
+            };
+
+            match response {
+                Ok(answer) => answers.push((backend, answer)),
+                Err(error) => {
+                    debug!(target: LOG_BLOCKCHAIN, method, backend, ?error, "Quorum backend failed");
+                }
+            }
+
+            if enough(&answers) {
+                break;
+            }
+            if self.threshold <= answers.len() {
+                deadline = deadline.min(now() + QUORUM_GRACE_PERIOD);
+            }
+        }
+
+        answers
+    }
+
+    /// Returns the answer at least `threshold` backends gave
+    async fn query_agreement<T, F, Fut>(&self, method: &'static str, query: F) -> anyhow::Result<T>
+    where
+        T: Eq + Clone + Debug,
+        F: Fn(DynBitcoindRpc) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let threshold = self.threshold;
+        let answers = self
+            .query_all(method, query, |answers| {
+                agreed_answer(answers, threshold).is_some()
+            })
+            .await;
+
+        if answers.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+            record_disagreement(method, &answers);
+        }
+
+        agreed_answer(&answers, threshold).ok_or_else(|| {
+            record_failure(method);
+            format_err!("Not enough bitcoin backends agreed on {method}")
+        })
+    }
+}
+
+fn agreed_answer<T: Eq + Clone>(answers: &[(usize, T)], threshold: usize) -> Option<T> {
+    answers
+        .iter()
+        .find(|(_, answer)| {
+            answers.iter().filter(|(_, other)| other == answer).count() >= threshold
+        })
+        .map(|(_, answer)| answer.clone())
+}
+
+fn record_disagreement<T: Debug>(method: &'static str, answers: &[(usize, T)]) {
+    warn!(target: LOG_BLOCKCHAIN, method, ?answers, "Bitcoin backends disagree");
+    #[cfg(feature = "metrics")]
+    crate::metrics::BITCOIN_RPC_QUORUM_DISAGREEMENTS_TOTAL
+        .with_label_values(&[method])
+        .inc();
+}
+
+fn record_failure(method: &'static str) {
+    #[cfg(feature = "metrics")]
+    crate::metrics::BITCOIN_RPC_QUORUM_FAILURES_TOTAL
+        .with_label_values(&[method])
+        .inc();
+    #[cfg(not(feature = "metrics"))]
+    let _ = method;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IBitcoindRpc for QuorumBitcoindRpc {
+    async fn get_network(&self) -> anyhow::Result<Network> {
+        self.query_agreement("get_network", |rpc| async move { rpc.get_network().await })
+            .await
+    }
+
+    /// Returns the highest block count at least `threshold` backends reached,
+    /// so a backend can't make us believe in blocks that don't exist
+    async fn get_block_count(&self) -> anyhow::Result<u64> {
+        let mut answers = self
+            .query_all(
+                "get_block_count",
+                |rpc| async move { rpc.get_block_count().await },
+                |answers| answers.len() == self.backends.len(),
+            )
+            .await;
+
+        answers.sort_unstable_by_key(|(_, block_count)| std::cmp::Reverse(*block_count));
+        if answers.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+            // Backends may lag behind by a block for a moment
+            debug!(target: LOG_BLOCKCHAIN, ?answers, "Bitcoin backends report different block counts");
+        }
+
+        match answers.get(self.threshold - 1) {
+            Some((_, block_count)) => Ok(*block_count),
+            None => {
+                record_failure("get_block_count");
+                bail!("Not enough bitcoin backends returned a block count")
+            }
+        }
+    }
+
+    async fn get_block_hash(&self, height: u64) -> anyhow::Result<BlockHash> {
+        self.query_agreement("get_block_hash", |rpc| async move {
+            rpc.get_block_hash(height).await
+        })
+        .await
+    }
+
+    /// Returns the median of the backends' estimates
+    async fn get_fee_rate(&self, confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+        let answers = self
+            .query_all(
+                "get_fee_rate",
+                |rpc| async move { rpc.get_fee_rate(confirmation_target).await },
+                |answers| answers.len() == self.backends.len(),
+            )
+            .await;
+
+        let mut fee_rates = answers
+            .iter()
+            .filter_map(|(_, fee_rate)| *fee_rate)
+            .collect::<Vec<_>>();
+        if fee_rates.len() < self.threshold {
+            return Ok(None);
+        }
+
+        fee_rates.sort_unstable();
+        let median = fee_rates[fee_rates.len() / 2];
+
+        // Estimates always differ a bit, so we only consider outliers a disagreement
+        if fee_rates.iter().any(|fee_rate| {
+            median.sats_per_kvb * 2 < fee_rate.sats_per_kvb
+                || fee_rate.sats_per_kvb * 2 < median.sats_per_kvb
+        }) {
+            record_disagreement("get_fee_rate", &answers);
+        }
+
+        Ok(Some(median))
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) {
+        futures::future::join_all(
+            self.backends
+                .iter()
+                .map(|rpc| rpc.submit_transaction(transaction.clone())),
+        )
+        .await;
+    }
+
+    async fn get_tx_block_height(&self, txid: &Txid) -> anyhow::Result<Option<u64>> {
+        self.query_agreement("get_tx_block_height", |rpc| async move {
+            rpc.get_tx_block_height(txid).await
+        })
+        .await
+    }
+
+    async fn watch_script_history(&self, script: &ScriptBuf) -> anyhow::Result<()> {
+        let answers = self
+            .query_all(
+                "watch_script_history",
+                |rpc| async move { rpc.watch_script_history(script).await },
+                |answers| answers.len() == self.backends.len(),
+            )
+            .await;
+
+        if answers.len() < self.threshold {
+            record_failure("watch_script_history");
+            bail!("Not enough bitcoin backends watch the script");
+        }
+        Ok(())
+    }
+
+    /// Returns the transactions reported by at least `threshold` backends
+    async fn get_script_history(&self, script: &ScriptBuf) -> anyhow::Result<Vec<Transaction>> {
+        let answers = self
+            .query_all(
+                "get_script_history",
+                |rpc| async move { rpc.get_script_history(script).await },
+                |answers| answers.len() == self.backends.len(),
+            )
+            .await;
+
+        if answers.len() < self.threshold {
+            record_failure("get_script_history");
+            bail!("Not enough bitcoin backends returned the script history");
+        }
+
+        let mut reports: BTreeMap<Txid, (usize, &Transaction)> = BTreeMap::new();
+        let mut order = vec![];
+        for (_, transactions) in &answers {
+            for transaction in transactions {
+                let txid = transaction.txid();
+                reports
+                    .entry(txid)
+                    .or_insert_with(|| {
+                        order.push(txid);
+                        (0, transaction)
+                    })
+                    .0 += 1;
+            }
+        }
+
+        if reports.values().any(|(count, _)| *count != answers.len()) {
+            let txids = answers
+                .iter()
+                .map(|(backend, transactions)| {
+                    (
+                        *backend,
+                        transactions
+                            .iter()
+                            .map(Transaction::txid)
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            record_disagreement("get_script_history", &txids);
+        }
+
+        Ok(order
+            .into_iter()
+            .filter_map(|txid| {
+                let (count, transaction) = reports[&txid];
+                (self.threshold <= count).then(|| transaction.clone())
+            })
+            .collect())
+    }
+
+    async fn get_txout_proof(&self, txid: Txid) -> anyhow::Result<TxOutProof> {
+        // Proofs don't implement `Eq`, so we compare their encoding
+        let proof = self
+            .query_agreement("get_txout_proof", |rpc| async move {
+                Ok(rpc.get_txout_proof(txid).await?.consensus_encode_to_vec())
+            })
+            .await?;
+
+        Ok(
+            TxOutProof::consensus_decode_vec(proof, &ModuleDecoderRegistry::default())
+                .expect("We encoded the proof ourselves"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::{bail, format_err};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, Network, ScriptBuf, Transaction, Txid};
+    use fedimint_core::txoproof::TxOutProof;
+    use fedimint_core::{apply, async_trait_maybe_send, runtime, Feerate};
+
+    use super::{agreed_answer, QuorumBitcoindRpc};
+    use crate::{DynBitcoindRpc, IBitcoindRpc};
+
+    /// Backend answering with fixed values, or failing if it has none
+    #[derive(Debug, Default)]
+    struct FakeBackend {
+        block_count: Option<u64>,
+        block_hash: Option<BlockHash>,
+        fee_rate: Option<Feerate>,
+        /// Answers only after this delay
+        delay: Duration,
+    }
+
+    impl FakeBackend {
+        fn new(block_count: u64, block_hash: u8, sats_per_kvb: u64) -> Self {
+            FakeBackend {
+                block_count: Some(block_count),
+                block_hash: Some(BlockHash::from_byte_array([block_hash; 32])),
+                fee_rate: Some(Feerate { sats_per_kvb }),
+                delay: Duration::ZERO,
+            }
+        }
+
+        fn failing() -> Self {
+            FakeBackend::default()
+        }
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IBitcoindRpc for FakeBackend {
+        async fn get_network(&self) -> anyhow::Result<Network> {
+            Ok(Network::Regtest)
+        }
+
+        async fn get_block_count(&self) -> anyhow::Result<u64> {
+            runtime::sleep(self.delay).await;
+            self.block_count
+                .ok_or_else(|| format_err!("Backend is down"))
+        }
+
+        async fn get_block_hash(&self, _height: u64) -> anyhow::Result<BlockHash> {
+            runtime::sleep(self.delay).await;
+            self.block_hash
+                .ok_or_else(|| format_err!("Backend is down"))
+        }
+
+        async fn get_fee_rate(&self, _confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+            runtime::sleep(self.delay).await;
+            match self.fee_rate {
+                Some(fee_rate) => Ok(Some(fee_rate)),
+                None => bail!("Backend is down"),
+            }
+        }
+
+        async fn submit_transaction(&self, _transaction: Transaction) {}
+
+        async fn get_tx_block_height(&self, _txid: &Txid) -> anyhow::Result<Option<u64>> {
+            bail!("Not implemented in FakeBackend")
+        }
+
+        async fn watch_script_history(&self, _script: &ScriptBuf) -> anyhow::Result<()> {
+            bail!("Not implemented in FakeBackend")
+        }
+
+        async fn get_script_history(
+            &self,
+            _script: &ScriptBuf,
+        ) -> anyhow::Result<Vec<Transaction>> {
+            bail!("Not implemented in FakeBackend")
+        }
+
+        async fn get_txout_proof(&self, _txid: Txid) -> anyhow::Result<TxOutProof> {
+            bail!("Not implemented in FakeBackend")
+        }
+    }
+
+    fn quorum(backends: Vec<FakeBackend>, threshold: usize) -> QuorumBitcoindRpc {
+        QuorumBitcoindRpc::new(
+            backends.into_iter().map(DynBitcoindRpc::from).collect(),
+            threshold,
+        )
+        .expect("Threshold is possible")
+    }
+
+    #[test]
+    fn agreed_answer_requires_threshold_equal_answers() {
+        assert_eq!(agreed_answer::<u64>(&[], 1), None);
+        assert_eq!(agreed_answer(&[(0, 5), (1, 6)], 2), None);
+        assert_eq!(agreed_answer(&[(0, 5), (1, 6), (2, 6)], 2), Some(6));
+        assert_eq!(agreed_answer(&[(0, 5), (1, 6), (2, 7)], 1), Some(5));
+    }
+
+    #[test]
+    fn rejects_impossible_thresholds() {
+        assert!(QuorumBitcoindRpc::new(vec![], 1).is_err());
+        assert!(
+            QuorumBitcoindRpc::new(vec![DynBitcoindRpc::from(FakeBackend::failing())], 0).is_err()
+        );
+        assert!(
+            QuorumBitcoindRpc::new(vec![DynBitcoindRpc::from(FakeBackend::failing())], 2).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn block_count_is_reached_by_threshold_backends() {
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 0, 1000),
+                FakeBackend::new(102, 0, 1000),
+                FakeBackend::new(101, 0, 1000),
+            ],
+            2,
+        );
+        assert_eq!(rpc.get_block_count().await.unwrap(), 101);
+
+        // A single backend can't make us believe in blocks nobody else knows
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 0, 1000),
+                FakeBackend::new(1_000_000, 0, 1000),
+                FakeBackend::failing(),
+            ],
+            2,
+        );
+        assert_eq!(rpc.get_block_count().await.unwrap(), 100);
+
+        let rpc = quorum(
+            vec![FakeBackend::new(100, 0, 1000), FakeBackend::failing()],
+            2,
+        );
+        assert!(rpc.get_block_count().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fee_rate_is_median_of_backends() {
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 0, 1000),
+                FakeBackend::new(100, 0, 50_000),
+                FakeBackend::new(100, 0, 2000),
+            ],
+            2,
+        );
+        assert_eq!(
+            rpc.get_fee_rate(1).await.unwrap(),
+            Some(Feerate { sats_per_kvb: 2000 })
+        );
+
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 0, 1000),
+                FakeBackend::failing(),
+                FakeBackend::failing(),
+            ],
+            2,
+        );
+        assert_eq!(rpc.get_fee_rate(1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn disagreeing_backend_is_outvoted() {
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 1, 1000),
+                FakeBackend::new(100, 2, 1000),
+                FakeBackend::new(100, 1, 1000),
+            ],
+            2,
+        );
+        assert_eq!(
+            rpc.get_block_hash(100).await.unwrap(),
+            BlockHash::from_byte_array([1; 32])
+        );
+
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 1, 1000),
+                FakeBackend::new(100, 2, 1000),
+                FakeBackend::failing(),
+            ],
+            2,
+        );
+        assert!(rpc.get_block_hash(100).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_backend_does_not_delay_agreement() {
+        let slow = FakeBackend {
+            delay: Duration::from_secs(30),
+            ..FakeBackend::new(100, 2, 1000)
+        };
+        let rpc = quorum(
+            vec![
+                FakeBackend::new(100, 1, 1000),
+                FakeBackend::new(100, 1, 1000),
+                slow,
+            ],
+            2,
+        );
+
+        let block_hash = runtime::timeout(Duration::from_secs(5), rpc.get_block_hash(100))
+            .await
+            .expect("Quorum doesn't wait for the slow backend")
+            .unwrap();
+        assert_eq!(block_hash, BlockHash::from_byte_array([1; 32]));
+    }
+}
@@ -1,14 +1,18 @@
 use std::collections::BTreeMap;
 use std::ffi;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Context};
+use base64::Engine;
 use bip39::Mnemonic;
 use bitcoin::address::NetworkUnchecked;
 use bitcoin::{secp256k1, Network};
 use clap::Subcommand;
 use fedimint_client::backup::Metadata;
+use fedimint_client::oplog::{OperationLogExportFormat, OperationLogFilter, OperationOutcomeState};
+use fedimint_client::policy::SpendingPolicy;
 use fedimint_client::ClientHandleArc;
 use fedimint_core::bitcoin_migration::{
     bitcoin29_to_bitcoin30_network, bitcoin30_to_bitcoin29_address, bitcoin30_to_bitcoin29_amount,
@@ -18,16 +22,15 @@ use fedimint_core::config::{ClientModuleConfig, FederationId};
 use fedimint_core::core::{ModuleInstanceId, ModuleKind, OperationId};
 use fedimint_core::encoding::Encodable;
 use fedimint_core::time::now;
-use fedimint_core::{Amount, BitcoinAmountOrAll, TieredMulti, TieredSummary};
+use fedimint_core::{Amount, BitcoinAmountOrAll, Feerate, TieredMulti, TieredSummary};
 use fedimint_ln_client::cli::LnInvoiceResponse;
 use fedimint_ln_client::{
     LightningClientModule, LnReceiveState, OutgoingLightningPayment, PayType,
 };
 use fedimint_logging::LOG_CLIENT;
-use fedimint_mint_client::{
-    MintClientModule, OOBNotes, SelectNotesWithAtleastAmount, SelectNotesWithExactAmount,
-};
-use fedimint_wallet_client::{WalletClientModule, WithdrawState};
+use fedimint_mint_client::{MintClientModule, NotesSelectorKind, OOBNotes};
+use fedimint_wallet_client::external_wallet::{bip21_uri, DepositFunding};
+use fedimint_wallet_client::{DepositState, WalletClientModule, WithdrawState};
 use futures::StreamExt;
 use itertools::Itertools;
 use lightning_invoice::{Bolt11InvoiceDescription, Description};
@@ -38,6 +41,7 @@ use time::OffsetDateTime;
 use tracing::{debug, info, warn};
 
 use crate::metadata_from_clap_cli;
+use crate::utils::parse_time;
 
 #[derive(Debug, Clone)]
 pub enum ModuleSelector {
@@ -92,6 +96,11 @@ pub enum ClientCmd {
         /// belongs to should be included in the serialized notes
         #[clap(long)]
         include_invite: bool,
+        /// Strategy used to select the notes to spend (at-least, exact,
+        /// minimal-change or balanced). If not set `exact` is used, or the
+        /// client's default selector if `--allow-overpay` is set.
+        #[clap(long)]
+        selector: Option<NotesSelectorKind>,
     },
     /// Verifies the signatures of e-cash notes, but *not* if they have been
     /// spent already
@@ -153,9 +162,51 @@ pub enum ClientCmd {
         /// transactions, time in seconds
         #[clap(long, default_value_t = 60*60*24*7)]
         timeout: u64,
+        /// Amount to request in the BIP21 URI and to pay in the PSBT
+        #[clap(long)]
+        amount: Option<Amount>,
+        /// Label of the BIP21 URI
+        #[clap(long)]
+        label: Option<String>,
+        /// Create an unsigned PSBT for the deposit spending all of these UTXOs,
+        /// a JSON array of `{"outpoint": "<txid>:<vout>", "txout": {"value":
+        /// <sats>, "script_pubkey": "<hex>"}}`
+        #[clap(
+            long,
+            requires_all = ["amount", "psbt_change_address"],
+            conflicts_with = "psbt_descriptor"
+        )]
+        psbt_utxos: Option<String>,
+        /// Address receiving the change of the PSBT spending `--psbt-utxos`
+        #[clap(long, requires = "psbt_utxos")]
+        psbt_change_address: Option<bitcoin::Address<NetworkUnchecked>>,
+        /// Create an unsigned PSBT for the deposit funded from the addresses
+        /// of this output descriptor of an external wallet. Descriptors with
+        /// wildcard have to derive receive and change addresses, e.g.
+        /// `wpkh([d34db33f/84'/0'/0']xpub.../<0;1>/*)`.
+        #[clap(long, requires = "amount")]
+        psbt_descriptor: Option<String>,
+        /// Fee rate of the PSBT in sats/vB, estimated if not set
+        #[clap(long)]
+        fee_rate: Option<u64>,
     },
     /// Wait for deposit on previously generated address
-    AwaitDeposit { operation_id: OperationId },
+    AwaitDeposit {
+        operation_id: OperationId,
+        /// Keep waiting for further deposits until the address isn't watched
+        /// anymore instead of returning after the first claim
+        #[clap(long)]
+        all: bool,
+    },
+    /// Watch the address of a previous deposit again and claim all deposits
+    /// to it that weren't claimed yet
+    RescanDepositAddress {
+        operation_id: OperationId,
+        /// How long the client should watch the address for incoming
+        /// transactions, time in seconds
+        #[clap(long, default_value_t = 60*60*24*7)]
+        timeout: u64,
+    },
     /// Withdraw funds from the federation
     Withdraw {
         #[clap(long)]
@@ -185,9 +236,74 @@ pub enum ClientCmd {
     },
     /// Print the secret key of the client
     PrintSecret,
+    /// List operations, newest first, optionally only the ones matching all
+    /// given filters
     ListOperations {
         #[clap(long, default_value = "10")]
         limit: usize,
+        /// Kind of the module that created the operation, e.g. `ln`
+        #[clap(long)]
+        kind: Option<String>,
+        /// Type of the operation within its module, e.g. `pay`
+        #[clap(long = "type")]
+        operation_type: Option<String>,
+        /// Outcome state of the operation: pending, success or failure
+        #[clap(long)]
+        outcome: Option<OperationOutcomeState>,
+        /// Only operations created at or after this time (unix timestamp or
+        /// ISO 8601)
+        #[clap(long, value_parser = parse_time)]
+        since: Option<SystemTime>,
+        /// Only operations created before this time (unix timestamp or ISO
+        /// 8601)
+        #[clap(long, value_parser = parse_time)]
+        until: Option<SystemTime>,
+        /// Only operations of at least this amount, only operations with an
+        /// amount in their meta data match
+        #[clap(long)]
+        min_amount: Option<Amount>,
+        /// Only operations of at most this amount, only operations with an
+        /// amount in their meta data match
+        #[clap(long)]
+        max_amount: Option<Amount>,
+        /// Text the meta data or outcome of the operation has to contain
+        #[clap(long)]
+        search: Option<String>,
+    },
+    /// Export the operation history as a ledger for accounting, newest
+    /// operation first
+    ExportHistory {
+        /// File format of the export: csv or jsonl (one JSON object per line)
+        #[clap(long, default_value = "csv")]
+        format: OperationLogExportFormat,
+        /// Only operations created at or after this time (unix timestamp or
+        /// ISO 8601)
+        #[clap(long, value_parser = parse_time)]
+        since: Option<SystemTime>,
+        /// Only operations created before this time (unix timestamp or ISO
+        /// 8601)
+        #[clap(long, value_parser = parse_time)]
+        until: Option<SystemTime>,
+        /// File to write the export to
+        #[clap(long)]
+        output: PathBuf,
+    },
+    /// Show the spending policy and the funds sent within the last 24 hours,
+    /// or replace the policy if any limit is given
+    SpendingPolicy {
+        /// Maximum amount a single operation may send
+        #[clap(long)]
+        max_payment: Option<Amount>,
+        /// Maximum amount all operations may send within 24 hours
+        #[clap(long)]
+        max_daily_outflow: Option<Amount>,
+        /// Module kind allowed to send funds, can be given multiple times. All
+        /// module kinds are allowed if none is given.
+        #[clap(long = "allowed-module-kind")]
+        allowed_module_kinds: Vec<String>,
+        /// Remove the spending policy
+        #[clap(long, conflicts_with_all = ["max_payment", "max_daily_outflow", "allowed_module_kinds"])]
+        clear: bool,
     },
     /// Call a module subcommand
     // Make `--help` be passed to the module handler, not root cli one
@@ -235,42 +351,34 @@ pub async fn handle_command(
             allow_overpay,
             timeout,
             include_invite,
+            selector,
         } => {
             warn!("The client will try to double-spend these notes after the duration specified by the --timeout option to recover any unclaimed e-cash.");
 
             let mint_module = client.get_first_module::<MintClientModule>();
             let timeout = Duration::from_secs(timeout);
-            let (operation, notes) = if allow_overpay {
-                let (operation, notes) = mint_module
-                    .spend_notes_with_selector(
-                        &SelectNotesWithAtleastAmount,
-                        amount,
-                        timeout,
-                        include_invite,
-                        (),
-                    )
-                    .await?;
-
-                let overspend_amount = notes.total_amount() - amount;
-                if overspend_amount != Amount::ZERO {
-                    warn!(
-                        "Selected notes {} worth more than requested",
-                        overspend_amount
-                    );
-                }
-
-                (operation, notes)
-            } else {
-                mint_module
-                    .spend_notes_with_selector(
-                        &SelectNotesWithExactAmount,
-                        amount,
-                        timeout,
-                        include_invite,
-                        (),
-                    )
-                    .await?
+            let selector = match selector {
+                Some(selector) => selector,
+                None if allow_overpay => mint_module.default_notes_selector().await,
+                None => NotesSelectorKind::Exact,
             };
+            let (operation, notes) = mint_module
+                .spend_notes_with_selector(
+                    &mint_module.notes_selector(selector),
+                    amount,
+                    timeout,
+                    include_invite,
+                    (),
+                )
+                .await?;
+
+            let overspend_amount = notes.total_amount() - amount;
+            if overspend_amount != Amount::ZERO {
+                warn!(
+                    "Selected notes {} worth more than requested",
+                    overspend_amount
+                );
+            }
             info!("Spend e-cash operation: {operation}");
 
             Ok(json!({
@@ -472,29 +580,107 @@ pub async fn handle_command(
 
             Ok(json!(&gateways))
         }
-        ClientCmd::DepositAddress { timeout } => {
-            let (operation_id, address) = client
-                .get_first_module::<WalletClientModule>()
-                .get_deposit_address(now() + Duration::from_secs(timeout), ())
-                .await?;
+        ClientCmd::DepositAddress {
+            timeout,
+            amount,
+            label,
+            psbt_utxos,
+            psbt_change_address,
+            psbt_descriptor,
+            fee_rate,
+        } => {
+            let wallet_module = client.get_first_module::<WalletClientModule>();
+            let amount = amount
+                .map(|amount| {
+                    anyhow::Ok(bitcoin30_to_bitcoin29_amount(bitcoin::Amount::from_sat(
+                        amount.try_into_sats()?,
+                    )))
+                })
+                .transpose()?;
+
+            let funding = match (psbt_utxos, psbt_change_address, psbt_descriptor) {
+                (Some(utxos), Some(change_address), None) => Some(DepositFunding::Utxos {
+                    utxos: serde_json::from_str(&utxos).context("Invalid UTXOs")?,
+                    change_address: bitcoin30_to_bitcoin29_address(
+                        change_address.require_network(bitcoin29_to_bitcoin30_network(
+                            wallet_module.get_network(),
+                        ))?,
+                    ),
+                }),
+                (None, None, Some(descriptor)) => {
+                    Some(DepositFunding::from_descriptor_str(&descriptor)?)
+                }
+                _ => None,
+            };
+
+            let valid_until = now() + Duration::from_secs(timeout);
+            let (operation_id, address, psbt) = match (funding, amount) {
+                (Some(funding), Some(amount)) => {
+                    let fee_rate = fee_rate.map(|sats_per_vb| Feerate {
+                        sats_per_kvb: sats_per_vb * 1000,
+                    });
+                    let (operation_id, address, psbt) = wallet_module
+                        .get_deposit_address_with_psbt(valid_until, amount, funding, fee_rate, ())
+                        .await?;
+                    let psbt = base64::engine::general_purpose::STANDARD
+                        .encode(psbt.consensus_encode_to_vec());
+                    (operation_id, address, Some(psbt))
+                }
+                _ => {
+                    let (operation_id, address) =
+                        wallet_module.get_deposit_address(valid_until, ()).await?;
+                    (operation_id, address, None)
+                }
+            };
+
             Ok(serde_json::json! {
                 {
                     "address": address,
                     "operation_id": operation_id,
+                    "uri": bip21_uri(&address, amount, label.as_deref()),
+                    "psbt": psbt,
                 }
             })
         }
-        ClientCmd::AwaitDeposit { operation_id } => {
+        ClientCmd::AwaitDeposit { operation_id, all } => {
             let mut updates = client
                 .get_first_module::<WalletClientModule>()
                 .subscribe_deposit_updates(operation_id)
                 .await?
                 .into_stream();
 
+            let mut claimed = vec![];
             while let Some(update) = updates.next().await {
                 debug!(target: LOG_CLIENT, ?update, "Await deposit state update");
+                match update {
+                    DepositState::Claimed(tx_data) => {
+                        let outpoint =
+                            format!("{}:{}", tx_data.btc_transaction.txid(), tx_data.out_idx);
+                        info!(target: LOG_CLIENT, %outpoint, "Deposit claimed");
+                        claimed.push(outpoint);
+                    }
+                    DepositState::Failed(error) => {
+                        warn!(target: LOG_CLIENT, %error, "Deposit failed");
+                    }
+                    _ => continue,
+                }
+
+                if !all {
+                    break;
+                }
             }
 
+            Ok(json!({ "claimed": claimed }))
+        }
+        ClientCmd::RescanDepositAddress {
+            operation_id,
+            timeout,
+        } => {
+            client
+                .get_first_module::<WalletClientModule>()
+                .rescan_deposit_address(operation_id, now() + Duration::from_secs(timeout))
+                .await?;
+
             Ok(serde_json::to_value(()).unwrap())
         }
 
@@ -517,7 +703,17 @@ pub async fn handle_command(
                 "secret": mnemonic,
             }))
         }
-        ClientCmd::ListOperations { limit } => {
+        ClientCmd::ListOperations {
+            limit,
+            kind,
+            operation_type,
+            outcome,
+            since,
+            until,
+            min_amount,
+            max_amount,
+            search,
+        } => {
             #[derive(Serialize)]
             #[serde(rename_all = "snake_case")]
             struct OperationOutput {
@@ -532,9 +728,19 @@ pub async fn handle_command(
             const ISO8601_CONFIG: iso8601::EncodedConfig = iso8601::Config::DEFAULT
                 .set_formatted_components(iso8601::FormattedComponents::DateTime)
                 .encode();
+            let filter = OperationLogFilter {
+                operation_module_kind: kind,
+                operation_type,
+                outcome_state: outcome,
+                since,
+                until,
+                min_amount,
+                max_amount,
+                text: search,
+            };
             let operations = client
                 .operation_log()
-                .list_operations(limit, None)
+                .list_operations_filtered(&filter, limit, None)
                 .await
                 .into_iter()
                 .map(|(k, v)| {
@@ -562,34 +768,68 @@ pub async fn handle_command(
                 "operations": operations,
             }))
         }
+        ClientCmd::ExportHistory {
+            format,
+            since,
+            until,
+            output,
+        } => {
+            let filter = OperationLogFilter {
+                since,
+                until,
+                ..Default::default()
+            };
+            let file = std::fs::File::create(&output)
+                .with_context(|| format!("Failed to create {}", output.display()))?;
+            let records = client
+                .export_operation_log(&filter, format, std::io::BufWriter::new(file))
+                .await?;
+
+            Ok(json!({
+                "output": output,
+                "format": format.to_string(),
+                "records": records,
+            }))
+        }
+        ClientCmd::SpendingPolicy {
+            max_payment,
+            max_daily_outflow,
+            allowed_module_kinds,
+            clear,
+        } => {
+            if clear {
+                client.set_spending_policy(None).await;
+            } else if max_payment.is_some()
+                || max_daily_outflow.is_some()
+                || !allowed_module_kinds.is_empty()
+            {
+                client
+                    .set_spending_policy(Some(SpendingPolicy {
+                        max_payment,
+                        max_daily_outflow,
+                        allowed_module_kinds: (!allowed_module_kinds.is_empty())
+                            .then(|| allowed_module_kinds.into_iter().collect()),
+                    }))
+                    .await;
+            }
+
+            Ok(serde_json::to_value(client.spending_policy_status().await).unwrap())
+        }
         ClientCmd::Withdraw { amount, address } => {
             let wallet_module = client.get_first_module::<WalletClientModule>();
+            let address = bitcoin30_to_bitcoin29_address(address.assume_checked());
             let (amount, fees) = match amount {
-                // If the amount is "all", then we need to subtract the fees from
-                // the amount we are withdrawing
+                // If the amount is "all", then we need to subtract all fees from
+                // the balance we are withdrawing
                 BitcoinAmountOrAll::All => {
-                    let balance = bitcoin30_to_bitcoin29_amount(bitcoin::Amount::from_sat(
-                        client.get_balance().await.msats / 1000,
-                    ));
-                    let fees = wallet_module
-                        .get_withdraw_fees(
-                            bitcoin30_to_bitcoin29_address(address.clone().assume_checked()),
-                            balance,
-                        )
-                        .await?;
-                    let amount = balance.checked_sub(fees.amount());
-                    if amount.is_none() {
-                        bail!("Not enough funds to pay fees");
-                    }
-                    (amount.unwrap(), fees)
+                    wallet_module
+                        .get_max_withdraw_amount(address.clone())
+                        .await?
                 }
                 BitcoinAmountOrAll::Amount(amount) => (
                     amount,
                     wallet_module
-                        .get_withdraw_fees(
-                            bitcoin30_to_bitcoin29_address(address.clone().assume_checked()),
-                            amount,
-                        )
+                        .get_withdraw_fees(address.clone(), amount)
                         .await?,
                 ),
             };
@@ -597,14 +837,7 @@ pub async fn handle_command(
 
             info!("Attempting withdraw with fees: {fees:?}");
 
-            let operation_id = wallet_module
-                .withdraw(
-                    bitcoin30_to_bitcoin29_address(address.assume_checked()),
-                    amount,
-                    fees,
-                    (),
-                )
-                .await?;
+            let operation_id = wallet_module.withdraw(address, amount, fees, ()).await?;
 
             let mut updates = wallet_module
                 .subscribe_withdraw_updates(operation_id)
@@ -7,3 +7,9 @@ pub const FM_OUR_ID_ENV: &str = "FM_OUR_ID";
 
 // Env variable to set the guardian password for authentication
 pub const FM_PASSWORD_ENV: &str = "FM_PASSWORD";
+
+// Env variable to select the client database backend (`rocksdb` or `sqlite`)
+pub const FM_DB_BACKEND_ENV: &str = "FM_DB_BACKEND";
+
+// Env variable to set the password the client database is encrypted with
+pub const FM_DB_PASSWORD_ENV: &str = "FM_DB_PASSWORD";
@@ -23,6 +23,7 @@ use fedimint_api_client::api::{
     DynGlobalApi, FederationApiExt, FederationError, IRawFederationApi, WsFederationApi,
 };
 use fedimint_bip39::Bip39RootSecretStrategy;
+use fedimint_client::db::encrypted::{EncryptedDatabase, EncryptionOptions};
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitRegistry};
 use fedimint_client::module::ClientModule as _;
 use fedimint_client::secret::{get_default_client_secret, RootSecretStrategy};
@@ -31,7 +32,7 @@ use fedimint_core::admin_client::{ConfigGenConnectionsRequest, ConfigGenParamsRe
 use fedimint_core::config::{
     ClientConfig, FederationId, FederationIdPrefix, ServerModuleConfigGenParamsRegistry,
 };
-use fedimint_core::db::{Database, DatabaseValue};
+use fedimint_core::db::{Database, DatabaseValue, IRawDatabase};
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::{ApiAuth, ApiRequestErased};
 use fedimint_core::util::{handle_version_hash_command, retry, ConstantBackoff, SafeUrl};
@@ -41,6 +42,7 @@ use fedimint_logging::{TracingSetup, LOG_CLIENT};
 use fedimint_meta_client::MetaClientInit;
 use fedimint_mint_client::{MintClientInit, MintClientModule, OOBNotes, SpendableNote};
 use fedimint_server::config::io::SALT_FILE;
+use fedimint_sqlite::DatabaseBackend;
 use fedimint_wallet_client::api::WalletFederationApi;
 use fedimint_wallet_client::{WalletClientInit, WalletClientModule};
 use futures::future::pending;
@@ -52,7 +54,12 @@ use tracing::{debug, error, info};
 use utils::parse_peer_id;
 
 use crate::client::ClientCmd;
-use crate::envs::{FM_CLIENT_DIR_ENV, FM_OUR_ID_ENV, FM_PASSWORD_ENV};
+use crate::envs::{
+    FM_CLIENT_DIR_ENV, FM_DB_BACKEND_ENV, FM_DB_PASSWORD_ENV, FM_OUR_ID_ENV, FM_PASSWORD_ENV,
+};
+
+/// Name of the client database in the data dir, see [`DatabaseBackend::path`]
+const CLIENT_DB_FILE: &str = "client.db";
 
 /// Type of output the cli produces
 #[derive(Serialize)]
@@ -203,6 +210,16 @@ struct Opts {
     #[arg(short = 'v', long)]
     verbose: bool,
 
+    /// Database backend used to store the client state
+    #[arg(long, env = FM_DB_BACKEND_ENV, value_enum, default_value_t = DatabaseBackend::Rocksdb)]
+    db_backend: DatabaseBackend,
+
+    /// Password the client database is encrypted with. A new database gets
+    /// encrypted if it is set, an existing one only if it was encrypted when
+    /// it was created.
+    #[arg(long, env = FM_DB_PASSWORD_ENV)]
+    db_password: Option<String>,
+
     #[clap(subcommand)]
     command: Command,
 }
@@ -236,18 +253,92 @@ impl Opts {
         Ok(ApiAuth(password))
     }
 
-    async fn load_rocks_db(&self) -> CliResult<Database> {
-        debug!(target: LOG_CLIENT, "Loading client database");
-        let db_path = self.data_dir_create().await?.join("client.db");
-        let lock_path = db_path.with_extension("db.lock");
-        Ok(LockedBuilder::new(&lock_path)
+    async fn load_db(&self) -> CliResult<Database> {
+        debug!(target: LOG_CLIENT, db_backend = ?self.db_backend, "Loading client database");
+        let data_dir = self.data_dir_create().await?;
+        let lock_path = data_dir.join("client.db.lock");
+        let locked_builder = LockedBuilder::new(&lock_path)
+            .await
+            .map_err_cli_msg("could not lock database")?;
+        // Encryption wraps the raw database, so it can't use `DatabaseBackend::open`
+        let db_path = self.db_backend.path(data_dir, CLIENT_DB_FILE);
+        match self.db_backend {
+            DatabaseBackend::Rocksdb => {
+                self.decrypt_db(
+                    locked_builder.with_db(
+                        fedimint_rocksdb::RocksDb::open(db_path)
+                            .map_err_cli_msg("could not open database")?,
+                    ),
+                )
+                .await
+            }
+            DatabaseBackend::Sqlite => {
+                self.decrypt_db(
+                    locked_builder.with_db(
+                        fedimint_sqlite::SqliteDb::open(db_path)
+                            .map_err_cli_msg("could not open database")?,
+                    ),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Opens `raw_db` encrypted with `--db-password` if it is set
+    async fn decrypt_db(&self, raw_db: impl IRawDatabase) -> CliResult<Database> {
+        match &self.db_password {
+            Some(db_password) => {
+                Ok(
+                    EncryptedDatabase::open(raw_db, db_password, EncryptionOptions::default())
+                        .await
+                        .map_err_cli_msg("could not decrypt database")?
+                        .into(),
+                )
+            }
+            None => {
+                if EncryptedDatabase::is_encrypted(&raw_db)
+                    .await
+                    .map_err_cli()?
+                {
+                    return Err(anyhow::anyhow!(
+                        "The database is encrypted, `--db-password` is required"
+                    ))
+                    .map_err_cli();
+                }
+                Ok(raw_db.into())
+            }
+        }
+    }
+
+    /// Re-encrypts the database with `new_db_password`
+    async fn change_db_password(&self, new_db_password: &str) -> CliResult<()> {
+        let db_password = self
+            .db_password
+            .as_deref()
+            .ok_or_cli_msg("`--db-password` of the encrypted database is required")?;
+        let data_dir = self.data_dir()?;
+        let _lock = LockedBuilder::new(&data_dir.join("client.db.lock"))
             .await
-            .map_err_cli_msg("could not lock database")?
-            .with_db(
-                fedimint_rocksdb::RocksDb::open(db_path)
+            .map_err_cli_msg("could not lock database")?;
+        let db_path = self.db_backend.path(data_dir, CLIENT_DB_FILE);
+        match self.db_backend {
+            DatabaseBackend::Rocksdb => EncryptedDatabase::change_passphrase(
+                &fedimint_rocksdb::RocksDb::open(db_path)
                     .map_err_cli_msg("could not open database")?,
+                db_password,
+                new_db_password,
             )
-            .into())
+            .await
+            .map_err_cli(),
+            DatabaseBackend::Sqlite => EncryptedDatabase::change_passphrase(
+                &fedimint_sqlite::SqliteDb::open(db_path)
+                    .map_err_cli_msg("could not open database")?,
+                db_password,
+                new_db_password,
+            )
+            .await
+            .map_err_cli(),
+        }
     }
 }
 
@@ -291,6 +382,13 @@ enum Command {
         invite_code: String,
     },
 
+    /// Re-encrypt the client database, which has to be opened with
+    /// `--db-password`, with a new password
+    ChangeDbPassword {
+        #[arg(long)]
+        new_db_password: String,
+    },
+
     Completion {
         shell: clap_complete::Shell,
     },
@@ -307,6 +405,14 @@ enum AdminCmd {
     /// Download guardian config to back it up
     GuardianConfigBackup,
 
+    /// Download a snapshot of the guardian database to back it up
+    GuardianDatabaseSnapshot {
+        /// File to write the snapshot to, it can be restored into an empty
+        /// database using `fedimint-dbtool import-snapshot`
+        #[arg(long)]
+        out_file: PathBuf,
+    },
+
     Dkg(DkgAdminArgs),
 }
 
@@ -548,7 +654,7 @@ impl FedimintCli {
     }
 
     async fn make_client_builder(&self, cli: &Opts) -> CliResult<ClientBuilder> {
-        let db = cli.load_rocks_db().await?;
+        let db = cli.load_db().await?;
         let mut client_builder = Client::builder(db);
         client_builder.with_module_inits(self.module_inits.clone());
         client_builder.with_primary_module(1);
@@ -662,7 +768,7 @@ impl FedimintCli {
     async fn handle_command(&mut self, cli: Opts) -> CliOutputResult {
         match cli.command.clone() {
             Command::InviteCode { peer } => {
-                let db = cli.load_rocks_db().await?;
+                let db = cli.load_db().await?;
                 let client_config = Client::get_config_from_db(&db)
                     .await
                     .ok_or_cli_msg("client config code not found")?;
@@ -686,6 +792,10 @@ impl FedimintCli {
                     joined: invite_code,
                 })
             }
+            Command::ChangeDbPassword { new_db_password } => {
+                cli.change_db_password(&new_db_password).await?;
+                Ok(CliOutput::Raw(Value::Null))
+            }
             Command::VersionHash => Ok(CliOutput::VersionHash {
                 hash: fedimint_build_code_version_env!().to_string(),
             }),
@@ -749,6 +859,44 @@ impl FedimintCli {
                         .map_err_cli_msg("invalid response")?,
                 ))
             }
+            Command::Admin(AdminCmd::GuardianDatabaseSnapshot { out_file }) => {
+                let client = self.client_open(&cli).await?;
+
+                let admin_client = cli.admin_client(client.get_config())?;
+                let auth = cli.auth()?;
+
+                // The snapshot is downloaded in chunks so neither side has to hold the
+                // whole database in memory
+                let mut writer = std::io::BufWriter::new(
+                    fs::File::create(&out_file)
+                        .map_err_cli_msg("failed to create snapshot file")?,
+                );
+                let snapshot = admin_client
+                    .start_guardian_database_snapshot(auth.clone())
+                    .await?;
+                let mut offset = 0;
+                while offset < snapshot.total_bytes {
+                    let chunk = admin_client
+                        .guardian_database_snapshot_chunk(snapshot.id, offset, auth.clone())
+                        .await?;
+                    if chunk.chunk_bytes.is_empty() {
+                        return Err(CliError {
+                            error: "guardian returned an empty snapshot chunk".to_string(),
+                        });
+                    }
+                    writer
+                        .write_all(&chunk.chunk_bytes)
+                        .map_err_cli_msg("failed to write snapshot file")?;
+                    offset += chunk.chunk_bytes.len() as u64;
+                }
+                writer
+                    .flush()
+                    .map_err_cli_msg("failed to write snapshot file")?;
+
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(snapshot.summary).map_err_cli_msg("invalid response")?,
+                ))
+            }
             Command::Admin(AdminCmd::Dkg(dkg_args)) => {
                 self.handle_admin_dkg_command(cli, dkg_args).await
             }
@@ -1,7 +1,20 @@
 use std::num::ParseIntError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use fedimint_core::PeerId;
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
 
 pub fn parse_peer_id(s: &str) -> Result<PeerId, ParseIntError> {
     Ok(PeerId::from(s.parse::<u16>()?))
 }
+
+/// Parses either a unix timestamp in seconds or an ISO 8601 date and time,
+/// e.g. `2024-05-01T12:00:00Z`
+pub fn parse_time(s: &str) -> anyhow::Result<SystemTime> {
+    if let Ok(timestamp) = s.parse::<u64>() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(timestamp));
+    }
+
+    Ok(OffsetDateTime::parse(s, &Iso8601::DEFAULT)?.into())
+}
@@ -8,27 +8,31 @@ use fedimint_core::core::{ModuleInstanceId, OperationId};
 use fedimint_core::db::{
     create_database_version, Database, DatabaseTransaction, DatabaseValue, DatabaseVersion,
     DatabaseVersionKey, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped,
-    MODULE_GLOBAL_PREFIX,
+    ServerMigrationFn, MODULE_GLOBAL_PREFIX,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::util::BoxFuture;
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
 use fedimint_logging::LOG_CLIENT_DB;
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use serde::Serialize;
 use strum_macros::EnumIter;
 use tracing::{debug, info, trace, warn};
 
 use crate::backup::{ClientBackup, Metadata};
+use crate::events::{ClientEvent, ClientEventRecord};
 use crate::module::recovery::RecoveryProgress;
-use crate::oplog::OperationLogEntry;
+use crate::oplog::{OperationLogAttributes, OperationLogEntry, OperationOutcomeState};
+use crate::policy::SpendingPolicy;
 use crate::sm::executor::{
     ActiveStateKeyBytes, ActiveStateKeyPrefixBytes, InactiveStateKeyBytes,
     InactiveStateKeyPrefixBytes,
 };
 use crate::sm::{ActiveStateMeta, InactiveStateMeta};
 
+pub mod encrypted;
+
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
@@ -44,6 +48,17 @@ pub enum DbKeyPrefix {
     ClientLastBackup = 0x33,
     ClientMetaField = 0x34,
     ClientMetaServiceInfo = 0x35,
+    OperationLogAttributes = 0x36,
+    OperationLogModuleKindIndex = 0x37,
+    OperationLogTypeIndex = 0x38,
+    OperationLogOutcomeIndex = 0x39,
+    PendingClientEvent = 0x3a,
+    ClientEvent = 0x3b,
+    NextClientEventId = 0x3c,
+    ClientEventSinkCursor = 0x3d,
+    SpendingPolicy = 0x3e,
+    SpendingOutflow = 0x3f,
+    OperationLogIndexBackfill = 0x40,
     /// Arbitrary data of the applications integrating Fedimint client and
     /// wanting to store some Federation-specific data in Fedimint client
     /// database.
@@ -120,6 +135,209 @@ impl_db_lookup!(
     query_prefix = ChronologicalOperationLogKeyPrefix
 );
 
+/// Attributes of an operation log entry the secondary indices are built from
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OperationLogAttributesKey {
+    pub operation_id: OperationId,
+}
+
+impl_db_record!(
+    key = OperationLogAttributesKey,
+    value = OperationLogAttributes,
+    db_prefix = DbKeyPrefix::OperationLogAttributes
+);
+
+/// Key used to lookup operation log entries of a module kind in chronological
+/// order
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationLogModuleKindIndexKey {
+    pub operation_module_kind: String,
+    pub creation_time: SystemTime,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationLogModuleKindIndexPrefix {
+    pub operation_module_kind: String,
+}
+
+impl_db_record!(
+    key = OperationLogModuleKindIndexKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationLogModuleKindIndex
+);
+
+impl_db_lookup!(
+    key = OperationLogModuleKindIndexKey,
+    query_prefix = OperationLogModuleKindIndexPrefix
+);
+
+/// Key used to lookup operation log entries of a module kind and operation
+/// type in chronological order
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationLogTypeIndexKey {
+    pub operation_module_kind: String,
+    pub operation_type: String,
+    pub creation_time: SystemTime,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationLogTypeIndexPrefix {
+    pub operation_module_kind: String,
+    pub operation_type: String,
+}
+
+impl_db_record!(
+    key = OperationLogTypeIndexKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationLogTypeIndex
+);
+
+impl_db_lookup!(
+    key = OperationLogTypeIndexKey,
+    query_prefix = OperationLogTypeIndexPrefix
+);
+
+/// Key used to lookup operation log entries of an outcome state in
+/// chronological order
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationLogOutcomeIndexKey {
+    pub outcome_state: OperationOutcomeState,
+    pub creation_time: SystemTime,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationLogOutcomeIndexPrefix {
+    pub outcome_state: OperationOutcomeState,
+}
+
+impl_db_record!(
+    key = OperationLogOutcomeIndexKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationLogOutcomeIndex
+);
+
+impl_db_lookup!(
+    key = OperationLogOutcomeIndexKey,
+    query_prefix = OperationLogOutcomeIndexPrefix
+);
+
+/// Marks that the secondary operation log indices of operations created before
+/// they existed still have to be backfilled, see
+/// [`crate::oplog::OperationLog::backfill_indices`]
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OperationLogIndexBackfillKey;
+
+impl_db_record!(
+    key = OperationLogIndexBackfillKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationLogIndexBackfill
+);
+
+/// Event logged as part of a database transaction that wasn't assigned an
+/// id yet, see [`crate::events`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct PendingClientEventKey {
+    pub time: SystemTime,
+    /// Random value making keys of events logged at the same time unique
+    pub nonce: u64,
+}
+
+#[derive(Debug, Encodable)]
+pub struct PendingClientEventKeyPrefix;
+
+impl_db_record!(
+    key = PendingClientEventKey,
+    value = ClientEvent,
+    db_prefix = DbKeyPrefix::PendingClientEvent
+);
+
+impl_db_lookup!(
+    key = PendingClientEventKey,
+    query_prefix = PendingClientEventKeyPrefix
+);
+
+/// Event with its consecutive id, kept until all event sinks received it
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ClientEventKey {
+    pub id: u64,
+}
+
+#[derive(Debug, Encodable)]
+pub struct ClientEventKeyPrefix;
+
+impl_db_record!(
+    key = ClientEventKey,
+    value = ClientEventRecord,
+    db_prefix = DbKeyPrefix::ClientEvent
+);
+
+impl_db_lookup!(key = ClientEventKey, query_prefix = ClientEventKeyPrefix);
+
+/// Id the next event will be assigned, notified to wake up the event sequencer
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct NextClientEventIdKey;
+
+impl_db_record!(
+    key = NextClientEventIdKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::NextClientEventId,
+    notify_on_modify = true
+);
+
+/// Id of the next event to deliver to the named event sink
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ClientEventSinkCursorKey {
+    pub name: String,
+}
+
+#[derive(Debug, Encodable)]
+pub struct ClientEventSinkCursorKeyPrefix;
+
+impl_db_record!(
+    key = ClientEventSinkCursorKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ClientEventSinkCursor
+);
+
+impl_db_lookup!(
+    key = ClientEventSinkCursorKey,
+    query_prefix = ClientEventSinkCursorKeyPrefix
+);
+
+/// Spending policy checked before submitting transactions, see
+/// [`crate::policy`]
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct SpendingPolicyKey;
+
+impl_db_record!(
+    key = SpendingPolicyKey,
+    value = SpendingPolicy,
+    db_prefix = DbKeyPrefix::SpendingPolicy
+);
+
+/// Funds sent out of the wallet within an hour since the unix epoch
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct SpendingOutflowKey {
+    pub hour: u64,
+}
+
+#[derive(Debug, Encodable)]
+pub struct SpendingOutflowKeyPrefix;
+
+impl_db_record!(
+    key = SpendingOutflowKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::SpendingOutflow
+);
+
+impl_db_lookup!(
+    key = SpendingOutflowKey,
+    query_prefix = SpendingOutflowKeyPrefix
+);
+
 #[derive(Debug, Encodable, Decodable)]
 pub struct CachedApiVersionSetKey;
 
@@ -333,6 +551,29 @@ impl_db_record!(
 
 impl_db_lookup!(key = MetaFieldKey, query_prefix = MetaFieldPrefix);
 
+/// Version of the client's global (non-module) database entries
+pub const CORE_CLIENT_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+
+/// Migrations of the client's global (non-module) database entries
+pub fn get_core_client_database_migrations() -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
+    let mut migrations: BTreeMap<DatabaseVersion, ServerMigrationFn> = BTreeMap::new();
+    migrations.insert(DatabaseVersion(0), |dbtx| {
+        schedule_operation_log_index_backfill(dbtx).boxed()
+    });
+    migrations
+}
+
+/// Schedules the secondary operation log indices of operations that were
+/// created before the indices existed to be created. The backfill itself runs
+/// when the client is built since it needs the module inits to classify the
+/// operations.
+async fn schedule_operation_log_index_backfill(
+    dbtx: &mut DatabaseTransaction<'_>,
+) -> anyhow::Result<()> {
+    dbtx.insert_entry(&OperationLogIndexBackfillKey, &()).await;
+    Ok(())
+}
+
 /// `ClientMigrationFn` is a function that modules can implement to "migrate"
 /// the database to the next database version.
 pub type ClientMigrationFn = for<'r, 'tx> fn(
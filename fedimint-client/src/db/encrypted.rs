@@ -0,0 +1,524 @@
+//! Encryption at rest of the client database
+//!
+//! [`EncryptedDatabase`] wraps any [`IRawDatabase`] and encrypts all values
+//! with ChaCha20-Poly1305 using a key derived from a user passphrase, see
+//! [`fedimint_aead`]. Each value is authenticated together with its key, so
+//! values can't be swapped between keys unnoticed.
+//!
+//! Keys can optionally be encrypted too. Since the database relies on prefix
+//! scans, keys are encrypted deterministically byte by byte, with every byte
+//! masked by a keyed hash of all preceding bytes. This preserves prefixes, but
+//! not the order of keys, so prefix scans over encrypted keys are loaded into
+//! memory and sorted after decryption. It also reveals which keys share a
+//! prefix and how long they are.
+//!
+//! The salt of the key derivation and a check value for the passphrase are
+//! stored unencrypted in a header entry of the wrapped database.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_aead::{
+    decrypt_with_aad, encrypt_with_aad, get_encryption_key_from_bytes, random_salt,
+    stretch_password, LessSafeKey,
+};
+use fedimint_core::db::{
+    IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction,
+    PrefixStream,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{apply, async_trait_maybe_send};
+use futures::{stream, StreamExt};
+
+/// Key of the unencrypted header in the wrapped database
+const HEADER_KEY: [u8; 1] = [0x00];
+
+/// Prefix of all (encrypted) entries in the wrapped database
+const DATA_PREFIX: u8 = 0x01;
+
+/// Known plaintext encrypted in the header to detect a wrong passphrase
+const PASSPHRASE_CHECK: &[u8] = b"fedimint client database";
+
+/// Options chosen when a new database gets encrypted, they are stored in the
+/// database and can't be changed later
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncryptionOptions {
+    /// Whether to encrypt keys too, see the [module documentation](self)
+    pub encrypt_keys: bool,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+struct EncryptionHeader {
+    /// Salt of the key derivation from the passphrase
+    salt: String,
+    encrypt_keys: bool,
+    /// [`PASSPHRASE_CHECK`] encrypted with the value key
+    check: Vec<u8>,
+}
+
+/// Keys derived from the passphrase
+struct DatabaseCipher {
+    value_key: LessSafeKey,
+    /// Secret of the keyed hash that masks keys, `None` if keys are stored
+    /// unencrypted
+    key_secret: Option<[u8; 32]>,
+}
+
+impl fmt::Debug for DatabaseCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseCipher")
+            .field("encrypt_keys", &self.key_secret.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl DatabaseCipher {
+    /// Derives the keys of a newly encrypted database and returns them with
+    /// the header to store
+    fn new(passphrase: &str, encrypt_keys: bool) -> anyhow::Result<(Self, EncryptionHeader)> {
+        let salt = random_salt();
+        let cipher = Self::derive(passphrase, &salt, encrypt_keys)?;
+        let check = encrypt_with_aad(PASSPHRASE_CHECK.to_vec(), &HEADER_KEY, &cipher.value_key)?;
+
+        Ok((
+            cipher,
+            EncryptionHeader {
+                salt,
+                encrypt_keys,
+                check,
+            },
+        ))
+    }
+
+    /// Derives the keys of an existing database, fails if the passphrase is
+    /// wrong
+    fn from_header(header: &EncryptionHeader, passphrase: &str) -> anyhow::Result<Self> {
+        let cipher = Self::derive(passphrase, &header.salt, header.encrypt_keys)?;
+        let mut check = header.check.clone();
+        ensure!(
+            decrypt_with_aad(&mut check, &HEADER_KEY, &cipher.value_key).ok()
+                == Some(PASSPHRASE_CHECK),
+            "Wrong database passphrase"
+        );
+
+        Ok(cipher)
+    }
+
+    fn derive(passphrase: &str, salt: &str, encrypt_keys: bool) -> anyhow::Result<Self> {
+        let master = stretch_password(passphrase, salt)?;
+        let subkey = |purpose: &[u8]| {
+            let mut engine = HmacEngine::<sha256::Hash>::new(&master);
+            engine.input(purpose);
+            Hmac::from_engine(engine).into_inner()
+        };
+
+        Ok(Self {
+            value_key: get_encryption_key_from_bytes(&subkey(b"value"))?,
+            key_secret: encrypt_keys.then(|| subkey(b"key")),
+        })
+    }
+
+    fn encrypt_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut encrypted = vec![DATA_PREFIX];
+        match &self.key_secret {
+            Some(key_secret) => {
+                let mut engine = HmacEngine::<sha256::Hash>::new(key_secret);
+                for byte in key {
+                    encrypted.push(byte ^ Hmac::from_engine(engine.clone()).into_inner()[0]);
+                    engine.input(&[*byte]);
+                }
+            }
+            None => encrypted.extend_from_slice(key),
+        }
+        encrypted
+    }
+
+    fn decrypt_key(&self, encrypted: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let Some((&DATA_PREFIX, encrypted)) = encrypted.split_first() else {
+            bail!("Database key is not part of the encrypted data");
+        };
+
+        Ok(match &self.key_secret {
+            Some(key_secret) => {
+                let mut engine = HmacEngine::<sha256::Hash>::new(key_secret);
+                let mut key = Vec::with_capacity(encrypted.len());
+                for byte in encrypted {
+                    let byte = byte ^ Hmac::from_engine(engine.clone()).into_inner()[0];
+                    engine.input(&[byte]);
+                    key.push(byte);
+                }
+                key
+            }
+            None => encrypted.to_vec(),
+        })
+    }
+
+    fn encrypt_value(&self, key: &[u8], value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        encrypt_with_aad(value.to_vec(), key, &self.value_key)
+    }
+
+    fn decrypt_value(&self, key: &[u8], mut encrypted: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(decrypt_with_aad(&mut encrypted, key, &self.value_key)
+            .context("Failed to decrypt database value")?
+            .to_vec())
+    }
+}
+
+/// Reads the header of `dbtx`, returns `None` if it isn't encrypted
+async fn read_header(
+    dbtx: &mut impl IDatabaseTransactionOpsCore,
+) -> anyhow::Result<Option<EncryptionHeader>> {
+    dbtx.raw_get_bytes(&HEADER_KEY)
+        .await?
+        .map(|header| {
+            EncryptionHeader::consensus_decode_vec(header, &Default::default())
+                .context("Invalid database encryption header")
+        })
+        .transpose()
+}
+
+/// Database decorator encrypting the data of the wrapped database, see the
+/// [module documentation](self)
+#[derive(Debug)]
+pub struct EncryptedDatabase<D> {
+    inner: D,
+    cipher: Arc<DatabaseCipher>,
+}
+
+impl<D: IRawDatabase> EncryptedDatabase<D> {
+    /// Opens the encrypted database `inner` with `passphrase`. If `inner` is
+    /// empty, it gets encrypted using `options`.
+    ///
+    /// ## Errors
+    /// If the passphrase is wrong or `inner` contains unencrypted data.
+    pub async fn open(
+        inner: D,
+        passphrase: &str,
+        options: EncryptionOptions,
+    ) -> anyhow::Result<Self> {
+        let mut dbtx = inner.begin_transaction().await;
+        let cipher = match read_header(&mut dbtx).await? {
+            Some(header) => DatabaseCipher::from_header(&header, passphrase)?,
+            None => {
+                ensure!(
+                    dbtx.raw_find_by_prefix(&[]).await?.next().await.is_none(),
+                    "Database already contains unencrypted data"
+                );
+                let (cipher, header) = DatabaseCipher::new(passphrase, options.encrypt_keys)?;
+                dbtx.raw_insert_bytes(&HEADER_KEY, &header.consensus_encode_to_vec())
+                    .await?;
+                cipher
+            }
+        };
+        dbtx.commit_tx().await?;
+
+        Ok(Self {
+            inner,
+            cipher: Arc::new(cipher),
+        })
+    }
+
+    /// Returns whether `inner` is an encrypted database
+    pub async fn is_encrypted(inner: &D) -> anyhow::Result<bool> {
+        Ok(read_header(&mut inner.begin_transaction().await)
+            .await?
+            .is_some())
+    }
+
+    /// Re-encrypts all data of the encrypted database `inner` with a key
+    /// derived from `new_passphrase` in a single transaction. The database
+    /// must not be opened while doing so.
+    pub async fn change_passphrase(
+        inner: &D,
+        passphrase: &str,
+        new_passphrase: &str,
+    ) -> anyhow::Result<()> {
+        let mut dbtx = inner.begin_transaction().await;
+        let Some(header) = read_header(&mut dbtx).await? else {
+            bail!("Database is not encrypted");
+        };
+        let cipher = DatabaseCipher::from_header(&header, passphrase)?;
+        let (new_cipher, new_header) = DatabaseCipher::new(new_passphrase, header.encrypt_keys)?;
+
+        let entries = dbtx
+            .raw_find_by_prefix(&[DATA_PREFIX])
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        // Remove all entries first, since with encrypted keys a re-encrypted key
+        // could collide with a key that wasn't re-encrypted yet
+        dbtx.raw_remove_by_prefix(&[DATA_PREFIX]).await?;
+        for (encrypted_key, encrypted_value) in entries {
+            let key = cipher.decrypt_key(&encrypted_key)?;
+            let value = cipher.decrypt_value(&key, encrypted_value)?;
+            dbtx.raw_insert_bytes(
+                &new_cipher.encrypt_key(&key),
+                &new_cipher.encrypt_value(&key, &value)?,
+            )
+            .await?;
+        }
+        dbtx.raw_insert_bytes(&HEADER_KEY, &new_header.consensus_encode_to_vec())
+            .await?;
+
+        dbtx.commit_tx().await
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<D: IRawDatabase> IRawDatabase for EncryptedDatabase<D> {
+    type Transaction<'a> = EncryptedDatabaseTransaction<D::Transaction<'a>>;
+
+    async fn begin_transaction<'a>(&'a self) -> Self::Transaction<'a> {
+        EncryptedDatabaseTransaction {
+            inner: self.inner.begin_transaction().await,
+            cipher: self.cipher.clone(),
+        }
+    }
+}
+
+/// Transaction of an [`EncryptedDatabase`]
+#[derive(Debug)]
+pub struct EncryptedDatabaseTransaction<T> {
+    inner: T,
+    cipher: Arc<DatabaseCipher>,
+}
+
+impl<T: IRawDatabaseTransaction> EncryptedDatabaseTransaction<T> {
+    async fn find_by_prefix(
+        &mut self,
+        key_prefix: &[u8],
+        descending: bool,
+    ) -> anyhow::Result<PrefixStream<'_>> {
+        let encrypted_prefix = self.cipher.encrypt_key(key_prefix);
+        let cipher = self.cipher.clone();
+        let entries = if descending {
+            self.inner
+                .raw_find_by_prefix_sorted_descending(&encrypted_prefix)
+                .await?
+        } else {
+            self.inner.raw_find_by_prefix(&encrypted_prefix).await?
+        };
+        let decrypt = move |(encrypted_key, encrypted_value): (Vec<u8>, Vec<u8>)| {
+            let key = cipher
+                .decrypt_key(&encrypted_key)
+                .expect("Unrecoverable error when decrypting database key");
+            let value = cipher
+                .decrypt_value(&key, encrypted_value)
+                .expect("Unrecoverable error when decrypting database value");
+            (key, value)
+        };
+
+        if self.cipher.key_secret.is_none() {
+            return Ok(Box::pin(entries.map(decrypt)));
+        }
+
+        // The order of encrypted keys is meaningless
+        let mut entries = entries.map(decrypt).collect::<Vec<_>>().await;
+        entries.sort_unstable_by(|(a, _), (b, _)| if descending { b.cmp(a) } else { a.cmp(b) });
+        Ok(Box::pin(stream::iter(entries)))
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: IRawDatabaseTransaction> IDatabaseTransactionOpsCore for EncryptedDatabaseTransaction<T> {
+    async fn raw_insert_bytes(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let encrypted_value = self.cipher.encrypt_value(key, value)?;
+        self.inner
+            .raw_insert_bytes(&self.cipher.encrypt_key(key), &encrypted_value)
+            .await?
+            .map(|previous| self.cipher.decrypt_value(key, previous))
+            .transpose()
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        self.inner
+            .raw_get_bytes(&self.cipher.encrypt_key(key))
+            .await?
+            .map(|value| self.cipher.decrypt_value(key, value))
+            .transpose()
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        self.inner
+            .raw_remove_entry(&self.cipher.encrypt_key(key))
+            .await?
+            .map(|previous| self.cipher.decrypt_value(key, previous))
+            .transpose()
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<PrefixStream<'_>> {
+        self.find_by_prefix(key_prefix, false).await
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> anyhow::Result<PrefixStream<'_>> {
+        self.find_by_prefix(key_prefix, true).await
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        self.inner
+            .raw_remove_by_prefix(&self.cipher.encrypt_key(key_prefix))
+            .await
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: IRawDatabaseTransaction> IDatabaseTransactionOps for EncryptedDatabaseTransaction<T> {
+    async fn set_tx_savepoint(&mut self) -> anyhow::Result<()> {
+        self.inner.set_tx_savepoint().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> anyhow::Result<()> {
+        self.inner.rollback_tx_to_savepoint().await
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: IRawDatabaseTransaction> IRawDatabaseTransaction for EncryptedDatabaseTransaction<T> {
+    async fn commit_tx(self) -> anyhow::Result<()> {
+        self.inner.commit_tx().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_aead::envs::FM_TEST_FAST_WEAK_CRYPTO_ENV;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction};
+    use futures::StreamExt;
+
+    use super::{EncryptedDatabase, EncryptionOptions};
+
+    async fn write_entries(db: &impl IRawDatabase) {
+        let mut dbtx = db.begin_transaction().await;
+        for key in [&b"\x01bb"[..], b"\x01a", b"\x01ab", b"\x02a"] {
+            dbtx.raw_insert_bytes(key, b"secret value").await.unwrap();
+        }
+        dbtx.commit_tx().await.unwrap();
+    }
+
+    async fn assert_entries(db: &impl IRawDatabase) {
+        let mut dbtx = db.begin_transaction().await;
+        let keys = dbtx
+            .raw_find_by_prefix(&[0x01])
+            .await
+            .unwrap()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            keys,
+            vec![b"\x01a".to_vec(), b"\x01ab".to_vec(), b"\x01bb".to_vec()]
+        );
+        let keys = dbtx
+            .raw_find_by_prefix_sorted_descending(&[0x01])
+            .await
+            .unwrap()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            keys,
+            vec![b"\x01bb".to_vec(), b"\x01ab".to_vec(), b"\x01a".to_vec()]
+        );
+        assert_eq!(
+            dbtx.raw_get_bytes(b"\x02a").await.unwrap(),
+            Some(b"secret value".to_vec())
+        );
+    }
+
+    /// Returns whether any raw key or value contains `needle`
+    async fn raw_contains(raw: &MemDatabase, needle: &[u8]) -> bool {
+        raw.begin_transaction()
+            .await
+            .raw_find_by_prefix(&[])
+            .await
+            .unwrap()
+            .any(|(key, value)| async move {
+                key.windows(needle.len()).any(|window| window == needle)
+                    || value.windows(needle.len()).any(|window| window == needle)
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn encrypts_values_and_keys() {
+        std::env::set_var(FM_TEST_FAST_WEAK_CRYPTO_ENV, "1");
+
+        for encrypt_keys in [false, true] {
+            let db = EncryptedDatabase::open(
+                MemDatabase::new(),
+                "password",
+                EncryptionOptions { encrypt_keys },
+            )
+            .await
+            .unwrap();
+            write_entries(&db).await;
+            assert_entries(&db).await;
+
+            assert!(!raw_contains(&db.inner, b"secret value").await);
+            assert_eq!(raw_contains(&db.inner, b"\x01ab").await, !encrypt_keys);
+
+            // The options of an existing database can't be changed
+            let db = EncryptedDatabase::open(db.inner, "password", EncryptionOptions::default())
+                .await
+                .unwrap();
+            assert_entries(&db).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_passphrase() {
+        std::env::set_var(FM_TEST_FAST_WEAK_CRYPTO_ENV, "1");
+
+        let raw = MemDatabase::new();
+        assert!(!EncryptedDatabase::is_encrypted(&raw).await.unwrap());
+        let db = EncryptedDatabase::open(raw, "old", EncryptionOptions { encrypt_keys: true })
+            .await
+            .unwrap();
+        write_entries(&db).await;
+        let raw = db.inner;
+        assert!(EncryptedDatabase::is_encrypted(&raw).await.unwrap());
+
+        assert!(EncryptedDatabase::change_passphrase(&raw, "wrong", "new")
+            .await
+            .is_err());
+        EncryptedDatabase::change_passphrase(&raw, "old", "new")
+            .await
+            .unwrap();
+
+        // The old passphrase doesn't work anymore
+        assert!(EncryptedDatabase::change_passphrase(&raw, "old", "other")
+            .await
+            .is_err());
+        let db = EncryptedDatabase::open(raw, "new", EncryptionOptions::default())
+            .await
+            .unwrap();
+        assert_entries(&db).await;
+    }
+
+    #[tokio::test]
+    async fn refuses_unencrypted_data() {
+        std::env::set_var(FM_TEST_FAST_WEAK_CRYPTO_ENV, "1");
+
+        let raw = MemDatabase::new();
+        let mut dbtx = raw.begin_transaction().await;
+        dbtx.raw_insert_bytes(b"\x01a", b"plaintext").await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+
+        assert!(
+            EncryptedDatabase::open(raw, "password", EncryptionOptions::default())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,679 @@
+//! Client-wide event bus
+//!
+//! The creation of every operation, every state machine transition and every
+//! operation outcome is logged as a [`ClientEvent`] in the same database
+//! transaction that causes it, so events can't get lost if the client stops.
+//! Once the first sink is registered with [`ClientEventBus::add_sink`], a
+//! background task assigns consecutive ids to the logged events whenever it is
+//! notified about new ones, which are then delivered to all sinks.
+//!
+//! Every sink has a persistent cursor of the next event to deliver to it, so
+//! delivery resumes after a restart and each event is delivered at least
+//! once, unless delivering it failed [`MAX_EVENT_DELIVERY_ATTEMPTS`] times in a
+//! row, in which case it is dropped for that sink. Events are removed once all
+//! sinks received them. While no sink is registered at all, events aren't
+//! logged.
+//!
+//! **Attention**: events contain the operation meta and outcome, which can
+//! include sensitive data like e-cash notes, so only register trusted sinks.
+
+use std::fmt::Debug;
+use std::io::{Read, Write};
+#[cfg(not(target_family = "wasm"))]
+use std::path::PathBuf;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use fedimint_core::core::{ModuleInstanceId, OperationId};
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
+use fedimint_core::time::now;
+use fedimint_core::util::{retry, ExponentialBackoff, SafeUrl};
+use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_logging::LOG_CLIENT;
+use futures::{future, poll, StreamExt};
+use serde::{Deserialize, Serialize, Serializer};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, watch, OnceCell};
+use tracing::{debug, warn};
+
+use crate::db::{
+    ClientEventKey, ClientEventKeyPrefix, ClientEventSinkCursorKey, ClientEventSinkCursorKeyPrefix,
+    NextClientEventIdKey, PendingClientEventKey, PendingClientEventKeyPrefix,
+};
+use crate::oplog::OperationOutcomeState;
+
+/// Number of events loaded from the database at once for delivery
+const EVENT_DELIVERY_BATCH_SIZE: usize = 100;
+
+/// Number of times delivering an event to a sink is attempted before it is
+/// dropped for that sink
+pub const MAX_EVENT_DELIVERY_ATTEMPTS: usize = 10;
+
+/// Something that happened to an operation of the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientEvent {
+    /// A new operation was added to the operation log
+    OperationCreated {
+        operation_id: OperationId,
+        operation_module_kind: String,
+        meta: serde_json::Value,
+    },
+    /// A state machine of the operation was started or transitioned into a
+    /// new state
+    StateTransition {
+        operation_id: OperationId,
+        module_instance_id: ModuleInstanceId,
+        /// If the state machine finished with this transition
+        terminal: bool,
+    },
+    /// The final outcome of the operation was stored in the operation log
+    OperationOutcome {
+        operation_id: OperationId,
+        outcome: serde_json::Value,
+        outcome_state: OperationOutcomeState,
+    },
+}
+
+impl ClientEvent {
+    pub fn operation_id(&self) -> OperationId {
+        match self {
+            ClientEvent::OperationCreated { operation_id, .. }
+            | ClientEvent::StateTransition { operation_id, .. }
+            | ClientEvent::OperationOutcome { operation_id, .. } => *operation_id,
+        }
+    }
+}
+
+// Events contain JSON values, so we store them as JSON like the operation log
+impl Encodable for ClientEvent {
+    fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        serde_json::to_string(self)
+            .expect("JSON serialization should not fail")
+            .consensus_encode(writer)
+    }
+}
+
+impl Decodable for ClientEvent {
+    fn consensus_decode<R: Read>(
+        r: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let event_str = String::consensus_decode(r, modules)?;
+        serde_json::from_str(&event_str).map_err(DecodeError::from_err)
+    }
+}
+
+/// A [`ClientEvent`] as delivered to the sinks
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize)]
+pub struct ClientEventRecord {
+    /// Consecutive id of the event, can be used by sinks to detect events
+    /// that were delivered more than once
+    pub id: u64,
+    #[serde(serialize_with = "serialize_rfc3339")]
+    pub time: SystemTime,
+    #[serde(flatten)]
+    pub event: ClientEvent,
+}
+
+fn serialize_rfc3339<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    OffsetDateTime::from(*time)
+        .format(&Rfc3339)
+        .map_err(serde::ser::Error::custom)?
+        .serialize(serializer)
+}
+
+/// Destination events get delivered to, see [`ClientEventBus::add_sink`]
+#[apply(async_trait_maybe_send!)]
+pub trait ClientEventSink: Debug + MaybeSend + MaybeSync + 'static {
+    /// Delivers a single event. Failed deliveries are retried with exponential
+    /// backoff up to [`MAX_EVENT_DELIVERY_ATTEMPTS`] times, later events are
+    /// only delivered afterwards.
+    async fn deliver(&self, event: &ClientEventRecord) -> anyhow::Result<()>;
+}
+
+/// Sink sending events to a channel within the process
+#[derive(Debug)]
+pub struct ChannelClientEventSink {
+    sender: mpsc::Sender<ClientEventRecord>,
+}
+
+impl ChannelClientEventSink {
+    /// Returns the sink and the receiving end of its channel which buffers up
+    /// to `capacity` events
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<ClientEventRecord>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (Self { sender }, receiver)
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientEventSink for ChannelClientEventSink {
+    async fn deliver(&self, event: &ClientEventRecord) -> anyhow::Result<()> {
+        self.sender
+            .send(event.clone())
+            .await
+            .map_err(|_| anyhow!("Event receiver was dropped"))
+    }
+}
+
+/// Sink appending events to a file, one JSON object per line
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug)]
+pub struct JsonLinesFileClientEventSink {
+    path: PathBuf,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl JsonLinesFileClientEventSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[apply(async_trait_maybe_send!)]
+impl ClientEventSink for JsonLinesFileClientEventSink {
+    async fn deliver(&self, event: &ClientEventRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+
+        // File writes block, so they must not run on the async runtime
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&line)
+        })
+        .await??;
+
+        Ok(())
+    }
+}
+
+/// Sink posting every event as JSON to a webhook URL, any response status
+/// other than a success counts as a failed delivery
+#[derive(Debug)]
+pub struct WebhookClientEventSink {
+    url: SafeUrl,
+    client: reqwest::Client,
+}
+
+impl WebhookClientEventSink {
+    pub fn new(url: SafeUrl) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientEventSink for WebhookClientEventSink {
+    async fn deliver(&self, event: &ClientEventRecord) -> anyhow::Result<()> {
+        self.client
+            .post(self.url.clone().to_unsafe())
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Delivers the events of a client to the registered sinks, see the [module
+/// documentation](self)
+#[derive(Debug, Clone)]
+pub struct ClientEventBus {
+    db: Database,
+    task_group: TaskGroup,
+    /// Id the next sequenced event will get, set once the first sink started
+    /// the sequencer
+    next_id: Arc<OnceLock<watch::Receiver<u64>>>,
+    /// Whether any sink cursor exists, read from the database once and kept
+    /// up to date by [`Self::add_sink`] and [`Self::remove_sink`] so logging
+    /// events doesn't have to scan the cursors
+    has_sinks: Arc<OnceCell<AtomicBool>>,
+}
+
+impl ClientEventBus {
+    /// Creates the event bus of the client's `db`, which only starts working
+    /// once a sink is added
+    pub(crate) fn new(db: Database, task_group: &TaskGroup) -> Self {
+        Self {
+            db,
+            task_group: task_group.clone(),
+            next_id: Arc::default(),
+            has_sinks: Arc::default(),
+        }
+    }
+
+    async fn has_sinks(&self) -> &AtomicBool {
+        self.has_sinks
+            .get_or_init(|| async {
+                let mut dbtx = self.db.begin_transaction_nc().await;
+                AtomicBool::new(has_sink_cursors(&mut dbtx).await)
+            })
+            .await
+    }
+
+    /// Logs `event` as part of `dbtx`, it is only delivered if `dbtx` gets
+    /// committed. Does nothing if no sink is registered.
+    pub(crate) async fn log_event(&self, dbtx: &mut DatabaseTransaction<'_>, event: ClientEvent) {
+        if !self.has_sinks().await.load(Ordering::Acquire) {
+            return;
+        }
+
+        dbtx.insert_new_entry(
+            &PendingClientEventKey {
+                time: now(),
+                nonce: rand::random(),
+            },
+            &event,
+        )
+        .await;
+        // Wakes up the sequencer without writing a key shared by all
+        // transactions logging events
+        dbtx.notify_on_commit(&NextClientEventIdKey);
+    }
+
+    /// Starts delivering events to `sink` in the background until the client
+    /// shuts down.
+    ///
+    /// Delivery continues where it stopped the last time a sink with the same
+    /// `name` was registered. A sink registered under a new name receives the
+    /// events logged from now on.
+    pub async fn add_sink(&self, name: impl Into<String>, sink: impl ClientEventSink) {
+        let name = name.into();
+
+        let mut dbtx = self.db.begin_transaction().await;
+        let cursor_key = ClientEventSinkCursorKey { name: name.clone() };
+        let cursor = match dbtx.get_value(&cursor_key).await {
+            Some(cursor) => cursor,
+            None => {
+                let cursor = dbtx.get_value(&NextClientEventIdKey).await.unwrap_or(0);
+                dbtx.insert_new_entry(&cursor_key, &cursor).await;
+                cursor
+            }
+        };
+        dbtx.commit_tx().await;
+        self.has_sinks().await.store(true, Ordering::Release);
+
+        let next_id = self.next_id.get_or_init(|| {
+            let (next_id_sender, next_id) = watch::channel(0);
+            self.task_group.spawn_cancellable(
+                "client event sequencer",
+                run_event_sequencer(self.db.clone(), next_id_sender),
+            );
+            next_id
+        });
+
+        debug!(target: LOG_CLIENT, %name, cursor, "Adding client event sink");
+        self.task_group.spawn_cancellable(
+            format!("client event sink {name}"),
+            run_event_sink(self.db.clone(), name, sink, cursor, next_id.clone()),
+        );
+    }
+
+    /// Removes the cursor of a sink that won't be registered anymore, so the
+    /// events it didn't receive yet can be removed. Removing the last sink
+    /// removes all stored events.
+    pub async fn remove_sink(&self, name: &str) {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.remove_entry(&ClientEventSinkCursorKey {
+            name: name.to_owned(),
+        })
+        .await;
+        let has_sinks = has_sink_cursors(&mut dbtx.to_ref_nc()).await;
+        if !has_sinks {
+            dbtx.remove_by_prefix(&PendingClientEventKeyPrefix).await;
+            dbtx.remove_by_prefix(&ClientEventKeyPrefix).await;
+        }
+        dbtx.commit_tx().await;
+        self.has_sinks().await.store(has_sinks, Ordering::Release);
+    }
+
+    /// Returns up to `limit` stored events starting with id `start`
+    pub async fn get_events(&self, start: u64, limit: usize) -> Vec<ClientEventRecord> {
+        get_events(&self.db, start, limit).await
+    }
+}
+
+async fn has_sink_cursors(dbtx: &mut DatabaseTransaction<'_>) -> bool {
+    dbtx.find_by_prefix(&ClientEventSinkCursorKeyPrefix)
+        .await
+        .next()
+        .await
+        .is_some()
+}
+
+async fn get_events(db: &Database, start: u64, limit: usize) -> Vec<ClientEventRecord> {
+    db.begin_transaction_nc()
+        .await
+        .find_by_prefix(&ClientEventKeyPrefix)
+        .await
+        .filter(|(key, _)| future::ready(start <= key.id))
+        .take(limit)
+        .map(|(_, event)| event)
+        .collect()
+        .await
+}
+
+/// Assigns ids to pending events and removes the events all sinks received
+/// whenever events are logged or delivered
+async fn run_event_sequencer(db: Database, next_id_sender: watch::Sender<u64>) {
+    loop {
+        // Polled once to register for notifications before sequencing, so events
+        // logged in the meantime aren't missed
+        let mut notified = pin!(db.wait_key_notified(&NextClientEventIdKey));
+        let _ = poll!(notified.as_mut());
+
+        let next_id = db
+            .autocommit::<_, _, anyhow::Error>(
+                |dbtx, _| Box::pin(async move { Ok(sequence_pending_events(dbtx).await) }),
+                None,
+            )
+            .await
+            .expect("Autocommit keeps trying to commit and the closure doesn't fail");
+
+        next_id_sender.send_if_modified(|current| {
+            let modified = *current != next_id;
+            *current = next_id;
+            modified
+        });
+
+        notified.await;
+    }
+}
+
+/// Assigns ids to all pending events and returns the id of the next event
+async fn sequence_pending_events(dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+    let mut next_id = dbtx.get_value(&NextClientEventIdKey).await.unwrap_or(0);
+
+    let pending = dbtx
+        .find_by_prefix(&PendingClientEventKeyPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    if !pending.is_empty() {
+        for (key, event) in pending {
+            dbtx.remove_entry(&key).await;
+            dbtx.insert_new_entry(
+                &ClientEventKey { id: next_id },
+                &ClientEventRecord {
+                    id: next_id,
+                    time: key.time,
+                    event,
+                },
+            )
+            .await;
+            next_id += 1;
+        }
+        dbtx.insert_entry(&NextClientEventIdKey, &next_id).await;
+    }
+
+    let delivered_until = dbtx
+        .find_by_prefix(&ClientEventSinkCursorKeyPrefix)
+        .await
+        .map(|(_, cursor)| cursor)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .min()
+        .unwrap_or(next_id);
+    let delivered = dbtx
+        .find_by_prefix(&ClientEventKeyPrefix)
+        .await
+        .map(|(key, _)| key)
+        .take_while(|key| future::ready(key.id < delivered_until))
+        .collect::<Vec<_>>()
+        .await;
+    for key in delivered {
+        dbtx.remove_entry(&key).await;
+    }
+
+    next_id
+}
+
+/// Delivers events to `sink` in order, starting with the event with id
+/// `cursor`
+async fn run_event_sink(
+    db: Database,
+    name: String,
+    sink: impl ClientEventSink,
+    mut cursor: u64,
+    mut next_id: watch::Receiver<u64>,
+) {
+    loop {
+        next_id.borrow_and_update();
+        let events = get_events(&db, cursor, EVENT_DELIVERY_BATCH_SIZE).await;
+        if events.is_empty() {
+            if next_id.changed().await.is_err() {
+                // The sequencer stopped, so the client is shutting down
+                return;
+            }
+            continue;
+        }
+
+        for event in events {
+            if let Err(e) = retry(
+                format!("Delivering client event {} to sink {name}", event.id),
+                ExponentialBackoff::default()
+                    .with_min_delay(Duration::from_secs(1))
+                    .with_max_delay(Duration::from_secs(60))
+                    .with_max_times(MAX_EVENT_DELIVERY_ATTEMPTS - 1),
+                || sink.deliver(&event),
+            )
+            .await
+            {
+                warn!(
+                    target: LOG_CLIENT,
+                    %name,
+                    id = event.id,
+                    err = %e,
+                    "Dropping client event after {MAX_EVENT_DELIVERY_ATTEMPTS} failed delivery attempts"
+                );
+            }
+
+            cursor = event.id + 1;
+            let mut dbtx = db.begin_transaction().await;
+            dbtx.insert_entry(&ClientEventSinkCursorKey { name: name.clone() }, &cursor)
+                .await;
+            // Lets the sequencer remove the events all sinks received
+            dbtx.notify_on_commit(&NextClientEventIdKey);
+            if let Err(e) = dbtx.commit_tx_result().await {
+                warn!(target: LOG_CLIENT, %name, err = %e, "Failed to store client event sink cursor");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::core::OperationId;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::runtime;
+    use fedimint_core::task::TaskGroup;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    use super::{ChannelClientEventSink, ClientEvent, ClientEventBus};
+    use crate::db::{NextClientEventIdKey, PendingClientEventKeyPrefix};
+    use crate::oplog::OperationLog;
+
+    async fn next_event(
+        receiver: &mut tokio::sync::mpsc::Receiver<super::ClientEventRecord>,
+    ) -> super::ClientEventRecord {
+        runtime::timeout(Duration::from_secs(10), receiver.recv())
+            .await
+            .expect("Event is delivered in time")
+            .expect("Sink is alive")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivers_events_to_sinks() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let task_group = TaskGroup::new();
+        let event_bus = ClientEventBus::new(db.clone(), &task_group);
+        let op_log =
+            OperationLog::new(db.clone(), Default::default()).with_event_bus(event_bus.clone());
+
+        let (sink, mut receiver) = ChannelClientEventSink::new(10);
+        event_bus.add_sink("channel", sink).await;
+
+        let operation_id = OperationId([1; 32]);
+        let mut dbtx = db.begin_transaction().await;
+        op_log
+            .add_operation_log_entry(&mut dbtx.to_ref_nc(), operation_id, "foo", "bar")
+            .await;
+        dbtx.commit_tx().await;
+        op_log
+            .set_operation_outcome(operation_id, &"baz")
+            .await
+            .unwrap();
+
+        let created = next_event(&mut receiver).await;
+        assert_eq!(created.id, 0);
+        assert_eq!(
+            created.event,
+            ClientEvent::OperationCreated {
+                operation_id,
+                operation_module_kind: "foo".to_string(),
+                meta: json!("bar"),
+            }
+        );
+        let outcome = next_event(&mut receiver).await;
+        assert_eq!(outcome.id, 1);
+        assert_eq!(outcome.event.operation_id(), operation_id);
+
+        let record = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(record["type"], "operation_outcome");
+        assert_eq!(record["outcome"], "baz");
+
+        // Delivered events get removed
+        runtime::sleep(Duration::from_secs(1)).await;
+        assert!(event_bus.get_events(0, 10).await.is_empty());
+
+        task_group.shutdown_join_all(None).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resumes_delivery_after_restart() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+
+        let task_group = TaskGroup::new();
+        let event_bus = ClientEventBus::new(db.clone(), &task_group);
+        let (sink, _receiver) = ChannelClientEventSink::new(10);
+        event_bus.add_sink("channel", sink).await;
+        task_group.shutdown_join_all(None).await.unwrap();
+
+        // Events logged while no sink is running are kept
+        let task_group = TaskGroup::new();
+        let event_bus = ClientEventBus::new(db.clone(), &task_group);
+        let op_log =
+            OperationLog::new(db.clone(), Default::default()).with_event_bus(event_bus.clone());
+        for operation_id in [OperationId([1; 32]), OperationId([2; 32])] {
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(&mut dbtx.to_ref_nc(), operation_id, "foo", "bar")
+                .await;
+            dbtx.commit_tx().await;
+        }
+
+        let (sink, mut receiver) = ChannelClientEventSink::new(10);
+        event_bus.add_sink("channel", sink).await;
+        assert_eq!(
+            next_event(&mut receiver).await.event.operation_id(),
+            OperationId([1; 32])
+        );
+        assert_eq!(
+            next_event(&mut receiver).await.event.operation_id(),
+            OperationId([2; 32])
+        );
+
+        // A new sink only receives new events
+        let (sink, mut new_receiver) = ChannelClientEventSink::new(10);
+        event_bus.add_sink("new", sink).await;
+        let mut dbtx = db.begin_transaction().await;
+        op_log
+            .add_operation_log_entry(&mut dbtx.to_ref_nc(), OperationId([3; 32]), "foo", "bar")
+            .await;
+        dbtx.commit_tx().await;
+        let event = next_event(&mut new_receiver).await;
+        assert_eq!(event.id, 2);
+        assert_eq!(event.event.operation_id(), OperationId([3; 32]));
+        assert_eq!(
+            db.begin_transaction_nc()
+                .await
+                .get_value(&NextClientEventIdKey)
+                .await,
+            Some(3)
+        );
+
+        task_group.shutdown_join_all(None).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skips_events_without_sinks() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let task_group = TaskGroup::new();
+        let event_bus = ClientEventBus::new(db.clone(), &task_group);
+        let op_log =
+            OperationLog::new(db.clone(), Default::default()).with_event_bus(event_bus.clone());
+
+        let log_operation = |operation_id| {
+            let db = db.clone();
+            let op_log = op_log.clone();
+            async move {
+                let mut dbtx = db.begin_transaction().await;
+                op_log
+                    .add_operation_log_entry(&mut dbtx.to_ref_nc(), operation_id, "foo", "bar")
+                    .await;
+                dbtx.commit_tx().await;
+            }
+        };
+        let pending_events = || async {
+            db.begin_transaction_nc()
+                .await
+                .find_by_prefix(&PendingClientEventKeyPrefix)
+                .await
+                .count()
+                .await
+        };
+
+        log_operation(OperationId([1; 32])).await;
+        assert_eq!(pending_events().await, 0);
+
+        // Removing the last sink removes the events it didn't receive yet
+        let (sink, mut receiver) = ChannelClientEventSink::new(10);
+        event_bus.add_sink("channel", sink).await;
+        log_operation(OperationId([2; 32])).await;
+        assert_eq!(
+            next_event(&mut receiver).await.event.operation_id(),
+            OperationId([2; 32])
+        );
+        task_group.shutdown_join_all(None).await.unwrap();
+
+        log_operation(OperationId([3; 32])).await;
+        assert_eq!(pending_events().await, 1);
+        event_bus.remove_sink("channel").await;
+        assert_eq!(pending_events().await, 0);
+
+        log_operation(OperationId([4; 32])).await;
+        assert_eq!(pending_events().await, 0);
+    }
+}
@@ -68,7 +68,7 @@
 //! For a hacky instantiation of a complete client see the [`ng` subcommand of `fedimint-cli`](https://github.com/fedimint/fedimint/blob/55f9d88e17d914b92a7018de677d16e57ed42bf6/fedimint-cli/src/ng.rs#L56-L73).
 
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::ops::{self, Range};
 use std::pin::Pin;
@@ -79,9 +79,9 @@ use anyhow::{anyhow, bail, ensure, Context};
 use async_stream::stream;
 use backup::ClientBackup;
 use db::{
-    apply_migrations_client, CachedApiVersionSet, CachedApiVersionSetKey, ClientConfigKey,
-    ClientConfigKeyPrefix, ClientInitStateKey, ClientModuleRecovery, EncodedClientSecretKey,
-    InitMode,
+    apply_migrations_client, get_core_client_database_migrations, CachedApiVersionSet,
+    CachedApiVersionSetKey, ClientConfigKey, ClientConfigKeyPrefix, ClientInitStateKey,
+    ClientModuleRecovery, EncodedClientSecretKey, InitMode, CORE_CLIENT_DATABASE_VERSION,
 };
 use envs::get_discover_api_version_timeout;
 use fedimint_api_client::api::{ApiVersionSet, DynGlobalApi, DynModuleApi, IGlobalFederationApi};
@@ -93,7 +93,8 @@ use fedimint_core::core::{
     DynInput, DynOutput, IInput, IOutput, ModuleInstanceId, ModuleKind, OperationId,
 };
 use fedimint_core::db::{
-    AutocommitError, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
+    apply_migrations, AutocommitError, Database, DatabaseTransaction,
+    IDatabaseTransactionOpsCoreTyped, IRawDatabase,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
@@ -127,12 +128,17 @@ use tokio_stream::wrappers::WatchStream;
 use tracing::{debug, error, info, warn};
 
 use crate::backup::Metadata;
-use crate::db::{ClientMetadataKey, ClientModuleRecoveryState, InitState, OperationLogKey};
+use crate::db::encrypted::{EncryptedDatabase, EncryptionOptions};
+use crate::db::{
+    ClientMetadataKey, ClientModuleRecoveryState, InitState, OperationLogKey, SpendingPolicyKey,
+};
+use crate::events::ClientEventBus;
 use crate::module::init::{
     ClientModuleInit, ClientModuleInitRegistry, DynClientModuleInit, IClientModuleInit,
 };
 use crate::module::{ClientModule, ClientModuleRegistry, IClientModule, StateGenerator};
-use crate::oplog::OperationLog;
+use crate::oplog::{OperationLog, OperationLogExportFormat, OperationLogFilter};
+use crate::policy::{SpendingPolicy, SpendingPolicyError, SpendingPolicyStatus};
 use crate::sm::executor::{
     ActiveOperationStateKeyPrefix, ContextGen, InactiveOperationStateKeyPrefix,
 };
@@ -151,10 +157,16 @@ pub mod backup;
 pub mod db;
 /// Environment variables
 pub mod envs;
+/// Client-wide event bus and its sinks
+pub mod events;
 /// Module client interface definitions
 pub mod module;
+/// Clients of several federations sharing one database
+pub mod multi_federation;
 /// Operation log subsystem of the client
 pub mod oplog;
+/// Spending limits enforced before submitting transactions
+pub mod policy;
 /// Secret handling & derivation
 pub mod secret;
 /// Client state machine interfaces and executor implementation
@@ -508,6 +520,21 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
     }
 }
 
+async fn set_spending_policy_dbtx(
+    dbtx: &mut DatabaseTransaction<'_>,
+    spending_policy: Option<SpendingPolicy>,
+) {
+    match spending_policy {
+        Some(spending_policy) => {
+            dbtx.insert_entry(&SpendingPolicyKey, &spending_policy)
+                .await;
+        }
+        None => {
+            dbtx.remove_entry(&SpendingPolicyKey).await;
+        }
+    }
+}
+
 fn states_add_instance(
     module_instance_id: ModuleInstanceId,
     state_gen: StateGenerator<Box<maybe_add_send_sync!(dyn IState + 'static)>>,
@@ -751,6 +778,7 @@ pub struct Client {
     api: DynGlobalApi,
     root_secret: DerivableSecret,
     operation_log: OperationLog,
+    event_bus: ClientEventBus,
     secp_ctx: Secp256k1<secp256k1_zkp::All>,
     meta_service: Arc<MetaService>,
 
@@ -768,6 +796,18 @@ impl Client {
         ClientBuilder::new(db)
     }
 
+    /// Initialize a client builder using `raw_db` encrypted with
+    /// `passphrase`, see [`EncryptedDatabase`]. An empty `raw_db` gets
+    /// encrypted using `options`.
+    pub async fn encrypted_builder(
+        raw_db: impl IRawDatabase,
+        passphrase: &str,
+        options: EncryptionOptions,
+    ) -> anyhow::Result<ClientBuilder> {
+        let db = EncryptedDatabase::open(raw_db, passphrase, options).await?;
+        Ok(ClientBuilder::new(db.into()))
+    }
+
     pub fn api(&self) -> &(dyn IGlobalFederationApi + 'static) {
         self.api.as_ref()
     }
@@ -994,6 +1034,31 @@ impl Client {
         &self.operation_log
     }
 
+    /// Returns the event bus notifying about operations, see [`events`]
+    pub fn event_bus(&self) -> &ClientEventBus {
+        &self.event_bus
+    }
+
+    /// Writes the operations matching `filter` to `writer` as a ledger for
+    /// accounting, see [`OperationLog::export`]. Returns the number of
+    /// exported operations.
+    pub async fn export_operation_log(
+        &self,
+        filter: &OperationLogFilter,
+        format: OperationLogExportFormat,
+        writer: impl std::io::Write,
+    ) -> anyhow::Result<usize> {
+        self.operation_log
+            .export(
+                self.federation_id,
+                &self.module_inits,
+                filter,
+                format,
+                writer,
+            )
+            .await
+    }
+
     /// Get the meta manager to read meta fields.
     pub fn meta_service(&self) -> &Arc<MetaService> {
         &self.meta_service
@@ -1060,12 +1125,28 @@ impl Client {
         Ok((tx, states, change_range))
     }
 
+    /// Checks whether the primary module could fund `tx_builder` and pay for
+    /// its change right now, without reserving any funds or submitting it.
+    ///
+    /// Useful to find the largest amount that can be spent, since the fees of
+    /// the primary module's inputs depend on which of its funds are spent.
+    pub async fn can_fund_transaction(&self, tx_builder: TransactionBuilder) -> bool {
+        let mut dbtx = self.db().begin_transaction().await;
+        dbtx.ignore_uncommitted();
+
+        let finalized = self
+            .finalize_transaction(&mut dbtx.to_ref_nc(), OperationId::new_random(), tx_builder)
+            .await;
+        finalized.is_ok()
+    }
+
     /// Add funding and/or change to the transaction builder as needed, finalize
     /// the transaction and submit it to the federation.
     ///
     /// ## Errors
     /// The function will return an error if the operation with given ID already
-    /// exists.
+    /// exists or if the transaction violates the [`SpendingPolicy`] of the
+    /// client, in which case the error is a [`SpendingPolicyError`].
     ///
     /// ## Panics
     /// The function will panic if the database transaction collides with
@@ -1096,6 +1177,8 @@ impl Client {
                             bail!("There already exists an operation with id {operation_id:?}")
                         }
 
+                        self.enforce_spending_policy(dbtx, &tx_builder).await?;
+
                         let (txid, change) = self
                             .finalize_and_submit_transaction_inner(dbtx, operation_id, tx_builder)
                             .await?;
@@ -1128,6 +1211,48 @@ impl Client {
         }
     }
 
+    /// Checks the outflow of `tx_builder` against the spending policy, see
+    /// [`policy`]
+    async fn enforce_spending_policy(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        tx_builder: &TransactionBuilder,
+    ) -> Result<(), SpendingPolicyError> {
+        let mut outflow = Amount::ZERO;
+        let mut module_kinds = BTreeSet::new();
+        for output in &tx_builder.outputs {
+            let module_instance_id = output.output.module_instance_id();
+            if module_instance_id == self.primary_module_instance {
+                continue;
+            }
+
+            let (module_kind, _) = self
+                .modules
+                .get_with_kind(module_instance_id)
+                .expect("Module instance not found");
+            module_kinds.insert(module_kind.clone());
+            outflow += output.amount;
+        }
+
+        policy::enforce_spending_policy(dbtx, &module_kinds, outflow).await
+    }
+
+    /// Returns the spending policy and the outflow it is checked against
+    pub async fn spending_policy_status(&self) -> SpendingPolicyStatus {
+        let mut dbtx = self.db().begin_transaction_nc().await;
+        SpendingPolicyStatus {
+            policy: dbtx.get_value(&SpendingPolicyKey).await,
+            daily_outflow: policy::daily_outflow(&mut dbtx).await,
+        }
+    }
+
+    /// Replaces the spending policy, or removes it if `None`
+    pub async fn set_spending_policy(&self, spending_policy: Option<SpendingPolicy>) {
+        let mut dbtx = self.db().begin_transaction().await;
+        set_spending_policy_dbtx(&mut dbtx.to_ref_nc(), spending_policy).await;
+        dbtx.commit_tx().await;
+    }
+
     async fn finalize_and_submit_transaction_inner(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -1789,6 +1914,7 @@ pub struct ClientBuilder {
     admin_creds: Option<AdminCreds>,
     db_no_decoders: Database,
     meta_service: Arc<MetaService>,
+    spending_policy: Option<SpendingPolicy>,
     stopped: bool,
 }
 
@@ -1802,6 +1928,7 @@ impl ClientBuilder {
             db_no_decoders: db,
             stopped: false,
             meta_service,
+            spending_policy: None,
         }
     }
 
@@ -1814,6 +1941,7 @@ impl ClientBuilder {
             stopped: false,
             // non unique
             meta_service: client.meta_service.clone(),
+            spending_policy: None,
         }
     }
 
@@ -1851,8 +1979,24 @@ impl ClientBuilder {
         self.meta_service = meta_service;
     }
 
+    /// Stores `spending_policy` in the client database when building the
+    /// client, replacing the previous one. See [`policy`] for more
+    /// information.
+    pub fn with_spending_policy(&mut self, spending_policy: SpendingPolicy) {
+        self.spending_policy = Some(spending_policy);
+    }
+
     async fn migrate_database(&self, db: &Database) -> anyhow::Result<()> {
-        // Only apply the client database migrations if the database has been
+        apply_migrations(
+            db,
+            "fedimint-client".to_string(),
+            CORE_CLIENT_DATABASE_VERSION,
+            get_core_client_database_migrations(),
+            None,
+        )
+        .await?;
+
+        // Only apply the client module database migrations if the database has been
         // initialized.
         if let Ok(client_config) = self.load_existing_config().await {
             for (module_id, module_cfg) in client_config.modules {
@@ -1892,6 +2036,36 @@ impl ClientBuilder {
         self.admin_creds = Some(creds);
     }
 
+    /// Writes the initial state of a client of the federation of `config` to
+    /// `dbtx`, which has to be isolated to the client's database. Allows
+    /// initializing a client atomically with other changes, the client can
+    /// then be built using [`Self::open`].
+    pub(crate) async fn init_dbtx(
+        dbtx: &mut DatabaseTransaction<'_>,
+        config: &ClientConfig,
+        init_mode: InitMode,
+    ) {
+        // Save config to DB
+        dbtx.insert_new_entry(
+            &ClientConfigKey {
+                id: config.calculate_federation_id(),
+            },
+            config,
+        )
+        .await;
+
+        let init_state = InitState::Pending(init_mode);
+        dbtx.insert_entry(&ClientInitStateKey, &init_state).await;
+
+        let metadata = init_state
+            .does_require_recovery()
+            .flatten()
+            .map(|s| s.metadata)
+            .unwrap_or(Metadata::empty());
+
+        dbtx.insert_new_entry(&ClientMetadataKey, &metadata).await;
+    }
+
     async fn init(
         self,
         root_secret: DerivableSecret,
@@ -1907,26 +2081,7 @@ impl ClientBuilder {
         {
             debug!(target: LOG_CLIENT, "Initializing client database");
             let mut dbtx = self.db_no_decoders.begin_transaction().await;
-            // Save config to DB
-            dbtx.insert_new_entry(
-                &ClientConfigKey {
-                    id: config.calculate_federation_id(),
-                },
-                &config,
-            )
-            .await;
-
-            let init_state = InitState::Pending(init_mode);
-            dbtx.insert_entry(&ClientInitStateKey, &init_state).await;
-
-            let metadata = init_state
-                .does_require_recovery()
-                .flatten()
-                .map(|s| s.metadata)
-                .unwrap_or(Metadata::empty());
-
-            dbtx.insert_new_entry(&ClientMetadataKey, &metadata).await;
-
+            Self::init_dbtx(&mut dbtx.to_ref_nc(), &config, init_mode).await;
             dbtx.commit_tx_result().await?;
         }
 
@@ -2291,6 +2446,17 @@ impl ClientBuilder {
             dbtx.commit_tx().await;
         }
 
+        if let Some(spending_policy) = self.spending_policy.clone() {
+            let mut dbtx = db.begin_transaction().await;
+            set_spending_policy_dbtx(&mut dbtx.to_ref_nc(), Some(spending_policy)).await;
+            dbtx.commit_tx().await;
+        }
+
+        let event_bus = ClientEventBus::new(db.clone(), &task_group);
+        let operation_log = OperationLog::new(db.clone(), self.module_inits.clone())
+            .with_event_bus(event_bus.clone());
+        operation_log.backfill_indices().await?;
+
         let executor = {
             let mut executor_builder = Executor::builder();
             executor_builder
@@ -2305,7 +2471,7 @@ impl ClientBuilder {
             }
 
             executor_builder
-                .build(db.clone(), notifier, task_group.clone())
+                .build(db.clone(), notifier, event_bus.clone(), task_group.clone())
                 .await
         };
 
@@ -2330,8 +2496,9 @@ impl ClientBuilder {
             api,
             secp_ctx: Secp256k1::new(),
             root_secret,
+            event_bus,
             task_group,
-            operation_log: OperationLog::new(db),
+            operation_log,
             client_recovery_progress_receiver,
             meta_service: self.meta_service,
         });
@@ -13,7 +13,7 @@ use fedimint_core::module::{
     ApiAuth, ApiVersion, CommonModuleInit, IDynCommonModuleInit, ModuleInit, MultiApiVersion,
 };
 use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
-use fedimint_core::{apply, async_trait_maybe_send, dyn_newtype_define, NumPeers};
+use fedimint_core::{apply, async_trait_maybe_send, dyn_newtype_define, Amount, NumPeers};
 use fedimint_derive_secret::DerivableSecret;
 use tokio::sync::watch;
 use tracing::warn;
@@ -22,6 +22,7 @@ use super::recovery::{DynModuleBackup, RecoveryProgress};
 use super::{ClientContext, FinalClient};
 use crate::db::ClientMigrationFn;
 use crate::module::{ClientModule, DynClientModule};
+use crate::oplog::{OperationLogEntry, OperationLogRecordDetails, OperationOutcomeState};
 use crate::sm::{ModuleNotifier, Notifier};
 
 pub type ClientModuleInitRegistry = ModuleInitRegistry<DynClientModuleInit>;
@@ -248,6 +249,34 @@ pub trait ClientModuleInit: ModuleInit + Sized {
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ClientMigrationFn> {
         BTreeMap::new()
     }
+
+    /// Extracts the amounts and counterparties of an operation of this module
+    /// from its meta data and outcome for exporting the operation log, see
+    /// [`crate::oplog::OperationLog::export`]. Fails if the meta data or
+    /// outcome can't be decoded, in which case the export contains them as raw
+    /// JSON instead.
+    fn operation_log_record_details(
+        &self,
+        _entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails> {
+        Ok(OperationLogRecordDetails::default())
+    }
+
+    /// Returns the amount of an operation of this module that the operation
+    /// log can be filtered by. Defaults to the amount of the operation's export
+    /// record, see [`ClientModuleInit::operation_log_record_details`].
+    fn operation_amount(&self, entry: &OperationLogEntry) -> Option<Amount> {
+        self.operation_log_record_details(entry)
+            .ok()
+            .and_then(|details| details.amount)
+    }
+
+    /// Classifies the outcome of an operation of this module, usually with
+    /// [`OperationOutcomeState::classify`]. Modules whose operations can fail
+    /// have to override this, by default every outcome counts as a success.
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        OperationOutcomeState::unclassified(entry)
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -299,6 +328,15 @@ pub trait IClientModuleInit: IDynCommonModuleInit + Debug + MaybeSend + MaybeSyn
     ) -> anyhow::Result<DynClientModule>;
 
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ClientMigrationFn>;
+
+    fn operation_log_record_details(
+        &self,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails>;
+
+    fn operation_amount(&self, entry: &OperationLogEntry) -> Option<Amount>;
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -421,6 +459,21 @@ where
     fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ClientMigrationFn> {
         <Self as ClientModuleInit>::get_database_migrations(self)
     }
+
+    fn operation_log_record_details(
+        &self,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails> {
+        <Self as ClientModuleInit>::operation_log_record_details(self, entry)
+    }
+
+    fn operation_amount(&self, entry: &OperationLogEntry) -> Option<Amount> {
+        <Self as ClientModuleInit>::operation_amount(self, entry)
+    }
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        <Self as ClientModuleInit>::operation_outcome_state(self, entry)
+    }
 }
 
 dyn_newtype_define!(
@@ -25,7 +25,7 @@ use secp256k1_zkp::PublicKey;
 
 use self::init::ClientModuleInit;
 use crate::module::recovery::{DynModuleBackup, ModuleBackup};
-use crate::sm::{self, ActiveStateMeta, Context, DynContext, DynState, State};
+use crate::sm::{self, ActiveStateMeta, Context, DynContext, DynState, InactiveStateMeta, State};
 use crate::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 use crate::{oplog, AddStateMachinesResult, Client, ClientStrong, ClientWeak, TransactionUpdates};
 
@@ -175,6 +175,60 @@ where
             .add_state_machines_dbtx(self.dbtx, states)
             .await
     }
+
+    /// Like [`ClientContext::manual_operation_start`], but as part of this
+    /// database transaction
+    pub async fn manual_operation_start(
+        &mut self,
+        operation_id: OperationId,
+        op_type: &str,
+        operation_meta: impl serde::Serialize + Debug,
+        sms: Vec<DynState>,
+    ) -> anyhow::Result<()> {
+        self.client
+            .manual_operation_start_dbtx(self.dbtx, operation_id, op_type, operation_meta, sms)
+            .await
+    }
+
+    /// Like [`ClientContext::finalize_and_submit_transaction`], but as part of
+    /// this database transaction, so the transaction is only submitted if
+    /// everything else done in it (e.g. removing spent notes) is committed
+    pub async fn finalize_and_submit_transaction<Meta>(
+        &mut self,
+        operation_id: OperationId,
+        operation_type: &str,
+        operation_meta: impl FnOnce(TransactionId, Vec<OutPoint>) -> Meta,
+        tx_builder: TransactionBuilder,
+    ) -> anyhow::Result<(TransactionId, Vec<OutPoint>)>
+    where
+        Meta: serde::Serialize,
+    {
+        let client = self.client.client.get();
+
+        if Client::operation_exists_dbtx(self.dbtx, operation_id).await {
+            bail!("There already exists an operation with id {operation_id:?}")
+        }
+
+        client
+            .enforce_spending_policy(self.dbtx, &tx_builder)
+            .await?;
+
+        let (txid, change) = client
+            .finalize_and_submit_transaction_inner(self.dbtx, operation_id, tx_builder)
+            .await?;
+
+        client
+            .operation_log()
+            .add_operation_log_entry(
+                self.dbtx,
+                operation_id,
+                operation_type,
+                operation_meta(txid, change.clone()),
+            )
+            .await;
+
+        Ok((txid, change))
+    }
 }
 
 impl<M> ClientContext<M>
@@ -361,6 +415,16 @@ where
             .await
     }
 
+    /// See [`crate::Client::can_fund_transaction`]
+    pub async fn can_fund_transaction(&self, tx_builder: TransactionBuilder) -> bool {
+        self.client.get().can_fund_transaction(tx_builder).await
+    }
+
+    /// See [`crate::Client::get_balance`]
+    pub async fn get_balance(&self) -> Amount {
+        self.client.get().get_balance().await
+    }
+
     /// See [`crate::Client::transaction_updates`]
     pub async fn transaction_updates(&self, operation_id: OperationId) -> TransactionUpdates {
         self.client.get().transaction_updates(operation_id).await
@@ -402,6 +466,12 @@ where
         self.client.get().db().clone()
     }
 
+    /// Returns the client's operation log, e.g. to cache the outcome of an
+    /// operation with [`oplog::OperationLogEntry::outcome_or_updates`]
+    pub fn operation_log(&self) -> oplog::OperationLog {
+        self.client.get().operation_log().clone()
+    }
+
     pub fn module_db(&self) -> &Database {
         &self.module_db
     }
@@ -434,6 +504,26 @@ where
             .collect()
     }
 
+    pub async fn get_own_inactive_states(&self) -> Vec<(M::States, InactiveStateMeta)> {
+        self.client
+            .get()
+            .executor
+            .get_inactive_states()
+            .await
+            .into_iter()
+            .filter(|s| s.0.module_instance_id() == self.module_instance_id)
+            .map(|s| {
+                (
+                    s.0.as_any()
+                        .downcast_ref::<M::States>()
+                        .expect("incorrect output type passed to module plugin")
+                        .clone(),
+                    s.1,
+                )
+            })
+            .collect()
+    }
+
     pub fn get_config(&self) -> ClientConfig {
         self.client.get().get_config().clone()
     }
@@ -470,27 +560,47 @@ where
         let db = self.client.get().db().clone();
         let mut dbtx = db.begin_transaction().await;
 
-        if Client::operation_exists_dbtx(&mut dbtx.to_ref_nc(), operation_id).await {
+        self.manual_operation_start_dbtx(
+            &mut dbtx.to_ref_nc(),
+            operation_id,
+            op_type,
+            operation_meta,
+            sms,
+        )
+        .await?;
+
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| anyhow!("Operation with id {operation_id} already exists"))?;
+
+        Ok(())
+    }
+
+    async fn manual_operation_start_dbtx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        op_type: &str,
+        operation_meta: impl serde::Serialize + Debug,
+        sms: Vec<DynState>,
+    ) -> anyhow::Result<()> {
+        if Client::operation_exists_dbtx(dbtx, operation_id).await {
             bail!("Operation with id {operation_id} already exists");
         }
 
         self.client
             .get()
             .operation_log
-            .add_operation_log_entry(&mut dbtx.to_ref_nc(), operation_id, op_type, operation_meta)
+            .add_operation_log_entry(dbtx, operation_id, op_type, operation_meta)
             .await;
 
         self.client
             .get()
             .executor
-            .add_state_machines_dbtx(&mut dbtx.to_ref_nc(), sms)
+            .add_state_machines_dbtx(dbtx, sms)
             .await
             .expect("State machine is valid");
 
-        dbtx.commit_tx_result()
-            .await
-            .map_err(|_| anyhow!("Operation with id {operation_id} already exists"))?;
-
         Ok(())
     }
 }
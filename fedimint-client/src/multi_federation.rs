@@ -0,0 +1,243 @@
+//! Clients of several federations sharing a single database
+//!
+//! Every joined federation gets its own [`Client`] whose database is isolated
+//! in a partition of the shared database derived from the [`FederationId`].
+//! The root secret of each client is derived from a common root secret using
+//! [`get_default_client_secret`], so one secret backs all federations.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context};
+use fedimint_core::config::{FederationId, FederationIdPrefix};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::invite_code::InviteCode;
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use fedimint_derive_secret::DerivableSecret;
+use futures::StreamExt;
+use serde::Serialize;
+use strum_macros::EnumIter;
+use tracing::info;
+
+use crate::db::{ChronologicalOperationLogKey, InitMode};
+use crate::module::init::ClientModuleInitRegistry;
+use crate::oplog::OperationLogEntry;
+use crate::secret::get_default_client_secret;
+use crate::{Client, ClientBuilder, ClientHandleArc};
+
+/// Key prefixes of the shared database, the client databases are nested in
+/// the [`DbKeyPrefix::ClientDatabase`] partition
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Federation = 0x00,
+    ClientDatabase = 0x01,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Marks a federation as joined
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct FederationKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationKeyPrefix;
+
+impl_db_record!(
+    key = FederationKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Federation,
+);
+impl_db_lookup!(key = FederationKey, query_prefix = FederationKeyPrefix);
+
+/// Holds one [`Client`] per joined federation, all of them stored in the same
+/// database
+pub struct MultiFederationClient {
+    db: Database,
+    module_inits: ClientModuleInitRegistry,
+    primary_module: ModuleInstanceId,
+    root_secret: DerivableSecret,
+    clients: BTreeMap<FederationId, ClientHandleArc>,
+}
+
+impl MultiFederationClient {
+    /// Opens the clients of all federations previously joined using `db`
+    pub async fn open(
+        db: Database,
+        module_inits: ClientModuleInitRegistry,
+        primary_module: ModuleInstanceId,
+        root_secret: DerivableSecret,
+    ) -> anyhow::Result<Self> {
+        let federation_ids = db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&FederationKeyPrefix)
+            .await
+            .map(|(key, ())| key.id)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut multi_client = Self {
+            db,
+            module_inits,
+            primary_module,
+            root_secret,
+            clients: BTreeMap::new(),
+        };
+
+        for federation_id in federation_ids {
+            let client = multi_client
+                .client_builder(federation_id)
+                .open(get_default_client_secret(
+                    &multi_client.root_secret,
+                    &federation_id,
+                ))
+                .await
+                .with_context(|| format!("Failed to open client of federation {federation_id}"))?;
+            multi_client.clients.insert(federation_id, Arc::new(client));
+        }
+
+        Ok(multi_client)
+    }
+
+    /// Joins the federation of `invite_code` and returns its client
+    pub async fn join(&mut self, invite_code: &InviteCode) -> anyhow::Result<ClientHandleArc> {
+        let federation_id = invite_code.federation_id();
+        ensure!(
+            !self.clients.contains_key(&federation_id),
+            "Already joined federation {federation_id}"
+        );
+
+        let client_config = fedimint_api_client::download_from_invite_code(invite_code).await?;
+
+        // The client database is initialized in the same transaction that marks the
+        // federation as joined, so a crash can't leave one without the other
+        let mut dbtx = self.db.begin_transaction().await;
+        ensure!(
+            dbtx.get_value(&FederationKey { id: federation_id })
+                .await
+                .is_none(),
+            "Already joined federation {federation_id}"
+        );
+        ClientBuilder::init_dbtx(
+            &mut dbtx
+                .to_ref_nc()
+                .with_prefix(Self::client_db_prefix(federation_id)),
+            &client_config,
+            InitMode::Fresh,
+        )
+        .await;
+        dbtx.insert_entry(&FederationKey { id: federation_id }, &())
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        let client = Arc::new(
+            self.client_builder(federation_id)
+                .open(get_default_client_secret(&self.root_secret, &federation_id))
+                .await?,
+        );
+
+        info!(%federation_id, "Joined federation");
+        self.clients.insert(federation_id, client.clone());
+        Ok(client)
+    }
+
+    /// Returns the ids of all joined federations
+    pub fn federation_ids(&self) -> impl Iterator<Item = FederationId> + '_ {
+        self.clients.keys().copied()
+    }
+
+    /// Returns the client of a joined federation
+    pub fn get(&self, federation_id: &FederationId) -> Option<ClientHandleArc> {
+        self.clients.get(federation_id).cloned()
+    }
+
+    /// Returns the client of the joined federation whose id starts with
+    /// `prefix`, e.g. the one e-cash notes belong to
+    pub fn get_by_prefix(&self, prefix: &FederationIdPrefix) -> anyhow::Result<ClientHandleArc> {
+        let mut matching = self
+            .clients
+            .iter()
+            .filter(|(federation_id, _)| federation_id.to_prefix() == *prefix);
+
+        let Some((_, client)) = matching.next() else {
+            bail!("No joined federation with id prefix {prefix}");
+        };
+        if matching.next().is_some() {
+            bail!("Multiple joined federations with id prefix {prefix}");
+        }
+
+        Ok(client.clone())
+    }
+
+    /// Returns the balance held with each joined federation
+    pub async fn balances(&self) -> BTreeMap<FederationId, Amount> {
+        let mut balances = BTreeMap::new();
+        for (federation_id, client) in &self.clients {
+            balances.insert(*federation_id, client.get_balance().await);
+        }
+        balances
+    }
+
+    /// Returns the balance held with all joined federations together
+    pub async fn total_balance(&self) -> Amount {
+        self.balances().await.into_values().sum()
+    }
+
+    /// Returns the last `limit` operations of all joined federations, newest
+    /// first. To fetch the next page, pass the last operation's
+    /// [`ChronologicalOperationLogKey`] as `start_after`.
+    pub async fn list_operations(
+        &self,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<(
+        FederationId,
+        ChronologicalOperationLogKey,
+        OperationLogEntry,
+    )> {
+        let mut operations = vec![];
+        for (federation_id, client) in &self.clients {
+            operations.extend(
+                client
+                    .operation_log()
+                    .list_operations(limit, start_after)
+                    .await
+                    .into_iter()
+                    .map(|(key, entry)| (*federation_id, key, entry)),
+            );
+        }
+
+        operations.sort_by(|(_, a, _), (_, b, _)| {
+            b.creation_time
+                .cmp(&a.creation_time)
+                .then(b.operation_id.cmp(&a.operation_id))
+        });
+        operations.truncate(limit);
+        operations
+    }
+
+    /// Prefix of the partition of the shared database holding the client
+    /// database of a federation
+    fn client_db_prefix(federation_id: FederationId) -> Vec<u8> {
+        let mut prefix = vec![DbKeyPrefix::ClientDatabase as u8];
+        prefix.append(&mut federation_id.consensus_encode_to_vec());
+        prefix
+    }
+
+    fn client_builder(&self, federation_id: FederationId) -> ClientBuilder {
+        let mut client_builder =
+            Client::builder(self.db.with_prefix(Self::client_db_prefix(federation_id)));
+        client_builder.with_module_inits(self.module_inits.clone());
+        client_builder.with_primary_module(self.primary_module);
+        client_builder
+    }
+}
@@ -1,32 +1,75 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::future;
 use std::io::{Read, Write};
+use std::str::FromStr;
+use std::time::SystemTime;
 
+use anyhow::bail;
 use async_stream::stream;
-use fedimint_core::core::OperationId;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::{ModuleKind, OperationId};
 use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::time::now;
 use fedimint_core::util::BoxStream;
+use fedimint_core::Amount;
+use fedimint_logging::LOG_CLIENT_DB;
 use futures::{stream, Stream, StreamExt};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument, warn};
 
 use crate::db::{
-    ChronologicalOperationLogKey, ChronologicalOperationLogKeyPrefix, OperationLogKey,
+    ChronologicalOperationLogKey, ChronologicalOperationLogKeyPrefix, OperationLogAttributesKey,
+    OperationLogIndexBackfillKey, OperationLogKey, OperationLogModuleKindIndexKey,
+    OperationLogModuleKindIndexPrefix, OperationLogOutcomeIndexKey, OperationLogOutcomeIndexPrefix,
+    OperationLogTypeIndexKey, OperationLogTypeIndexPrefix,
 };
+use crate::events::{ClientEvent, ClientEventBus};
+use crate::module::init::ClientModuleInitRegistry;
+
+mod export;
+
+pub use self::export::{
+    write_records, OperationDirection, OperationLogExportFormat, OperationLogRecord,
+    OperationLogRecordDetails, OperationLogRecordWriter,
+};
+
+/// Number of operations loaded from the database at once when exporting the
+/// operation log, see [`OperationLog::export`]
+pub const EXPORT_PAGE_SIZE: usize = 100;
 
 #[derive(Debug, Clone)]
 pub struct OperationLog {
     db: Database,
+    /// Used to classify the amount and outcome of operations by the modules
+    /// that created them
+    module_inits: ClientModuleInitRegistry,
+    /// Events about new operations and their outcomes are logged to, if set
+    event_bus: Option<ClientEventBus>,
 }
 
 impl OperationLog {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, module_inits: ClientModuleInitRegistry) -> Self {
+        Self {
+            db,
+            module_inits,
+            event_bus: None,
+        }
+    }
+
+    /// Logs events about new operations and their outcomes to `event_bus`
+    pub(crate) fn with_event_bus(mut self, event_bus: ClientEventBus) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    async fn log_event(&self, dbtx: &mut DatabaseTransaction<'_>, event: ClientEvent) {
+        if let Some(event_bus) = &self.event_bus {
+            event_bus.log_event(dbtx, event).await;
+        }
     }
 
     pub async fn add_operation_log_entry(
@@ -36,19 +79,147 @@ impl OperationLog {
         operation_type: &str,
         operation_meta: impl serde::Serialize,
     ) {
+        let creation_time = now();
+        let entry = OperationLogEntry {
+            operation_module_kind: operation_type.to_string(),
+            meta: serde_json::to_value(operation_meta)
+                .expect("Can only fail if meta is not serializable"),
+            outcome: None,
+        };
+        let attributes = self.attributes(creation_time, &entry);
+
+        dbtx.insert_new_entry(&OperationLogKey { operation_id }, &entry)
+            .await;
         dbtx.insert_new_entry(
-            &OperationLogKey { operation_id },
-            &OperationLogEntry {
-                operation_module_kind: operation_type.to_string(),
-                meta: serde_json::to_value(operation_meta)
-                    .expect("Can only fail if meta is not serializable"),
-                outcome: None,
+            &ChronologicalOperationLogKey {
+                creation_time,
+                operation_id,
             },
+            &(),
         )
         .await;
-        dbtx.insert_new_entry(
-            &ChronologicalOperationLogKey {
-                creation_time: now(),
+        Self::insert_attributes(dbtx, operation_id, &attributes).await;
+        self.log_event(
+            dbtx,
+            ClientEvent::OperationCreated {
+                operation_id,
+                operation_module_kind: entry.operation_module_kind,
+                meta: entry.meta,
+            },
+        )
+        .await;
+    }
+
+    /// Derives the attributes the operation log is indexed by from `entry`.
+    /// The amount and outcome state are classified by the module that created
+    /// the operation, see [`crate::module::init::ClientModuleInit::operation_amount`] and
+    /// [`crate::module::init::ClientModuleInit::operation_outcome_state`].
+    fn attributes(
+        &self,
+        creation_time: SystemTime,
+        entry: &OperationLogEntry,
+    ) -> OperationLogAttributes {
+        let module_init = self
+            .module_inits
+            .get(&ModuleKind::clone_from_str(&entry.operation_module_kind));
+
+        OperationLogAttributes {
+            creation_time,
+            operation_module_kind: entry.operation_module_kind.clone(),
+            operation_type: entry
+                .meta
+                .get("variant")
+                .map_or_else(|| variant_name(&entry.meta), variant_name)
+                .map(ToOwned::to_owned),
+            amount: module_init.and_then(|init| init.operation_amount(entry)),
+            outcome_state: module_init.map_or_else(
+                || OperationOutcomeState::unclassified(entry),
+                |init| init.operation_outcome_state(entry),
+            ),
+        }
+    }
+
+    /// Creates the secondary operation log indices of operations that were
+    /// created before the indices existed, once the database migration
+    /// scheduled it. Has to run after the database migrations since only the
+    /// modules can classify their operations.
+    pub(crate) async fn backfill_indices(&self) -> anyhow::Result<()> {
+        let mut dbtx = self.db.begin_transaction().await;
+        if dbtx
+            .get_value(&OperationLogIndexBackfillKey)
+            .await
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let operations = dbtx
+            .find_by_prefix(&ChronologicalOperationLogKeyPrefix)
+            .await
+            .map(|(key, ())| key)
+            .collect::<Vec<_>>()
+            .await;
+
+        for ChronologicalOperationLogKey {
+            creation_time,
+            operation_id,
+        } in operations
+        {
+            if dbtx
+                .get_value(&OperationLogAttributesKey { operation_id })
+                .await
+                .is_some()
+            {
+                continue;
+            }
+
+            let Some(entry) = dbtx.get_value(&OperationLogKey { operation_id }).await else {
+                warn!(target: LOG_CLIENT_DB, %operation_id, "Operation log entry missing, not indexing it");
+                continue;
+            };
+
+            let attributes = self.attributes(creation_time, &entry);
+            Self::insert_attributes(&mut dbtx.to_ref_nc(), operation_id, &attributes).await;
+        }
+
+        dbtx.remove_entry(&OperationLogIndexBackfillKey).await;
+        dbtx.commit_tx_result().await
+    }
+
+    /// Stores the attributes of an operation and adds it to the secondary
+    /// indices built from them
+    pub(crate) async fn insert_attributes(
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        attributes: &OperationLogAttributes,
+    ) {
+        dbtx.insert_entry(&OperationLogAttributesKey { operation_id }, attributes)
+            .await;
+        dbtx.insert_entry(
+            &OperationLogModuleKindIndexKey {
+                operation_module_kind: attributes.operation_module_kind.clone(),
+                creation_time: attributes.creation_time,
+                operation_id,
+            },
+            &(),
+        )
+        .await;
+        if let Some(operation_type) = &attributes.operation_type {
+            dbtx.insert_entry(
+                &OperationLogTypeIndexKey {
+                    operation_module_kind: attributes.operation_module_kind.clone(),
+                    operation_type: operation_type.clone(),
+                    creation_time: attributes.creation_time,
+                    operation_id,
+                },
+                &(),
+            )
+            .await;
+        }
+        dbtx.insert_entry(
+            &OperationLogOutcomeIndexKey {
+                outcome_state: attributes.outcome_state,
+                creation_time: attributes.creation_time,
                 operation_id,
             },
             &(),
@@ -104,6 +275,224 @@ impl OperationLog {
         operation_entries
     }
 
+    /// Returns the last `limit` operations matching `filter`, newest first. To
+    /// fetch the next page, pass the last operation's
+    /// [`ChronologicalOperationLogKey`] as `start_after`.
+    ///
+    /// Depending on the filter the operations are looked up using the module
+    /// kind, operation type or outcome state index, the remaining conditions
+    /// are checked for each operation found that way.
+    pub async fn list_operations_filtered(
+        &self,
+        filter: &OperationLogFilter,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+        let mut index_dbtx = self.db.begin_transaction_nc().await;
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        let candidates: BoxStream<'_, ChronologicalOperationLogKey> =
+            match (&filter.operation_module_kind, &filter.operation_type) {
+                (Some(operation_module_kind), Some(operation_type)) => Box::pin(
+                    index_dbtx
+                        .find_by_prefix_sorted_descending(&OperationLogTypeIndexPrefix {
+                            operation_module_kind: operation_module_kind.clone(),
+                            operation_type: operation_type.clone(),
+                        })
+                        .await
+                        .map(|(key, ())| ChronologicalOperationLogKey {
+                            creation_time: key.creation_time,
+                            operation_id: key.operation_id,
+                        }),
+                ),
+                (Some(operation_module_kind), None) => Box::pin(
+                    index_dbtx
+                        .find_by_prefix_sorted_descending(&OperationLogModuleKindIndexPrefix {
+                            operation_module_kind: operation_module_kind.clone(),
+                        })
+                        .await
+                        .map(|(key, ())| ChronologicalOperationLogKey {
+                            creation_time: key.creation_time,
+                            operation_id: key.operation_id,
+                        }),
+                ),
+                (None, _) => match filter.outcome_state {
+                    Some(outcome_state) => Box::pin(
+                        index_dbtx
+                            .find_by_prefix_sorted_descending(&OperationLogOutcomeIndexPrefix {
+                                outcome_state,
+                            })
+                            .await
+                            .map(|(key, ())| ChronologicalOperationLogKey {
+                                creation_time: key.creation_time,
+                                operation_id: key.operation_id,
+                            }),
+                    ),
+                    None => Box::pin(
+                        index_dbtx
+                            .find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
+                            .await
+                            .map(|(key, ())| key),
+                    ),
+                },
+            };
+
+        let mut candidates = candidates
+            .skip_while(move |key| {
+                let too_new = filter.until.is_some_and(|until| until <= key.creation_time)
+                    || start_after.is_some_and(|start_after| {
+                        (start_after.creation_time, start_after.operation_id)
+                            <= (key.creation_time, key.operation_id)
+                    });
+                future::ready(too_new)
+            })
+            .take_while(move |key| {
+                future::ready(filter.since.is_none_or(|since| since <= key.creation_time))
+            });
+
+        let mut operations = vec![];
+        while operations.len() < limit {
+            let Some(key) = candidates.next().await else {
+                break;
+            };
+
+            let attributes = dbtx
+                .get_value(&OperationLogAttributesKey {
+                    operation_id: key.operation_id,
+                })
+                .await
+                .expect("Inconsistent DB");
+            if !filter.matches_attributes(&attributes) {
+                continue;
+            }
+
+            let entry = dbtx
+                .get_value(&OperationLogKey {
+                    operation_id: key.operation_id,
+                })
+                .await
+                .expect("Inconsistent DB");
+            if !filter.matches_entry(&entry) {
+                continue;
+            }
+
+            operations.push((key, entry));
+        }
+
+        operations
+    }
+
+    /// Returns up to `limit` operations matching `filter` that were created
+    /// before `start_after` as normalized ledger records, newest first, to be
+    /// paginated like [`OperationLog::list_operations_filtered`]. The module
+    /// specific details are extracted by the module inits in `module_inits`,
+    /// operations of modules missing from it only get the generic fields.
+    pub async fn export_records(
+        &self,
+        federation_id: FederationId,
+        module_inits: &ClientModuleInitRegistry,
+        filter: &OperationLogFilter,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> anyhow::Result<Vec<(ChronologicalOperationLogKey, OperationLogRecord)>> {
+        self.list_operations_filtered(filter, limit, start_after)
+            .await
+            .into_iter()
+            .map(|(key, entry)| {
+                let record = self.export_record(federation_id, module_inits, key, &entry)?;
+                Ok((key, record))
+            })
+            .collect()
+    }
+
+    fn export_record(
+        &self,
+        federation_id: FederationId,
+        module_inits: &ClientModuleInitRegistry,
+        key: ChronologicalOperationLogKey,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecord> {
+        let attributes = self.attributes(key.creation_time, entry);
+        let details = module_inits
+            .get(&ModuleKind::clone_from_str(
+                &attributes.operation_module_kind,
+            ))
+            .map(|init| init.operation_log_record_details(entry))
+            .unwrap_or_else(|| Ok(OperationLogRecordDetails::default()));
+
+        let (details, raw_data) = match details {
+            Ok(details) => (details, None),
+            Err(e) => {
+                warn!(
+                    target: LOG_CLIENT_DB,
+                    operation_id = %key.operation_id,
+                    "Can't extract the details of an operation, exporting its raw data: {e}"
+                );
+                let raw_data = serde_json::json!({
+                    "meta": entry.meta,
+                    "outcome": entry.outcome,
+                });
+                (
+                    OperationLogRecordDetails::default(),
+                    Some(raw_data.to_string()),
+                )
+            }
+        };
+
+        OperationLogRecord::new(
+            key.operation_id,
+            key.creation_time,
+            federation_id,
+            attributes.operation_module_kind,
+            attributes.operation_type,
+            attributes.outcome_state,
+            details,
+            raw_data,
+        )
+    }
+
+    /// Writes all operations matching `filter` to `writer` as normalized
+    /// ledger records, newest first, see [`OperationLog::export_records`]. The
+    /// operations are loaded and written in pages of [`EXPORT_PAGE_SIZE`].
+    /// Returns the number of exported operations.
+    pub async fn export(
+        &self,
+        federation_id: FederationId,
+        module_inits: &ClientModuleInitRegistry,
+        filter: &OperationLogFilter,
+        format: OperationLogExportFormat,
+        writer: impl std::io::Write,
+    ) -> anyhow::Result<usize> {
+        let mut writer = OperationLogRecordWriter::new(format, writer);
+        let mut exported = 0;
+        let mut start_after = None;
+
+        loop {
+            let records = self
+                .export_records(
+                    federation_id,
+                    module_inits,
+                    filter,
+                    EXPORT_PAGE_SIZE,
+                    start_after,
+                )
+                .await?;
+
+            for (_, record) in &records {
+                writer.write(record)?;
+            }
+            exported += records.len();
+
+            match records.last() {
+                Some((key, _)) if records.len() == EXPORT_PAGE_SIZE => start_after = Some(*key),
+                _ => break,
+            }
+        }
+
+        writer.flush()?;
+        Ok(exported)
+    }
+
     pub async fn get_operation(&self, operation_id: OperationId) -> Option<OperationLogEntry> {
         Self::get_operation_inner(
             &mut self.db.begin_transaction().await.into_nc(),
@@ -120,21 +509,51 @@ impl OperationLog {
     }
 
     /// Sets the outcome of an operation
-    #[instrument(skip(db), level = "debug")]
+    #[instrument(skip(self), level = "debug")]
     pub async fn set_operation_outcome(
-        db: &Database,
+        &self,
         operation_id: OperationId,
         outcome: &(impl Serialize + Debug),
     ) -> anyhow::Result<()> {
         let outcome_json = serde_json::to_value(outcome).expect("Outcome is not serializable");
 
-        let mut dbtx = db.begin_transaction().await;
+        let mut dbtx = self.db.begin_transaction().await;
         let mut operation = Self::get_operation_inner(&mut dbtx.to_ref_nc(), operation_id)
             .await
             .expect("Operation exists");
         operation.outcome = Some(outcome_json);
         dbtx.insert_entry(&OperationLogKey { operation_id }, &operation)
             .await;
+
+        // The outcome can change the outcome state and the amount reported by the
+        // module, so the attributes have to be derived again
+        let attributes = match dbtx
+            .get_value(&OperationLogAttributesKey { operation_id })
+            .await
+        {
+            Some(attributes) => {
+                dbtx.remove_entry(&OperationLogOutcomeIndexKey {
+                    outcome_state: attributes.outcome_state,
+                    creation_time: attributes.creation_time,
+                    operation_id,
+                })
+                .await;
+                let attributes = self.attributes(attributes.creation_time, &operation);
+                Self::insert_attributes(&mut dbtx.to_ref_nc(), operation_id, &attributes).await;
+                attributes
+            }
+            // Not indexed yet, the backfill will index the operation
+            None => self.attributes(now(), &operation),
+        };
+        self.log_event(
+            &mut dbtx.to_ref_nc(),
+            ClientEvent::OperationOutcome {
+                operation_id,
+                outcome_state: attributes.outcome_state,
+                outcome: operation.outcome.expect("Outcome was just set"),
+            },
+        )
+        .await;
         dbtx.commit_tx_result().await?;
 
         Ok(())
@@ -145,16 +564,170 @@ impl OperationLog {
     /// from an update stream, failing to save it isn't a problem in cases where
     /// we do this merely for caching.
     pub async fn optimistically_set_operation_outcome(
-        db: &Database,
+        &self,
         operation_id: OperationId,
         outcome: &(impl Serialize + Debug),
     ) {
-        if let Err(e) = Self::set_operation_outcome(db, operation_id, outcome).await {
+        if let Err(e) = self.set_operation_outcome(operation_id, outcome).await {
             warn!("Error setting operation outcome: {e}");
         }
     }
 }
 
+/// Conditions operations have to fulfill to be returned by
+/// [`OperationLog::list_operations_filtered`], unset conditions match any
+/// operation
+#[derive(Debug, Clone, Default)]
+pub struct OperationLogFilter {
+    /// Kind of the module that created the operation
+    pub operation_module_kind: Option<String>,
+    /// Type of the operation, see [`OperationLogAttributes::operation_type`]
+    pub operation_type: Option<String>,
+    pub outcome_state: Option<OperationOutcomeState>,
+    /// Only operations created at or after this time
+    pub since: Option<SystemTime>,
+    /// Only operations created before this time
+    pub until: Option<SystemTime>,
+    /// Only operations of at least this amount, see
+    /// [`OperationLogAttributes::amount`]
+    pub min_amount: Option<Amount>,
+    /// Only operations of at most this amount, see
+    /// [`OperationLogAttributes::amount`]
+    pub max_amount: Option<Amount>,
+    /// Text the JSON of the operation's meta data or outcome has to contain,
+    /// ignoring case
+    pub text: Option<String>,
+}
+
+impl OperationLogFilter {
+    fn matches_attributes(&self, attributes: &OperationLogAttributes) -> bool {
+        let amount_in_range = match (self.min_amount, self.max_amount) {
+            (None, None) => true,
+            (min_amount, max_amount) => attributes.amount.is_some_and(|amount| {
+                min_amount.is_none_or(|min_amount| min_amount <= amount)
+                    && max_amount.is_none_or(|max_amount| amount <= max_amount)
+            }),
+        };
+
+        amount_in_range
+            && self
+                .operation_module_kind
+                .as_ref()
+                .is_none_or(|kind| *kind == attributes.operation_module_kind)
+            && self.operation_type.as_ref().is_none_or(|operation_type| {
+                attributes.operation_type.as_ref() == Some(operation_type)
+            })
+            && self
+                .outcome_state
+                .is_none_or(|outcome_state| outcome_state == attributes.outcome_state)
+    }
+
+    fn matches_entry(&self, entry: &OperationLogEntry) -> bool {
+        let Some(text) = &self.text else {
+            return true;
+        };
+        let text = text.to_lowercase();
+
+        entry.meta.to_string().to_lowercase().contains(&text)
+            || entry
+                .outcome
+                .as_ref()
+                .is_some_and(|outcome| outcome.to_string().to_lowercase().contains(&text))
+    }
+}
+
+/// Coarse state of an operation derived from its cached outcome
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationOutcomeState {
+    /// No outcome has been cached for the operation yet, it may still be in
+    /// progress or just never have been watched until it finished
+    Pending,
+    Success,
+    Failure,
+}
+
+impl OperationOutcomeState {
+    /// Classifies the cached outcome of `entry`, decoded as the module's
+    /// outcome type `O`, using `classify`. Operations without an outcome are
+    /// pending, as are those whose outcome can't be decoded as `O`.
+    pub fn classify<O: DeserializeOwned>(
+        entry: &OperationLogEntry,
+        classify: impl FnOnce(O) -> Self,
+    ) -> Self {
+        match entry.try_outcome::<O>() {
+            None => OperationOutcomeState::Pending,
+            Some(Ok(outcome)) => classify(outcome),
+            Some(Err(e)) => {
+                warn!(
+                    operation_module_kind = %entry.operation_module_kind,
+                    "Can't decode operation outcome, considering it pending: {e}"
+                );
+                OperationOutcomeState::Pending
+            }
+        }
+    }
+
+    /// Outcome state of an operation that its module can't classify: pending
+    /// until it has an outcome, a success afterwards
+    pub fn unclassified(entry: &OperationLogEntry) -> Self {
+        if entry.outcome.is_some() {
+            OperationOutcomeState::Success
+        } else {
+            OperationOutcomeState::Pending
+        }
+    }
+}
+
+impl fmt::Display for OperationOutcomeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationOutcomeState::Pending => f.write_str("pending"),
+            OperationOutcomeState::Success => f.write_str("success"),
+            OperationOutcomeState::Failure => f.write_str("failure"),
+        }
+    }
+}
+
+impl FromStr for OperationOutcomeState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pending" => Ok(OperationOutcomeState::Pending),
+            "success" => Ok(OperationOutcomeState::Success),
+            "failure" => Ok(OperationOutcomeState::Failure),
+            _ => bail!("Unknown outcome state {s}, expected pending, success or failure"),
+        }
+    }
+}
+
+/// Attributes of an operation log entry the operation log is indexed by,
+/// derived from the entry's JSON meta data and outcome
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize)]
+pub struct OperationLogAttributes {
+    pub creation_time: SystemTime,
+    pub operation_module_kind: String,
+    /// Name of the `variant` of the operation meta, e.g. `reissuance` for the
+    /// mint module, or of the meta itself if it is an enum
+    pub operation_type: Option<String>,
+    /// Amount of the operation as reported by its module, see
+    /// [`crate::module::init::ClientModuleInit::operation_amount`]
+    pub amount: Option<Amount>,
+    pub outcome_state: OperationOutcomeState,
+}
+
+/// Returns the name of the variant if `value` is a serialized enum
+fn variant_name(value: &serde_json::Value) -> Option<&str> {
+    match value {
+        serde_json::Value::String(name) => Some(name),
+        serde_json::Value::Object(fields) if fields.len() == 1 => {
+            fields.keys().next().map(String::as_str)
+        }
+        _ => None,
+    }
+}
+
 /// Represents an operation triggered by a user, typically related to sending or
 /// receiving money.
 ///
@@ -197,6 +770,13 @@ impl OperationLogEntry {
         serde_json::from_value(self.meta.clone()).expect("JSON deserialization should not fail")
     }
 
+    /// Returns the meta data of the operation like [`OperationLogEntry::meta`]
+    /// but fails instead of panicking if it can't be deserialized into `M`,
+    /// e.g. because the operation was created by an older client version
+    pub fn try_meta<M: DeserializeOwned>(&self) -> serde_json::Result<M> {
+        serde_json::from_value(self.meta.clone())
+    }
+
     /// Returns the last state update of the operation, if any was cached yet.
     /// If this hasn't been the case yet and `None` is returned subscribe to the
     /// appropriate update stream.
@@ -220,13 +800,22 @@ impl OperationLogEntry {
         })
     }
 
+    /// Returns the cached outcome of the operation like
+    /// [`OperationLogEntry::outcome`] but fails instead of panicking if it
+    /// can't be deserialized into `D`
+    pub fn try_outcome<D: DeserializeOwned>(&self) -> Option<serde_json::Result<D>> {
+        self.outcome
+            .as_ref()
+            .map(|outcome| serde_json::from_value(outcome.clone()))
+    }
+
     /// Returns an a [`UpdateStreamOrOutcome`] enum that can be converted into
     /// an update stream for easier handling using
     /// [`UpdateStreamOrOutcome::into_stream`] but can also be matched over to
     /// shortcut the handling of final outcomes.
     pub fn outcome_or_updates<U, S>(
         &self,
-        db: &Database,
+        operation_log: &OperationLog,
         operation_id: OperationId,
         stream_gen: impl FnOnce() -> S,
     ) -> UpdateStreamOrOutcome<U>
@@ -237,7 +826,7 @@ impl OperationLogEntry {
         match self.outcome::<U>() {
             Some(outcome) => UpdateStreamOrOutcome::Outcome(outcome),
             None => UpdateStreamOrOutcome::UpdateStream(caching_operation_update_stream(
-                db.clone(),
+                operation_log.clone(),
                 operation_id,
                 stream_gen(),
             )),
@@ -314,7 +903,7 @@ where
 /// Wraps an operation update stream such that the last update before it closes
 /// is tried to be written to the operation log entry as its outcome.
 pub fn caching_operation_update_stream<'a, U, S>(
-    db: Database,
+    operation_log: OperationLog,
     operation_id: OperationId,
     stream: S,
 ) -> BoxStream<'a, U>
@@ -335,21 +924,34 @@ where
             return;
         };
 
-        OperationLog::optimistically_set_operation_outcome(&db, operation_id, &last_update).await;
+        operation_log
+            .optimistically_set_operation_outcome(operation_id, &last_update)
+            .await;
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use fedimint_core::config::FederationId;
     use fedimint_core::core::OperationId;
     use fedimint_core::db::mem_impl::MemDatabase;
-    use fedimint_core::db::{Database, IRawDatabaseExt};
+    use fedimint_core::db::{
+        apply_migrations, Database, IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt,
+    };
+    use fedimint_core::Amount;
     use futures::stream::StreamExt;
     use serde::{Deserialize, Serialize};
+    use serde_json::json;
 
     use super::UpdateStreamOrOutcome;
-    use crate::db::ChronologicalOperationLogKey;
-    use crate::oplog::{OperationLog, OperationLogEntry};
+    use crate::db::{
+        get_core_client_database_migrations, ChronologicalOperationLogKey,
+        OperationLogAttributesKey, CORE_CLIENT_DATABASE_VERSION,
+    };
+    use crate::oplog::{
+        OperationLog, OperationLogEntry, OperationLogExportFormat, OperationLogFilter,
+        OperationOutcomeState, EXPORT_PAGE_SIZE,
+    };
 
     #[test]
     fn test_operation_log_entry_serde() {
@@ -389,7 +991,7 @@ mod tests {
         let op_id = OperationId([0x32; 32]);
 
         let db = Database::new(MemDatabase::new(), Default::default());
-        let op_log = OperationLog::new(db.clone());
+        let op_log = OperationLog::new(db.clone(), Default::default());
 
         let mut dbtx = db.begin_transaction().await;
         op_log
@@ -400,15 +1002,13 @@ mod tests {
         let op = op_log.get_operation(op_id).await.expect("op exists");
         assert_eq!(op.outcome, None);
 
-        OperationLog::set_operation_outcome(&db, op_id, &"baz")
-            .await
-            .unwrap();
+        op_log.set_operation_outcome(op_id, &"baz").await.unwrap();
 
         let op = op_log.get_operation(op_id).await.expect("op exists");
         assert_eq!(op.outcome::<String>(), Some("baz".to_string()));
 
         let update_stream_or_outcome =
-            op.outcome_or_updates::<String, _>(&db, op_id, futures::stream::empty);
+            op.outcome_or_updates::<String, _>(&op_log, op_id, futures::stream::empty);
 
         assert!(matches!(
             &update_stream_or_outcome,
@@ -427,7 +1027,7 @@ mod tests {
         let op_id = OperationId([0x32; 32]);
 
         let db = MemDatabase::new().into_database();
-        let op_log = OperationLog::new(db.clone());
+        let op_log = OperationLog::new(db.clone(), Default::default());
 
         let mut dbtx = db.begin_transaction().await;
         op_log
@@ -438,8 +1038,9 @@ mod tests {
         let op = op_log.get_operation(op_id).await.expect("op exists");
 
         let updates = vec!["bar".to_owned(), "bob".to_owned(), "baz".to_owned()];
-        let update_stream = op
-            .outcome_or_updates::<String, _>(&db, op_id, || futures::stream::iter(updates.clone()));
+        let update_stream = op.outcome_or_updates::<String, _>(&op_log, op_id, || {
+            futures::stream::iter(updates.clone())
+        });
 
         let received_updates = update_stream.into_stream().collect::<Vec<_>>().await;
         assert_eq!(received_updates, updates);
@@ -451,7 +1052,7 @@ mod tests {
     #[tokio::test]
     async fn test_pagination() {
         let db = Database::new(MemDatabase::new(), Default::default());
-        let op_log = OperationLog::new(db.clone());
+        let op_log = OperationLog::new(db.clone(), Default::default());
 
         for operation_idx in 0u8..98 {
             let mut dbtx = db.begin_transaction().await;
@@ -490,4 +1091,262 @@ mod tests {
         assert_eq!(page.len(), 8);
         assert_page_entries(page, 9);
     }
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum TestVariant {
+        Send { fee: u64 },
+        Receive,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct TestMeta {
+        variant: TestVariant,
+        amount: Amount,
+    }
+
+    async fn add_test_operations(db: &Database, op_log: &OperationLog) {
+        for operation_idx in 0u8..30 {
+            let (kind, variant) = match operation_idx % 3 {
+                0 => ("ln", TestVariant::Send { fee: 1 }),
+                1 => ("ln", TestVariant::Receive),
+                _ => ("mint", TestVariant::Receive),
+            };
+
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx.to_ref_nc(),
+                    OperationId([operation_idx; 32]),
+                    kind,
+                    TestMeta {
+                        variant,
+                        amount: Amount::from_sats(u64::from(operation_idx) * 1000),
+                    },
+                )
+                .await;
+            dbtx.commit_tx().await;
+        }
+    }
+
+    fn operation_indices(page: &[(ChronologicalOperationLogKey, OperationLogEntry)]) -> Vec<u8> {
+        page.iter().map(|(key, _)| key.operation_id.0[0]).collect()
+    }
+
+    #[test]
+    fn test_outcome_state() {
+        #[derive(Debug, Deserialize)]
+        enum TestState {
+            Success,
+            Refunded,
+            Waiting,
+        }
+
+        let entry = |outcome: Option<serde_json::Value>| OperationLogEntry {
+            operation_module_kind: "test".to_string(),
+            meta: json!(null),
+            outcome,
+        };
+        let classify = |entry: &OperationLogEntry| {
+            OperationOutcomeState::classify(entry, |state| match state {
+                TestState::Success => OperationOutcomeState::Success,
+                TestState::Refunded => OperationOutcomeState::Failure,
+                TestState::Waiting => OperationOutcomeState::Pending,
+            })
+        };
+
+        assert_eq!(classify(&entry(None)), OperationOutcomeState::Pending);
+        assert_eq!(
+            classify(&entry(Some(json!("Success")))),
+            OperationOutcomeState::Success
+        );
+        assert_eq!(
+            classify(&entry(Some(json!("Refunded")))),
+            OperationOutcomeState::Failure
+        );
+        assert_eq!(
+            classify(&entry(Some(json!("Waiting")))),
+            OperationOutcomeState::Pending
+        );
+        assert_eq!(
+            classify(&entry(Some(json!({"Unknown": "state"})))),
+            OperationOutcomeState::Pending
+        );
+
+        assert_eq!(
+            OperationOutcomeState::unclassified(&entry(None)),
+            OperationOutcomeState::Pending
+        );
+        assert_eq!(
+            OperationOutcomeState::unclassified(&entry(Some(json!({"Failed": "error"})))),
+            OperationOutcomeState::Success
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_in_pages() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone(), Default::default());
+
+        let operations = EXPORT_PAGE_SIZE * 2 + 5;
+        for operation_idx in 0..operations {
+            let mut id = [0; 32];
+            id[..8].copy_from_slice(&(operation_idx as u64).to_be_bytes());
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx.to_ref_nc(),
+                    OperationId(id),
+                    "foo",
+                    operation_idx,
+                )
+                .await;
+            dbtx.commit_tx().await;
+        }
+
+        let mut jsonl = vec![];
+        let exported = op_log
+            .export(
+                FederationId::dummy(),
+                &Default::default(),
+                &OperationLogFilter::default(),
+                OperationLogExportFormat::JsonLines,
+                &mut jsonl,
+            )
+            .await
+            .unwrap();
+        assert_eq!(exported, operations);
+
+        let lines = String::from_utf8(jsonl).unwrap();
+        let operation_ids = lines
+            .lines()
+            .map(|line| {
+                let record = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                record["operation_id"].as_str().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>();
+        let expected_ids = op_log
+            .list_operations(operations + 1, None)
+            .await
+            .into_iter()
+            .map(|(key, _)| key.operation_id.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(operation_ids, expected_ids);
+    }
+
+    #[tokio::test]
+    async fn test_filtered_operations() {
+        let db = MemDatabase::new().into_database();
+        let op_log = OperationLog::new(db.clone(), Default::default());
+        add_test_operations(&db, &op_log).await;
+
+        // Without module inits every outcome counts as a success
+        for operation_idx in [0, 3, 6] {
+            op_log
+                .set_operation_outcome(
+                    OperationId([operation_idx; 32]),
+                    &json!({"Failed": "error"}),
+                )
+                .await
+                .unwrap();
+        }
+
+        let ln_sends = OperationLogFilter {
+            operation_module_kind: Some("ln".to_string()),
+            operation_type: Some("send".to_string()),
+            ..Default::default()
+        };
+        let page = op_log.list_operations_filtered(&ln_sends, 4, None).await;
+        assert_eq!(operation_indices(&page), vec![27, 24, 21, 18]);
+        let page = op_log
+            .list_operations_filtered(&ln_sends, 4, Some(page[3].0))
+            .await;
+        assert_eq!(operation_indices(&page), vec![15, 12, 9, 6]);
+
+        let finished = OperationLogFilter {
+            outcome_state: Some(OperationOutcomeState::Success),
+            ..Default::default()
+        };
+        let page = op_log.list_operations_filtered(&finished, 10, None).await;
+        assert_eq!(operation_indices(&page), vec![6, 3, 0]);
+
+        let pending_ln = OperationLogFilter {
+            operation_module_kind: Some("ln".to_string()),
+            outcome_state: Some(OperationOutcomeState::Pending),
+            ..Default::default()
+        };
+        let page = op_log.list_operations_filtered(&pending_ln, 30, None).await;
+        assert_eq!(page.len(), 17);
+
+        // Only modules can tell the amount of their operations
+        let amount_range = OperationLogFilter {
+            min_amount: Some(Amount::from_sats(10_000)),
+            max_amount: Some(Amount::from_sats(20_000)),
+            ..Default::default()
+        };
+        let page = op_log
+            .list_operations_filtered(&amount_range, 30, None)
+            .await;
+        assert!(page.is_empty());
+
+        let all = op_log
+            .list_operations_filtered(&OperationLogFilter::default(), 30, None)
+            .await;
+        let time_range = OperationLogFilter {
+            since: Some(all[20].0.creation_time),
+            until: Some(all[10].0.creation_time),
+            ..Default::default()
+        };
+        let page = op_log.list_operations_filtered(&time_range, 30, None).await;
+        assert!(page
+            .iter()
+            .all(|(key, _)| all[20].0.creation_time <= key.creation_time
+                && key.creation_time < all[10].0.creation_time));
+
+        let text = OperationLogFilter {
+            text: Some("fee".to_string()),
+            ..Default::default()
+        };
+        let page = op_log.list_operations_filtered(&text, 30, None).await;
+        assert_eq!(page.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_operation_log_indices() {
+        let db = MemDatabase::new().into_database();
+        let op_log = OperationLog::new(db.clone(), Default::default());
+        add_test_operations(&db, &op_log).await;
+
+        // Pretend the operations were created before the indices existed
+        let mut dbtx = db.begin_transaction().await;
+        for operation_idx in 0u8..30 {
+            dbtx.remove_entry(&OperationLogAttributesKey {
+                operation_id: OperationId([operation_idx; 32]),
+            })
+            .await;
+        }
+        dbtx.remove_by_prefix(&crate::db::OperationLogModuleKindIndexPrefix {
+            operation_module_kind: "mint".to_string(),
+        })
+        .await;
+        dbtx.commit_tx().await;
+
+        apply_migrations(
+            &db,
+            "fedimint-client".to_string(),
+            CORE_CLIENT_DATABASE_VERSION,
+            get_core_client_database_migrations(),
+            None,
+        )
+        .await
+        .unwrap();
+        op_log.backfill_indices().await.unwrap();
+
+        let mints = OperationLogFilter {
+            operation_module_kind: Some("mint".to_string()),
+            ..Default::default()
+        };
+        let page = op_log.list_operations_filtered(&mints, 30, None).await;
+        assert_eq!(page.len(), 10);
+    }
 }
@@ -0,0 +1,275 @@
+//! Export of the operation log as a ledger, e.g. for accounting
+//!
+//! Every operation is turned into a flat [`OperationLogRecord`]. The generic
+//! fields are taken from the operation log itself while amounts, fees and
+//! counterparties are extracted by the module that created the operation, see
+//! [`crate::module::init::ClientModuleInit::operation_log_record_details`].
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::bail;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use super::OperationOutcomeState;
+
+/// File format of an operation log export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationLogExportFormat {
+    /// Comma separated values with a header row
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl fmt::Display for OperationLogExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationLogExportFormat::Csv => f.write_str("csv"),
+            OperationLogExportFormat::JsonLines => f.write_str("jsonl"),
+        }
+    }
+}
+
+impl FromStr for OperationLogExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(OperationLogExportFormat::Csv),
+            "jsonl" | "json-lines" => Ok(OperationLogExportFormat::JsonLines),
+            _ => bail!("Unknown export format {s}, expected csv or jsonl"),
+        }
+    }
+}
+
+/// Direction in which an operation moves funds from the client's point of
+/// view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationDirection {
+    Incoming,
+    Outgoing,
+    /// Funds are moved between the client's own notes or accounts
+    Internal,
+}
+
+/// Module specific part of an [`OperationLogRecord`], all fields the module
+/// doesn't know about are left empty
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationLogRecordDetails {
+    pub direction: Option<OperationDirection>,
+    /// Amount sent or received, excluding fees
+    pub amount: Option<Amount>,
+    /// Fees paid for the operation
+    pub fee: Option<Amount>,
+    /// Lightning invoice that was paid or created
+    pub invoice: Option<String>,
+    /// On-chain address funds were sent to or received on
+    pub address: Option<String>,
+    /// Id of the federation or on-chain transaction of the operation
+    pub txid: Option<String>,
+}
+
+/// Normalized ledger record of a single operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OperationLogRecord {
+    pub operation_id: OperationId,
+    /// Creation time of the operation as RFC 3339 timestamp
+    pub time: String,
+    pub federation_id: FederationId,
+    pub module_kind: String,
+    pub operation_type: Option<String>,
+    pub outcome_state: OperationOutcomeState,
+    pub direction: Option<OperationDirection>,
+    #[serde(rename = "amount_msat")]
+    pub amount: Option<Amount>,
+    #[serde(rename = "fee_msat")]
+    pub fee: Option<Amount>,
+    pub invoice: Option<String>,
+    pub address: Option<String>,
+    pub txid: Option<String>,
+    /// Meta data and outcome of the operation as JSON if the module couldn't
+    /// extract the details from them
+    pub raw_data: Option<String>,
+}
+
+impl OperationLogRecord {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        operation_id: OperationId,
+        creation_time: SystemTime,
+        federation_id: FederationId,
+        module_kind: String,
+        operation_type: Option<String>,
+        outcome_state: OperationOutcomeState,
+        details: OperationLogRecordDetails,
+        raw_data: Option<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            operation_id,
+            time: OffsetDateTime::from(creation_time).format(&Rfc3339)?,
+            federation_id,
+            module_kind,
+            operation_type,
+            outcome_state,
+            direction: details.direction,
+            amount: details.amount,
+            fee: details.fee,
+            invoice: details.invoice,
+            address: details.address,
+            txid: details.txid,
+            raw_data,
+        })
+    }
+}
+
+/// Writes [`OperationLogRecord`]s one by one in the given format, so an
+/// export doesn't have to be held in memory as a whole
+pub enum OperationLogRecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    JsonLines(W),
+}
+
+impl<W: Write> OperationLogRecordWriter<W> {
+    pub fn new(format: OperationLogExportFormat, writer: W) -> Self {
+        match format {
+            OperationLogExportFormat::Csv => {
+                OperationLogRecordWriter::Csv(Box::new(csv::Writer::from_writer(writer)))
+            }
+            OperationLogExportFormat::JsonLines => OperationLogRecordWriter::JsonLines(writer),
+        }
+    }
+
+    pub fn write(&mut self, record: &OperationLogRecord) -> anyhow::Result<()> {
+        match self {
+            OperationLogRecordWriter::Csv(writer) => writer.serialize(record)?,
+            OperationLogRecordWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        match self {
+            OperationLogRecordWriter::Csv(writer) => writer.flush()?,
+            OperationLogRecordWriter::JsonLines(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes `records` to `writer` in the given format
+pub fn write_records<'a>(
+    records: impl IntoIterator<Item = &'a OperationLogRecord>,
+    format: OperationLogExportFormat,
+    writer: impl Write,
+) -> anyhow::Result<()> {
+    let mut writer = OperationLogRecordWriter::new(format, writer);
+    for record in records {
+        writer.write(record)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use fedimint_core::config::FederationId;
+    use fedimint_core::core::OperationId;
+    use fedimint_core::Amount;
+
+    use super::{
+        write_records, OperationDirection, OperationLogExportFormat, OperationLogRecord,
+        OperationLogRecordDetails,
+    };
+    use crate::oplog::OperationOutcomeState;
+
+    fn records() -> Vec<OperationLogRecord> {
+        let federation_id = FederationId::dummy();
+        vec![
+            OperationLogRecord::new(
+                OperationId([1; 32]),
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                federation_id,
+                "ln".to_string(),
+                Some("pay".to_string()),
+                OperationOutcomeState::Success,
+                OperationLogRecordDetails {
+                    direction: Some(OperationDirection::Outgoing),
+                    amount: Some(Amount::from_sats(1000)),
+                    fee: Some(Amount::from_msats(1500)),
+                    invoice: Some("lnbc1, with comma".to_string()),
+                    address: None,
+                    txid: None,
+                },
+                None,
+            )
+            .unwrap(),
+            OperationLogRecord::new(
+                OperationId([2; 32]),
+                UNIX_EPOCH + Duration::from_secs(1_700_000_060),
+                federation_id,
+                "mint".to_string(),
+                None,
+                OperationOutcomeState::Pending,
+                OperationLogRecordDetails::default(),
+                Some(r#"{"meta":{"unknown":1},"outcome":null}"#.to_string()),
+            )
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn exports_csv() {
+        let mut csv = vec![];
+        write_records(&records(), OperationLogExportFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "operation_id,time,federation_id,module_kind,operation_type,outcome_state,\
+             direction,amount_msat,fee_msat,invoice,address,txid,raw_data"
+        );
+        assert!(lines[1].contains(",2023-11-14T22:13:20Z,"));
+        assert!(
+            lines[1].ends_with(",ln,pay,success,outgoing,1000000,1500,\"lnbc1, with comma\",,,")
+        );
+        assert!(lines[2]
+            .ends_with(r#",mint,,pending,,,,,,,"{""meta"":{""unknown"":1},""outcome"":null}""#));
+    }
+
+    #[test]
+    fn exports_json_lines() {
+        let mut jsonl = vec![];
+        write_records(&records(), OperationLogExportFormat::JsonLines, &mut jsonl).unwrap();
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        let lines = jsonl
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["amount_msat"], 1_000_000);
+        assert_eq!(lines[0]["direction"], "outgoing");
+        assert_eq!(lines[1]["outcome_state"], "pending");
+        assert!(lines[1]["amount_msat"].is_null());
+        assert!(lines[0]["raw_data"].is_null());
+        assert_eq!(
+            lines[1]["raw_data"],
+            r#"{"meta":{"unknown":1},"outcome":null}"#
+        );
+    }
+}
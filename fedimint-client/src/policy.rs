@@ -0,0 +1,297 @@
+//! Spending limits for client operations
+//!
+//! A [`SpendingPolicy`] is stored in the client database and checked by
+//! [`Client::finalize_and_submit_transaction`](crate::Client::finalize_and_submit_transaction)
+//! before a transaction gets submitted. Only the outflow of a transaction is
+//! limited, which is the amount of all outputs not belonging to the primary
+//! module, i.e. funds leaving the wallet. The allowed module kinds are checked
+//! against the modules of these outputs. E-cash spent out of band doesn't
+//! involve a transaction and thus isn't limited.
+//!
+//! The outflow is counted in hourly buckets, so the daily limit applies to a
+//! rolling window of the last 24 hours.
+
+use std::collections::BTreeSet;
+
+use fedimint_core::core::ModuleKind;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::Amount;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::db::{SpendingOutflowKey, SpendingOutflowKeyPrefix, SpendingPolicyKey};
+
+/// Number of hourly outflow buckets the daily limit is enforced over
+const OUTFLOW_WINDOW_HOURS: u64 = 24;
+
+/// Guardrails for funds leaving the wallet, see the [module
+/// documentation](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SpendingPolicy {
+    /// Maximum outflow of a single operation
+    pub max_payment: Option<Amount>,
+    /// Maximum outflow of all operations within the last 24 hours
+    pub max_daily_outflow: Option<Amount>,
+    /// Module kinds allowed to send funds out of the wallet, all of them if
+    /// `None`
+    pub allowed_module_kinds: Option<BTreeSet<String>>,
+}
+
+/// Reason a transaction was rejected by the [`SpendingPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SpendingPolicyError {
+    #[error("Payment of {amount} exceeds the maximum payment of {max_payment}")]
+    PaymentTooLarge { amount: Amount, max_payment: Amount },
+    #[error(
+        "Payment of {amount} exceeds the daily outflow limit of {max_daily_outflow}, \
+         {outflow} were already sent within the last 24 hours"
+    )]
+    DailyOutflowExceeded {
+        amount: Amount,
+        outflow: Amount,
+        max_daily_outflow: Amount,
+    },
+    #[error("Module kind {module_kind} is not allowed to send funds")]
+    ModuleKindNotAllowed { module_kind: String },
+}
+
+impl SpendingPolicy {
+    /// Checks a transaction sending `amount` out of the wallet through outputs
+    /// of `module_kinds`, given that `outflow` was already sent within the last
+    /// 24 hours
+    pub fn check(
+        &self,
+        module_kinds: &BTreeSet<ModuleKind>,
+        amount: Amount,
+        outflow: Amount,
+    ) -> Result<(), SpendingPolicyError> {
+        if let Some(allowed_module_kinds) = &self.allowed_module_kinds {
+            if let Some(module_kind) = module_kinds
+                .iter()
+                .find(|module_kind| !allowed_module_kinds.contains(module_kind.as_str()))
+            {
+                return Err(SpendingPolicyError::ModuleKindNotAllowed {
+                    module_kind: module_kind.as_str().to_owned(),
+                });
+            }
+        }
+
+        if let Some(max_payment) = self.max_payment {
+            if max_payment < amount {
+                return Err(SpendingPolicyError::PaymentTooLarge {
+                    amount,
+                    max_payment,
+                });
+            }
+        }
+
+        if let Some(max_daily_outflow) = self.max_daily_outflow {
+            if max_daily_outflow < outflow + amount {
+                return Err(SpendingPolicyError::DailyOutflowExceeded {
+                    amount,
+                    outflow,
+                    max_daily_outflow,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The spending policy of the client together with the outflow it is
+/// currently checked against
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SpendingPolicyStatus {
+    pub policy: Option<SpendingPolicy>,
+    /// Funds sent within the last 24 hours
+    pub daily_outflow: Amount,
+}
+
+fn current_hour() -> u64 {
+    duration_since_epoch().as_secs() / 3600
+}
+
+/// Returns the outflow within the last 24 hours
+pub(crate) async fn daily_outflow(dbtx: &mut DatabaseTransaction<'_>) -> Amount {
+    let current_hour = current_hour();
+
+    // Reading every bucket individually makes concurrent transactions conflict
+    // instead of both passing the limit
+    let mut outflow = Amount::ZERO;
+    for hour in current_hour.saturating_sub(OUTFLOW_WINDOW_HOURS - 1)..=current_hour {
+        outflow += dbtx
+            .get_value(&SpendingOutflowKey { hour })
+            .await
+            .unwrap_or(Amount::ZERO);
+    }
+    outflow
+}
+
+/// Checks a transaction sending `amount` out of the wallet through outputs of
+/// `module_kinds` against the spending policy and counts it towards the daily
+/// outflow as part of `dbtx`
+pub(crate) async fn enforce_spending_policy(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_kinds: &BTreeSet<ModuleKind>,
+    amount: Amount,
+) -> Result<(), SpendingPolicyError> {
+    if amount == Amount::ZERO {
+        return Ok(());
+    }
+
+    if let Some(policy) = dbtx.get_value(&SpendingPolicyKey).await {
+        policy.check(module_kinds, amount, daily_outflow(dbtx).await)?;
+    }
+
+    let current_hour = current_hour();
+    let expired = dbtx
+        .find_by_prefix(&SpendingOutflowKeyPrefix)
+        .await
+        .map(|(key, _)| key)
+        .filter(|key| std::future::ready(key.hour + OUTFLOW_WINDOW_HOURS <= current_hour))
+        .collect::<Vec<_>>()
+        .await;
+    for key in expired {
+        dbtx.remove_entry(&key).await;
+    }
+
+    let bucket = SpendingOutflowKey { hour: current_hour };
+    let outflow = dbtx.get_value(&bucket).await.unwrap_or(Amount::ZERO);
+    dbtx.insert_entry(&bucket, &(outflow + amount)).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::core::ModuleKind;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::Amount;
+
+    use super::{daily_outflow, enforce_spending_policy, SpendingPolicy, SpendingPolicyError};
+    use crate::db::{SpendingOutflowKey, SpendingPolicyKey};
+
+    fn kinds(kinds: &[&'static str]) -> BTreeSet<ModuleKind> {
+        kinds
+            .iter()
+            .map(|kind| ModuleKind::from_static_str(kind))
+            .collect()
+    }
+
+    #[test]
+    fn check_policy() {
+        let policy = SpendingPolicy {
+            max_payment: Some(Amount::from_sats(100)),
+            max_daily_outflow: Some(Amount::from_sats(250)),
+            allowed_module_kinds: Some(BTreeSet::from(["ln".to_string()])),
+        };
+
+        assert_eq!(
+            policy.check(
+                &kinds(&["ln"]),
+                Amount::from_sats(100),
+                Amount::from_sats(150)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check(
+                &kinds(&["ln", "wallet"]),
+                Amount::from_sats(1),
+                Amount::ZERO
+            ),
+            Err(SpendingPolicyError::ModuleKindNotAllowed {
+                module_kind: "wallet".to_string()
+            })
+        );
+        assert_eq!(
+            policy.check(&kinds(&["ln"]), Amount::from_sats(101), Amount::ZERO),
+            Err(SpendingPolicyError::PaymentTooLarge {
+                amount: Amount::from_sats(101),
+                max_payment: Amount::from_sats(100),
+            })
+        );
+        assert_eq!(
+            policy.check(
+                &kinds(&["ln"]),
+                Amount::from_sats(100),
+                Amount::from_sats(151)
+            ),
+            Err(SpendingPolicyError::DailyOutflowExceeded {
+                amount: Amount::from_sats(100),
+                outflow: Amount::from_sats(151),
+                max_daily_outflow: Amount::from_sats(250),
+            })
+        );
+        assert_eq!(
+            SpendingPolicy::default().check(
+                &kinds(&["wallet"]),
+                Amount::from_sats(1000),
+                Amount::ZERO
+            ),
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn enforce_daily_outflow() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &SpendingPolicyKey,
+            &SpendingPolicy {
+                max_daily_outflow: Some(Amount::from_sats(100)),
+                ..Default::default()
+            },
+        )
+        .await;
+        // Outflow older than 24 hours doesn't count and gets removed
+        let old_bucket = SpendingOutflowKey {
+            hour: super::current_hour() - 24,
+        };
+        dbtx.insert_entry(&old_bucket, &Amount::from_sats(1000))
+            .await;
+
+        enforce_spending_policy(
+            &mut dbtx.to_ref_nc(),
+            &kinds(&["ln"]),
+            Amount::from_sats(60),
+        )
+        .await
+        .unwrap();
+        assert_eq!(dbtx.get_value(&old_bucket).await, None);
+        assert_eq!(
+            daily_outflow(&mut dbtx.to_ref_nc()).await,
+            Amount::from_sats(60)
+        );
+
+        assert!(matches!(
+            enforce_spending_policy(
+                &mut dbtx.to_ref_nc(),
+                &kinds(&["ln"]),
+                Amount::from_sats(60)
+            )
+            .await,
+            Err(SpendingPolicyError::DailyOutflowExceeded { .. })
+        ));
+        enforce_spending_policy(
+            &mut dbtx.to_ref_nc(),
+            &kinds(&["ln"]),
+            Amount::from_sats(40),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            daily_outflow(&mut dbtx.to_ref_nc()).await,
+            Amount::from_sats(100)
+        );
+    }
+}
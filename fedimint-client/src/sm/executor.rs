@@ -25,6 +25,7 @@ use tokio::sync::{mpsc, oneshot, Mutex};
 use tracing::{debug, error, info, trace, warn, Instrument};
 
 use super::state::StateTransitionFunction;
+use crate::events::{ClientEvent, ClientEventBus};
 use crate::sm::notifier::Notifier;
 use crate::sm::state::{DynContext, DynState};
 use crate::sm::{ClientSMDatabaseTransaction, State, StateTransition};
@@ -62,6 +63,7 @@ struct ExecutorInner {
     module_contexts: BTreeMap<ModuleInstanceId, DynContext>,
     valid_module_ids: BTreeSet<ModuleInstanceId>,
     notifier: Notifier,
+    event_bus: ClientEventBus,
     shutdown_executor: Mutex<Option<oneshot::Sender<()>>>,
     /// Any time executor should notice state machine update (e.g. because it
     /// was created), it's must be sent through this channel for it to notice.
@@ -88,6 +90,10 @@ impl Executor {
         self.inner.get_active_states().await
     }
 
+    pub async fn get_inactive_states(&self) -> Vec<(DynState, InactiveStateMeta)> {
+        self.inner.get_inactive_states().await
+    }
+
     /// Adds a number of state machines to the executor atomically. They will be
     /// driven to completion automatically in the background.
     ///
@@ -176,6 +182,17 @@ impl Executor {
                 &ActiveStateMeta::default(),
             )
             .await;
+            self.inner
+                .event_bus
+                .log_event(
+                    dbtx,
+                    ClientEvent::StateTransition {
+                        operation_id: state.operation_id(),
+                        module_instance_id: state.module_instance_id(),
+                        terminal: false,
+                    },
+                )
+                .await;
             let notify_sender = self.inner.notifier.sender();
             let sm_updates_tx = self.inner.sm_update_tx.clone();
             dbtx.on_commit(move || {
@@ -528,6 +545,7 @@ impl ExecutorInner {
                         let notifier = self.notifier.clone();
                         let module_contexts = self.module_contexts.clone();
                         let global_context_gen = global_context_gen.clone();
+                        let event_bus = self.event_bus.clone();
                         Box::pin(
                             async move {
                                 debug!(
@@ -545,6 +563,7 @@ impl ExecutorInner {
 
                                 let module_contexts = &module_contexts;
                                 let global_context_gen = &global_context_gen;
+                                let event_bus = &event_bus;
 
                                 let outcome = db
                                     .autocommit::<'_, '_, _, _, Infallible>(
@@ -580,7 +599,19 @@ impl ExecutorInner {
                                                     state.module_instance_id(),
                                                     state.operation_id(),
                                                 );
-                                                if new_state.is_terminal(context, &global_context) {
+                                                let terminal =
+                                                    new_state.is_terminal(context, &global_context);
+                                                event_bus.log_event(
+                                                    dbtx,
+                                                    ClientEvent::StateTransition {
+                                                        operation_id: new_state.operation_id(),
+                                                        module_instance_id: new_state
+                                                            .module_instance_id(),
+                                                        terminal,
+                                                    },
+                                                )
+                                                .await;
+                                                if terminal {
                                                     let k = InactiveStateKey::from_state(
                                                         new_state.clone(),
                                                     );
@@ -778,6 +809,7 @@ impl ExecutorBuilder {
         self,
         db: Database,
         notifier: Notifier,
+        event_bus: ClientEventBus,
         client_task_group: TaskGroup,
     ) -> Executor {
         let (sm_update_tx, sm_update_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -788,6 +820,7 @@ impl ExecutorBuilder {
             module_contexts: self.module_contexts,
             valid_module_ids: self.valid_module_ids,
             notifier,
+            event_bus,
             shutdown_executor: Default::default(),
             sm_update_tx,
             sm_update_rx: Mutex::new(Some(sm_update_rx)),
@@ -1170,6 +1203,7 @@ mod tests {
     use tokio::sync::broadcast::Sender;
     use tracing::{info, trace};
 
+    use crate::events::ClientEventBus;
     use crate::sm::state::{Context, DynContext, DynState};
     use crate::sm::{Executor, Notifier, State, StateTransition};
     use crate::DynGlobalClientContext;
@@ -1291,7 +1325,12 @@ mod tests {
             },
         );
         let executor = executor_builder
-            .build(db.clone(), Notifier::new(db.clone()), TaskGroup::new())
+            .build(
+                db.clone(),
+                Notifier::new(db.clone()),
+                ClientEventBus::new(db.clone(), &TaskGroup::new()),
+                TaskGroup::new(),
+            )
             .await;
         executor
             .start_executor(Arc::new(|_, _| DynGlobalClientContext::new_fake()))
@@ -736,8 +736,7 @@ impl ServerModuleConfig {
     pub fn to_typed<T: TypedServerModuleConfig>(&self) -> anyhow::Result<T> {
         let local = serde_json::from_value(self.local.value().clone())?;
         let private = serde_json::from_value(self.private.value().clone())?;
-        let consensus =
-            <T::Consensus>::consensus_decode(&mut &self.consensus.config[..], &Default::default())?;
+        let consensus = <T::Consensus>::from_erased(&self.consensus)?;
 
         Ok(TypedServerModuleConfig::from_parts(
             local, private, consensus,
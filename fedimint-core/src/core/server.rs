@@ -49,6 +49,10 @@ pub trait IServerModule: Debug {
         peer_id: PeerId,
     ) -> anyhow::Result<()>;
 
+    /// This function is called once at the end of every session, in the same
+    /// database transaction that persists the session outcome.
+    async fn end_session<'a>(&self, dbtx: &mut DatabaseTransaction<'a>, session_index: u64);
+
     /// Try to spend a transaction input. On success all necessary updates will
     /// be part of the database transaction. On failure (e.g. double spend)
     /// the database transaction is rolled back and the operation will take
@@ -159,6 +163,12 @@ where
         .await
     }
 
+    /// This function is called once at the end of every session, in the same
+    /// database transaction that persists the session outcome.
+    async fn end_session<'a>(&self, dbtx: &mut DatabaseTransaction<'a>, session_index: u64) {
+        <Self as ServerModule>::end_session(self, dbtx, session_index).await
+    }
+
     /// Try to spend a transaction input. On success all necessary updates will
     /// be part of the database transaction. On failure (e.g. double spend)
     /// the database transaction is rolled back and the operation will take
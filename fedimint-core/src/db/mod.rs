@@ -63,6 +63,7 @@ use crate::{async_trait_maybe_send, maybe_add_send, timing};
 
 pub mod mem_impl;
 pub mod notifications;
+pub mod snapshot;
 
 pub use test_utils::*;
 
@@ -536,6 +537,18 @@ impl Database {
     {
         self.wait_key_check(key, std::convert::identity).await.0
     }
+
+    /// Waits for the next notification of `key`, i.e. a committed modification
+    /// or [`DatabaseTransaction::notify_on_commit`]
+    ///
+    /// The notification is only guaranteed to be received if it happens after
+    /// the returned future was polled for the first time.
+    pub async fn wait_key_notified<K>(&self, key: &K)
+    where
+        K: DatabaseKey + DatabaseRecord + DatabaseKeyWithNotify,
+    {
+        self.inner.register(&key.to_bytes()).await;
+    }
 }
 
 fn module_instance_id_to_byte_prefix(module_instance_id: u16) -> Vec<u8> {
@@ -630,6 +643,11 @@ where
         self.inner.commit_tx().await
     }
 
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        let key = self.get_full_key(key);
+        self.inner.add_notification_key(&key)
+    }
+
     fn prefix_len(&self) -> usize {
         self.inner.prefix_len() + self.prefix.len()
     }
@@ -1059,6 +1077,9 @@ pub trait IDatabaseTransaction: MaybeSend + IDatabaseTransactionOps + fmt::Debug
     /// Commit the transaction
     async fn commit_tx(&mut self) -> Result<()>;
 
+    /// Notify the waiters of `key` once the transaction is committed
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()>;
+
     /// The prefix len of this database instance
     fn prefix_len(&self) -> usize;
 }
@@ -1071,6 +1092,9 @@ where
     async fn commit_tx(&mut self) -> Result<()> {
         (**self).commit_tx().await
     }
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        (**self).add_notification_key(key)
+    }
     fn prefix_len(&self) -> usize {
         (**self).prefix_len()
     }
@@ -1084,6 +1108,9 @@ where
     async fn commit_tx(&mut self) -> Result<()> {
         (**self).commit_tx().await
     }
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        (**self).add_notification_key(key)
+    }
     fn prefix_len(&self) -> usize {
         (**self).prefix_len()
     }
@@ -1121,7 +1148,7 @@ where
         }
     }
 
-    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+    fn queue_notification(&mut self, key: &[u8]) -> Result<()> {
         self.notify_queue
             .as_mut()
             .context("can not call add_notification_key after commit")?
@@ -1133,7 +1160,7 @@ where
 #[apply(async_trait_maybe_send!)]
 impl<Tx: IRawDatabaseTransaction> IDatabaseTransactionOpsCore for BaseDatabaseTransaction<Tx> {
     async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.add_notification_key(key)?;
+        self.queue_notification(key)?;
         self.raw
             .as_mut()
             .context("Cannot insert into already consumed transaction")?
@@ -1150,7 +1177,7 @@ impl<Tx: IRawDatabaseTransaction> IDatabaseTransactionOpsCore for BaseDatabaseTr
     }
 
     async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.add_notification_key(key)?;
+        self.queue_notification(key)?;
         self.raw
             .as_mut()
             .context("Cannot remove from already consumed transaction")?
@@ -1226,6 +1253,10 @@ where
         Ok(())
     }
 
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        self.queue_notification(key)
+    }
+
     fn prefix_len(&self) -> usize {
         0
     }
@@ -1512,6 +1543,20 @@ impl<'tx, Cap> DatabaseTransaction<'tx, Cap> {
     pub fn on_commit(&mut self, f: maybe_add_send!(impl FnOnce() + 'static)) {
         self.on_commit_hooks.push(Box::new(f));
     }
+
+    /// Notify the waiters of `key` once the transaction is committed, like a
+    /// modification of `key` would, without writing it
+    ///
+    /// Allows waking up [`Database::wait_key_notified`] from transactions that
+    /// otherwise don't share a key, which would make them conflict.
+    pub fn notify_on_commit<K>(&mut self, key: &K)
+    where
+        K: DatabaseKey + DatabaseRecord + DatabaseKeyWithNotify,
+    {
+        self.tx
+            .add_notification_key(&key.to_bytes())
+            .expect("Transaction is not committed yet");
+    }
 }
 
 impl<'tx> DatabaseTransaction<'tx, Committable> {
@@ -0,0 +1,293 @@
+//! Online snapshots of a whole [`Database`]
+//!
+//! A snapshot is a self-contained file holding every key/value pair of a
+//! database, read from a single transaction and therefore consistent even if
+//! the database is being written to concurrently. Since the database version
+//! entries ([`super::DatabaseVersionKey`]) are regular entries they are part
+//! of every snapshot, so migrations keep working after an import.
+//!
+//! # Format (version 1)
+//!
+//! All integers are big endian.
+//!
+//! ```text
+//! magic     8 bytes   "FMDBSNAP"
+//! version   u16       1
+//! entries   repeated  0x01 | key length u32 | key | value length u32 | value
+//! trailer             0x00 | number of entries u64 | sha256 of all prior bytes
+//! ```
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{Database, IDatabaseTransactionOpsCore};
+use crate::task::MaybeSend;
+
+const SNAPSHOT_MAGIC: [u8; 8] = *b"FMDBSNAP";
+const SNAPSHOT_VERSION: u16 = 1;
+
+const ENTRY_TAG: u8 = 0x01;
+const TRAILER_TAG: u8 = 0x00;
+
+/// Summary of an exported or imported database snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotSummary {
+    /// Number of key/value pairs contained in the snapshot
+    pub entries: u64,
+    /// Checksum stored in the snapshot trailer
+    pub checksum: sha256::Hash,
+}
+
+/// Writer that hashes everything written through it
+struct HashingWriter<W> {
+    inner: W,
+    engine: sha256::HashEngine,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        self.engine.input(bytes);
+        self.inner.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let len = u32::try_from(bytes.len()).context("Database entry too large for snapshot")?;
+        self.write_all(&len.to_be_bytes())?;
+        self.write_all(bytes)
+    }
+}
+
+/// Reader that hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    engine: sha256::HashEngine,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.inner
+            .read_exact(&mut buf)
+            .context("Unexpected end of snapshot")?;
+        self.engine.input(&buf);
+        Ok(buf)
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = u32::from_be_bytes(self.read_array()?) as usize;
+        let mut buf = vec![];
+        (&mut self.inner)
+            .take(len as u64)
+            .read_to_end(&mut buf)
+            .context("Failed to read snapshot entry")?;
+        ensure!(buf.len() == len, "Unexpected end of snapshot");
+        self.engine.input(&buf);
+        Ok(buf)
+    }
+}
+
+impl Database {
+    /// Writes every key/value pair of the database to `writer` using a single
+    /// read transaction, see the [module docs](self) for the format.
+    pub async fn export_snapshot<W>(&self, writer: W) -> Result<SnapshotSummary>
+    where
+        W: Write + MaybeSend,
+    {
+        let mut writer = HashingWriter {
+            inner: writer,
+            engine: sha256::Hash::engine(),
+        };
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+
+        let mut dbtx = self.begin_transaction_nc().await;
+        let mut entries_stream = dbtx.raw_find_by_prefix(&[]).await?;
+        let mut entries = 0u64;
+        while let Some((key, value)) = entries_stream.next().await {
+            writer.write_all(&[ENTRY_TAG])?;
+            writer.write_bytes(&key)?;
+            writer.write_bytes(&value)?;
+            entries += 1;
+        }
+
+        writer.write_all(&[TRAILER_TAG])?;
+        writer.write_all(&entries.to_be_bytes())?;
+        let checksum = sha256::Hash::from_engine(writer.engine);
+        writer.inner.write_all(checksum.as_inner())?;
+        writer.inner.flush()?;
+
+        info!(entries, %checksum, "Exported database snapshot");
+        Ok(SnapshotSummary { entries, checksum })
+    }
+
+    /// Restores a snapshot created by [`Self::export_snapshot`] into this
+    /// database, which has to be empty.
+    ///
+    /// All entries are written in a single transaction that is only committed
+    /// after the checksum was verified, so a corrupted snapshot leaves the
+    /// database untouched.
+    pub async fn import_snapshot<R>(&self, reader: R) -> Result<SnapshotSummary>
+    where
+        R: Read + MaybeSend,
+    {
+        let mut reader = HashingReader {
+            inner: reader,
+            engine: sha256::Hash::engine(),
+        };
+        ensure!(
+            reader.read_array()? == SNAPSHOT_MAGIC,
+            "Not a database snapshot"
+        );
+        let version = u16::from_be_bytes(reader.read_array()?);
+        ensure!(
+            version == SNAPSHOT_VERSION,
+            "Unsupported snapshot version {version}"
+        );
+
+        let mut dbtx = self.begin_transaction().await;
+        // Nothing gets committed if the snapshot turns out to be invalid
+        dbtx.ignore_uncommitted();
+        ensure!(
+            dbtx.raw_find_by_prefix(&[]).await?.next().await.is_none(),
+            "Can only import a snapshot into an empty database"
+        );
+
+        let mut entries = 0u64;
+        loop {
+            match reader.read_array::<1>()?[0] {
+                ENTRY_TAG => {
+                    let key = reader.read_bytes()?;
+                    let value = reader.read_bytes()?;
+                    ensure!(
+                        dbtx.raw_insert_bytes(&key, &value).await?.is_none(),
+                        "Duplicate key in snapshot"
+                    );
+                    entries += 1;
+                }
+                TRAILER_TAG => break,
+                tag => bail!("Invalid snapshot record tag {tag}"),
+            }
+        }
+
+        let expected_entries = u64::from_be_bytes(reader.read_array()?);
+        ensure!(
+            expected_entries == entries,
+            "Snapshot contains {entries} entries, trailer claims {expected_entries}"
+        );
+        let checksum = sha256::Hash::from_engine(reader.engine);
+        let mut expected_checksum = [0u8; 32];
+        reader
+            .inner
+            .read_exact(&mut expected_checksum)
+            .context("Unexpected end of snapshot")?;
+        ensure!(
+            checksum.into_inner() == expected_checksum,
+            "Snapshot checksum mismatch"
+        );
+        ensure!(
+            reader.inner.read(&mut [0u8; 1])? == 0,
+            "Unexpected data after snapshot trailer"
+        );
+
+        dbtx.commit_tx_result().await?;
+
+        info!(entries, %checksum, "Imported database snapshot");
+        Ok(SnapshotSummary { entries, checksum })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::core::ModuleInstanceId;
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::{
+        DatabaseVersion, DatabaseVersionKey, IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt,
+    };
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    const MODULE_ID: ModuleInstanceId = 3;
+
+    async fn all_entries(db: &Database) -> Vec<(Vec<u8>, Vec<u8>)> {
+        db.begin_transaction_nc()
+            .await
+            .raw_find_by_prefix(&[])
+            .await
+            .expect("DB read failed")
+            .collect()
+            .await
+    }
+
+    async fn populated_database() -> Database {
+        let db = MemDatabase::new().into_database();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &DatabaseVersionKey(crate::core::MODULE_INSTANCE_ID_GLOBAL),
+            &DatabaseVersion(2),
+        )
+        .await;
+        dbtx.insert_new_entry(&DatabaseVersionKey(MODULE_ID), &DatabaseVersion(1))
+            .await;
+        for i in 0u8..100 {
+            dbtx.raw_insert_bytes(&[0x10, i], &vec![i; i as usize])
+                .await
+                .expect("DB write failed");
+        }
+        dbtx.commit_tx().await;
+        db
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn export_import_roundtrip() {
+        let db = populated_database().await;
+        let mut snapshot = vec![];
+        let exported = db.export_snapshot(&mut snapshot).await.unwrap();
+        assert_eq!(exported.entries, 102);
+
+        let restored = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let imported = restored.import_snapshot(snapshot.as_slice()).await.unwrap();
+        assert_eq!(exported, imported);
+        assert_eq!(all_entries(&db).await, all_entries(&restored).await);
+
+        let mut dbtx = restored.begin_transaction_nc().await;
+        assert_eq!(
+            dbtx.get_value(&DatabaseVersionKey(MODULE_ID)).await,
+            Some(DatabaseVersion(1))
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn import_rejects_corrupted_snapshot() {
+        let db = populated_database().await;
+        let mut snapshot = vec![];
+        db.export_snapshot(&mut snapshot).await.unwrap();
+
+        let middle = snapshot.len() / 2;
+        snapshot[middle] ^= 0xff;
+
+        let restored = MemDatabase::new().into_database();
+        assert!(restored.import_snapshot(snapshot.as_slice()).await.is_err());
+        assert!(all_entries(&restored).await.is_empty());
+
+        let truncated = &snapshot[..snapshot.len() - 1];
+        assert!(restored.import_snapshot(truncated).await.is_err());
+        assert!(all_entries(&restored).await.is_empty());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn import_requires_empty_database() {
+        let db = populated_database().await;
+        let mut snapshot = vec![];
+        db.export_snapshot(&mut snapshot).await.unwrap();
+
+        assert!(db.import_snapshot(snapshot.as_slice()).await.is_err());
+    }
+}
@@ -1,6 +1,8 @@
 pub const ADD_CONFIG_GEN_PEER_ENDPOINT: &str = "add_config_gen_peer";
 pub const AUDIT_ENDPOINT: &str = "audit";
 pub const GUARDIAN_CONFIG_BACKUP_ENDPOINT: &str = "download_guardian_backup";
+pub const GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT: &str = "download_guardian_database_snapshot";
+pub const START_GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT: &str = "start_guardian_database_snapshot";
 pub const AUTH_ENDPOINT: &str = "auth";
 pub const AWAIT_OUTPUT_OUTCOME_ENDPOINT: &str = "await_output_outcome";
 pub const BACKUP_ENDPOINT: &str = "backup";
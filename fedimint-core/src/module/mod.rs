@@ -848,6 +848,18 @@ pub trait ServerModule: Debug + Sized {
         peer_id: PeerId,
     ) -> anyhow::Result<()>;
 
+    /// This function is called once at the end of every session, after all of
+    /// its consensus items have been processed and in the same database
+    /// transaction that persists the session outcome. Since every peer calls
+    /// it at the same point of consensus it may be used to record state once
+    /// per session.
+    async fn end_session<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransaction<'b>,
+        _session_index: u64,
+    ) {
+    }
+
     /// Try to spend a transaction input. On success all necessary updates will
     /// be part of the database transaction. On failure (e.g. double spend)
     /// the database transaction is rolled back and the operation will take
@@ -876,7 +888,8 @@ pub trait ServerModule: Debug + Sized {
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
-    /// output is unknown, **NOT** if it is just not ready yet.
+    /// output is unknown or, for outputs that are processed later in
+    /// consensus (e.g. batched), until its outcome was determined.
     async fn output_status(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
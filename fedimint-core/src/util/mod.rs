@@ -110,6 +110,9 @@ impl SafeUrl {
     pub fn path(&self) -> &str {
         self.0.path()
     }
+    pub fn query_pairs(&self) -> url::form_urlencoded::Parse<'_> {
+        self.0.query_pairs()
+    }
     /// Warning: This will expose username & password if present.
     pub fn as_str(&self) -> &str {
         self.0.as_str()
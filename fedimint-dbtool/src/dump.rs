@@ -13,10 +13,10 @@ use fedimint_core::db::{
 use fedimint_core::encoding::Encodable;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::push_db_pair_items_no_serde;
-use fedimint_rocksdb::RocksDbReadOnly;
 use fedimint_server::config::io::read_server_config;
 use fedimint_server::config::ServerConfig;
 use fedimint_server::db as ConsensusRange;
+use fedimint_sqlite::DatabaseBackend;
 use futures::StreamExt;
 use ln_gateway::Gateway;
 use strum::IntoEnumIterator;
@@ -50,16 +50,17 @@ impl DatabaseDump {
     pub async fn new(
         cfg_dir: PathBuf,
         data_dir: String,
+        db_backend: DatabaseBackend,
         password: String,
         module_inits: ServerModuleInitRegistry,
         client_module_inits: ClientModuleInitRegistry,
         modules: Vec<String>,
         prefixes: Vec<String>,
     ) -> anyhow::Result<DatabaseDump> {
-        let read_only = match RocksDbReadOnly::open_read_only(data_dir.clone()) {
-            Ok(db) => Database::new(db, Default::default()),
+        let read_only = match db_backend.open_read_only(&data_dir) {
+            Ok(db) => db,
             Err(_) => {
-                panic!("Error reading RocksDB database. Quitting...");
+                panic!("Error reading database. Quitting...");
             }
         };
 
@@ -76,10 +77,10 @@ impl DatabaseDump {
         } else {
             // Check if this database is a client database by reading the `ClientConfig`
             // from the database.
-            let db = match RocksDbReadOnly::open_read_only(data_dir) {
-                Ok(db) => Database::new(db, Default::default()),
+            let db = match db_backend.open_read_only(&data_dir) {
+                Ok(db) => db,
                 Err(_) => {
-                    panic!("Error reading RocksDB database. Quitting...");
+                    panic!("Error reading database. Quitting...");
                 }
             };
 
@@ -1,6 +1,9 @@
 // Env variable to TODO
 pub const FM_DBTOOL_DATABASE_ENV: &str = "FM_DBTOOL_DATABASE";
 
+// Env variable to select the storage engine of the database
+pub const FM_DBTOOL_DB_BACKEND_ENV: &str = "FM_DBTOOL_DB_BACKEND";
+
 // Env variable to TODO
 pub const FM_DBTOOL_CONFIG_DIR_ENV: &str = "FM_DBTOOL_CONFIG_DIR";
 
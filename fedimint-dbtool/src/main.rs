@@ -1,6 +1,8 @@
 #![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
 pub mod envs;
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
 
 use anyhow::Result;
@@ -8,7 +10,7 @@ use bytes::Bytes;
 use clap::{Parser, Subcommand};
 use fedimint_client::module::init::{ClientModuleInitRegistry, DynClientModuleInit};
 use fedimint_core::config::ServerModuleInitRegistry;
-use fedimint_core::db::{IDatabaseTransactionOpsCore, IRawDatabaseExt};
+use fedimint_core::db::IDatabaseTransactionOpsCore;
 use fedimint_core::module::DynServerModuleInit;
 use fedimint_core::util::handle_version_hash_command;
 use fedimint_ln_client::LightningClientInit;
@@ -16,13 +18,16 @@ use fedimint_ln_server::LightningInit;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_client::MintClientInit;
 use fedimint_mint_server::MintInit;
+use fedimint_sqlite::DatabaseBackend;
 use fedimint_wallet_client::WalletClientInit;
 use fedimint_wallet_server::WalletInit;
 use futures::StreamExt;
 use hex::ToHex;
 
 use crate::dump::DatabaseDump;
-use crate::envs::{FM_DBTOOL_CONFIG_DIR_ENV, FM_DBTOOL_DATABASE_ENV, FM_PASSWORD_ENV};
+use crate::envs::{
+    FM_DBTOOL_CONFIG_DIR_ENV, FM_DBTOOL_DATABASE_ENV, FM_DBTOOL_DB_BACKEND_ENV, FM_PASSWORD_ENV,
+};
 
 mod dump;
 
@@ -32,6 +37,10 @@ struct Options {
     #[clap(long, env = FM_DBTOOL_DATABASE_ENV)]
     database: String,
 
+    /// Storage engine of the database
+    #[clap(long, env = FM_DBTOOL_DB_BACKEND_ENV, value_enum, default_value_t = DatabaseBackend::Rocksdb)]
+    db_backend: DatabaseBackend,
+
     #[clap(long, hide = true)]
     /// Run dbtool like it doesn't know about any module kind. This is a
     /// internal option for testing.
@@ -41,8 +50,8 @@ struct Options {
     command: DbCommand,
 }
 
-/// Tool to inspect and manipulate rocksdb databases. All binary arguments
-/// (keys, values) have to be hex encoded.
+/// Tool to inspect and manipulate RocksDB and SQLite databases. All binary
+/// arguments (keys, values) have to be hex encoded.
 #[derive(Debug, Clone, Subcommand)]
 enum DbCommand {
     /// List all key-value pairs where the key begins with `prefix`
@@ -83,6 +92,19 @@ enum DbCommand {
         #[arg(long, required = false)]
         prefixes: Option<String>,
     },
+    /// Write a consistent snapshot of the whole database to `out_file`. The
+    /// database is opened read-only, so this can be run while it is in use.
+    ExportSnapshot {
+        #[arg(long)]
+        out_file: PathBuf,
+    },
+    /// Restore a snapshot created by `export-snapshot` or downloaded from a
+    /// guardian into an empty database. The checksum is verified before any
+    /// entry is committed.
+    ImportSnapshot {
+        #[arg(long)]
+        in_file: PathBuf,
+    },
 }
 
 fn hex_parser(hex: &str) -> Result<Bytes> {
@@ -107,10 +129,8 @@ async fn main() -> Result<()> {
 
     match options.command {
         DbCommand::List { prefix } => {
-            let rocksdb = fedimint_rocksdb::RocksDb::open(&options.database)
-                .unwrap()
-                .into_database();
-            let mut dbtx = rocksdb.begin_transaction().await;
+            let db = options.db_backend.open(&options.database).unwrap();
+            let mut dbtx = db.begin_transaction().await;
             let prefix_iter = dbtx
                 .raw_find_by_prefix(&prefix)
                 .await?
@@ -122,23 +142,19 @@ async fn main() -> Result<()> {
             dbtx.commit_tx().await;
         }
         DbCommand::Write { key, value } => {
-            let rocksdb = fedimint_rocksdb::RocksDb::open(&options.database)
-                .unwrap()
-                .into_database();
-            let mut dbtx = rocksdb.begin_transaction().await;
+            let db = options.db_backend.open(&options.database).unwrap();
+            let mut dbtx = db.begin_transaction().await;
             dbtx.raw_insert_bytes(&key, &value)
                 .await
-                .expect("Error inserting entry into RocksDb");
+                .expect("Error inserting entry into database");
             dbtx.commit_tx().await;
         }
         DbCommand::Delete { key } => {
-            let rocksdb = fedimint_rocksdb::RocksDb::open(&options.database)
-                .unwrap()
-                .into_database();
-            let mut dbtx = rocksdb.begin_transaction().await;
+            let db = options.db_backend.open(&options.database).unwrap();
+            let mut dbtx = db.begin_transaction().await;
             dbtx.raw_remove_entry(&key)
                 .await
-                .expect("Error removing entry from RocksDb");
+                .expect("Error removing entry from database");
             dbtx.commit_tx().await;
         }
         DbCommand::Dump {
@@ -186,6 +202,7 @@ async fn main() -> Result<()> {
             let mut dbdump = DatabaseDump::new(
                 cfg_dir,
                 options.database,
+                options.db_backend,
                 password,
                 module_inits,
                 client_module_inits,
@@ -196,13 +213,23 @@ async fn main() -> Result<()> {
             dbdump.dump_database().await?;
         }
         DbCommand::DeletePrefix { prefix } => {
-            let rocksdb = fedimint_rocksdb::RocksDb::open(&options.database)
-                .unwrap()
-                .into_database();
-            let mut dbtx = rocksdb.begin_transaction().await;
+            let db = options.db_backend.open(&options.database).unwrap();
+            let mut dbtx = db.begin_transaction().await;
             dbtx.raw_remove_by_prefix(&prefix).await?;
             dbtx.commit_tx().await;
         }
+        DbCommand::ExportSnapshot { out_file } => {
+            let db = options.db_backend.open_read_only(&options.database)?;
+            let writer = BufWriter::new(File::create(&out_file)?);
+            let summary = db.export_snapshot(writer).await?;
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        }
+        DbCommand::ImportSnapshot { in_file } => {
+            let db = options.db_backend.open(&options.database)?;
+            let reader = BufReader::new(File::open(&in_file)?);
+            let summary = db.import_snapshot(reader).await?;
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        }
     }
 
     Ok(())
@@ -6,7 +6,7 @@ use std::time::Duration;
 use anyhow::{anyhow, bail, Context, Result};
 use bitcoin::secp256k1;
 use devimint::cmd;
-use devimint::util::{ClnLightningCli, FedimintCli, LnCli};
+use devimint::util::{ClnLightningCli, FedimintCli, GatewayFakeCli, LnCli};
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::transaction::TransactionBuilder;
 use fedimint_client::{Client, ClientHandleArc};
@@ -23,6 +23,7 @@ use fedimint_mint_client::{MintClientInit, MintClientModule, MintCommonInit, OOB
 use fedimint_wallet_client::WalletClientInit;
 use futures::StreamExt;
 use lightning_invoice::Bolt11Invoice;
+use ln_gateway::lightning::fake::{create_fake_invoice, FakePaymentOutcome};
 use tokio::sync::mpsc;
 use tracing::info;
 use tracing::log::warn;
@@ -325,6 +326,27 @@ pub async fn cln_wait_invoice_payment(label: &str) -> anyhow::Result<()> {
     }
 }
 
+/// Creates an invoice that the fake lightning node of a gateway pays
+/// successfully
+pub fn fake_create_invoice(amount: Amount) -> Bolt11Invoice {
+    create_fake_invoice(amount, FakePaymentOutcome::Succeed)
+}
+
+/// Pays an invoice by simulating an incoming payment through the gateway that
+/// runs a fake lightning node
+pub async fn fake_pay_invoice(invoice: Bolt11Invoice) -> anyhow::Result<()> {
+    cmd!(
+        GatewayFakeCli,
+        "lightning",
+        "simulate-payment",
+        "--invoice",
+        invoice.to_string()
+    )
+    .run()
+    .await?;
+    Ok(())
+}
+
 pub fn parse_gateway_id(s: &str) -> Result<secp256k1::PublicKey, secp256k1::Error> {
     secp256k1::PublicKey::from_str(s)
 }
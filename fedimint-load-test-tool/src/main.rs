@@ -7,12 +7,12 @@ use std::vec;
 use anyhow::{bail, Context};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use common::{
-    cln_create_invoice, cln_pay_invoice, cln_wait_invoice_payment, gateway_pay_invoice,
-    get_note_summary, lnd_create_invoice, lnd_pay_invoice, lnd_wait_invoice_payment,
-    parse_gateway_id, reissue_notes,
+    cln_create_invoice, cln_pay_invoice, cln_wait_invoice_payment, fake_create_invoice,
+    fake_pay_invoice, gateway_pay_invoice, get_note_summary, lnd_create_invoice, lnd_pay_invoice,
+    lnd_wait_invoice_payment, parse_gateway_id, reissue_notes,
 };
 use devimint::cmd;
-use devimint::util::{GatewayClnCli, GatewayLndCli};
+use devimint::util::{GatewayClnCli, GatewayFakeCli, GatewayLndCli};
 use fedimint_client::ClientHandleArc;
 use fedimint_core::bitcoin_migration::bitcoin30_to_bitcoin29_secp256k1_public_key;
 use fedimint_core::endpoint_constants::SESSION_COUNT_ENDPOINT;
@@ -64,6 +64,8 @@ struct Opts {
 enum LnInvoiceGeneration {
     ClnLightningCli,
     LnCli,
+    /// Invoices paid by a gateway running a fake lightning node
+    Fake,
 }
 
 #[derive(Subcommand, Clone)]
@@ -678,6 +680,18 @@ async fn do_load_test_user_task(
                     .await?;
                     lnd_wait_invoice_payment(r_hash).await?;
                 }
+                Some(LnInvoiceGeneration::Fake) => {
+                    let invoice = fake_create_invoice(invoice_amount);
+                    gateway_pay_invoice(
+                        &prefix,
+                        "FAKE",
+                        &client,
+                        invoice,
+                        &event_sender,
+                        ln_gateway.clone(),
+                    )
+                    .await?;
+                }
                 None if additional_invoices.is_empty() => {
                     debug!("No method given to generate an invoice and no invoices on file, will not test the gateway");
                     break;
@@ -928,6 +942,39 @@ async fn run_two_gateways_strategy(
             .await?;
             *invoice_generation = LnInvoiceGeneration::ClnLightningCli;
         }
+        LnInvoiceGeneration::Fake => {
+            // The fake gateway both pays and receives, so there is no other
+            // gateway to switch to
+            let invoice = fake_create_invoice(*invoice_amount);
+            let elapsed = create_invoice_time.elapsed()?;
+            info!("Created fake invoice in {elapsed:?}");
+            event_sender.send(MetricEvent {
+                name: GATEWAY_CREATE_INVOICE.into(),
+                duration: elapsed,
+            })?;
+            gateway_pay_invoice(
+                prefix,
+                "FAKE",
+                client,
+                invoice,
+                event_sender,
+                ln_gateway.clone(),
+            )
+            .await?;
+            let (operation_id, invoice) =
+                client_create_invoice(client, *invoice_amount, event_sender, ln_gateway).await?;
+            let pay_invoice_time = fedimint_core::time::now();
+            fake_pay_invoice(invoice).await?;
+            wait_invoice_payment(
+                prefix,
+                "FAKE",
+                client,
+                operation_id,
+                event_sender,
+                pay_invoice_time,
+            )
+            .await?;
+        }
     };
     Ok(())
 }
@@ -1348,6 +1395,7 @@ async fn get_gateway_id(generate_invoice_with: LnInvoiceGeneration) -> anyhow::R
             // and vice-versa
             cmd!(GatewayClnCli, "info").out_json().await
         }
+        LnInvoiceGeneration::Fake => cmd!(GatewayFakeCli, "info").out_json().await,
     }?;
     let gateway_id = gateway_json["gateway_id"]
         .as_str()
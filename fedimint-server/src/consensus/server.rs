@@ -202,6 +202,7 @@ impl ConsensusServer {
             latest_contribution_by_peer: Arc::clone(&latest_contribution_by_peer),
             peer_status_channels,
             consensus_status_cache: ExpiringCache::new(Duration::from_millis(500)),
+            database_snapshots: Arc::default(),
         };
 
         for (module_id, kind, module) in modules.iter_modules() {
@@ -601,6 +602,17 @@ impl ConsensusServer {
             panic!("We tried to overwrite a signed session outcome");
         }
 
+        for (module_instance_id, _, module) in self.modules.iter_modules() {
+            module
+                .end_session(
+                    &mut dbtx
+                        .to_ref_with_prefix_module_id(module_instance_id)
+                        .into_nc(),
+                    session_index,
+                )
+                .await;
+        }
+
         dbtx.commit_tx_result()
             .await
             .expect("This is the only place where we write to this key");
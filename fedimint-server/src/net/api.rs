@@ -2,6 +2,8 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -11,7 +13,8 @@ use async_trait::async_trait;
 use bitcoin_hashes::sha256;
 use fedimint_aead::{encrypt, get_encryption_key, random_salt};
 use fedimint_api_client::api::{
-    FederationStatus, GuardianConfigBackup, PeerConnectionStatus, PeerStatus, StatusResponse,
+    FederationStatus, GuardianConfigBackup, GuardianDatabaseSnapshot,
+    GuardianDatabaseSnapshotChunk, PeerConnectionStatus, PeerStatus, StatusResponse,
 };
 use fedimint_core::admin_client::ServerStatus;
 use fedimint_core::backup::{ClientBackupKey, ClientBackupSnapshot};
@@ -26,9 +29,10 @@ use fedimint_core::endpoint_constants::{
     AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_SESSION_OUTCOME_ENDPOINT,
     AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_ENDPOINT,
     CLIENT_CONFIG_ENDPOINT, FEDERATION_ID_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT,
-    INVITE_CODE_ENDPOINT, MODULES_CONFIG_JSON_ENDPOINT, RECOVER_ENDPOINT,
-    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT,
-    STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, VERIFY_CONFIG_HASH_ENDPOINT, VERSION_ENDPOINT,
+    GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT, INVITE_CODE_ENDPOINT, MODULES_CONFIG_JSON_ENDPOINT,
+    RECOVER_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT,
+    SESSION_STATUS_ENDPOINT, START_GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT, STATUS_ENDPOINT,
+    SUBMIT_TRANSACTION_ENDPOINT, VERIFY_CONFIG_HASH_ENDPOINT, VERSION_ENDPOINT,
 };
 use fedimint_core::epoch::ConsensusItem;
 use fedimint_core::module::audit::{Audit, AuditSummary};
@@ -40,12 +44,13 @@ use fedimint_core::module::{
 use fedimint_core::server::DynServerModule;
 use fedimint_core::session_outcome::{SessionOutcome, SessionStatus, SignedSessionOutcome};
 use fedimint_core::transaction::{SerdeTransaction, Transaction, TransactionError};
-use fedimint_core::{OutPoint, PeerId, TransactionId};
+use fedimint_core::{runtime, OutPoint, PeerId, TransactionId};
 use fedimint_logging::LOG_NET_API;
 use futures::StreamExt;
 use jsonrpsee::RpcModule;
 use secp256k1::SECP256K1;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::spawn_blocking;
 use tracing::{debug, info};
 
 use super::peers::PeerStatusChannels;
@@ -60,6 +65,12 @@ use crate::fedimint_core::encoding::Encodable;
 use crate::metrics::{BACKUP_WRITE_SIZE_BYTES, STORED_BACKUPS_COUNT};
 use crate::{check_auth, get_verification_hashes, ApiResult, HasApiContext};
 
+/// Maximum number of bytes of a database snapshot returned by a single request
+const DATABASE_SNAPSHOT_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Time after which a database snapshot that nobody downloaded from is deleted
+const DATABASE_SNAPSHOT_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
 /// A state that has context for the API, passed to each rpc handler callback
 #[derive(Clone)]
 pub struct RpcHandlerCtx<M> {
@@ -80,6 +91,15 @@ impl<M: Debug> Debug for RpcHandlerCtx<M> {
     }
 }
 
+/// Maximum time [`ConsensusApi::await_output_outcome`] waits for the outcome of
+/// an output that is only determined after its transaction was accepted, e.g.
+/// a batched peg-out, before the request fails and has to be repeated
+const OUTPUT_OUTCOME_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn database_snapshot_error(e: &dyn std::fmt::Display) -> ApiError {
+    ApiError::server_error(format!("Database snapshot failed: {e}"))
+}
+
 #[derive(Clone)]
 pub struct ConsensusApi {
     /// Our server configuration
@@ -96,6 +116,16 @@ pub struct ConsensusApi {
     pub latest_contribution_by_peer: Arc<RwLock<LatestContributionByPeer>>,
     pub consensus_status_cache: ExpiringCache<ApiResult<FederationStatus>>,
     pub supported_api_versions: SupportedApiVersionsSummary,
+    /// Database snapshots guardians are currently downloading, by id
+    pub database_snapshots: Arc<Mutex<BTreeMap<u64, DatabaseSnapshotFile>>>,
+}
+
+/// Temporary file holding an exported database snapshot
+pub struct DatabaseSnapshotFile {
+    /// Only read from blocking tasks, locked to seek and read in one go
+    file: Arc<std::sync::Mutex<File>>,
+    total_bytes: u64,
+    last_access: Instant,
 }
 
 impl ConsensusApi {
@@ -161,18 +191,38 @@ impl ConsensusApi {
             .nth(outpoint.out_idx as usize)
             .ok_or(anyhow!("Outpoint index out of bounds {:?}", outpoint))?;
 
-        let outcome = self
-            .modules
-            .get_expect(module_id)
-            .output_status(
-                &mut dbtx.to_ref_with_prefix_module_id(module_id).into_nc(),
-                outpoint,
-                module_id,
+        let deadline = fedimint_core::time::now() + OUTPUT_OUTCOME_TIMEOUT;
+        loop {
+            // The transaction is accepted, so the output is known to the module, but
+            // its outcome might only be determined later in consensus
+            if let Some(outcome) = self
+                .modules
+                .get_expect(module_id)
+                .output_status(
+                    &mut dbtx.to_ref_with_prefix_module_id(module_id).into_nc(),
+                    outpoint,
+                    module_id,
+                )
+                .await
+            {
+                return Ok((&outcome).into());
+            }
+
+            // The outcome is determined while processing a consensus item, so it is
+            // known by the end of the current session at the latest
+            let session_index = get_finished_session_count_static(&mut dbtx.to_ref_nc()).await;
+            let remaining = deadline
+                .duration_since(fedimint_core::time::now())
+                .unwrap_or_default();
+            fedimint_core::runtime::timeout(
+                remaining,
+                self.await_signed_session_outcome(session_index),
             )
             .await
-            .expect("The transaction is accepted");
+            .map_err(|_| anyhow!("Outcome of output {outpoint:?} is not determined yet"))?;
 
-        Ok((&outcome).into())
+            dbtx = self.db.begin_transaction().await;
+        }
     }
 
     pub async fn session_count(&self) -> u64 {
@@ -343,6 +393,83 @@ impl ConsensusApi {
         Ok(GuardianConfigBackup { tar_archive_bytes })
     }
 
+    /// Exports a consistent snapshot of the whole database into a temporary
+    /// file, so the database never has to be held in memory, which guardians
+    /// can then download in chunks using the returned id. Unlike the config
+    /// backup it is not encrypted and contains all consensus state, so it
+    /// should be stored securely.
+    async fn start_guardian_database_snapshot(&self) -> ApiResult<GuardianDatabaseSnapshot> {
+        let db = self.db.clone();
+        let (file, summary) = spawn_blocking(move || -> anyhow::Result<_> {
+            let mut writer = BufWriter::new(tempfile::tempfile()?);
+            let summary = runtime::block_on(db.export_snapshot(&mut writer))?;
+            Ok((writer.into_inner()?, summary))
+        })
+        .await
+        .map_err(|e| database_snapshot_error(&e))?
+        .map_err(|e| database_snapshot_error(&e))?;
+        let total_bytes = file
+            .metadata()
+            .map_err(|e| database_snapshot_error(&e))?
+            .len();
+
+        let id = rand::random();
+        let mut snapshots = self.database_snapshots.lock().await;
+        // Snapshots whose download was abandoned would otherwise never be deleted
+        snapshots.retain(|_, snapshot| snapshot.last_access.elapsed() < DATABASE_SNAPSHOT_EXPIRY);
+        snapshots.insert(
+            id,
+            DatabaseSnapshotFile {
+                file: Arc::new(std::sync::Mutex::new(file)),
+                total_bytes,
+                last_access: Instant::now(),
+            },
+        );
+
+        Ok(GuardianDatabaseSnapshot {
+            id,
+            summary,
+            total_bytes,
+        })
+    }
+
+    /// Returns the chunk of the database snapshot `id` starting at `offset`.
+    /// The snapshot is deleted once its last chunk was served.
+    async fn get_guardian_database_snapshot_chunk(
+        &self,
+        id: u64,
+        offset: u64,
+    ) -> ApiResult<GuardianDatabaseSnapshotChunk> {
+        let (file, total_bytes) = {
+            let mut snapshots = self.database_snapshots.lock().await;
+            let snapshot = snapshots
+                .get_mut(&id)
+                .ok_or_else(|| ApiError::bad_request(format!("Unknown database snapshot {id}")))?;
+            snapshot.last_access = Instant::now();
+            (snapshot.file.clone(), snapshot.total_bytes)
+        };
+
+        let chunk_bytes = spawn_blocking(move || {
+            let mut file = file.lock().expect("Locking failed");
+            let mut chunk_bytes = vec![];
+            file.seek(SeekFrom::Start(offset))?;
+            (&mut *file)
+                .take(DATABASE_SNAPSHOT_CHUNK_SIZE)
+                .read_to_end(&mut chunk_bytes)?;
+            Ok::<_, std::io::Error>(chunk_bytes)
+        })
+        .await
+        .map_err(|e| database_snapshot_error(&e))?
+        .map_err(|e| database_snapshot_error(&e))?;
+
+        // The temporary file is deleted once the last chunk was served
+        if total_bytes <= offset + chunk_bytes.len() as u64 {
+            self.database_snapshots.lock().await.remove(&id);
+        }
+
+        Ok(GuardianDatabaseSnapshotChunk { chunk_bytes })
+    }
+
     async fn handle_backup_request<'s, 'dbtx, 'a>(
         &'s self,
         dbtx: &'dbtx mut DatabaseTransaction<'a>,
@@ -568,6 +695,23 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(fedimint.get_guardian_config_backup(password).await?)
             }
         },
+        api_endpoint! {
+            START_GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> GuardianDatabaseSnapshot {
+                check_auth(context)?;
+                Ok(fedimint.start_guardian_database_snapshot().await?)
+            }
+        },
+        api_endpoint! {
+            GUARDIAN_DATABASE_SNAPSHOT_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, params: (u64, u64)| -> GuardianDatabaseSnapshotChunk {
+                check_auth(context)?;
+                let (id, offset) = params;
+                Ok(fedimint.get_guardian_database_snapshot_chunk(id, offset).await?)
+            }
+        },
         api_endpoint! {
             VERIFY_CONFIG_HASH_ENDPOINT,
             ApiVersion::new(0, 0),
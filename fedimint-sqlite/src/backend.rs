@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::ensure;
+use clap::ValueEnum;
+use fedimint_core::db::{Database, IRawDatabaseExt};
+use fedimint_rocksdb::{RocksDb, RocksDbReadOnly};
+
+use crate::SqliteDb;
+
+/// Storage engine of a database, selectable on the command line of
+/// `fedimintd`, `fedimint-cli`, `gatewayd` and `dbtool`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DatabaseBackend {
+    /// RocksDB database directory
+    Rocksdb,
+    /// Single-file SQLite database
+    Sqlite,
+}
+
+impl DatabaseBackend {
+    /// Location of the database called `name` in `dir`. RocksDB stores it in
+    /// the directory `name`, SQLite in a single file named like it with the
+    /// `sqlite` extension (e.g. `client.db` becomes `client.sqlite`).
+    pub fn path(self, dir: &Path, name: &str) -> PathBuf {
+        match self {
+            DatabaseBackend::Rocksdb => dir.join(name),
+            DatabaseBackend::Sqlite => dir.join(name).with_extension("sqlite"),
+        }
+    }
+
+    /// Opens the database at `path`, creating it if it doesn't exist yet
+    pub fn open(self, path: impl AsRef<Path>) -> anyhow::Result<Database> {
+        Ok(match self {
+            DatabaseBackend::Rocksdb => RocksDb::open(path)?.into_database(),
+            DatabaseBackend::Sqlite => SqliteDb::open(path)?.into_database(),
+        })
+    }
+
+    /// Opens the existing database at `path` to read it while it may be used
+    /// by another process. SQLite databases are opened like in
+    /// [`Self::open`] since their readers never block writers.
+    pub fn open_read_only(self, path: impl AsRef<Path>) -> anyhow::Result<Database> {
+        Ok(match self {
+            DatabaseBackend::Rocksdb => RocksDbReadOnly::open_read_only(path)?.into_database(),
+            DatabaseBackend::Sqlite => {
+                let path = path.as_ref();
+                ensure!(path.is_file(), "No sqlite database at {path:?}");
+                SqliteDb::open(path)?.into_database()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::DatabaseBackend;
+
+    #[test]
+    fn database_paths() {
+        let dir = Path::new("/data");
+        assert_eq!(
+            DatabaseBackend::Rocksdb.path(dir, "client.db"),
+            Path::new("/data/client.db")
+        );
+        assert_eq!(
+            DatabaseBackend::Sqlite.path(dir, "client.db"),
+            Path::new("/data/client.sqlite")
+        );
+        assert_eq!(
+            DatabaseBackend::Sqlite.path(dir, "database"),
+            Path::new("/data/database.sqlite")
+        );
+    }
+}
@@ -0,0 +1,3 @@
+// Env variable to set the time (in milliseconds) a connection waits for a
+// database lock before giving up
+pub const FM_SQLITE_BUSY_TIMEOUT_MS_ENV: &str = "FM_SQLITE_BUSY_TIMEOUT_MS";
@@ -0,0 +1,686 @@
+#![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+
+mod backend;
+pub mod envs;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use fedimint_core::db::{
+    IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction,
+    PrefixStream,
+};
+use futures::stream;
+pub use rusqlite;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use tracing::{debug, warn};
+
+pub use crate::backend::DatabaseBackend;
+use crate::envs::FM_SQLITE_BUSY_TIMEOUT_MS_ENV;
+
+const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often [`IRawDatabase::begin_transaction`] retries if sqlite reports
+/// `SQLITE_BUSY` despite the busy timeout, e.g. while recovering the WAL
+const BEGIN_TRANSACTION_ATTEMPTS: u32 = 10;
+const BEGIN_TRANSACTION_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A single-file [`IRawDatabase`] backed by SQLite
+///
+/// All key/value pairs live in one `kv` table ordered by their raw key bytes.
+/// The database runs in WAL mode, which gives every transaction a consistent
+/// read snapshot while other connections keep committing.
+///
+/// Transactions are optimistic, just like `fedimint-rocksdb`: writes are
+/// buffered in memory and only applied on commit, after checking that no
+/// other transaction modified any of the written keys since the snapshot was
+/// taken.
+pub struct SqliteDb {
+    path: PathBuf,
+    busy_timeout: Duration,
+    /// Idle connections that can be reused by new transactions
+    connections: Mutex<Vec<Connection>>,
+}
+
+impl fmt::Debug for SqliteDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteDb")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Buffered writes of a transaction, `None` values mark deleted keys
+type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+pub struct SqliteDbTransaction<'a> {
+    db: &'a SqliteDb,
+    /// Connection holding the read snapshot, `None` only after commit
+    conn: Option<Connection>,
+    writes: WriteSet,
+    /// Value each written key had in the snapshot, checked on commit to
+    /// detect write-write conflicts
+    snapshot_values: WriteSet,
+    savepoint: (WriteSet, WriteSet),
+}
+
+impl<'a> fmt::Debug for SqliteDbTransaction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "SqliteDbTransaction {{ db={:?}, writes_len={} }}",
+            self.db,
+            self.writes.len(),
+        ))
+    }
+}
+
+impl SqliteDb {
+    pub fn open(db_path: impl AsRef<Path>) -> anyhow::Result<SqliteDb> {
+        let busy_timeout = get_busy_timeout()?;
+        let db = SqliteDb {
+            path: db_path.as_ref().to_owned(),
+            busy_timeout,
+            connections: Mutex::new(vec![]),
+        };
+
+        let conn = db.new_connection()?;
+        // `journal_mode` is persisted in the database file, so it only needs to be set
+        // once, but it has to happen outside of any transaction
+        let journal_mode: String =
+            conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            bail!("Could not enable WAL mode for sqlite database, got {journal_mode}");
+        }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS kv (key BLOB PRIMARY KEY NOT NULL, value BLOB NOT NULL) WITHOUT ROWID",
+            [],
+        )?;
+        db.release_connection(conn);
+
+        Ok(db)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn new_connection(&self) -> anyhow::Result<Connection> {
+        let conn = Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_CREATE
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .with_context(|| format!("Could not open sqlite database at {:?}", self.path))?;
+        conn.busy_timeout(self.busy_timeout)?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        Ok(conn)
+    }
+
+    fn acquire_connection(&self) -> anyhow::Result<Connection> {
+        let idle = self
+            .connections
+            .lock()
+            .expect("Connection pool lock poisoned")
+            .pop();
+        match idle {
+            Some(conn) => Ok(conn),
+            None => self.new_connection(),
+        }
+    }
+
+    fn release_connection(&self, conn: Connection) {
+        if !conn.is_autocommit() {
+            // Connection is still inside a transaction and can't be reused safely
+            warn!("Dropping sqlite connection that is still inside a transaction");
+            return;
+        }
+        self.connections
+            .lock()
+            .expect("Connection pool lock poisoned")
+            .push(conn);
+    }
+
+    /// Returns a connection inside a read transaction whose snapshot is pinned
+    /// to the current state of the database
+    fn begin_read_snapshot(&self) -> anyhow::Result<Connection> {
+        let conn = self.acquire_connection()?;
+        // A `DEFERRED` transaction only takes its snapshot on the first read, so we
+        // read right away to pin it to the moment the transaction started
+        let res = conn
+            .execute_batch("BEGIN DEFERRED")
+            .context("Could not start sqlite transaction")
+            .and_then(|()| {
+                conn.query_row("SELECT count(*) FROM kv WHERE key = x''", [], |_| Ok(()))
+                    .context("Could not take sqlite read snapshot")
+            });
+        match res {
+            Ok(()) => Ok(conn),
+            Err(e) => {
+                if !conn.is_autocommit() {
+                    let _ = conn.execute_batch("ROLLBACK");
+                }
+                self.release_connection(conn);
+                Err(e)
+            }
+        }
+    }
+}
+
+fn is_busy(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<rusqlite::Error>()
+        .and_then(rusqlite::Error::sqlite_error_code)
+        == Some(rusqlite::ErrorCode::DatabaseBusy)
+}
+
+fn get_busy_timeout() -> anyhow::Result<Duration> {
+    match std::env::var(FM_SQLITE_BUSY_TIMEOUT_MS_ENV) {
+        Ok(var) => {
+            debug!(var, "Using custom sqlite busy timeout");
+            let millis: u64 = FromStr::from_str(&var)
+                .with_context(|| format!("Could not parse {FM_SQLITE_BUSY_TIMEOUT_MS_ENV}"))?;
+            Ok(Duration::from_millis(millis))
+        }
+        Err(_) => Ok(DEFAULT_BUSY_TIMEOUT),
+    }
+}
+
+// Upper (exclusive) bound of all keys starting with `prefix` in lexicographic
+// ordering, which is also the order sqlite uses to compare blobs.
+// Will return None if there is no next prefix (i.e prefix is already the last
+// possible/max one)
+fn next_prefix(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut next_prefix = prefix.to_vec();
+    while let Some(last) = next_prefix.pop() {
+        if last < u8::MAX {
+            next_prefix.push(last + 1);
+            return Some(next_prefix);
+        }
+    }
+    None
+}
+
+fn select_prefix(conn: &Connection, key_prefix: &[u8]) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+    let rows = match next_prefix(key_prefix) {
+        Some(next_prefix) => conn
+            .prepare_cached("SELECT key, value FROM kv WHERE key >= ?1 AND key < ?2")?
+            .query_map(params![key_prefix, next_prefix], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<_>>()?,
+        None => conn
+            .prepare_cached("SELECT key, value FROM kv WHERE key >= ?1")?
+            .query_map(params![key_prefix], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?,
+    };
+    Ok(rows)
+}
+
+fn select_value(conn: &Connection, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    Ok(conn
+        .prepare_cached("SELECT value FROM kv WHERE key = ?1")?
+        .query_row(params![key], |row| row.get(0))
+        .optional()?)
+}
+
+impl<'a> SqliteDbTransaction<'a> {
+    fn conn(&self) -> Result<&Connection> {
+        self.conn
+            .as_ref()
+            .context("Transaction was already committed")
+    }
+
+    /// Value of `key` as seen by this transaction, including its own writes
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => select_value(self.conn()?, key),
+        }
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+        let old_value = self.get(key)?;
+        if !self.snapshot_values.contains_key(key) {
+            let snapshot_value = select_value(self.conn()?, key)?;
+            self.snapshot_values.insert(key.to_vec(), snapshot_value);
+        }
+        self.writes.insert(key.to_vec(), value);
+        Ok(old_value)
+    }
+
+    fn find_by_prefix(&self, key_prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = select_prefix(self.conn()?, key_prefix)?;
+        let own_writes = self
+            .writes
+            .range(key_prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(key_prefix));
+        for (key, value) in own_writes {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let conn = self.conn.take().context("Can only commit once")?;
+        let res = commit_writes(&conn, &self.writes, &self.snapshot_values);
+        if res.is_err() && !conn.is_autocommit() {
+            conn.execute_batch("ROLLBACK")?;
+        }
+        self.db.release_connection(conn);
+        res
+    }
+}
+
+fn commit_writes(conn: &Connection, writes: &WriteSet, snapshot_values: &WriteSet) -> Result<()> {
+    // Release the read snapshot, we need to look at the latest state now
+    conn.execute_batch("COMMIT")?;
+    if writes.is_empty() {
+        return Ok(());
+    }
+
+    // `IMMEDIATE` takes the write lock right away, so nobody can commit between
+    // our conflict check and our writes
+    conn.execute_batch("BEGIN IMMEDIATE")?;
+    for (key, snapshot_value) in snapshot_values {
+        if select_value(conn, key)? != *snapshot_value {
+            bail!("write-write conflict");
+        }
+    }
+    for (key, value) in writes {
+        match value {
+            Some(value) => {
+                conn.prepare_cached("INSERT OR REPLACE INTO kv (key, value) VALUES (?1, ?2)")?
+                    .execute(params![key, value])?;
+            }
+            None => {
+                conn.prepare_cached("DELETE FROM kv WHERE key = ?1")?
+                    .execute(params![key])?;
+            }
+        }
+    }
+    conn.execute_batch("COMMIT")?;
+    Ok(())
+}
+
+impl<'a> Drop for SqliteDbTransaction<'a> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if let Err(e) = conn.execute_batch("ROLLBACK") {
+                warn!(?e, "Failed to roll back sqlite read transaction");
+            }
+            self.db.release_connection(conn);
+        }
+    }
+}
+
+#[async_trait]
+impl IRawDatabase for SqliteDb {
+    type Transaction<'a> = SqliteDbTransaction<'a>;
+    async fn begin_transaction<'a>(&'a self) -> SqliteDbTransaction<'a> {
+        fedimint_core::runtime::block_in_place(|| {
+            // The trait doesn't let us return an error, so all we can do is retry
+            // transient failures before giving up
+            let mut attempt = 1;
+            let conn = loop {
+                match self.begin_read_snapshot() {
+                    Ok(conn) => break conn,
+                    Err(e) if is_busy(&e) && attempt < BEGIN_TRANSACTION_ATTEMPTS => {
+                        warn!(%attempt, "sqlite database is busy, retrying to begin transaction");
+                        std::thread::sleep(BEGIN_TRANSACTION_RETRY_DELAY);
+                        attempt += 1;
+                    }
+                    Err(e) => panic!(
+                        "Error beginning transaction on sqlite database {:?}: {e:#}",
+                        self.path
+                    ),
+                }
+            };
+
+            SqliteDbTransaction {
+                db: self,
+                conn: Some(conn),
+                writes: BTreeMap::new(),
+                snapshot_values: BTreeMap::new(),
+                savepoint: (BTreeMap::new(), BTreeMap::new()),
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<'a> IDatabaseTransactionOpsCore for SqliteDbTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        fedimint_core::runtime::block_in_place(|| self.write(key, Some(value.to_vec())))
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        fedimint_core::runtime::block_in_place(|| self.get(key))
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        fedimint_core::runtime::block_in_place(|| self.write(key, None))
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let entries = fedimint_core::runtime::block_in_place(|| self.find_by_prefix(key_prefix))?;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> anyhow::Result<()> {
+        fedimint_core::runtime::block_in_place(|| {
+            for (key, _) in self.find_by_prefix(key_prefix)? {
+                self.write(&key, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let mut entries =
+            fedimint_core::runtime::block_in_place(|| self.find_by_prefix(key_prefix))?;
+        entries.reverse();
+        Ok(Box::pin(stream::iter(entries)))
+    }
+}
+
+#[async_trait]
+impl<'a> IDatabaseTransactionOps for SqliteDbTransaction<'a> {
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        let (writes, snapshot_values) = self.savepoint.clone();
+        self.writes = writes;
+        self.snapshot_values = snapshot_values;
+        Ok(())
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.savepoint = (self.writes.clone(), self.snapshot_values.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> IRawDatabaseTransaction for SqliteDbTransaction<'a> {
+    async fn commit_tx(mut self) -> Result<()> {
+        fedimint_core::runtime::block_in_place(|| self.commit())
+    }
+}
+
+#[cfg(test)]
+mod fedimint_sqlite_tests {
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::{impl_db_lookup, impl_db_record};
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn open_temp_db(temp_path: &str) -> Database {
+        let path = tempfile::Builder::new()
+            .prefix(temp_path)
+            .tempdir()
+            .unwrap()
+            .into_path();
+
+        Database::new(
+            SqliteDb::open(path.join("database.sqlite")).unwrap(),
+            ModuleDecoderRegistry::default(),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_insert_elements() {
+        fedimint_core::db::verify_insert_elements(open_temp_db("fcb-sqlite-test-insert-elements"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_nonexisting() {
+        fedimint_core::db::verify_remove_nonexisting(open_temp_db(
+            "fcb-sqlite-test-remove-nonexisting",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_existing() {
+        fedimint_core::db::verify_remove_existing(open_temp_db("fcb-sqlite-test-remove-existing"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_read_own_writes() {
+        fedimint_core::db::verify_read_own_writes(open_temp_db("fcb-sqlite-test-read-own-writes"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_prevent_dirty_reads() {
+        fedimint_core::db::verify_prevent_dirty_reads(open_temp_db(
+            "fcb-sqlite-test-prevent-dirty-reads",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_find_by_prefix() {
+        fedimint_core::db::verify_find_by_prefix(open_temp_db("fcb-sqlite-test-find-by-prefix"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_commit() {
+        fedimint_core::db::verify_commit(open_temp_db("fcb-sqlite-test-commit")).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_prevent_nonrepeatable_reads() {
+        fedimint_core::db::verify_prevent_nonrepeatable_reads(open_temp_db(
+            "fcb-sqlite-test-prevent-nonrepeatable-reads",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_rollback_to_savepoint() {
+        fedimint_core::db::verify_rollback_to_savepoint(open_temp_db(
+            "fcb-sqlite-test-rollback-to-savepoint",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_phantom_entry() {
+        fedimint_core::db::verify_phantom_entry(open_temp_db("fcb-sqlite-test-phantom-entry"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_write_conflict() {
+        fedimint_core::db::expect_write_conflict(open_temp_db("fcb-sqlite-test-write-conflict"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_remove_by_prefix() {
+        fedimint_core::db::verify_remove_by_prefix(open_temp_db(
+            "fcb-sqlite-test-remove-by-prefix",
+        ))
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_module_dbtx() {
+        fedimint_core::db::verify_module_prefix(open_temp_db("fcb-sqlite-test-module-prefix"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_module_db() {
+        let module_instance_id = 1;
+        let module_db = open_temp_db("fcb-sqlite-test-module-db-prefix");
+
+        fedimint_core::db::verify_module_db(
+            open_temp_db("fcb-sqlite-test-module-db"),
+            module_db.with_prefix_module_id(module_instance_id),
+        )
+        .await;
+    }
+
+    #[test]
+    fn test_next_prefix() {
+        assert_eq!(next_prefix(&[1, 2, 3]).unwrap(), vec![1, 2, 4]);
+        assert_eq!(next_prefix(&[1, 2, 254]).unwrap(), vec![1, 2, 255]);
+        assert_eq!(next_prefix(&[1, 2, 255]).unwrap(), vec![1, 3]);
+        assert_eq!(next_prefix(&[1, 255, 255]).unwrap(), vec![2]);
+        // this is a "max" prefix
+        assert!(next_prefix(&[255, 255, 255]).is_none());
+        assert_eq!(next_prefix(&[0]).unwrap(), vec![1]);
+        assert!(next_prefix(&[255]).is_none());
+        assert!(next_prefix(&[]).is_none());
+    }
+
+    #[repr(u8)]
+    #[derive(Clone)]
+    pub enum TestDbKeyPrefix {
+        Test = 254,
+        MaxTest = 255,
+    }
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestKey(pub Vec<u8>);
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestVal(pub Vec<u8>);
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct DbPrefixTestPrefix;
+
+    impl_db_record!(
+        key = TestKey,
+        value = TestVal,
+        db_prefix = TestDbKeyPrefix::Test,
+        notify_on_modify = true,
+    );
+    impl_db_lookup!(key = TestKey, query_prefix = DbPrefixTestPrefix);
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestKey2(pub Vec<u8>);
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable)]
+    pub(super) struct TestVal2(pub Vec<u8>);
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct DbPrefixTestPrefixMax;
+
+    impl_db_record!(
+        key = TestKey2,
+        value = TestVal2,
+        db_prefix = TestDbKeyPrefix::MaxTest, // max/last prefix
+        notify_on_modify = true,
+    );
+    impl_db_lookup!(key = TestKey2, query_prefix = DbPrefixTestPrefixMax);
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retrieve_descending_order() {
+        let db = open_temp_db("fcb-sqlite-test-descending-order");
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(vec![0]), &TestVal(vec![3]))
+            .await;
+        dbtx.insert_entry(&TestKey(vec![254]), &TestVal(vec![1]))
+            .await;
+        dbtx.insert_entry(&TestKey2(vec![0]), &TestVal2(vec![3]))
+            .await;
+        dbtx.insert_entry(&TestKey2(vec![254]), &TestVal2(vec![1]))
+            .await;
+        dbtx.commit_tx().await;
+
+        // Mix committed entries with uncommitted ones of the same transaction
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(vec![255]), &TestVal(vec![2]))
+            .await;
+        dbtx.insert_entry(&TestKey2(vec![255]), &TestVal2(vec![2]))
+            .await;
+        let query = dbtx
+            .find_by_prefix_sorted_descending(&DbPrefixTestPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            query,
+            vec![
+                (TestKey(vec![255]), TestVal(vec![2])),
+                (TestKey(vec![254]), TestVal(vec![1])),
+                (TestKey(vec![0]), TestVal(vec![3]))
+            ]
+        );
+        let query = dbtx
+            .find_by_prefix_sorted_descending(&DbPrefixTestPrefixMax)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            query,
+            vec![
+                (TestKey2(vec![255]), TestVal2(vec![2])),
+                (TestKey2(vec![254]), TestVal2(vec![1])),
+                (TestKey2(vec![0]), TestVal2(vec![3]))
+            ]
+        );
+        dbtx.commit_tx().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reopen_persists_data() {
+        let path = tempfile::Builder::new()
+            .prefix("fcb-sqlite-test-reopen")
+            .tempdir()
+            .unwrap();
+        let db_path = path.path().join("database.sqlite");
+        {
+            let db = Database::new(SqliteDb::open(&db_path).unwrap(), Default::default());
+            let mut dbtx = db.begin_transaction().await;
+            dbtx.insert_entry(&TestKey(vec![1]), &TestVal(vec![2]))
+                .await;
+            dbtx.commit_tx().await;
+        }
+
+        let db = Database::new(SqliteDb::open(&db_path).unwrap(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.get_value(&TestKey(vec![1])).await,
+            Some(TestVal(vec![2]))
+        );
+    }
+
+    #[test]
+    fn test_is_busy() {
+        let path = tempfile::Builder::new()
+            .prefix("fcb-sqlite-test-busy")
+            .tempdir()
+            .unwrap();
+        let db = SqliteDb::open(path.path().join("database.sqlite")).unwrap();
+        let writer = db.new_connection().unwrap();
+        writer.execute_batch("BEGIN IMMEDIATE").unwrap();
+
+        let conn = db.new_connection().unwrap();
+        conn.busy_timeout(Duration::ZERO).unwrap();
+        let err = conn
+            .execute_batch("BEGIN IMMEDIATE")
+            .context("Could not start sqlite transaction")
+            .unwrap_err();
+        assert!(is_busy(&err));
+        assert!(!is_busy(&anyhow::anyhow!("Some other error")));
+    }
+}
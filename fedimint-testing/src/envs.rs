@@ -7,6 +7,9 @@ pub const FM_TEST_USE_REAL_DAEMONS_ENV: &str = "FM_TEST_USE_REAL_DAEMONS";
 // Env variable to TODO
 pub const FM_PORT_ESPLORA_ENV: &str = "FM_PORT_ESPLORA";
 
+// Env variable to set the P2P port of the bitcoind used in real daemon tests
+pub const FM_PORT_BTC_P2P_ENV: &str = "FM_PORT_BTC_P2P";
+
 // Env variable to TODO
 pub const FM_TEST_DIR_ENV: &str = "FM_TEST_DIR";
 
@@ -20,7 +20,7 @@ use ln_gateway::client::GatewayClientBuilder;
 use ln_gateway::lightning::{ILnRpcClient, LightningBuilder};
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::{ConnectFedPayload, FederationInfo, V1_API_ENDPOINT};
-use ln_gateway::{Gateway, GatewayState};
+use ln_gateway::{DatabaseBackend, Gateway, GatewayState};
 use secp256k1::PublicKey;
 use tempfile::TempDir;
 use tracing::{info, warn};
@@ -117,7 +117,7 @@ impl GatewayTest {
 
         // Create federation client builder for the gateway
         let client_builder: GatewayClientBuilder =
-            GatewayClientBuilder::new(path.clone(), registry, 0);
+            GatewayClientBuilder::new(path.clone(), registry, 0, DatabaseBackend::Rocksdb);
 
         let lightning_builder: Arc<dyn LightningBuilder + Send + Sync> = if Fixtures::is_real_test()
         {
@@ -260,11 +260,11 @@ pub struct RealLightningBuilder {
 
 #[async_trait]
 impl LightningBuilder for RealLightningBuilder {
-    async fn build(&self) -> Box<dyn ILnRpcClient> {
-        match &self.node_type {
+    async fn build(&self) -> anyhow::Result<Box<dyn ILnRpcClient>> {
+        Ok(match &self.node_type {
             LightningNodeType::Cln => Box::new(ClnLightningTest::new().await),
             LightningNodeType::Lnd => Box::new(LndLightningTest::new().await),
-        }
+        })
     }
 }
 
@@ -273,7 +273,7 @@ pub struct FakeLightningBuilder;
 
 #[async_trait]
 impl LightningBuilder for FakeLightningBuilder {
-    async fn build(&self) -> Box<dyn ILnRpcClient> {
-        Box::new(FakeLightningTest::new())
+    async fn build(&self) -> anyhow::Result<Box<dyn ILnRpcClient>> {
+        Ok(Box::new(FakeLightningTest::new()))
     }
 }
@@ -218,7 +218,15 @@ impl ILnRpcClient for FakeLightningTest {
     }
 
     async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
-        unimplemented!("FakeLightningTest does not support getting a funding address")
+        let address = bitcoin30::Address::p2wpkh(
+            &bitcoin30::PublicKey::new(self.gateway_node_pub_key),
+            bitcoin30::Network::Regtest,
+        )
+        .expect("Node key is compressed");
+
+        Ok(GetFundingAddressResponse {
+            address: address.to_string(),
+        })
     }
 
     async fn open_channel(
@@ -7,6 +7,9 @@ pub const FM_EXTRA_DKG_META_ENV: &str = "FM_EXTRA_DKG_META";
 // Env variable to TODO
 pub const FM_DATA_DIR_ENV: &str = "FM_DATA_DIR";
 
+// Env variable to select the database backend (`rocksdb` or `sqlite`)
+pub const FM_DB_BACKEND_ENV: &str = "FM_DB_BACKEND";
+
 // Env variable to TODO
 pub const FM_PASSWORD_ENV: &str = "FM_PASSWORD";
 
@@ -31,6 +34,34 @@ pub const FM_BITCOIN_NETWORK_ENV: &str = "FM_BITCOIN_NETWORK";
 // Env variable to TODO
 pub const FM_FINALITY_DELAY_ENV: &str = "FM_FINALITY_DELAY";
 
+// Env variable to enable peg-out batching with a window of the given number of
+// blocks
+pub const FM_PEG_OUT_BATCH_WINDOW_ENV: &str = "FM_PEG_OUT_BATCH_WINDOW";
+
+// Env variable to set the number of queued peg-outs that trigger a batch
+pub const FM_PEG_OUT_MAX_BATCH_SIZE_ENV: &str = "FM_PEG_OUT_MAX_BATCH_SIZE";
+
+// Env variable to use Taproot peg-in addresses for a new federation
+pub const FM_TAPROOT_PEG_IN_ENV: &str = "FM_TAPROOT_PEG_IN";
+
+// Env variable to enable UTXO consolidation below the given fee rate in sats
+// per kvB
+pub const FM_CONSOLIDATION_MAX_FEE_RATE_ENV: &str = "FM_CONSOLIDATION_MAX_FEE_RATE";
+
+// Env variable to set the number of UTXOs above which UTXOs get consolidated
+pub const FM_CONSOLIDATION_MIN_UTXOS_ENV: &str = "FM_CONSOLIDATION_MIN_UTXOS";
+
+// Env variable to set the maximum number of UTXOs swept per consolidation
+pub const FM_CONSOLIDATION_MAX_INPUTS_ENV: &str = "FM_CONSOLIDATION_MAX_INPUTS";
+
+// Env variable to enable CPFP fee bumps up to the given fee rate in sats per
+// kvB
+pub const FM_CPFP_MAX_FEE_RATE_ENV: &str = "FM_CPFP_MAX_FEE_RATE";
+
+// Env variable to set the maximum fee in sats paid by a single CPFP child
+// transaction
+pub const FM_CPFP_MAX_FEE_ENV: &str = "FM_CPFP_MAX_FEE";
+
 // Env variable to TODO
 pub const FM_BIND_METRICS_API_ENV: &str = "FM_BIND_METRICS_API";
 
@@ -8,17 +8,19 @@ use std::time::Duration;
 use anyhow::{format_err, Context};
 use clap::{Parser, Subcommand};
 use fedimint_core::admin_client::ConfigGenParamsRequest;
-use fedimint_core::bitcoin_migration::bitcoin30_to_bitcoin29_network;
+use fedimint_core::bitcoin_migration::{
+    bitcoin30_to_bitcoin29_amount, bitcoin30_to_bitcoin29_network,
+};
 use fedimint_core::config::{
     ModuleInitParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
 };
 use fedimint_core::core::ModuleKind;
-use fedimint_core::db::Database;
 use fedimint_core::envs::{is_env_var_set, BitcoinRpcConfig, FM_USE_UNKNOWN_MODULE_ENV};
 use fedimint_core::module::{ServerApiVersionsSummary, ServerDbVersionsSummary, ServerModuleInit};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::timing;
 use fedimint_core::util::{handle_version_hash_command, write_overwrite, SafeUrl};
+use fedimint_core::Feerate;
 use fedimint_ln_common::config::{
     LightningGenParams, LightningGenParamsConsensus, LightningGenParamsLocal,
 };
@@ -31,9 +33,11 @@ use fedimint_server::config::api::ConfigGenSettings;
 use fedimint_server::config::io::{DB_FILE, PLAINTEXT_PASSWORD};
 use fedimint_server::config::ServerConfig;
 use fedimint_server::FedimintServer;
+use fedimint_sqlite::DatabaseBackend;
 use fedimint_unknown_common::config::UnknownGenParams;
 use fedimint_unknown_server::UnknownInit;
 use fedimint_wallet_server::common::config::{
+    CpfpConfig, PegInDescriptorKind, PegOutBatchingConfig, UtxoConsolidationConfig,
     WalletGenParams, WalletGenParamsConsensus, WalletGenParamsLocal,
 };
 use fedimint_wallet_server::WalletInit;
@@ -43,8 +47,11 @@ use tracing::{debug, error, info};
 use crate::default_esplora_server;
 use crate::envs::{
     FM_API_URL_ENV, FM_BIND_API_ENV, FM_BIND_METRICS_API_ENV, FM_BIND_P2P_ENV,
-    FM_BITCOIN_NETWORK_ENV, FM_DATA_DIR_ENV, FM_DISABLE_META_MODULE_ENV, FM_EXTRA_DKG_META_ENV,
-    FM_FINALITY_DELAY_ENV, FM_P2P_URL_ENV, FM_PASSWORD_ENV, FM_TOKIO_CONSOLE_BIND_ENV,
+    FM_BITCOIN_NETWORK_ENV, FM_CONSOLIDATION_MAX_FEE_RATE_ENV, FM_CONSOLIDATION_MAX_INPUTS_ENV,
+    FM_CONSOLIDATION_MIN_UTXOS_ENV, FM_CPFP_MAX_FEE_ENV, FM_CPFP_MAX_FEE_RATE_ENV, FM_DATA_DIR_ENV,
+    FM_DB_BACKEND_ENV, FM_DISABLE_META_MODULE_ENV, FM_EXTRA_DKG_META_ENV, FM_FINALITY_DELAY_ENV,
+    FM_P2P_URL_ENV, FM_PASSWORD_ENV, FM_PEG_OUT_BATCH_WINDOW_ENV, FM_PEG_OUT_MAX_BATCH_SIZE_ENV,
+    FM_TAPROOT_PEG_IN_ENV, FM_TOKIO_CONSOLE_BIND_ENV,
 };
 use crate::fedimintd::metrics::APP_START_TS;
 
@@ -87,6 +94,35 @@ pub struct ServerOpts {
     /// The number of blocks the federation stays behind the blockchain tip
     #[arg(long, env = FM_FINALITY_DELAY_ENV, default_value = "10")]
     finality_delay: u32,
+    /// Combine peg-outs into batch transactions, waiting at most this many
+    /// blocks for a batch to fill up
+    #[arg(long, env = FM_PEG_OUT_BATCH_WINDOW_ENV)]
+    peg_out_batch_window: Option<u32>,
+    /// The number of queued peg-outs that are batched right away
+    #[arg(long, env = FM_PEG_OUT_MAX_BATCH_SIZE_ENV, default_value = "50")]
+    peg_out_max_batch_size: u32,
+    /// Use Taproot instead of SegWit v0 peg-in addresses, only has an effect
+    /// when generating the config of a new federation
+    #[arg(long, env = FM_TAPROOT_PEG_IN_ENV)]
+    taproot_peg_in: bool,
+    /// Consolidate the federation's UTXOs while the fee rate is at or below
+    /// this many sats per kvB
+    #[arg(long, env = FM_CONSOLIDATION_MAX_FEE_RATE_ENV)]
+    consolidation_max_fee_rate: Option<u64>,
+    /// The number of UTXOs the federation needs to hold before they get
+    /// consolidated
+    #[arg(long, env = FM_CONSOLIDATION_MIN_UTXOS_ENV, default_value = "100")]
+    consolidation_min_utxos: u32,
+    /// The maximum number of UTXOs swept by a single consolidation transaction
+    #[arg(long, env = FM_CONSOLIDATION_MAX_INPUTS_ENV, default_value = "50")]
+    consolidation_max_inputs: u32,
+    /// Bump the fees of stuck peg-out transactions with CPFP up to this many
+    /// sats per kvB
+    #[arg(long, env = FM_CPFP_MAX_FEE_RATE_ENV)]
+    cpfp_max_fee_rate: Option<u64>,
+    /// The maximum fee in sats paid by a single CPFP transaction
+    #[arg(long, env = FM_CPFP_MAX_FEE_ENV, default_value = "100000")]
+    cpfp_max_fee: u64,
 
     #[arg(long, env = FM_BIND_METRICS_API_ENV)]
     bind_metrics_api: Option<SocketAddr>,
@@ -96,6 +132,10 @@ pub struct ServerOpts {
     #[arg(long, env = FM_EXTRA_DKG_META_ENV, value_parser = parse_map, default_value="")]
     extra_dkg_meta: BTreeMap<String, String>,
 
+    /// Database backend used to store the federation state
+    #[arg(long, env = FM_DB_BACKEND_ENV, value_enum, default_value_t = DatabaseBackend::Rocksdb)]
+    db_backend: DatabaseBackend,
+
     #[clap(subcommand)]
     subcommand: Option<ServerSubcommand>,
 }
@@ -246,6 +286,32 @@ impl Fedimintd {
 
         let bitcoind_rpc = self.bitcoind_rpc.clone();
         let finality_delay = self.opts.finality_delay;
+        let peg_out_batching = self
+            .opts
+            .peg_out_batch_window
+            .map(|window| PegOutBatchingConfig {
+                window,
+                max_batch_size: self.opts.peg_out_max_batch_size,
+            });
+        let peg_in_descriptor_kind = if self.opts.taproot_peg_in {
+            PegInDescriptorKind::Taproot
+        } else {
+            PegInDescriptorKind::Wsh
+        };
+        let utxo_consolidation =
+            self.opts
+                .consolidation_max_fee_rate
+                .map(|sats_per_kvb| UtxoConsolidationConfig {
+                    max_fee_rate: Feerate { sats_per_kvb },
+                    min_utxos: self.opts.consolidation_min_utxos,
+                    max_inputs: self.opts.consolidation_max_inputs,
+                });
+        let cpfp = self.opts.cpfp_max_fee_rate.map(|sats_per_kvb| CpfpConfig {
+            max_fee_rate: Feerate { sats_per_kvb },
+            max_fee: bitcoin30_to_bitcoin29_amount(bitcoin::Amount::from_sat(
+                self.opts.cpfp_max_fee,
+            )),
+        });
         let s = self
             .with_module_kind(LightningInit)
             .with_module_instance(
@@ -284,6 +350,10 @@ impl Fedimintd {
                         finality_delay,
                         client_default_bitcoin_rpc: default_esplora_server(network),
                         fee_consensus: Default::default(),
+                        peg_out_batching,
+                        peg_in_descriptor_kind,
+                        utxo_consolidation,
+                        cpfp,
                     },
                 },
             );
@@ -454,10 +524,9 @@ async fn run(
         registry: module_inits.clone(),
     };
 
-    let db = Database::new(
-        fedimint_rocksdb::RocksDb::open(data_dir.join(DB_FILE))?,
-        Default::default(),
-    );
+    let db = opts
+        .db_backend
+        .open(opts.db_backend.path(&data_dir, DB_FILE))?;
 
     FedimintServer::new(data_dir, settings, db, version_hash)
         .run(&module_inits, task_group.clone())
@@ -10,13 +10,19 @@ use fedimint_core::bitcoin_migration::{
 };
 use fedimint_core::config::FederationId;
 use fedimint_core::util::{retry, ConstantBackoff, SafeUrl};
-use fedimint_core::{fedimint_build_code_version_env, BitcoinAmountOrAll};
+use fedimint_core::{fedimint_build_code_version_env, Amount, BitcoinAmountOrAll};
 use fedimint_logging::TracingSetup;
+use lightning_invoice::Bolt11Invoice;
+use ln_gateway::fees::FeePolicyConfig;
+use ln_gateway::liquidity::LiquidityTarget;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::{
     BackupPayload, BalancePayload, ConfigPayload, ConnectFedPayload, ConnectToPeerPayload,
     DepositAddressPayload, FederationRoutingFees, GetFundingAddressPayload, LeaveFedPayload,
-    OpenChannelPayload, RestorePayload, SetConfigurationPayload, WithdrawPayload, V1_API_ENDPOINT,
+    LiquidityLogPayload, ListPaymentsPayload, OpenChannelPayload, PaymentSummaryPayload,
+    RebalancePayload, RestorePayload, SetConfigurationPayload, SetFederationFeesPayload,
+    SetFeePolicyPayload, SetLiquidityTargetPayload, SimulatePaymentPayload, WithdrawPayload,
+    V1_API_ENDPOINT,
 };
 use serde::Serialize;
 
@@ -106,13 +112,20 @@ pub enum Commands {
         #[clap(long)]
         network: Option<bitcoin::Network>,
 
-        /// Format federation id,base msat,proportional to millionths part. Any
-        /// other federations not given here will keep their current fees.
+        /// Outgoing fees of specific federations. Format federation id,base
+        /// msat,proportional to millionths part. Any other federations not
+        /// given here will keep their current fees.
         #[clap(long)]
         per_federation_routing_fees: Option<Vec<PerFederationRoutingFees>>,
     },
     #[command(subcommand)]
     Lightning(LightningCommands),
+    #[command(subcommand)]
+    Fees(FeesCommands),
+    #[command(subcommand)]
+    Payments(PaymentsCommands),
+    #[command(subcommand)]
+    Liquidity(LiquidityCommands),
 }
 
 /// This API is intentionally kept very minimal, as its main purpose is to
@@ -158,6 +171,154 @@ pub enum LightningCommands {
         #[clap(long)]
         retry_delay_seconds: Option<u64>,
     },
+    /// Simulate an incoming payment of an invoice through the gateway. Only
+    /// supported by gateways running a fake lightning node.
+    SimulatePayment {
+        #[clap(long)]
+        invoice: Bolt11Invoice,
+    },
+}
+
+/// Manage the fees the gateway charges for each federation
+#[derive(Subcommand)]
+pub enum FeesCommands {
+    /// Display the configured and announced fees of all federations
+    List,
+    /// Set the fees of a federation. Format: <base msat>,<proportional to
+    /// millionths part>
+    Set {
+        #[clap(long)]
+        federation_id: FederationId,
+
+        /// Fees for paying invoices on behalf of the federation's users
+        #[clap(long)]
+        outgoing: Option<FederationRoutingFees>,
+
+        /// Fees for receiving payments into the federation
+        #[clap(long)]
+        incoming: Option<FederationRoutingFees>,
+    },
+    /// Set the policy deriving the announced fees from the configured fees
+    #[command(subcommand)]
+    SetPolicy(FeePolicyCommands),
+}
+
+/// Inspect the payments the gateway routed. Times are unix timestamps in
+/// seconds.
+#[derive(Subcommand)]
+pub enum PaymentsCommands {
+    /// List payments, most recent first
+    List {
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+
+        /// Only list payments started at or after this time
+        #[clap(long)]
+        start_time: Option<u64>,
+
+        /// Only list payments started before this time
+        #[clap(long)]
+        end_time: Option<u64>,
+
+        /// Only list payments that failed
+        #[clap(long)]
+        failed: bool,
+
+        /// Maximum number of payments to list
+        #[clap(long)]
+        limit: Option<usize>,
+    },
+    /// Summarize the volume and the fees earned per federation
+    Summary {
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+
+        /// Only summarize payments started at or after this time
+        #[clap(long)]
+        start_time: Option<u64>,
+
+        /// Only summarize payments started before this time
+        #[clap(long)]
+        end_time: Option<u64>,
+    },
+}
+
+/// Manage the automatic rebalancing of ecash between the federations and the
+/// on-chain wallet of the lightning node. Amounts default to msat, other units
+/// can be given as suffix, e.g. `100000sat`.
+#[derive(Subcommand)]
+pub enum LiquidityCommands {
+    /// Display the ecash balances and liquidity targets of all federations
+    Status,
+    /// Peg in from the node wallet when the ecash balance of a federation
+    /// drops below `min` and peg out to the node wallet when it exceeds `max`,
+    /// in both cases restoring `target`
+    SetTarget {
+        #[clap(long)]
+        federation_id: FederationId,
+
+        #[clap(long)]
+        target: Amount,
+
+        #[clap(long)]
+        min: Amount,
+
+        #[clap(long)]
+        max: Amount,
+    },
+    /// Stop managing the liquidity of a federation
+    RemoveTarget {
+        #[clap(long)]
+        federation_id: FederationId,
+    },
+    /// Rebalance the ecash of a federation right away if it is outside of
+    /// its target's bounds
+    Rebalance {
+        #[clap(long)]
+        federation_id: FederationId,
+    },
+    /// List the peg-ins and peg-outs of the liquidity manager, most recent
+    /// first
+    Log {
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+
+        #[clap(long)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FeePolicyCommands {
+    /// Always announce the configured fees
+    Static,
+    /// Multiply the fees of a payment direction while the lightning liquidity
+    /// it consumes is scarce
+    Liquidity {
+        /// Share of the total channel balance, in parts per million, below
+        /// which liquidity is considered scarce
+        #[clap(long)]
+        scarcity_threshold_ppm: u64,
+
+        /// Percentage to multiply the fees by while liquidity is scarce
+        #[clap(long)]
+        scarcity_multiplier_percent: u64,
+    },
+}
+
+impl From<FeePolicyCommands> for FeePolicyConfig {
+    fn from(command: FeePolicyCommands) -> Self {
+        match command {
+            FeePolicyCommands::Static => FeePolicyConfig::Static,
+            FeePolicyCommands::Liquidity {
+                scarcity_threshold_ppm,
+                scarcity_multiplier_percent,
+            } => FeePolicyConfig::Liquidity {
+                scarcity_threshold_ppm,
+                scarcity_multiplier_percent,
+            },
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -346,6 +507,117 @@ async fn main() -> anyhow::Result<()> {
                 .await
                 .map_err(|_| anyhow::anyhow!("Timed out waiting for chain sync"))?;
             }
+            LightningCommands::SimulatePayment { invoice } => {
+                let response = client()
+                    .simulate_payment(SimulatePaymentPayload { invoice })
+                    .await?;
+                print_response(response).await;
+            }
+        },
+        Commands::Fees(fees_command) => match fees_command {
+            FeesCommands::List => {
+                let response = client().get_fees().await?;
+                print_response(response).await;
+            }
+            FeesCommands::Set {
+                federation_id,
+                outgoing,
+                incoming,
+            } => {
+                client()
+                    .set_federation_fees(SetFederationFeesPayload {
+                        federation_id,
+                        outgoing_fees: outgoing,
+                        incoming_fees: incoming,
+                    })
+                    .await?;
+            }
+            FeesCommands::SetPolicy(policy_command) => {
+                client()
+                    .set_fee_policy(SetFeePolicyPayload {
+                        fee_policy: policy_command.into(),
+                    })
+                    .await?;
+            }
+        },
+        Commands::Payments(payments_command) => match payments_command {
+            PaymentsCommands::List {
+                federation_id,
+                start_time,
+                end_time,
+                failed,
+                limit,
+            } => {
+                let response = client()
+                    .list_payments(ListPaymentsPayload {
+                        federation_id,
+                        start_time,
+                        end_time,
+                        failed_only: failed,
+                        limit,
+                    })
+                    .await?;
+                print_response(response).await;
+            }
+            PaymentsCommands::Summary {
+                federation_id,
+                start_time,
+                end_time,
+            } => {
+                let response = client()
+                    .payment_summary(PaymentSummaryPayload {
+                        federation_id,
+                        start_time,
+                        end_time,
+                    })
+                    .await?;
+                print_response(response).await;
+            }
+        },
+        Commands::Liquidity(liquidity_command) => match liquidity_command {
+            LiquidityCommands::Status => {
+                let response = client().get_liquidity().await?;
+                print_response(response).await;
+            }
+            LiquidityCommands::SetTarget {
+                federation_id,
+                target,
+                min,
+                max,
+            } => {
+                client()
+                    .set_liquidity_target(SetLiquidityTargetPayload {
+                        federation_id,
+                        target: Some(LiquidityTarget::new(target, min, max)?),
+                    })
+                    .await?;
+            }
+            LiquidityCommands::RemoveTarget { federation_id } => {
+                client()
+                    .set_liquidity_target(SetLiquidityTargetPayload {
+                        federation_id,
+                        target: None,
+                    })
+                    .await?;
+            }
+            LiquidityCommands::Rebalance { federation_id } => {
+                let response = client()
+                    .rebalance(RebalancePayload { federation_id })
+                    .await?;
+                print_response(response).await;
+            }
+            LiquidityCommands::Log {
+                federation_id,
+                limit,
+            } => {
+                let response = client()
+                    .liquidity_log(LiquidityLogPayload {
+                        federation_id,
+                        limit,
+                    })
+                    .await?;
+                print_response(response).await;
+            }
         },
     }
 
@@ -6,11 +6,11 @@ use std::sync::Arc;
 use fedimint_client::module::init::ClientModuleInitRegistry;
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::Client;
+use fedimint_core::config::FederationId;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{
     Committable, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
 };
-use fedimint_core::module::registry::ModuleDecoderRegistry;
 use futures::StreamExt;
 use rand::thread_rng;
 use tracing::info;
@@ -18,13 +18,14 @@ use tracing::info;
 use crate::db::{FederationConfig, FederationIdKey, FederationIdKeyPrefix};
 use crate::gateway_module_v2::GatewayClientInitV2;
 use crate::state_machine::GatewayClientInit;
-use crate::{Gateway, GatewayError, Result};
+use crate::{DatabaseBackend, Gateway, GatewayError, Result};
 
 #[derive(Debug, Clone)]
 pub struct GatewayClientBuilder {
     work_dir: PathBuf,
     registry: ClientModuleInitRegistry,
     primary_module: ModuleInstanceId,
+    db_backend: DatabaseBackend,
 }
 
 impl GatewayClientBuilder {
@@ -32,13 +33,25 @@ impl GatewayClientBuilder {
         work_dir: PathBuf,
         registry: ClientModuleInitRegistry,
         primary_module: ModuleInstanceId,
+        db_backend: DatabaseBackend,
     ) -> Self {
         Self {
             work_dir,
             registry,
             primary_module,
+            db_backend,
         }
     }
+
+    /// Opens the client database of a single federation
+    fn open_client_db(&self, federation_id: FederationId) -> anyhow::Result<Database> {
+        let db_path = self
+            .db_backend
+            .path(&self.work_dir, &format!("{federation_id}.db"));
+        self.db_backend
+            .open(db_path)
+            .map_err(|e| anyhow::anyhow!("Error opening {:?} database: {e:?}", self.db_backend))
+    }
 }
 
 impl GatewayClientBuilder {
@@ -64,12 +77,9 @@ impl GatewayClientBuilder {
         });
         registry.attach(GatewayClientInitV2 { gateway });
 
-        let db_path = self.work_dir.join(format!("{federation_id}.db"));
-
-        let rocksdb = fedimint_rocksdb::RocksDb::open(db_path.clone()).map_err(|e| {
-            GatewayError::DatabaseError(anyhow::anyhow!("Error opening rocksdb: {e:?}"))
-        })?;
-        let db = Database::new(rocksdb, ModuleDecoderRegistry::default());
+        let db = self
+            .open_client_db(federation_id)
+            .map_err(GatewayError::DatabaseError)?;
 
         let mut client_builder = Client::builder(db);
         client_builder.with_module_inits(registry);
@@ -3,23 +3,27 @@ use std::collections::BTreeMap;
 use bitcoin29::Network;
 use bitcoin_hashes::sha256;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
 use fedimint_core::db::{
     DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped, ServerMigrationFn,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::invite_code::InviteCode;
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
 use fedimint_ln_common::serde_routing_fees;
 use fedimint_lnv2_client::CreateInvoicePayload;
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt};
 use lightning_invoice::RoutingFees;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
+use crate::fees::{FederationFees, FeePolicyConfig};
+use crate::liquidity::{LiquidityActionKind, LiquidityTarget};
 use crate::rpc::rpc_server::hash_password;
+use crate::DEFAULT_INCOMING_FEES;
 
-pub const GATEWAYD_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+pub const GATEWAYD_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(2);
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -29,6 +33,13 @@ pub enum DbKeyPrefix {
     GatewayConfiguration = 0x07,
     PreimageAuthentication = 0x08,
     CreateInvoicePayload = 0x09,
+    LdkNodeSeed = 0x0a,
+    LdkKvStore = 0x0b,
+    FeePolicy = 0x0c,
+    LiquidityTarget = 0x0e,
+    LiquidityAction = 0x0f,
+    LdkPendingHtlc = 0x10,
+    LastLiquidityAction = 0x11,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -45,8 +56,16 @@ pub struct FederationIdKey {
 #[derive(Debug, Encodable, Decodable)]
 pub struct FederationIdKeyPrefix;
 
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FederationIdKeyV0 {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationIdKeyPrefixV0;
+
 #[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
-pub struct FederationConfig {
+pub struct FederationConfigV0 {
     pub invite_code: InviteCode,
     pub mint_channel_id: u64,
     pub timelock_delta: u64,
@@ -54,6 +73,27 @@ pub struct FederationConfig {
     pub fees: RoutingFees,
 }
 
+impl_db_record!(
+    key = FederationIdKeyV0,
+    value = FederationConfigV0,
+    db_prefix = DbKeyPrefix::FederationConfig,
+);
+
+impl_db_lookup!(
+    key = FederationIdKeyV0,
+    query_prefix = FederationIdKeyPrefixV0
+);
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationConfig {
+    pub invite_code: InviteCode,
+    pub mint_channel_id: u64,
+    pub timelock_delta: u64,
+    /// Fees the gateway is configured to charge for this federation, before
+    /// the fee policy is applied
+    pub fees: FederationFees,
+}
+
 impl_db_record!(
     key = FederationIdKey,
     value = FederationConfig,
@@ -131,6 +171,7 @@ impl_db_lookup!(
 pub fn get_gatewayd_database_migrations() -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
     let mut migrations: BTreeMap<DatabaseVersion, ServerMigrationFn> = BTreeMap::new();
     migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+    migrations.insert(DatabaseVersion(1), move |dbtx| migrate_to_v2(dbtx).boxed());
     migrations
 }
 
@@ -153,6 +194,32 @@ async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow:
     Ok(())
 }
 
+/// Splits the routing fees of each federation into outgoing and incoming fees.
+/// The previous fees were only charged for outgoing payments.
+async fn migrate_to_v2(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    let old_configs = dbtx
+        .find_by_prefix(&FederationIdKeyPrefixV0)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    for (old_key, old_config) in old_configs {
+        let new_config = FederationConfig {
+            invite_code: old_config.invite_code,
+            mint_channel_id: old_config.mint_channel_id,
+            timelock_delta: old_config.timelock_delta,
+            fees: FederationFees {
+                outgoing: old_config.fees,
+                incoming: DEFAULT_INCOMING_FEES,
+            },
+        };
+        dbtx.insert_entry(&FederationIdKey { id: old_key.id }, &new_config)
+            .await;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Encodable, Decodable)]
 pub struct CreateInvoicePayloadKey(pub [u8; 32]);
 
@@ -162,6 +229,308 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::CreateInvoicePayload,
 );
 
+/// Entropy the embedded LDK node derives its keys from
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct LdkNodeSeedKey;
+
+impl_db_record!(
+    key = LdkNodeSeedKey,
+    value = [u8; 64],
+    db_prefix = DbKeyPrefix::LdkNodeSeed,
+);
+
+/// Entry of the key-value store the embedded LDK node persists its channel
+/// state in
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct LdkKvStoreKey {
+    pub primary_namespace: String,
+    pub secondary_namespace: String,
+    pub key: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LdkKvStoreNamespacePrefix {
+    pub primary_namespace: String,
+    pub secondary_namespace: String,
+}
+
+impl_db_record!(
+    key = LdkKvStoreKey,
+    value = Vec<u8>,
+    db_prefix = DbKeyPrefix::LdkKvStore,
+);
+
+impl_db_lookup!(
+    key = LdkKvStoreKey,
+    query_prefix = LdkKvStoreNamespacePrefix
+);
+
+/// Payment held by the embedded LDK node that was handed to the gateway as an
+/// intercepted HTLC but not completed yet, so it can be handed out again after
+/// a restart
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct LdkPendingHtlcKey {
+    pub htlc_id: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LdkPendingHtlcKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct LdkPendingHtlc {
+    pub payment_hash: [u8; 32],
+    pub amount_msat: u64,
+    pub claim_deadline: Option<u32>,
+}
+
+impl_db_record!(
+    key = LdkPendingHtlcKey,
+    value = LdkPendingHtlc,
+    db_prefix = DbKeyPrefix::LdkPendingHtlc,
+);
+
+impl_db_lookup!(
+    key = LdkPendingHtlcKey,
+    query_prefix = LdkPendingHtlcKeyPrefix
+);
+
+/// Fee policy the gateway applies to the configured fees of all federations
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct FeePolicyKey;
+
+impl_db_record!(
+    key = FeePolicyKey,
+    value = FeePolicyConfig,
+    db_prefix = DbKeyPrefix::FeePolicy,
+);
+
+/// Prefixes of the records the gateway's client modules keep in their
+/// partition of a federation client's database. Keeping the payment ledger
+/// there allows writing it in the same transaction as the payment's operation
+/// and state transitions.
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum ClientModuleDbKeyPrefix {
+    PaymentLog = 0x01,
+}
+
+impl std::fmt::Display for ClientModuleDbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Entry of the payment ledger, keyed by the operation of the federation
+/// client that handles the payment
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct PaymentLogKey {
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentLogKeyPrefix;
+
+impl_db_record!(
+    key = PaymentLogKey,
+    value = PaymentLogEntry,
+    db_prefix = ClientModuleDbKeyPrefix::PaymentLog,
+);
+
+impl_db_lookup!(key = PaymentLogKey, query_prefix = PaymentLogKeyPrefix);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    /// The gateway paid a lightning invoice on behalf of a federation user
+    Outgoing,
+    /// The gateway received a lightning payment for a federation user
+    Incoming,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentModule {
+    LnV1,
+    LnV2,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Pending,
+    Succeeded,
+    Failed { reason: String },
+}
+
+/// A payment the gateway routed between a federation and the lightning network
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PaymentLogEntry {
+    pub direction: PaymentDirection,
+    pub module: PaymentModule,
+    pub payment_hash: sha256::Hash,
+    /// Amount sent or received over lightning
+    pub amount: Amount,
+    /// Difference between the ecash and the lightning side of the payment the
+    /// gateway keeps if the payment succeeds
+    pub fee: Amount,
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    /// Unix timestamp in seconds, set once the payment succeeded or failed
+    pub completed_at: Option<u64>,
+    pub status: PaymentStatus,
+}
+
+/// Ecash balance the liquidity manager maintains for a federation
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct LiquidityTargetKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LiquidityTargetKeyPrefix;
+
+impl_db_record!(
+    key = LiquidityTargetKey,
+    value = LiquidityTarget,
+    db_prefix = DbKeyPrefix::LiquidityTarget,
+);
+
+impl_db_lookup!(
+    key = LiquidityTargetKey,
+    query_prefix = LiquidityTargetKeyPrefix
+);
+
+/// Peg-in or peg-out of the liquidity manager, keyed by the unix timestamp in
+/// seconds it was started at
+#[derive(Debug, Clone, Encodable, Decodable, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct LiquidityActionKey {
+    pub created_at: u64,
+    pub federation_id: FederationId,
+    /// Counts the actions of the federation, so actions started within the
+    /// same second get distinct keys
+    pub index: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LiquidityActionKeyPrefix;
+
+impl_db_record!(
+    key = LiquidityActionKey,
+    value = LiquidityActionEntry,
+    db_prefix = DbKeyPrefix::LiquidityAction,
+);
+
+impl_db_lookup!(
+    key = LiquidityActionKey,
+    query_prefix = LiquidityActionKeyPrefix
+);
+
+/// Key of the latest action of the liquidity manager for a federation, so the
+/// cooldown between actions can be checked without scanning the whole log
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct LastLiquidityActionKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LastLiquidityActionKeyPrefix;
+
+impl_db_record!(
+    key = LastLiquidityActionKey,
+    value = LiquidityActionKey,
+    db_prefix = DbKeyPrefix::LastLiquidityAction,
+);
+
+impl_db_lookup!(
+    key = LastLiquidityActionKey,
+    query_prefix = LastLiquidityActionKeyPrefix
+);
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityActionStatus {
+    /// The funds were sent in the transaction with the given id
+    Sent {
+        txid: String,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct LiquidityActionEntry {
+    pub kind: LiquidityActionKind,
+    /// Amount that was pegged in or out
+    pub amount: Amount,
+    /// Ecash balance of the federation before the action
+    pub balance: Amount,
+    /// On-chain address the funds were sent to, if it could be obtained
+    pub address: Option<String>,
+    pub status: LiquidityActionStatus,
+}
+
+/// Records a payment the gateway started to handle. Has to be called with the
+/// module transaction that starts the payment's operation.
+pub async fn record_payment_started(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    direction: PaymentDirection,
+    module: PaymentModule,
+    payment_hash: sha256::Hash,
+    amount: Amount,
+    fee: Amount,
+) {
+    let entry = PaymentLogEntry {
+        direction,
+        module,
+        payment_hash,
+        amount,
+        fee,
+        started_at: fedimint_core::time::duration_since_epoch().as_secs(),
+        completed_at: None,
+        status: PaymentStatus::Pending,
+    };
+    dbtx.insert_new_entry(&PaymentLogKey { operation_id }, &entry)
+        .await;
+}
+
+/// Sets the fee of a payment from the amount of the contract funding it, for
+/// payments whose contract is only known after they started, like outgoing
+/// lnv1 payments.
+pub async fn record_payment_contract_amount(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    contract_amount: Amount,
+) {
+    let key = PaymentLogKey { operation_id };
+    if let Some(mut entry) = dbtx.get_value(&key).await {
+        entry.fee = contract_amount.saturating_sub(entry.amount);
+        dbtx.insert_entry(&key, &entry).await;
+    }
+}
+
+/// Records the outcome of a payment in the ledger. Payments that were never
+/// recorded as started, like the incoming side of direct swaps between
+/// federations, are ignored.
+pub async fn record_payment_outcome(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    outcome: Result<(), String>,
+) {
+    let key = PaymentLogKey { operation_id };
+    if let Some(mut entry) = dbtx.get_value(&key).await {
+        if entry.status == PaymentStatus::Pending {
+            entry.status = match outcome {
+                Ok(()) => PaymentStatus::Succeeded,
+                Err(reason) => PaymentStatus::Failed { reason },
+            };
+            entry.completed_at = Some(fedimint_core::time::duration_since_epoch().as_secs());
+            dbtx.insert_entry(&key, &entry).await;
+        }
+    }
+}
+
 #[cfg(test)]
 mod fedimint_migration_tests {
     use std::str::FromStr;
@@ -185,7 +554,7 @@ mod fedimint_migration_tests {
     use tracing::info;
 
     use super::{
-        FederationConfig, FederationIdKey, GatewayConfigurationKey, GatewayConfigurationKeyV0,
+        FederationConfigV0, FederationIdKeyV0, GatewayConfigurationKey, GatewayConfigurationKeyV0,
         GatewayConfigurationV0, GatewayPublicKey, PreimageAuthentication,
     };
     use crate::db::{
@@ -202,14 +571,14 @@ mod fedimint_migration_tests {
             0.into(),
             federation_id,
         );
-        let federation_config = FederationConfig {
+        let federation_config = FederationConfigV0 {
             invite_code,
             mint_channel_id: 2,
             timelock_delta: 10,
             fees: DEFAULT_FEES,
         };
 
-        dbtx.insert_new_entry(&FederationIdKey { id: federation_id }, &federation_config)
+        dbtx.insert_new_entry(&FederationIdKeyV0 { id: federation_id }, &federation_config)
             .await;
 
         let context = secp256k1::Secp256k1::new();
@@ -289,7 +658,14 @@ mod fedimint_migration_tests {
                             ensure!(gateway_configuration.is_some(), "validate_migrations was not able to read GatewayConfiguration");
                             info!("Validated GatewayConfiguration");
                         }
-                        DbKeyPrefix::CreateInvoicePayload => {}
+                        DbKeyPrefix::CreateInvoicePayload
+                        | DbKeyPrefix::LdkNodeSeed
+                        | DbKeyPrefix::LdkKvStore
+                        | DbKeyPrefix::FeePolicy
+                        | DbKeyPrefix::LiquidityTarget
+                        | DbKeyPrefix::LiquidityAction
+                        | DbKeyPrefix::LdkPendingHtlc
+                        | DbKeyPrefix::LastLiquidityAction => {}
                     }
                 }
                 Ok(())
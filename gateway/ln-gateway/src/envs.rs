@@ -19,6 +19,9 @@ pub const FM_GATEWAY_NETWORK_ENV: &str = "FM_GATEWAY_NETWORK";
 // Env variable to TODO
 pub const FM_GATEWAY_FEES_ENV: &str = "FM_GATEWAY_FEES";
 
+// Env variable to select the gateway database backend (`rocksdb` or `sqlite`)
+pub const FM_GATEWAY_DB_BACKEND_ENV: &str = "FM_GATEWAY_DB_BACKEND";
+
 // Env variable to TODO
 pub const FM_NUMBER_OF_ROUTE_HINTS_ENV: &str = "FM_NUMBER_OF_ROUTE_HINTS";
 
@@ -33,3 +36,12 @@ pub const FM_LND_MACAROON_ENV: &str = "FM_LND_MACAROON";
 
 // Env variable to TODO
 pub const FM_GATEWAY_LIGHTNING_ADDR_ENV: &str = "FM_GATEWAY_LIGHTNING_ADDR";
+
+// Env variable to set the bitcoind RPC URL used by the embedded LDK node
+pub const FM_LDK_BITCOIND_RPC_URL_ENV: &str = "FM_LDK_BITCOIND_RPC_URL";
+
+// Env variable to set the bitcoin network of the embedded LDK node
+pub const FM_LDK_NETWORK_ENV: &str = "FM_LDK_NETWORK";
+
+// Env variable to set the port the embedded LDK node listens on for peers
+pub const FM_PORT_LDK_ENV: &str = "FM_PORT_LDK";
@@ -0,0 +1,249 @@
+//! Routing fees the gateway charges per federation and the policies deriving
+//! the fees it announces from the configured ones
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use fedimint_ln_common::serde_routing_fees;
+use fedimint_lnv2_client::PaymentFee;
+use lightning_invoice::RoutingFees;
+use serde::{Deserialize, Serialize};
+
+use crate::lightning::ChannelLiquidity;
+
+/// Routing fees a gateway charges for payments of one federation, by the
+/// direction of the payment
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationFees {
+    /// Fees for paying lightning invoices on behalf of federation users
+    #[serde(with = "serde_routing_fees")]
+    pub outgoing: RoutingFees,
+    /// Fees for receiving lightning payments into the federation. Only
+    /// charged for lightning module v2 invoices, since the route hints of v1
+    /// invoices cannot carry a fee for the last hop.
+    #[serde(with = "serde_routing_fees")]
+    pub incoming: RoutingFees,
+}
+
+impl FederationFees {
+    /// Returns the lower of `self` and `other` for each fee component
+    pub fn lower(&self, other: &FederationFees) -> FederationFees {
+        FederationFees {
+            outgoing: lower_routing_fees(self.outgoing, other.outgoing),
+            incoming: lower_routing_fees(self.incoming, other.incoming),
+        }
+    }
+}
+
+fn lower_routing_fees(a: RoutingFees, b: RoutingFees) -> RoutingFees {
+    RoutingFees {
+        base_msat: a.base_msat.min(b.base_msat),
+        proportional_millionths: a.proportional_millionths.min(b.proportional_millionths),
+    }
+}
+
+/// Converts routing fees to the fee representation of the lightning module v2
+pub fn routing_fees_to_payment_fee(fees: RoutingFees) -> PaymentFee {
+    PaymentFee {
+        base: Amount::from_msats(fees.base_msat.into()),
+        parts_per_million: fees.proportional_millionths.into(),
+    }
+}
+
+/// A policy that decides which fees the gateway announces to a federation.
+///
+/// The gateway applies the policy every time it registers with its
+/// federations, so changes in the liquidity of the lightning node are picked up
+/// within one registration interval.
+pub trait IFeePolicy: Debug + Send + Sync {
+    /// Returns the fees to announce for a federation configured with `fees`.
+    /// `liquidity` is `None` if the lightning node could not report it.
+    fn effective_fees(
+        &self,
+        fees: FederationFees,
+        liquidity: Option<ChannelLiquidity>,
+    ) -> FederationFees;
+}
+
+/// Always announces the configured fees
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StaticFeePolicy;
+
+impl IFeePolicy for StaticFeePolicy {
+    fn effective_fees(
+        &self,
+        fees: FederationFees,
+        _liquidity: Option<ChannelLiquidity>,
+    ) -> FederationFees {
+        fees
+    }
+}
+
+/// Raises the fees of a payment direction while the lightning liquidity that
+/// direction consumes is scarce: outgoing payments consume outbound liquidity,
+/// incoming payments consume inbound liquidity.
+#[derive(Debug, Clone, Copy)]
+pub struct LiquidityFeePolicy {
+    /// Share of the total channel balance, in parts per million, below which
+    /// the liquidity of a direction is considered scarce
+    pub scarcity_threshold_ppm: u64,
+    /// Percentage the fees of a direction with scarce liquidity are multiplied
+    /// by, e.g. 200 doubles them
+    pub scarcity_multiplier_percent: u64,
+}
+
+impl LiquidityFeePolicy {
+    fn is_scarce(&self, available_msat: u64, total_msat: u64) -> bool {
+        u128::from(available_msat) * 1_000_000
+            < u128::from(self.scarcity_threshold_ppm) * u128::from(total_msat)
+    }
+
+    fn scale(&self, fees: RoutingFees) -> RoutingFees {
+        let scale = |fee: u32| {
+            let scaled = u64::from(fee) * self.scarcity_multiplier_percent / 100;
+            u32::try_from(scaled).unwrap_or(u32::MAX)
+        };
+
+        RoutingFees {
+            base_msat: scale(fees.base_msat),
+            proportional_millionths: scale(fees.proportional_millionths),
+        }
+    }
+}
+
+impl IFeePolicy for LiquidityFeePolicy {
+    fn effective_fees(
+        &self,
+        fees: FederationFees,
+        liquidity: Option<ChannelLiquidity>,
+    ) -> FederationFees {
+        let Some(liquidity) = liquidity else {
+            return fees;
+        };
+
+        let total_msat = liquidity
+            .outbound_msat
+            .saturating_add(liquidity.inbound_msat);
+        if total_msat == 0 {
+            return fees;
+        }
+
+        FederationFees {
+            outgoing: if self.is_scarce(liquidity.outbound_msat, total_msat) {
+                self.scale(fees.outgoing)
+            } else {
+                fees.outgoing
+            },
+            incoming: if self.is_scarce(liquidity.inbound_msat, total_msat) {
+                self.scale(fees.incoming)
+            } else {
+                fees.incoming
+            },
+        }
+    }
+}
+
+/// Persisted selection of the fee policy the gateway applies to all
+/// federations
+#[derive(
+    Debug, Clone, Copy, Default, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FeePolicyConfig {
+    /// See [`StaticFeePolicy`]
+    #[default]
+    Static,
+    /// See [`LiquidityFeePolicy`]
+    Liquidity {
+        scarcity_threshold_ppm: u64,
+        scarcity_multiplier_percent: u64,
+    },
+}
+
+impl FeePolicyConfig {
+    pub fn to_policy(&self) -> Arc<dyn IFeePolicy> {
+        match *self {
+            FeePolicyConfig::Static => Arc::new(StaticFeePolicy),
+            FeePolicyConfig::Liquidity {
+                scarcity_threshold_ppm,
+                scarcity_multiplier_percent,
+            } => Arc::new(LiquidityFeePolicy {
+                scarcity_threshold_ppm,
+                scarcity_multiplier_percent,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lightning_invoice::RoutingFees;
+
+    use super::{FederationFees, IFeePolicy, LiquidityFeePolicy};
+    use crate::lightning::ChannelLiquidity;
+
+    const FEES: FederationFees = FederationFees {
+        outgoing: RoutingFees {
+            base_msat: 1000,
+            proportional_millionths: 10_000,
+        },
+        incoming: RoutingFees {
+            base_msat: 0,
+            proportional_millionths: 5_000,
+        },
+    };
+
+    const POLICY: LiquidityFeePolicy = LiquidityFeePolicy {
+        scarcity_threshold_ppm: 200_000,
+        scarcity_multiplier_percent: 300,
+    };
+
+    #[test]
+    fn liquidity_policy_raises_fees_of_scarce_direction() {
+        let balanced = ChannelLiquidity {
+            outbound_msat: 500_000,
+            inbound_msat: 500_000,
+        };
+        assert_eq!(POLICY.effective_fees(FEES, Some(balanced)), FEES);
+
+        let low_outbound = ChannelLiquidity {
+            outbound_msat: 100_000,
+            inbound_msat: 900_000,
+        };
+        let fees = POLICY.effective_fees(FEES, Some(low_outbound));
+        assert_eq!(
+            fees.outgoing,
+            RoutingFees {
+                base_msat: 3000,
+                proportional_millionths: 30_000,
+            }
+        );
+        assert_eq!(fees.incoming, FEES.incoming);
+
+        let low_inbound = ChannelLiquidity {
+            outbound_msat: 900_000,
+            inbound_msat: 100_000,
+        };
+        let fees = POLICY.effective_fees(FEES, Some(low_inbound));
+        assert_eq!(fees.outgoing, FEES.outgoing);
+        assert_eq!(
+            fees.incoming,
+            RoutingFees {
+                base_msat: 0,
+                proportional_millionths: 15_000,
+            }
+        );
+    }
+
+    #[test]
+    fn liquidity_policy_keeps_fees_without_liquidity() {
+        assert_eq!(POLICY.effective_fees(FEES, None), FEES);
+        let no_channels = ChannelLiquidity {
+            outbound_msat: 0,
+            inbound_msat: 0,
+        };
+        assert_eq!(POLICY.effective_fees(FEES, Some(no_channels)), FEES);
+    }
+}
@@ -42,6 +42,7 @@ use serde::{Deserialize, Serialize};
 use tpe::{AggregatePublicKey, PublicKeyShare};
 use tracing::warn;
 
+use crate::db::{record_payment_started, PaymentDirection, PaymentModule};
 use crate::gateway_module_v2::complete_sm::{
     CompleteSMCommon, CompleteSMState, CompleteStateMachine,
 };
@@ -269,12 +270,38 @@ impl GatewayClientModuleV2 {
             state: SendSMState::Sending,
         });
 
+        let amount = Amount::from_msats(invoice_msats);
+        let payment_hash = payload.contract.payment_hash;
+        let fee = payload.contract.amount.saturating_sub(amount);
+
         self.client_ctx
-            .manual_operation_start(
-                operation_id,
-                LightningCommonInit::KIND.as_str(),
-                GatewayOperationMetaV2,
-                vec![self.client_ctx.make_dyn_state(send_sm)],
+            .module_autocommit_2(
+                |dbtx, _| {
+                    let send_sm = send_sm.clone();
+                    Box::pin(async move {
+                        dbtx.manual_operation_start(
+                            operation_id,
+                            LightningCommonInit::KIND.as_str(),
+                            GatewayOperationMetaV2,
+                            vec![self.client_ctx.make_dyn_state(send_sm)],
+                        )
+                        .await?;
+
+                        record_payment_started(
+                            &mut dbtx.module_dbtx(),
+                            operation_id,
+                            PaymentDirection::Outgoing,
+                            PaymentModule::LnV2,
+                            payment_hash,
+                            amount,
+                            fee,
+                        )
+                        .await;
+
+                        Ok(())
+                    })
+                },
+                Some(100),
             )
             .await
             .ok();
@@ -321,6 +348,9 @@ impl GatewayClientModuleV2 {
         }
 
         let refund_keypair = self.keypair;
+        let payment_hash = payload.contract.commitment.payment_hash;
+        let invoice_amount = payload.invoice_amount;
+        let fee = invoice_amount.saturating_sub(payload.contract.commitment.amount);
 
         let client_output = ClientOutput::<LightningOutput, GatewayClientStateMachinesV2> {
             output: LightningOutput::V0(LightningOutputV0::Incoming(payload.contract.clone())),
@@ -352,11 +382,33 @@ impl GatewayClientModuleV2 {
         let transaction = TransactionBuilder::new().with_output(client_output);
 
         self.client_ctx
-            .finalize_and_submit_transaction(
-                operation_id,
-                LightningCommonInit::KIND.as_str(),
-                |_, _| GatewayOperationMetaV2,
-                transaction,
+            .module_autocommit_2(
+                |dbtx, _| {
+                    let transaction = transaction.clone();
+                    Box::pin(async move {
+                        dbtx.finalize_and_submit_transaction(
+                            operation_id,
+                            LightningCommonInit::KIND.as_str(),
+                            |_, _| GatewayOperationMetaV2,
+                            transaction,
+                        )
+                        .await?;
+
+                        record_payment_started(
+                            &mut dbtx.module_dbtx(),
+                            operation_id,
+                            PaymentDirection::Incoming,
+                            PaymentModule::LnV2,
+                            payment_hash,
+                            invoice_amount,
+                            fee,
+                        )
+                        .await;
+
+                        Ok(())
+                    })
+                },
+                Some(100),
             )
             .await?;
 
@@ -22,6 +22,7 @@ use secp256k1::KeyPair;
 use tpe::{aggregate_decryption_shares, AggregatePublicKey, DecryptionKeyShare, PublicKeyShare};
 use tracing::{error, trace};
 
+use crate::db::record_payment_outcome;
 use crate::gateway_module_v2::GatewayClientContextV2;
 
 const RETRY_DELAY: Duration = Duration::from_secs(1);
@@ -90,8 +91,12 @@ impl State for ReceiveStateMachine {
                             global_context.clone(),
                             self.common.out_point.txid,
                         ),
-                        move |_, error, old_state| {
-                            Box::pin(Self::transition_funding_rejected(error, old_state))
+                        move |dbtx, error, old_state| {
+                            Box::pin(async move {
+                                let new_state =
+                                    Self::transition_funding_rejected(error, old_state).await;
+                                Self::record_outcome(dbtx, new_state).await
+                            })
                         },
                     ),
                     StateTransition::new(
@@ -103,13 +108,18 @@ impl State for ReceiveStateMachine {
                             self.common.contract.clone(),
                         ),
                         move |dbtx, output_outcomes, old_state| {
-                            Box::pin(Self::transition_outcome_ready(
-                                dbtx,
-                                output_outcomes,
-                                old_state,
-                                gc.clone(),
-                                tpe_agg_pk,
-                            ))
+                            let gc = gc.clone();
+                            Box::pin(async move {
+                                let new_state = Self::transition_outcome_ready(
+                                    dbtx,
+                                    output_outcomes,
+                                    old_state,
+                                    gc,
+                                    tpe_agg_pk,
+                                )
+                                .await;
+                                Self::record_outcome(dbtx, new_state).await
+                            })
                         },
                     ),
                 ]
@@ -252,4 +262,24 @@ impl ReceiveStateMachine {
 
         old_state.update(ReceiveSMState::Refunding(outpoints))
     }
+
+    /// Records the outcome of the incoming payment in the gateway's ledger
+    async fn record_outcome(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        state: ReceiveStateMachine,
+    ) -> ReceiveStateMachine {
+        let outcome = match &state.state {
+            ReceiveSMState::Funding => return state,
+            ReceiveSMState::Success(..) => Ok(()),
+            ReceiveSMState::Rejected(error) => {
+                Err(format!("Funding transaction was rejected: {error}"))
+            }
+            ReceiveSMState::Failure => Err("Failed to obtain the decryption key".to_string()),
+            ReceiveSMState::Refunding(..) => Err("Decrypted preimage is invalid".to_string()),
+        };
+
+        record_payment_outcome(&mut dbtx.module_tx(), state.common.operation_id, outcome).await;
+
+        state
+    }
 }
@@ -15,6 +15,7 @@ use lightning_invoice::Bolt11Invoice;
 use secp256k1::KeyPair;
 use serde::{Deserialize, Serialize};
 
+use crate::db::record_payment_outcome;
 use crate::gateway_lnrpc::PayInvoiceRequest;
 use crate::gateway_module_v2::{GatewayClientContextV2, GatewayClientModuleV2};
 
@@ -205,6 +206,13 @@ impl SendStateMachine {
         global_context: DynGlobalClientContext,
         result: Result<[u8; 32], Cancelled>,
     ) -> SendStateMachine {
+        record_payment_outcome(
+            &mut dbtx.module_tx(),
+            old_state.common.operation_id,
+            result.as_ref().map(|_| ()).map_err(|e| format!("{e:?}")),
+        )
+        .await;
+
         match result {
             Ok(preimage) => {
                 let client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
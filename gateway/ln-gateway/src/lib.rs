@@ -1,8 +1,10 @@
 pub mod client;
 mod db;
 pub mod envs;
+pub mod fees;
 pub mod gateway_module_v2;
 pub mod lightning;
+pub mod liquidity;
 pub mod rpc;
 pub mod state_machine;
 mod types;
@@ -30,16 +32,19 @@ use bitcoin_hashes::sha256;
 use clap::Parser;
 use client::GatewayClientBuilder;
 use db::{
-    DbKeyPrefix, FederationIdKey, GatewayConfiguration, GatewayConfigurationKey, GatewayPublicKey,
-    GATEWAYD_DATABASE_VERSION,
+    DbKeyPrefix, FederationIdKey, FeePolicyKey, GatewayConfiguration, GatewayConfigurationKey,
+    GatewayPublicKey, LastLiquidityActionKey, LastLiquidityActionKeyPrefix, LiquidityActionEntry,
+    LiquidityActionKey, LiquidityActionKeyPrefix, LiquidityActionStatus, LiquidityTargetKey,
+    LiquidityTargetKeyPrefix, PaymentLogKeyPrefix, PaymentStatus, GATEWAYD_DATABASE_VERSION,
 };
 use fedimint_api_client::api::FederationError;
 use fedimint_client::module::init::ClientModuleInitRegistry;
+use fedimint_client::module::ClientModule;
 use fedimint_client::ClientHandleArc;
 use fedimint_core::bitcoin_migration::{
     bitcoin29_to_bitcoin30_address, bitcoin29_to_bitcoin30_amount, bitcoin29_to_bitcoin30_network,
     bitcoin29_to_bitcoin30_secp256k1_public_key, bitcoin29_to_bitcoin30_txid,
-    bitcoin30_to_bitcoin29_amount, bitcoin30_to_bitcoin29_network,
+    bitcoin30_to_bitcoin29_address, bitcoin30_to_bitcoin29_amount, bitcoin30_to_bitcoin29_network,
 };
 use fedimint_core::config::FederationId;
 use fedimint_core::core::{
@@ -64,22 +69,31 @@ use fedimint_ln_common::config::{GatewayFee, LightningClientConfig};
 use fedimint_ln_common::contracts::Preimage;
 use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::LightningCommonInit;
-use fedimint_lnv2_client::{CreateInvoicePayload, PaymentFee, PaymentInfo, SendPaymentPayload};
+use fedimint_lnv2_client::{CreateInvoicePayload, PaymentInfo, SendPaymentPayload};
 use fedimint_mint_client::{MintClientInit, MintCommonInit};
+pub use fedimint_sqlite::DatabaseBackend;
 use fedimint_wallet_client::{
     WalletClientInit, WalletClientModule, WalletCommonInit, WithdrawState,
 };
+use fees::{routing_fees_to_payment_fee, FederationFees};
 use futures::stream::StreamExt;
 use gateway_lnrpc::intercept_htlc_response::Action;
 use gateway_lnrpc::{GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse};
 use hex::ToHex;
 use lightning::{ILnRpcClient, LightningBuilder, LightningMode, LightningRpcError};
 use lightning_invoice::{Bolt11Invoice, RoutingFees};
+use liquidity::{
+    LiquidityActionKind, LiquidityTarget, LIQUIDITY_ACTION_COOLDOWN, LIQUIDITY_CHECK_INTERVAL,
+};
 use rand::rngs::OsRng;
 use rand::Rng;
 use rpc::{
-    ConnectToPeerPayload, FederationInfo, GatewayFedConfig, GatewayInfo, LeaveFedPayload,
-    OpenChannelPayload, SetConfigurationPayload, V1_API_ENDPOINT,
+    ConnectToPeerPayload, FederationFeesInfo, FederationInfo, FederationLiquidityInfo,
+    FederationPaymentSummary, GatewayFedConfig, GatewayFees, GatewayInfo, GatewayLiquidity,
+    LeaveFedPayload, LiquidityActionInfo, LiquidityLogPayload, ListPaymentsPayload,
+    OpenChannelPayload, PaymentLogInfo, PaymentSummary, PaymentSummaryPayload, RebalancePayload,
+    SetConfigurationPayload, SetFederationFeesPayload, SetFeePolicyPayload,
+    SetLiquidityTargetPayload, SimulatePaymentPayload, V1_API_ENDPOINT,
 };
 use secp256k1::schnorr::Signature;
 use secp256k1::PublicKey;
@@ -123,6 +137,14 @@ pub const DEFAULT_FEES: RoutingFees = RoutingFees {
     proportional_millionths: 10000,
 };
 
+/// Fees charged for payments received into a federation, unless configured
+/// otherwise for the federation
+pub const DEFAULT_INCOMING_FEES: RoutingFees = RoutingFees {
+    base_msat: 0,
+    // 5000 is 0.5%
+    proportional_millionths: 5000,
+};
+
 const EXPIRATION_DELTA_MINIMUM_V2: u64 = 144;
 
 pub type Result<T> = std::result::Result<T, GatewayError>;
@@ -172,6 +194,15 @@ struct GatewayOpts {
         default_value_t = DEFAULT_NUM_ROUTE_HINTS
     )]
     pub num_route_hints: u32,
+
+    /// Database backend used for the gateway and its federation clients
+    #[arg(
+        long = "db-backend",
+        env = envs::FM_GATEWAY_DB_BACKEND_ENV,
+        value_enum,
+        default_value_t = DatabaseBackend::Rocksdb
+    )]
+    pub db_backend: DatabaseBackend,
 }
 
 impl GatewayOpts {
@@ -293,6 +324,10 @@ pub struct Gateway {
     // A public key representing the identity of the gateway. Private key is not used.
     pub gateway_id: secp256k1::PublicKey,
 
+    // Fees currently announced to each federation, derived from its configured fees by the fee
+    // policy.
+    announced_fees: Arc<RwLock<BTreeMap<FederationId, FederationFees>>>,
+
     // Tracker for short channel ID assignments. When connecting a new federation,
     // this value is incremented and assigned to the federation as the `mint_channel_id`
     max_used_scid: Arc<Mutex<u64>>,
@@ -313,6 +348,7 @@ impl std::fmt::Debug for Gateway {
             .field("gateway_db", &self.gateway_db)
             .field("clients", &self.clients)
             .field("scid_to_federation", &self.scid_to_federation)
+            .field("announced_fees", &self.announced_fees)
             .field("gateway_id", &self.gateway_id)
             .field("max_used_scid", &self.max_used_scid)
             .finish()
@@ -362,15 +398,16 @@ impl Gateway {
 
         let decoders = registry.available_decoders(DEFAULT_MODULE_KINDS.iter().cloned())?;
 
-        let gateway_db = Database::new(
-            fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?,
-            decoders.clone(),
-        );
+        let gateway_db = opts
+            .db_backend
+            .open(opts.db_backend.path(&opts.data_dir, DB_FILE))?
+            .with_decoders(decoders.clone());
 
         let client_builder = GatewayClientBuilder::new(
             opts.data_dir.clone(),
             registry.clone(),
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
+            opts.db_backend,
         );
 
         info!(
@@ -381,6 +418,8 @@ impl Gateway {
         Gateway::new(
             Arc::new(GatewayLightningBuilder {
                 lightning_mode: opts.mode.clone(),
+                gateway_db: gateway_db.clone(),
+                data_dir: opts.data_dir.clone(),
             }),
             opts.to_gateway_parameters()?,
             gateway_db,
@@ -417,6 +456,7 @@ impl Gateway {
             gateway_db,
             clients: Arc::new(RwLock::new(BTreeMap::new())),
             scid_to_federation: Arc::new(RwLock::new(BTreeMap::new())),
+            announced_fees: Arc::new(RwLock::new(BTreeMap::new())),
             client_joining_lock: Arc::new(Mutex::new(ClientsJoinLock)),
             versioned_api: gateway_parameters.versioned_api,
             listen: gateway_parameters.listen,
@@ -473,6 +513,41 @@ impl Gateway {
                             .insert("Gateway Public Key".to_string(), Box::new(public_key));
                     }
                 }
+                DbKeyPrefix::FeePolicy => {
+                    if let Some(fee_policy) = dbtx.get_value(&FeePolicyKey).await {
+                        gateway_items.insert("Fee Policy".to_string(), Box::new(fee_policy));
+                    }
+                }
+                DbKeyPrefix::LiquidityTarget => {
+                    push_db_pair_items!(
+                        dbtx,
+                        LiquidityTargetKeyPrefix,
+                        LiquidityTargetKey,
+                        LiquidityTarget,
+                        gateway_items,
+                        "Liquidity Targets"
+                    );
+                }
+                DbKeyPrefix::LiquidityAction => {
+                    push_db_pair_items!(
+                        dbtx,
+                        LiquidityActionKeyPrefix,
+                        LiquidityActionKey,
+                        LiquidityActionEntry,
+                        gateway_items,
+                        "Liquidity Actions"
+                    );
+                }
+                DbKeyPrefix::LastLiquidityAction => {
+                    push_db_pair_items!(
+                        dbtx,
+                        LastLiquidityActionKeyPrefix,
+                        LastLiquidityActionKey,
+                        LiquidityActionKey,
+                        gateway_items,
+                        "Last Liquidity Actions"
+                    );
+                }
                 _ => {}
             }
         }
@@ -482,6 +557,7 @@ impl Gateway {
 
     pub async fn run(mut self, tg: &mut TaskGroup) -> anyhow::Result<TaskShutdownToken> {
         self.register_clients_timer(tg).await;
+        self.start_liquidity_manager(tg);
         self.load_clients().await;
         self.start_gateway(tg).await?;
         // start webserver last to avoid handling requests before fully initialized
@@ -501,8 +577,15 @@ impl Gateway {
                     break;
                 }
 
+                let lnrpc_route = match self_copy.lightning_builder.build().await {
+                    Ok(lnrpc_route) => lnrpc_route,
+                    Err(e) => {
+                        warn!("Failed to build Lightning client: {e:?}. Waiting 5 seconds and trying again");
+                        sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
                 let mut htlc_task_group = tg.make_subgroup();
-                let lnrpc_route = self_copy.lightning_builder.build().await;
 
                 debug!("Will try to intercept HTLC stream...");
                 // Re-create the HTLC stream if the connection breaks
@@ -958,7 +1041,10 @@ impl Gateway {
                 invite_code,
                 mint_channel_id,
                 timelock_delta: 10,
-                fees: gateway_config.routing_fees,
+                fees: FederationFees {
+                    outgoing: gateway_config.routing_fees,
+                    incoming: DEFAULT_INCOMING_FEES,
+                },
             };
 
             let client = self
@@ -982,13 +1068,19 @@ impl Gateway {
             )
             .await?;
 
+            let announced_fees = self
+                .apply_fee_policy(
+                    &lightning_context,
+                    &[(federation_id, gw_client_cfg.clone())],
+                )
+                .await;
             client
                 .get_first_module::<GatewayClientModule>()
                 .register_with_federation(
                     // Route hints will be updated in the background
                     Vec::new(),
                     GW_ANNOUNCEMENT_TTL,
-                    gw_client_cfg.fees,
+                    announced_fees[&federation_id].outgoing,
                     lightning_context,
                 )
                 .await?;
@@ -1047,10 +1139,18 @@ impl Gateway {
 
         self.remove_client(payload.federation_id, &client_joining_lock)
             .await?;
+        self.announced_fees
+            .write()
+            .await
+            .remove(&payload.federation_id);
         dbtx.remove_entry(&FederationIdKey {
             id: payload.federation_id,
         })
         .await;
+        dbtx.remove_entry(&LiquidityTargetKey {
+            federation_id: payload.federation_id,
+        })
+        .await;
         dbtx.commit_tx_result()
             .await
             .map_err(GatewayError::DatabaseError)?;
@@ -1156,7 +1256,7 @@ impl Gateway {
             for (federation_id, routing_fees) in per_federation_routing_fees.iter() {
                 let federation_key = FederationIdKey { id: *federation_id };
                 if let Some(mut federation_config) = dbtx.get_value(&federation_key).await {
-                    federation_config.fees = routing_fees.clone().into();
+                    federation_config.fees.outgoing = routing_fees.clone().into();
                     dbtx.insert_entry(&federation_key, &federation_config).await;
                     register_federations.push((*federation_id, federation_config));
                 } else {
@@ -1224,6 +1324,353 @@ impl Gateway {
         Ok(())
     }
 
+    /// Simulates an incoming lightning payment of the given invoice through
+    /// the gateway and returns the preimage the gateway settled it with.
+    pub async fn handle_simulate_payment_msg(
+        &self,
+        SimulatePaymentPayload { invoice }: SimulatePaymentPayload,
+    ) -> Result<Vec<u8>> {
+        let context = self.get_lightning_context().await?;
+        let response = context.lnrpc.simulate_incoming_payment(invoice).await?;
+        Ok(response.preimage)
+    }
+
+    /// Returns the configured and announced fees of every connected federation
+    /// together with the fee policy in effect.
+    pub async fn handle_get_fees_msg(&self) -> Result<GatewayFees> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let fee_policy = dbtx.get_value(&FeePolicyKey).await.unwrap_or_default();
+        let configs: Vec<_> = dbtx
+            .find_by_prefix(&FederationIdKeyPrefix)
+            .await
+            .map(|(key, config)| (key.id, config))
+            .collect()
+            .await;
+
+        let liquidity = match self.get_lightning_context().await {
+            Ok(context) => context.lnrpc.channel_liquidity().await.ok(),
+            Err(_) => None,
+        };
+
+        let announced_fees = self.announced_fees.read().await;
+        let federations = configs
+            .into_iter()
+            .map(|(federation_id, config)| FederationFeesInfo {
+                federation_id,
+                configured_fees: config.fees,
+                announced_fees: announced_fees
+                    .get(&federation_id)
+                    .copied()
+                    .unwrap_or(config.fees),
+            })
+            .collect();
+
+        Ok(GatewayFees {
+            fee_policy,
+            liquidity,
+            federations,
+        })
+    }
+
+    /// Updates the fees configured for a federation and re-registers the
+    /// gateway with it, so the federation's users see the new fees.
+    pub async fn handle_set_federation_fees_msg(
+        &self,
+        SetFederationFeesPayload {
+            federation_id,
+            outgoing_fees,
+            incoming_fees,
+        }: SetFederationFeesPayload,
+    ) -> Result<()> {
+        let gateway_config = self.gateway_config.read().await.clone().ok_or(
+            GatewayError::GatewayConfigurationError(
+                "Gateway needs to be configured before setting fees".to_string(),
+            ),
+        )?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let federation_key = FederationIdKey { id: federation_id };
+        let mut federation_config =
+            dbtx.get_value(&federation_key)
+                .await
+                .ok_or(GatewayError::InvalidMetadata(format!(
+                    "No federation with id {federation_id}"
+                )))?;
+
+        if let Some(outgoing_fees) = outgoing_fees {
+            federation_config.fees.outgoing = outgoing_fees.into();
+        }
+        if let Some(incoming_fees) = incoming_fees {
+            federation_config.fees.incoming = incoming_fees.into();
+        }
+
+        dbtx.insert_entry(&federation_key, &federation_config).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        self.register_federations(&gateway_config, &[(federation_id, federation_config)])
+            .await?;
+
+        info!("Set fees of federation {federation_id} successfully.");
+
+        Ok(())
+    }
+
+    /// Replaces the fee policy and re-registers the gateway with all
+    /// federations to announce the fees it derives.
+    pub async fn handle_set_fee_policy_msg(
+        &self,
+        SetFeePolicyPayload { fee_policy }: SetFeePolicyPayload,
+    ) -> Result<()> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&FeePolicyKey, &fee_policy).await;
+        let all_federations_configs: Vec<_> = dbtx
+            .find_by_prefix(&FederationIdKeyPrefix)
+            .await
+            .map(|(key, config)| (key.id, config))
+            .collect()
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        if let Some(gateway_config) = self.gateway_config.read().await.clone() {
+            self.register_federations(&gateway_config, &all_federations_configs)
+                .await?;
+        }
+
+        info!("Set fee policy {fee_policy:?} successfully.");
+
+        Ok(())
+    }
+
+    /// Lists the payments recorded in the gateway's ledger, most recent first
+    pub async fn handle_list_payments_msg(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> Result<Vec<PaymentLogInfo>> {
+        let mut payments = self
+            .payment_log(payload.federation_id, payload.start_time, payload.end_time)
+            .await;
+
+        if payload.failed_only {
+            payments.retain(|payment| matches!(payment.entry.status, PaymentStatus::Failed { .. }));
+        }
+
+        payments.sort_by(|a, b| b.entry.started_at.cmp(&a.entry.started_at));
+        if let Some(limit) = payload.limit {
+            payments.truncate(limit);
+        }
+
+        Ok(payments)
+    }
+
+    /// Summarizes the payments recorded in the gateway's ledger per federation
+    pub async fn handle_payment_summary_msg(
+        &self,
+        payload: PaymentSummaryPayload,
+    ) -> Result<PaymentSummary> {
+        let payments = self
+            .payment_log(payload.federation_id, payload.start_time, payload.end_time)
+            .await;
+
+        let mut federations = BTreeMap::new();
+        for payment in &payments {
+            federations
+                .entry(payment.federation_id)
+                .or_insert_with(|| FederationPaymentSummary::new(payment.federation_id))
+                .add(&payment.entry);
+        }
+
+        Ok(PaymentSummary {
+            federations: federations.into_values().collect(),
+        })
+    }
+
+    /// Returns the ecash balance and liquidity target of every connected
+    /// federation together with the liquidity of the lightning node's channels
+    pub async fn handle_get_liquidity_msg(&self) -> Result<GatewayLiquidity> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let targets: BTreeMap<_, _> = dbtx
+            .find_by_prefix(&LiquidityTargetKeyPrefix)
+            .await
+            .map(|(key, target)| (key.federation_id, target))
+            .collect()
+            .await;
+
+        let channel_liquidity = match self.get_lightning_context().await {
+            Ok(context) => context.lnrpc.channel_liquidity().await.ok(),
+            Err(_) => None,
+        };
+
+        let clients: Vec<_> = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .map(|(federation_id, client)| (*federation_id, client.clone()))
+            .collect();
+
+        let mut federations = Vec::new();
+        for (federation_id, client) in clients {
+            federations.push(FederationLiquidityInfo {
+                federation_id,
+                balance: client.value().get_balance().await,
+                target: targets.get(&federation_id).copied(),
+            });
+        }
+
+        Ok(GatewayLiquidity {
+            channel_liquidity,
+            federations,
+        })
+    }
+
+    /// Sets the ecash balance target the liquidity manager maintains for a
+    /// federation, or stops managing the federation if no target is given.
+    pub async fn handle_set_liquidity_target_msg(
+        &self,
+        SetLiquidityTargetPayload {
+            federation_id,
+            target,
+        }: SetLiquidityTargetPayload,
+    ) -> Result<()> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if dbtx
+            .get_value(&FederationIdKey { id: federation_id })
+            .await
+            .is_none()
+        {
+            return Err(GatewayError::InvalidMetadata(format!(
+                "No federation with id {federation_id}"
+            )));
+        }
+
+        let key = LiquidityTargetKey { federation_id };
+        match target {
+            Some(target) => {
+                let target = LiquidityTarget::new(target.target, target.min, target.max)
+                    .map_err(|e| GatewayError::GatewayConfigurationError(e.to_string()))?;
+                dbtx.insert_entry(&key, &target).await;
+                info!("Set liquidity target of federation {federation_id} to {target:?}");
+            }
+            None => {
+                dbtx.remove_entry(&key).await;
+                info!("Removed liquidity target of federation {federation_id}");
+            }
+        }
+
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Rebalances the ecash of a federation right away, regardless of the
+    /// cooldown between automatic actions.
+    pub async fn handle_rebalance_msg(
+        &self,
+        RebalancePayload { federation_id }: RebalancePayload,
+    ) -> Result<Option<LiquidityActionInfo>> {
+        let target = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&LiquidityTargetKey { federation_id })
+            .await
+            .ok_or(GatewayError::GatewayConfigurationError(format!(
+                "No liquidity target set for federation {federation_id}"
+            )))?;
+
+        self.rebalance_federation(federation_id, target).await
+    }
+
+    /// Lists the actions of the liquidity manager, most recent first
+    pub async fn handle_liquidity_log_msg(
+        &self,
+        LiquidityLogPayload {
+            federation_id,
+            limit,
+        }: LiquidityLogPayload,
+    ) -> Result<Vec<LiquidityActionInfo>> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let actions = dbtx
+            .find_by_prefix_sorted_descending(&LiquidityActionKeyPrefix)
+            .await
+            .filter(|(key, _)| {
+                futures::future::ready(
+                    federation_id.is_none_or(|federation_id| key.federation_id == federation_id),
+                )
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(key, entry)| LiquidityActionInfo {
+                federation_id: key.federation_id,
+                created_at: key.created_at,
+                entry,
+            })
+            .collect()
+            .await;
+
+        Ok(actions)
+    }
+
+    /// Reads the payments of the ledger that started in the given time range.
+    /// The gateway modules of each federation client keep the ledger entries
+    /// of their payments in their module database.
+    async fn payment_log(
+        &self,
+        federation_id: Option<FederationId>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Vec<PaymentLogInfo> {
+        let clients: Vec<_> = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .filter(|(id, _)| federation_id.is_none_or(|federation_id| federation_id == **id))
+            .map(|(federation_id, client)| (*federation_id, client.clone()))
+            .collect();
+
+        let mut payments = Vec::new();
+        for (federation_id, client) in clients {
+            let client = client.value();
+            for module_kind in [GatewayClientModule::kind(), GatewayClientModuleV2::kind()] {
+                let Some(instance_id) = client.get_first_instance(&module_kind) else {
+                    continue;
+                };
+
+                let entries: Vec<_> = client
+                    .db()
+                    .with_prefix_module_id(instance_id)
+                    .begin_transaction_nc()
+                    .await
+                    .find_by_prefix(&PaymentLogKeyPrefix)
+                    .await
+                    .collect()
+                    .await;
+
+                payments.extend(
+                    entries
+                        .into_iter()
+                        .filter(|(_, entry)| {
+                            start_time.is_none_or(|start_time| start_time <= entry.started_at)
+                                && end_time.is_none_or(|end_time| entry.started_at < end_time)
+                        })
+                        .map(|(key, entry)| PaymentLogInfo {
+                            federation_id,
+                            operation_id: key.operation_id,
+                            entry,
+                        }),
+                );
+            }
+        }
+
+        payments
+    }
+
     /// Registers the gateway with each specified federation.
     async fn register_federations(
         &self,
@@ -1240,7 +1687,9 @@ impl Gateway {
                 warn!("Gateway did not retrieve any route hints, may reduce receive success rate.");
             }
 
-            for (federation_id, federation_config) in federations {
+            let announced_fees = self.apply_fee_policy(&lightning_context, federations).await;
+
+            for (federation_id, _) in federations {
                 if let Some(client) = self.clients.read().await.get(federation_id) {
                     if let Err(e) = async {
                         client
@@ -1249,7 +1698,7 @@ impl Gateway {
                             .register_with_federation(
                                 route_hints.clone(),
                                 GW_ANNOUNCEMENT_TTL,
-                                federation_config.fees,
+                                announced_fees[federation_id].outgoing,
                                 lightning_context.clone(),
                             )
                             .await
@@ -1269,6 +1718,83 @@ impl Gateway {
         Ok(())
     }
 
+    /// Derives the fees to announce to each of `federations` from its
+    /// configured fees using the persisted fee policy, and remembers them so
+    /// that payments are validated against the announced fees.
+    async fn apply_fee_policy(
+        &self,
+        lightning_context: &LightningContext,
+        federations: &[(FederationId, FederationConfig)],
+    ) -> BTreeMap<FederationId, FederationFees> {
+        let fee_policy = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FeePolicyKey)
+            .await
+            .unwrap_or_default()
+            .to_policy();
+
+        let liquidity = match lightning_context.lnrpc.channel_liquidity().await {
+            Ok(liquidity) => Some(liquidity),
+            Err(e) => {
+                debug!("Applying fee policy without channel liquidity: {e}");
+                None
+            }
+        };
+
+        let announced_fees = federations
+            .iter()
+            .map(|(federation_id, config)| {
+                (
+                    *federation_id,
+                    fee_policy.effective_fees(config.fees, liquidity),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        self.announced_fees
+            .write()
+            .await
+            .extend(announced_fees.clone());
+
+        announced_fees
+    }
+
+    /// Returns the fees a payment of `federation_id` has to pay at least: the
+    /// lower of its configured fees and the fees announced to it. Accepting
+    /// the configured fees keeps payments working that were created from an
+    /// announcement before the fee policy raised the fees.
+    pub async fn minimum_fees(&self, federation_id: FederationId) -> Option<FederationFees> {
+        let configured_fees = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationIdKey { id: federation_id })
+            .await?
+            .fees;
+
+        Some(match self.announced_fees.read().await.get(&federation_id) {
+            Some(announced_fees) => configured_fees.lower(announced_fees),
+            None => configured_fees,
+        })
+    }
+
+    /// Returns the fees currently announced to `federation_id`, or its
+    /// configured fees if the fee policy has not been applied to it yet.
+    pub async fn announced_fees(&self, federation_id: FederationId) -> Option<FederationFees> {
+        if let Some(announced_fees) = self.announced_fees.read().await.get(&federation_id) {
+            return Some(*announced_fees);
+        }
+
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationIdKey { id: federation_id })
+            .await
+            .map(|config| config.fees)
+    }
+
     /// This function will return a `GatewayConfiguration` one of two
     /// ways. To avoid conflicting configs, the below order is the
     /// order in which the gateway will respect configurations:
@@ -1462,6 +1988,155 @@ impl Gateway {
         });
     }
 
+    /// Periodically rebalances the ecash of every federation with a liquidity
+    /// target whose balance left the target's bounds.
+    fn start_liquidity_manager(&self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group.spawn_cancellable("liquidity manager", async move {
+            loop {
+                sleep(LIQUIDITY_CHECK_INTERVAL).await;
+
+                if let GatewayState::Running { .. } = gateway.state.read().await.clone() {
+                    gateway.check_liquidity().await;
+                } else {
+                    debug!("Skipping liquidity check because the gateway is not running");
+                }
+            }
+        });
+    }
+
+    async fn check_liquidity(&self) {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let targets: Vec<_> = dbtx
+            .find_by_prefix(&LiquidityTargetKeyPrefix)
+            .await
+            .map(|(key, target)| (key.federation_id, target))
+            .collect()
+            .await;
+
+        let now = fedimint_core::time::duration_since_epoch().as_secs();
+        for (federation_id, target) in targets {
+            if let Some(last_action) = dbtx
+                .get_value(&LastLiquidityActionKey { federation_id })
+                .await
+            {
+                if now < last_action.created_at + LIQUIDITY_ACTION_COOLDOWN.as_secs() {
+                    debug!(
+                        "Skipping liquidity check of federation {federation_id} during cooldown"
+                    );
+                    continue;
+                }
+            }
+
+            if let Err(e) = self.rebalance_federation(federation_id, target).await {
+                warn!("Failed to check liquidity of federation {federation_id}: {e:?}");
+            }
+        }
+    }
+
+    /// Pegs in or out of a federation to bring its ecash balance back to the
+    /// target and records the action in the liquidity log. Returns `None` if
+    /// the balance is within the target's bounds.
+    async fn rebalance_federation(
+        &self,
+        federation_id: FederationId,
+        target: LiquidityTarget,
+    ) -> Result<Option<LiquidityActionInfo>> {
+        let balance = self
+            .select_client(federation_id)
+            .await?
+            .value()
+            .get_balance()
+            .await;
+        let Some((kind, amount)) = target.rebalance(balance) else {
+            return Ok(None);
+        };
+
+        info!("Ecash balance {balance} of federation {federation_id} is outside of {target:?}, starting {kind:?} of {amount}");
+        let (address, result) = match kind {
+            LiquidityActionKind::PegOut => match self.handle_get_funding_address_msg().await {
+                Ok(address) => {
+                    let result = self
+                        .handle_withdraw_msg(WithdrawPayload {
+                            federation_id,
+                            amount: BitcoinAmountOrAll::Amount(bitcoin30_to_bitcoin29_amount(
+                                amount,
+                            )),
+                            address: bitcoin30_to_bitcoin29_address(address.clone()),
+                        })
+                        .await;
+                    (Some(address), result)
+                }
+                Err(e) => (None, Err(e)),
+            },
+            LiquidityActionKind::PegIn => match self
+                .handle_address_msg(DepositAddressPayload { federation_id })
+                .await
+            {
+                Ok(address) => {
+                    let result = self.send_from_node_wallet(address.clone(), amount).await;
+                    (Some(address), result)
+                }
+                Err(e) => (None, Err(e)),
+            },
+        };
+
+        let status = match result {
+            Ok(txid) => {
+                info!("{kind:?} of {amount} for federation {federation_id} sent in {txid}");
+                LiquidityActionStatus::Sent {
+                    txid: txid.to_string(),
+                }
+            }
+            Err(e) => {
+                warn!("{kind:?} of {amount} for federation {federation_id} failed: {e:?}");
+                LiquidityActionStatus::Failed {
+                    reason: e.to_string(),
+                }
+            }
+        };
+
+        let entry = LiquidityActionEntry {
+            kind,
+            amount: Amount::from_sats(amount.to_sat()),
+            balance,
+            address: address.map(|address| address.to_string()),
+            status,
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let last_action_key = LastLiquidityActionKey { federation_id };
+        let index = dbtx
+            .get_value(&last_action_key)
+            .await
+            .map_or(0, |last_action| last_action.index + 1);
+        let key = LiquidityActionKey {
+            created_at: fedimint_core::time::duration_since_epoch().as_secs(),
+            federation_id,
+            index,
+        };
+        dbtx.insert_new_entry(&key, &entry).await;
+        dbtx.insert_entry(&last_action_key, &key).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        Ok(Some(LiquidityActionInfo {
+            federation_id,
+            created_at: key.created_at,
+            entry,
+        }))
+    }
+
+    async fn send_from_node_wallet(
+        &self,
+        address: Address,
+        amount: bitcoin::Amount,
+    ) -> Result<Txid> {
+        let context = self.get_lightning_context().await?;
+        Ok(context.lnrpc.send_onchain(address, amount.to_sat()).await?)
+    }
+
     async fn fetch_lightning_route_hints(
         lnrpc: Arc<dyn ILnRpcClient>,
         num_route_hints: u32,
@@ -1505,7 +2180,7 @@ impl Gateway {
         let routing_fees = dbtx
             .get_value(&federation_key)
             .await
-            .map(|config| config.fees.into());
+            .map(|config| config.fees.outgoing.into());
 
         FederationInfo {
             federation_id,
@@ -1611,13 +2286,15 @@ impl Gateway {
     }
 
     pub async fn payment_info_v2(&self, federation_id: &FederationId) -> Option<PaymentInfo> {
+        let public_key = self.public_key_v2(federation_id).await?;
+        let announced_fees = self.announced_fees(*federation_id).await?;
+        let minimum_fees = self.minimum_fees(*federation_id).await?;
+
         Some(PaymentInfo {
-            public_key: bitcoin29_to_bitcoin30_secp256k1_public_key(
-                self.public_key_v2(federation_id).await?,
-            ),
-            send_fee_default: PaymentFee::one_percent(),
-            send_fee_minimum: PaymentFee::half_of_one_percent(),
-            receive_fee: PaymentFee::half_of_one_percent(),
+            public_key: bitcoin29_to_bitcoin30_secp256k1_public_key(public_key),
+            send_fee_default: routing_fees_to_payment_fee(announced_fees.outgoing),
+            send_fee_minimum: routing_fees_to_payment_fee(minimum_fees.outgoing),
+            receive_fee: routing_fees_to_payment_fee(announced_fees.incoming),
             expiration_delta_default: 500,
             expiration_delta_minimum: EXPIRATION_DELTA_MINIMUM_V2,
         })
@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::task::TaskGroup;
+use fedimint_core::Amount;
+use futures::StreamExt;
+use lightning_invoice::{
+    Bolt11Invoice, Bolt11InvoiceDescription, Currency, InvoiceBuilder, PaymentSecret,
+};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
+
+use super::cln::{HtlcResult, RouteHtlcStream};
+use super::{ChannelLiquidity, ILnRpcClient, LightningRpcError};
+use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
+use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Settle};
+use crate::gateway_lnrpc::{
+    CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse, GetFundingAddressResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
+};
+
+const FAKE_NODE_ALIAS: &str = "Fake Fedimint Gateway Node";
+
+/// Tag the secret key of the fake lightning node is derived from
+const FAKE_NODE_KEY_TAG: &[u8] = b"fedimint-fake-lightning-node";
+
+/// Tag the secret key of the fake node's only peer is derived from. It signs
+/// the invoices created by [`create_fake_invoice`].
+const FAKE_PEER_KEY_TAG: &[u8] = b"fedimint-fake-lightning-peer";
+
+/// Short channel id of the fake channel to the fake node's only peer
+const FAKE_CHANNEL_ID: u64 = 1;
+
+/// Capacity of the fake channel, which is split evenly between the fake node
+/// and its peer
+const FAKE_CHANNEL_CAPACITY_MSAT: u64 = 100_000_000_000;
+
+const FAKE_CLTV_EXPIRY_DELTA: u16 = 40;
+
+const FAKE_INVOICE_FAIL_DESCRIPTION: &str = "FAIL";
+
+const FAKE_INVOICE_HANG_DESCRIPTION: &str = "HANG";
+
+const HTLC_CHANNEL_SIZE: usize = 100;
+
+/// The outcome of paying an invoice with the fake lightning node, which is
+/// encoded in the description of the invoice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FakePaymentOutcome {
+    /// The payment succeeds if the payment secret of the invoice is the
+    /// preimage of its payment hash, which is the case for invoices created by
+    /// [`create_fake_invoice`]
+    Succeed,
+    /// The payment fails immediately
+    Fail,
+    /// The payment never completes
+    Hang,
+}
+
+impl FakePaymentOutcome {
+    fn description(self) -> &'static str {
+        match self {
+            FakePaymentOutcome::Succeed => "",
+            FakePaymentOutcome::Fail => FAKE_INVOICE_FAIL_DESCRIPTION,
+            FakePaymentOutcome::Hang => FAKE_INVOICE_HANG_DESCRIPTION,
+        }
+    }
+
+    fn from_invoice(invoice: &Bolt11Invoice) -> Self {
+        let Bolt11InvoiceDescription::Direct(description) = invoice.description() else {
+            return FakePaymentOutcome::Succeed;
+        };
+
+        match description.clone().into_inner().as_str() {
+            FAKE_INVOICE_FAIL_DESCRIPTION => FakePaymentOutcome::Fail,
+            FAKE_INVOICE_HANG_DESCRIPTION => FakePaymentOutcome::Hang,
+            _ => FakePaymentOutcome::Succeed,
+        }
+    }
+}
+
+/// Creates an invoice of the fake node's peer that the fake lightning node
+/// pays with the given `outcome`.
+pub fn create_fake_invoice(amount: Amount, outcome: FakePaymentOutcome) -> Bolt11Invoice {
+    // The payment secret doubles as the preimage, so the fake node can settle
+    // the payment without any state
+    let preimage: [u8; 32] = rand::random();
+
+    InvoiceBuilder::new(Currency::Regtest)
+        .description(outcome.description().to_string())
+        .payment_hash(sha256::Hash::hash(&preimage))
+        .payment_secret(PaymentSecret(preimage))
+        .current_timestamp()
+        .min_final_cltv_expiry_delta(u64::from(FAKE_CLTV_EXPIRY_DELTA))
+        .amount_milli_satoshis(amount.msats)
+        .build_signed(|m| Secp256k1::new().sign_ecdsa_recoverable(m, &fake_key(FAKE_PEER_KEY_TAG)))
+        .expect("Fake invoice is valid")
+}
+
+fn fake_key(tag: &[u8]) -> SecretKey {
+    SecretKey::from_slice(&sha256::Hash::hash(tag).into_inner()).expect("Hash is a valid key")
+}
+
+/// An `ILnRpcClient` that simulates a lightning node inside of the gateway
+/// process, for testing gateways without any lightning infrastructure.
+///
+/// The node behaves deterministically: its key is fixed, it has a single fake
+/// channel and the outcome of its payments is encoded in the paid invoices,
+/// see [`FakePaymentOutcome`]. Incoming payments are simulated by
+/// [`ILnRpcClient::simulate_incoming_payment`], which sends an HTLC for the
+/// given invoice to the HTLC stream and waits for the gateway to complete it.
+#[derive(Debug)]
+pub struct GatewayFakeClient {
+    node_sec_key: SecretKey,
+    node_pub_key: PublicKey,
+    /// Sender of the HTLC stream, set while the gateway routes HTLCs
+    htlc_sender: Arc<Mutex<Option<mpsc::Sender<HtlcResult>>>>,
+    /// Simulated HTLCs that were sent to the HTLC stream but not completed
+    /// yet, keyed by their `htlc_id`
+    pending_htlcs: Mutex<BTreeMap<u64, oneshot::Sender<InterceptHtlcResponse>>>,
+    next_htlc_id: AtomicU64,
+}
+
+impl GatewayFakeClient {
+    pub fn new() -> Self {
+        let node_sec_key = fake_key(FAKE_NODE_KEY_TAG);
+        let node_pub_key = PublicKey::from_secret_key(&Secp256k1::new(), &node_sec_key);
+        info!(%node_pub_key, "Started fake lightning node");
+
+        GatewayFakeClient {
+            node_sec_key,
+            node_pub_key,
+            htlc_sender: Arc::new(Mutex::new(None)),
+            pending_htlcs: Mutex::new(BTreeMap::new()),
+            next_htlc_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for GatewayFakeClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for GatewayFakeClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        Ok(GetNodeInfoResponse {
+            pub_key: self.node_pub_key.serialize().to_vec(),
+            alias: FAKE_NODE_ALIAS.to_string(),
+            network: "regtest".to_string(),
+            block_height: 0,
+            synced_to_chain: true,
+        })
+    }
+
+    async fn routehints(
+        &self,
+        num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        let peer_pub_key =
+            PublicKey::from_secret_key(&Secp256k1::new(), &fake_key(FAKE_PEER_KEY_TAG));
+        let route_hint = RouteHint {
+            hops: vec![RouteHintHop {
+                src_node_id: peer_pub_key.serialize().to_vec(),
+                short_channel_id: FAKE_CHANNEL_ID,
+                base_msat: 0,
+                proportional_millionths: 0,
+                cltv_expiry_delta: u32::from(FAKE_CLTV_EXPIRY_DELTA),
+                htlc_minimum_msat: None,
+                htlc_maximum_msat: None,
+            }],
+        };
+
+        Ok(GetRouteHintsResponse {
+            route_hints: std::iter::once(route_hint).take(num_route_hints).collect(),
+        })
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let invoice = Bolt11Invoice::from_str(&invoice.invoice).map_err(|e| {
+            LightningRpcError::FailedPayment {
+                failure_reason: format!("Invalid invoice: {e:?}"),
+            }
+        })?;
+
+        match FakePaymentOutcome::from_invoice(&invoice) {
+            FakePaymentOutcome::Succeed => {
+                let PaymentSecret(preimage) = *invoice.payment_secret();
+                if sha256::Hash::hash(&preimage) != *invoice.payment_hash() {
+                    return Err(LightningRpcError::FailedPayment {
+                        failure_reason:
+                            "Fake lightning node doesn't know the preimage of the invoice"
+                                .to_string(),
+                    });
+                }
+
+                Ok(PayInvoiceResponse {
+                    preimage: preimage.to_vec(),
+                })
+            }
+            FakePaymentOutcome::Fail => Err(LightningRpcError::FailedPayment {
+                failure_reason: "Invoice requested the payment to fail".to_string(),
+            }),
+            FakePaymentOutcome::Hang => {
+                info!(payment_hash = %invoice.payment_hash(), "Invoice requested the payment to hang");
+                std::future::pending().await
+            }
+        }
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        let (htlc_sender, htlc_receiver) = mpsc::channel::<HtlcResult>(HTLC_CHANNEL_SIZE);
+        *self.htlc_sender.lock().expect("Lock poisoned") = Some(htlc_sender);
+
+        // Close the HTLC stream once the gateway stops routing HTLCs
+        let htlc_sender = self.htlc_sender.clone();
+        task_group.spawn("fake lightning node", |handle| async move {
+            handle.make_shutdown_rx().await.await;
+            htlc_sender.lock().expect("Lock poisoned").take();
+        });
+
+        Ok((ReceiverStream::new(htlc_receiver).boxed(), Arc::new(*self)))
+    }
+
+    async fn complete_htlc(
+        &self,
+        htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let htlc_id = htlc.htlc_id;
+        let outcome_sender = self
+            .pending_htlcs
+            .lock()
+            .expect("Lock poisoned")
+            .remove(&htlc_id)
+            .ok_or_else(|| LightningRpcError::FailedToCompleteHtlc {
+                failure_reason: format!("Unknown HTLC {htlc_id}"),
+            })?;
+
+        if outcome_sender.send(htlc).is_err() {
+            warn!(
+                htlc_id,
+                "Simulated payment was dropped before its HTLC completed"
+            );
+        }
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn create_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        let payment_hash =
+            sha256::Hash::from_slice(&create_invoice_request.payment_hash).map_err(|e| {
+                LightningRpcError::FailedToGetInvoice {
+                    failure_reason: format!("Invalid payment hash: {e:?}"),
+                }
+            })?;
+        // The gateway doesn't know the preimage, so the payment secret is derived
+        // from the payment hash to keep the invoice deterministic
+        let payment_secret = sha256::Hash::hash(&payment_hash.into_inner()).into_inner();
+
+        let invoice = InvoiceBuilder::new(Currency::Regtest)
+            .description(create_invoice_request.description)
+            .payment_hash(payment_hash)
+            .payment_secret(PaymentSecret(payment_secret))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(u64::from(FAKE_CLTV_EXPIRY_DELTA))
+            .amount_milli_satoshis(create_invoice_request.amount_msat)
+            .expiry_time(Duration::from_secs(u64::from(
+                create_invoice_request.expiry,
+            )))
+            .build_signed(|m| Secp256k1::new().sign_ecdsa_recoverable(m, &self.node_sec_key))
+            .map_err(|e| LightningRpcError::FailedToGetInvoice {
+                failure_reason: format!("Failed to create invoice: {e:?}"),
+            })?;
+
+        Ok(CreateInvoiceResponse {
+            invoice: invoice.to_string(),
+        })
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        info!(%pubkey, %host, "Fake lightning node pretends to connect to peer");
+        Ok(EmptyResponse {})
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        let public_key = bitcoin::PublicKey::from_slice(&self.node_pub_key.serialize())
+            .expect("Node key is a valid public key");
+        let address = bitcoin::Address::p2wpkh(&public_key, bitcoin::Network::Regtest)
+            .expect("Node key is compressed");
+
+        Ok(GetFundingAddressResponse {
+            address: address.to_string(),
+        })
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        info!(
+            %pubkey,
+            channel_size_sats,
+            push_amount_sats,
+            "Fake lightning node pretends to open channel"
+        );
+        Ok(EmptyResponse {})
+    }
+
+    async fn channel_liquidity(&self) -> Result<ChannelLiquidity, LightningRpcError> {
+        Ok(ChannelLiquidity {
+            outbound_msat: FAKE_CHANNEL_CAPACITY_MSAT / 2,
+            inbound_msat: FAKE_CHANNEL_CAPACITY_MSAT / 2,
+        })
+    }
+
+    async fn simulate_incoming_payment(
+        &self,
+        invoice: Bolt11Invoice,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let amount_msat =
+            invoice
+                .amount_milli_satoshis()
+                .ok_or_else(|| LightningRpcError::FailedPayment {
+                    failure_reason: "Invoice is missing an amount".to_string(),
+                })?;
+
+        // Invoices of federation clients route the payment over the virtual
+        // channel of their federation, which is the last hop of the route hint
+        let short_channel_id = invoice
+            .route_hints()
+            .first()
+            .and_then(|route_hint| route_hint.0.last())
+            .map_or(0, |hop| hop.short_channel_id);
+
+        let htlc_sender = self
+            .htlc_sender
+            .lock()
+            .expect("Lock poisoned")
+            .clone()
+            .ok_or_else(|| LightningRpcError::FailedPayment {
+                failure_reason: "Gateway is not routing HTLCs".to_string(),
+            })?;
+
+        let htlc_id = self.next_htlc_id.fetch_add(1, Ordering::Relaxed);
+        let (outcome_sender, outcome_receiver) = oneshot::channel();
+        self.pending_htlcs
+            .lock()
+            .expect("Lock poisoned")
+            .insert(htlc_id, outcome_sender);
+
+        let htlc = InterceptHtlcRequest {
+            payment_hash: invoice.payment_hash().into_inner().to_vec(),
+            incoming_amount_msat: amount_msat,
+            outgoing_amount_msat: amount_msat,
+            incoming_expiry: invoice.min_final_cltv_expiry_delta() as u32,
+            short_channel_id,
+            incoming_chan_id: FAKE_CHANNEL_ID,
+            htlc_id,
+        };
+
+        if htlc_sender.send(Ok(htlc)).await.is_err() {
+            self.pending_htlcs
+                .lock()
+                .expect("Lock poisoned")
+                .remove(&htlc_id);
+            return Err(LightningRpcError::FailedPayment {
+                failure_reason: "HTLC stream closed".to_string(),
+            });
+        }
+
+        let outcome = outcome_receiver
+            .await
+            .map_err(|_| LightningRpcError::FailedPayment {
+                failure_reason: "Fake lightning node shut down before the HTLC completed"
+                    .to_string(),
+            })?;
+
+        match outcome.action {
+            Some(Action::Settle(Settle { preimage })) => {
+                if sha256::Hash::hash(&preimage) != *invoice.payment_hash() {
+                    return Err(LightningRpcError::FailedPayment {
+                        failure_reason: "Gateway settled the HTLC with an invalid preimage"
+                            .to_string(),
+                    });
+                }
+
+                Ok(PayInvoiceResponse { preimage })
+            }
+            Some(Action::Cancel(Cancel { reason })) => Err(LightningRpcError::FailedPayment {
+                failure_reason: reason,
+            }),
+            Some(Action::Forward(_)) | None => Err(LightningRpcError::FailedPayment {
+                failure_reason:
+                    "Gateway forwarded the HTLC, but the fake lightning node has no route"
+                        .to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::Amount;
+
+    use super::{create_fake_invoice, FakePaymentOutcome, GatewayFakeClient};
+    use crate::gateway_lnrpc::PayInvoiceRequest;
+    use crate::lightning::ILnRpcClient;
+
+    fn pay_request(outcome: FakePaymentOutcome) -> PayInvoiceRequest {
+        let invoice = create_fake_invoice(Amount::from_sats(1000), outcome);
+        PayInvoiceRequest {
+            invoice: invoice.to_string(),
+            max_delay: 0,
+            max_fee_msat: 0,
+            payment_hash: invoice.payment_hash().into_inner().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn payments_follow_invoice_outcome() {
+        let client = GatewayFakeClient::new();
+
+        let request = pay_request(FakePaymentOutcome::Succeed);
+        let response = client.pay(request.clone()).await.unwrap();
+        assert_eq!(
+            sha256::Hash::hash(&response.preimage).into_inner().to_vec(),
+            request.payment_hash
+        );
+
+        assert!(client
+            .pay(pay_request(FakePaymentOutcome::Fail))
+            .await
+            .is_err());
+
+        let hanging_payment = client.pay(pay_request(FakePaymentOutcome::Hang));
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), hanging_payment)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,716 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::runtime::timeout;
+use fedimint_core::task::{block_in_place, block_on, sleep, TaskGroup};
+use fedimint_core::util::SafeUrl;
+use futures::StreamExt;
+use ldk_node::bitcoin::hashes::Hash;
+use ldk_node::lightning::io;
+use ldk_node::lightning::ln::channelmanager::PaymentId;
+use ldk_node::lightning::ln::msgs::SocketAddress;
+use ldk_node::lightning::types::payment::{PaymentHash, PaymentPreimage};
+use ldk_node::lightning::util::persist::KVStore;
+use ldk_node::lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription, Description};
+use ldk_node::payment::{PaymentDetails, PaymentKind, PaymentStatus, SendingParameters};
+use ldk_node::{Builder, Event, Node};
+use rand::Rng;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, warn};
+
+use super::cln::{HtlcResult, RouteHtlcStream};
+use super::{ChannelLiquidity, ILnRpcClient, LightningRpcError};
+use crate::db::{
+    LdkKvStoreKey, LdkKvStoreNamespacePrefix, LdkNodeSeedKey, LdkPendingHtlc, LdkPendingHtlcKey,
+    LdkPendingHtlcKeyPrefix,
+};
+use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
+use crate::gateway_lnrpc::intercept_htlc_response::{Action, Settle};
+use crate::gateway_lnrpc::{
+    CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse, GetFundingAddressResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
+};
+
+const LDK_NODE_ALIAS: &str = "LDK Fedimint Gateway Node";
+
+const LDK_NODE_DIR: &str = "ldk_node";
+
+const LDK_PAYMENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time after which `pay` gives up waiting for an outgoing payment to resolve.
+/// Calling `pay` again for the same invoice resumes waiting for the payment.
+const LDK_PAYMENT_TIMEOUT: Duration = Duration::from_secs(180);
+
+const HTLC_CHANNEL_SIZE: usize = 100;
+
+/// An `ILnRpcClient` backed by an LDK node that runs inside of the gateway
+/// process, so no external lightning node has to be operated.
+///
+/// The node keeps its channel state in the gateway database. Incoming
+/// payments are intercepted by creating invoices for which the gateway doesn't
+/// know the preimage yet: LDK holds the HTLCs of such payments until the
+/// gateway settles or cancels them through `complete_htlc`. Held payments are
+/// persisted as well and handed out again when the HTLC stream is reopened. Since LDK can't
+/// settle HTLCs that are forwarded over a route hint with a virtual short
+/// channel id, only payments to invoices created with `create_invoice` can be
+/// intercepted.
+pub struct GatewayLdkClient {
+    node: Arc<Node>,
+    network: ldk_node::bitcoin::Network,
+    /// Stores the payments held by LDK that were sent to the HTLC stream but
+    /// not completed yet, see [`LdkPendingHtlcKey`]
+    gateway_db: Database,
+}
+
+impl GatewayLdkClient {
+    /// Creates and starts the embedded LDK node, syncing the chain from the
+    /// bitcoind at `bitcoind_rpc_url` and accepting lightning peers on
+    /// `lightning_port`.
+    pub async fn new(
+        gateway_db: Database,
+        data_dir: &Path,
+        bitcoind_rpc_url: &SafeUrl,
+        network: bitcoin::Network,
+        lightning_port: u16,
+    ) -> anyhow::Result<Self> {
+        let seed = Self::get_or_create_seed(&gateway_db).await;
+        let network = ldk_node::bitcoin::Network::from_str(&network.to_string())?;
+
+        let mut builder = Builder::new();
+        builder.set_network(network);
+        builder.set_entropy_seed_bytes(seed);
+        builder.set_chain_source_bitcoind_rpc(
+            bitcoind_rpc_url
+                .host_str()
+                .context("Bitcoind RPC URL is missing a host")?
+                .to_string(),
+            bitcoind_rpc_url
+                .port_or_known_default()
+                .context("Bitcoind RPC URL is missing a port")?,
+            bitcoind_rpc_url.username().to_string(),
+            bitcoind_rpc_url
+                .password()
+                .context("Bitcoind RPC URL is missing a password")?
+                .to_string(),
+        );
+        builder.set_storage_dir_path(
+            data_dir
+                .join(LDK_NODE_DIR)
+                .to_str()
+                .context("Invalid data dir")?
+                .to_string(),
+        );
+        builder.set_listening_addresses(vec![SocketAddress::TcpIpV4 {
+            addr: [0, 0, 0, 0],
+            port: lightning_port,
+        }])?;
+
+        let node = builder.build_with_store(Arc::new(GatewayLdkKvStore {
+            db: gateway_db.clone(),
+        }))?;
+        block_in_place(|| node.start())?;
+
+        info!(
+            node_id = %node.node_id(),
+            ?network,
+            lightning_port,
+            "Started embedded LDK node"
+        );
+
+        Ok(GatewayLdkClient {
+            node: Arc::new(node),
+            network,
+            gateway_db,
+        })
+    }
+
+    async fn get_or_create_seed(gateway_db: &Database) -> [u8; 64] {
+        let mut dbtx = gateway_db.begin_transaction().await;
+        if let Some(seed) = dbtx.get_value(&LdkNodeSeedKey).await {
+            return seed;
+        }
+
+        let mut seed = [0; 64];
+        rand::thread_rng().fill(&mut seed[..]);
+        dbtx.insert_new_entry(&LdkNodeSeedKey, &seed).await;
+        dbtx.commit_tx().await;
+        seed
+    }
+
+    /// Waits for the next event of the LDK node and sends the payments it
+    /// holds for us to the HTLC stream
+    async fn handle_next_event(
+        node: &Node,
+        gateway_db: &Database,
+        htlc_sender: &mpsc::Sender<HtlcResult>,
+    ) {
+        let event = node.next_event_async().await;
+        debug!(?event, "Received LDK node event");
+
+        if let Event::PaymentClaimable {
+            payment_hash,
+            claimable_amount_msat,
+            claim_deadline,
+            ..
+        } = event
+        {
+            let htlc = LdkPendingHtlc {
+                payment_hash: payment_hash.0,
+                amount_msat: claimable_amount_msat,
+                claim_deadline,
+            };
+
+            // The payment is persisted before the event is marked as handled, so
+            // it is handed out again after a restart either way
+            if let Some(htlc_id) = Self::persist_pending_htlc(gateway_db, &htlc).await {
+                Self::send_pending_htlc(node, gateway_db, htlc_sender, htlc_id, htlc).await;
+            }
+        }
+
+        if let Err(e) = node.event_handled() {
+            warn!("Failed to mark LDK node event as handled: {e:?}");
+        }
+    }
+
+    /// Stores a payment held by LDK and returns the `htlc_id` it is handed out
+    /// with, or `None` if the payment is already pending
+    async fn persist_pending_htlc(gateway_db: &Database, htlc: &LdkPendingHtlc) -> Option<u64> {
+        let mut dbtx = gateway_db.begin_transaction().await;
+        let pending_htlcs = dbtx
+            .find_by_prefix(&LdkPendingHtlcKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        if pending_htlcs
+            .iter()
+            .any(|(_, pending)| pending.payment_hash == htlc.payment_hash)
+        {
+            return None;
+        }
+
+        let htlc_id = pending_htlcs
+            .iter()
+            .map(|(key, _)| key.htlc_id + 1)
+            .max()
+            .unwrap_or_default();
+        dbtx.insert_new_entry(&LdkPendingHtlcKey { htlc_id }, htlc)
+            .await;
+        dbtx.commit_tx().await;
+        Some(htlc_id)
+    }
+
+    /// Sends a held payment to the HTLC stream, failing it if the stream was
+    /// closed
+    async fn send_pending_htlc(
+        node: &Node,
+        gateway_db: &Database,
+        htlc_sender: &mpsc::Sender<HtlcResult>,
+        htlc_id: u64,
+        htlc: LdkPendingHtlc,
+    ) {
+        let request = InterceptHtlcRequest {
+            payment_hash: htlc.payment_hash.to_vec(),
+            incoming_amount_msat: htlc.amount_msat,
+            outgoing_amount_msat: htlc.amount_msat,
+            incoming_expiry: htlc.claim_deadline.unwrap_or_default(),
+            short_channel_id: 0,
+            incoming_chan_id: 0,
+            htlc_id,
+        };
+
+        if htlc_sender.send(Ok(request)).await.is_err() {
+            let payment_hash = PaymentHash(htlc.payment_hash);
+            warn!(?payment_hash, "HTLC stream closed, failing held payment");
+            if let Err(e) = node.bolt11_payment().fail_for_hash(payment_hash) {
+                warn!(?payment_hash, "Failed to fail held payment: {e:?}");
+            }
+            let mut dbtx = gateway_db.begin_transaction().await;
+            dbtx.remove_entry(&LdkPendingHtlcKey { htlc_id }).await;
+            dbtx.commit_tx().await;
+        }
+    }
+
+    fn parse_public_key(
+        pubkey: secp256k1::PublicKey,
+    ) -> Result<ldk_node::bitcoin::secp256k1::PublicKey, String> {
+        ldk_node::bitcoin::secp256k1::PublicKey::from_slice(&pubkey.serialize())
+            .map_err(|e| format!("Invalid public key {e:?}"))
+    }
+}
+
+impl fmt::Debug for GatewayLdkClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GatewayLdkClient {}", self.node.node_id())
+    }
+}
+
+impl Drop for GatewayLdkClient {
+    fn drop(&mut self) {
+        // The node is already stopped if the HTLC stream was shut down
+        if let Err(e) = block_in_place(|| self.node.stop()) {
+            debug!("Stopping LDK node on drop: {e:?}");
+        }
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for GatewayLdkClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        let status = self.node.status();
+
+        Ok(GetNodeInfoResponse {
+            pub_key: self.node.node_id().serialize().to_vec(),
+            alias: LDK_NODE_ALIAS.to_string(),
+            network: self.network.to_string(),
+            block_height: status.current_best_block.height,
+            synced_to_chain: status.latest_lightning_wallet_sync_timestamp.is_some(),
+        })
+    }
+
+    async fn routehints(
+        &self,
+        num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        let mut channels = self
+            .node
+            .list_channels()
+            .into_iter()
+            .filter(|channel| channel.is_usable)
+            .collect::<Vec<_>>();
+
+        // Take the channels with the largest incoming capacity
+        channels.sort_by(|a, b| b.inbound_capacity_msat.cmp(&a.inbound_capacity_msat));
+
+        let route_hints = channels
+            .into_iter()
+            .filter_map(|channel| {
+                Some(RouteHint {
+                    hops: vec![RouteHintHop {
+                        src_node_id: channel.counterparty_node_id.serialize().to_vec(),
+                        short_channel_id: channel.short_channel_id?,
+                        base_msat: channel.counterparty_forwarding_info_fee_base_msat?,
+                        proportional_millionths: channel
+                            .counterparty_forwarding_info_fee_proportional_millionths?,
+                        cltv_expiry_delta: channel
+                            .counterparty_forwarding_info_cltv_expiry_delta?
+                            .into(),
+                        htlc_minimum_msat: Some(channel.inbound_htlc_minimum_msat),
+                        htlc_maximum_msat: channel.inbound_htlc_maximum_msat,
+                    }],
+                })
+            })
+            .take(num_route_hints)
+            .collect();
+
+        Ok(GetRouteHintsResponse { route_hints })
+    }
+
+    async fn pay(
+        &self,
+        request: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let invoice = Bolt11Invoice::from_str(&request.invoice).map_err(|e| {
+            LightningRpcError::FailedPayment {
+                failure_reason: format!("Failed to parse invoice {e:?}"),
+            }
+        })?;
+
+        // LDK identifies invoice payments by their payment hash, so if the payment
+        // exists we've already tried to pay the invoice
+        let payment_id = PaymentId(invoice.payment_hash().to_byte_array());
+        if self.node.payment(&payment_id).is_none() {
+            let sending_parameters = SendingParameters {
+                max_total_routing_fee_msat: Some(Some(request.max_fee_msat)),
+                max_total_cltv_expiry_delta: Some(request.max_delay.try_into().unwrap_or(u32::MAX)),
+                max_path_count: None,
+                max_channel_saturation_power_of_half: None,
+            };
+
+            self.node
+                .bolt11_payment()
+                .send(&invoice, Some(sending_parameters))
+                .map_err(|e| LightningRpcError::FailedPayment {
+                    failure_reason: format!("LDK payment failed to initialize: {e:?}"),
+                })?;
+        }
+
+        let payment_result = timeout(LDK_PAYMENT_TIMEOUT, async {
+            loop {
+                if let Some(PaymentDetails {
+                    kind: PaymentKind::Bolt11 { preimage, .. },
+                    status,
+                    ..
+                }) = self.node.payment(&payment_id)
+                {
+                    match (status, preimage) {
+                        (PaymentStatus::Succeeded, Some(preimage)) => {
+                            return Ok(PayInvoiceResponse {
+                                preimage: preimage.0.to_vec(),
+                            });
+                        }
+                        (PaymentStatus::Failed, _) => {
+                            return Err(LightningRpcError::FailedPayment {
+                                failure_reason: "LDK payment failed".to_string(),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+
+                sleep(LDK_PAYMENT_POLL_INTERVAL).await;
+            }
+        })
+        .await;
+
+        payment_result.unwrap_or_else(|_| {
+            Err(LightningRpcError::FailedPayment {
+                failure_reason: format!(
+                    "LDK payment did not resolve within {LDK_PAYMENT_TIMEOUT:?}"
+                ),
+            })
+        })
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        let (htlc_sender, htlc_receiver) = mpsc::channel::<HtlcResult>(HTLC_CHANNEL_SIZE);
+
+        let node = self.node.clone();
+        let gateway_db = self.gateway_db.clone();
+        task_group.spawn("LDK node event handler", |handle| async move {
+            // Payments that were held before the stream was reopened, e.g. before a
+            // restart, are handed out again since their events were already handled
+            let pending_htlcs = gateway_db
+                .begin_transaction_nc()
+                .await
+                .find_by_prefix(&LdkPendingHtlcKeyPrefix)
+                .await
+                .collect::<Vec<_>>()
+                .await;
+            for (key, htlc) in pending_htlcs {
+                Self::send_pending_htlc(&node, &gateway_db, &htlc_sender, key.htlc_id, htlc).await;
+            }
+
+            loop {
+                let next_event = Self::handle_next_event(&node, &gateway_db, &htlc_sender);
+                if handle.cancel_on_shutdown(next_event).await.is_err() {
+                    break;
+                }
+            }
+
+            // Stop the node before a new one can be built on top of the same
+            // database
+            info!("Stopping embedded LDK node");
+            if let Err(e) = block_in_place(|| node.stop()) {
+                warn!("Failed to stop LDK node: {e:?}");
+            }
+        });
+
+        Ok((ReceiverStream::new(htlc_receiver).boxed(), Arc::new(*self)))
+    }
+
+    async fn complete_htlc(
+        &self,
+        htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let InterceptHtlcResponse {
+            action, htlc_id, ..
+        } = htlc;
+
+        let htlc_key = LdkPendingHtlcKey { htlc_id };
+        let htlc = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&htlc_key)
+            .await
+            .ok_or_else(|| LightningRpcError::FailedToCompleteHtlc {
+                failure_reason: format!("Unknown HTLC {htlc_id}"),
+            })?;
+        let payment_hash = PaymentHash(htlc.payment_hash);
+
+        // A held payment can't be forwarded, so anything but settling fails it
+        let result = match action {
+            Some(Action::Settle(Settle { preimage })) => {
+                let preimage = PaymentPreimage(preimage.try_into().map_err(|_| {
+                    LightningRpcError::FailedToCompleteHtlc {
+                        failure_reason: "Invalid preimage length".to_string(),
+                    }
+                })?);
+                self.node
+                    .bolt11_payment()
+                    .claim_for_hash(payment_hash, htlc.amount_msat, preimage)
+            }
+            _ => self.node.bolt11_payment().fail_for_hash(payment_hash),
+        };
+
+        result.map_err(|e| LightningRpcError::FailedToCompleteHtlc {
+            failure_reason: format!("Failed to complete HTLC {e:?}"),
+        })?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.remove_entry(&htlc_key).await;
+        dbtx.commit_tx().await;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn create_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        let payment_hash = PaymentHash(create_invoice_request.payment_hash.try_into().map_err(
+            |_| LightningRpcError::FailedToGetInvoice {
+                failure_reason: "Invalid payment hash length".to_string(),
+            },
+        )?);
+        let description = Description::new(create_invoice_request.description).map_err(|e| {
+            LightningRpcError::FailedToGetInvoice {
+                failure_reason: format!("Invalid description {e:?}"),
+            }
+        })?;
+
+        // Since the gateway doesn't know the preimage, LDK holds the payment until
+        // it's completed through `complete_htlc`
+        let invoice = self
+            .node
+            .bolt11_payment()
+            .receive_for_hash(
+                create_invoice_request.amount_msat,
+                &Bolt11InvoiceDescription::Direct(description),
+                create_invoice_request.expiry,
+                payment_hash,
+            )
+            .map_err(|e| LightningRpcError::FailedToGetInvoice {
+                failure_reason: format!("Failed to create invoice {e:?}"),
+            })?;
+
+        Ok(CreateInvoiceResponse {
+            invoice: invoice.to_string(),
+        })
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let node_id = Self::parse_public_key(pubkey).map_err(|failure_reason| {
+            LightningRpcError::FailedToConnectToPeer { failure_reason }
+        })?;
+        let address = SocketAddress::from_str(&host).map_err(|e| {
+            LightningRpcError::FailedToConnectToPeer {
+                failure_reason: format!("Invalid peer address {e:?}"),
+            }
+        })?;
+
+        self.node.connect(node_id, address, true).map_err(|e| {
+            LightningRpcError::FailedToConnectToPeer {
+                failure_reason: format!("Failed to connect to peer {e:?}"),
+            }
+        })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        let address = self.node.onchain_payment().new_address().map_err(|e| {
+            LightningRpcError::FailedToGetFundingAddress {
+                failure_reason: format!("Failed to get funding address {e:?}"),
+            }
+        })?;
+
+        Ok(GetFundingAddressResponse {
+            address: address.to_string(),
+        })
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let node_id = Self::parse_public_key(pubkey)
+            .map_err(|failure_reason| LightningRpcError::FailedToOpenChannel { failure_reason })?;
+
+        // LDK needs the address of the peer to open a channel, so the peer has to
+        // be connected through `connect_to_peer` first
+        let address = self
+            .node
+            .list_peers()
+            .into_iter()
+            .find(|peer| peer.node_id == node_id)
+            .map(|peer| peer.address)
+            .ok_or_else(|| LightningRpcError::FailedToOpenChannel {
+                failure_reason: "Not connected to peer".to_string(),
+            })?;
+
+        self.node
+            .open_channel(
+                node_id,
+                address,
+                channel_size_sats,
+                Some(push_amount_sats * 1000),
+                None,
+            )
+            .map_err(|e| LightningRpcError::FailedToOpenChannel {
+                failure_reason: format!("Failed to open channel {e:?}"),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn channel_liquidity(&self) -> Result<ChannelLiquidity, LightningRpcError> {
+        let channels = self
+            .node
+            .list_channels()
+            .into_iter()
+            .filter(|channel| channel.is_usable)
+            .collect::<Vec<_>>();
+
+        Ok(ChannelLiquidity {
+            outbound_msat: channels
+                .iter()
+                .map(|channel| channel.outbound_capacity_msat)
+                .sum(),
+            inbound_msat: channels
+                .iter()
+                .map(|channel| channel.inbound_capacity_msat)
+                .sum(),
+        })
+    }
+
+    async fn send_onchain(
+        &self,
+        address: bitcoin::Address,
+        amount_sats: u64,
+    ) -> Result<bitcoin::Txid, LightningRpcError> {
+        // The LDK node uses a different version of the bitcoin crate
+        let address = ldk_node::bitcoin::Address::from_str(&address.to_string())
+            .map_err(|e| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Invalid address {e:?}"),
+            })?
+            .assume_checked();
+
+        let txid = self
+            .node
+            .onchain_payment()
+            .send_to_address(&address, amount_sats, None)
+            .map_err(|e| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Failed to send to address {e:?}"),
+            })?;
+
+        bitcoin::Txid::from_str(&txid.to_string()).map_err(|e| {
+            LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Failed to parse txid {e:?}"),
+            }
+        })
+    }
+}
+
+/// [`KVStore`] that persists the state of the embedded LDK node in the gateway
+/// database
+struct GatewayLdkKvStore {
+    db: Database,
+}
+
+impl GatewayLdkKvStore {
+    fn kv_key(primary_namespace: &str, secondary_namespace: &str, key: &str) -> LdkKvStoreKey {
+        LdkKvStoreKey {
+            primary_namespace: primary_namespace.to_string(),
+            secondary_namespace: secondary_namespace.to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    /// Writes `value` to `key`, removing the entry if `value` is `None`
+    fn commit(&self, key: LdkKvStoreKey, value: Option<Vec<u8>>) -> io::Result<()> {
+        block_in_place(|| {
+            block_on(async {
+                let mut dbtx = self.db.begin_transaction().await;
+                match value {
+                    Some(value) => {
+                        dbtx.insert_entry(&key, &value).await;
+                    }
+                    None => {
+                        dbtx.remove_entry(&key).await;
+                    }
+                }
+                dbtx.commit_tx_result().await
+            })
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+impl KVStore for GatewayLdkKvStore {
+    fn read(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+    ) -> io::Result<Vec<u8>> {
+        let kv_key = Self::kv_key(primary_namespace, secondary_namespace, key);
+        block_in_place(|| {
+            block_on(async {
+                let mut dbtx = self.db.begin_transaction_nc().await;
+                dbtx.get_value(&kv_key).await
+            })
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{kv_key:?} not found")))
+    }
+
+    fn write(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        self.commit(
+            Self::kv_key(primary_namespace, secondary_namespace, key),
+            Some(buf.to_vec()),
+        )
+    }
+
+    fn remove(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+        _lazy: bool,
+    ) -> io::Result<()> {
+        self.commit(
+            Self::kv_key(primary_namespace, secondary_namespace, key),
+            None,
+        )
+    }
+
+    fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+        let prefix = LdkKvStoreNamespacePrefix {
+            primary_namespace: primary_namespace.to_string(),
+            secondary_namespace: secondary_namespace.to_string(),
+        };
+        Ok(block_in_place(|| {
+            block_on(async {
+                let mut dbtx = self.db.begin_transaction_nc().await;
+                dbtx.find_by_prefix(&prefix)
+                    .await
+                    .map(|(kv_key, _)| kv_key.key)
+                    .collect::<Vec<_>>()
+                    .await
+            })
+        }))
+    }
+}
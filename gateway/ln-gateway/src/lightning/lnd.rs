@@ -18,7 +18,7 @@ use tonic_lnd::lnrpc::failure::FailureCode;
 use tonic_lnd::lnrpc::payment::PaymentStatus;
 use tonic_lnd::lnrpc::{
     ChanInfoRequest, ConnectPeerRequest, GetInfoRequest, LightningAddress, ListChannelsRequest,
-    OpenChannelRequest,
+    OpenChannelRequest, SendCoinsRequest,
 };
 use tonic_lnd::routerrpc::{
     CircuitKey, ForwardHtlcInterceptResponse, ResolveHoldForwardAction, SendPaymentRequest,
@@ -30,7 +30,7 @@ use tonic_lnd::{connect, Client as LndClient};
 use tracing::{debug, error, info, trace, warn};
 
 use super::cln::RouteHtlcStream;
-use super::{ILnRpcClient, LightningRpcError, MAX_LIGHTNING_RETRIES};
+use super::{ChannelLiquidity, ILnRpcClient, LightningRpcError, MAX_LIGHTNING_RETRIES};
 use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use crate::gateway_lnrpc::{
@@ -710,6 +710,68 @@ impl ILnRpcClient for GatewayLndClient {
             }),
         }
     }
+
+    async fn channel_liquidity(&self) -> Result<ChannelLiquidity, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let channels = client
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: true,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetChannelLiquidity {
+                failure_reason: format!("Failed to list channels {status:?}"),
+            })?
+            .into_inner()
+            .channels;
+
+        // LND reports channel balances in sats
+        Ok(ChannelLiquidity {
+            outbound_msat: channels
+                .iter()
+                .map(|chan| chan.local_balance.max(0) as u64 * 1000)
+                .sum(),
+            inbound_msat: channels
+                .iter()
+                .map(|chan| chan.remote_balance.max(0) as u64 * 1000)
+                .sum(),
+        })
+    }
+
+    async fn send_onchain(
+        &self,
+        address: bitcoin::Address,
+        amount_sats: u64,
+    ) -> Result<bitcoin::Txid, LightningRpcError> {
+        let amount =
+            i64::try_from(amount_sats).map_err(|_| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Amount of {amount_sats} sats is too large"),
+            })?;
+
+        let mut client = self.connect().await?;
+        let response = client
+            .lightning()
+            .send_coins(SendCoinsRequest {
+                addr: address.to_string(),
+                amount,
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Failed to send coins {status:?}"),
+            })?
+            .into_inner();
+
+        bitcoin::Txid::from_str(&response.txid).map_err(|e| {
+            LightningRpcError::FailedToSendOnchain {
+                failure_reason: format!("Failed to parse txid {e:?}"),
+            }
+        })
+    }
 }
 
 fn route_hints_to_lnd(
@@ -1,23 +1,33 @@
 pub mod cln;
+pub mod fake;
+pub mod ldk;
 pub mod lnd;
 
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use anyhow::Context;
 use async_trait::async_trait;
+use bitcoin::Network;
 use clap::Subcommand;
+use fedimint_core::db::Database;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::Amount;
 use fedimint_ln_common::PrunedInvoice;
+use lightning_invoice::Bolt11Invoice;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use self::cln::{NetworkLnRpcClient, RouteHtlcStream};
+use self::fake::GatewayFakeClient;
+use self::ldk::GatewayLdkClient;
 use self::lnd::GatewayLndClient;
 use crate::envs::{
-    FM_GATEWAY_LIGHTNING_ADDR_ENV, FM_LND_MACAROON_ENV, FM_LND_RPC_ADDR_ENV, FM_LND_TLS_CERT_ENV,
+    FM_GATEWAY_LIGHTNING_ADDR_ENV, FM_LDK_BITCOIND_RPC_URL_ENV, FM_LDK_NETWORK_ENV,
+    FM_LND_MACAROON_ENV, FM_LND_RPC_ADDR_ENV, FM_LND_TLS_CERT_ENV, FM_PORT_LDK_ENV,
 };
 use crate::gateway_lnrpc::{
     CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse, GetFundingAddressResponse,
@@ -51,6 +61,19 @@ pub enum LightningRpcError {
     FailedToGetFundingAddress { failure_reason: String },
     #[error("Failed to connect to peer: {failure_reason}")]
     FailedToConnectToPeer { failure_reason: String },
+    #[error("Failed to get channel liquidity: {failure_reason}")]
+    FailedToGetChannelLiquidity { failure_reason: String },
+    #[error("Failed to send on-chain funds: {failure_reason}")]
+    FailedToSendOnchain { failure_reason: String },
+}
+
+/// Liquidity of the lightning node summed over its usable channels
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChannelLiquidity {
+    /// Amount the node can currently send over its channels
+    pub outbound_msat: u64,
+    /// Amount the node can currently receive over its channels
+    pub inbound_msat: u64,
 }
 
 /// A trait that the gateway uses to interact with a lightning node. This allows
@@ -141,6 +164,37 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         channel_size_sats: u64,
         push_amount_sats: u64,
     ) -> Result<EmptyResponse, LightningRpcError>;
+
+    /// Get the liquidity the lightning node holds in its usable channels.
+    async fn channel_liquidity(&self) -> Result<ChannelLiquidity, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetChannelLiquidity {
+            failure_reason: "Channel liquidity not supported".to_string(),
+        })
+    }
+
+    /// Sends `amount_sats` from the lightning node's on-chain wallet to
+    /// `address` and returns the id of the transaction.
+    async fn send_onchain(
+        &self,
+        _address: bitcoin::Address,
+        _amount_sats: u64,
+    ) -> Result<bitcoin::Txid, LightningRpcError> {
+        Err(LightningRpcError::FailedToSendOnchain {
+            failure_reason: "Sending on-chain funds not supported".to_string(),
+        })
+    }
+
+    /// Simulates an incoming payment of `invoice` by sending an HTLC for it to
+    /// the stream returned by `route_htlcs` and waiting until the gateway
+    /// completes it. Only supported by simulated lightning nodes.
+    async fn simulate_incoming_payment(
+        &self,
+        _invoice: Bolt11Invoice,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedPayment {
+            failure_reason: "Simulating payments not supported".to_string(),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Subcommand, Serialize, Deserialize)]
@@ -164,22 +218,46 @@ pub enum LightningMode {
         #[arg(long = "cln-extension-addr", env = FM_GATEWAY_LIGHTNING_ADDR_ENV)]
         cln_extension_addr: SafeUrl,
     },
+    /// Run an LDK lightning node embedded in the gateway
+    #[clap(name = "ldk")]
+    Ldk {
+        /// Bitcoind RPC URL, including credentials, the LDK node syncs from
+        #[arg(long = "ldk-bitcoind-rpc-url", env = FM_LDK_BITCOIND_RPC_URL_ENV)]
+        bitcoind_rpc_url: SafeUrl,
+
+        /// Bitcoin network of the LDK node
+        #[arg(long = "ldk-network", env = FM_LDK_NETWORK_ENV)]
+        network: Network,
+
+        /// Port the LDK node listens on for lightning peers
+        #[arg(long = "ldk-lightning-port", env = FM_PORT_LDK_ENV)]
+        lightning_port: u16,
+    },
+    /// Simulate a lightning node inside the gateway, for testing
+    #[clap(name = "fake")]
+    Fake,
 }
 
 #[async_trait]
 pub trait LightningBuilder {
-    async fn build(&self) -> Box<dyn ILnRpcClient>;
+    /// Connects to or starts the lightning node, failing if the node can't be
+    /// reached or started
+    async fn build(&self) -> anyhow::Result<Box<dyn ILnRpcClient>>;
 }
 
 #[derive(Clone)]
 pub struct GatewayLightningBuilder {
     pub lightning_mode: LightningMode,
+    /// Database the embedded LDK node persists its state in
+    pub gateway_db: Database,
+    /// Directory the embedded LDK node keeps its logs in
+    pub data_dir: PathBuf,
 }
 
 #[async_trait]
 impl LightningBuilder for GatewayLightningBuilder {
-    async fn build(&self) -> Box<dyn ILnRpcClient> {
-        match self.lightning_mode.clone() {
+    async fn build(&self) -> anyhow::Result<Box<dyn ILnRpcClient>> {
+        Ok(match self.lightning_mode.clone() {
             LightningMode::Cln { cln_extension_addr } => {
                 Box::new(NetworkLnRpcClient::new(cln_extension_addr).await)
             }
@@ -190,6 +268,22 @@ impl LightningBuilder for GatewayLightningBuilder {
             } => Box::new(
                 GatewayLndClient::new(lnd_rpc_addr, lnd_tls_cert, lnd_macaroon, None).await,
             ),
-        }
+            LightningMode::Ldk {
+                bitcoind_rpc_url,
+                network,
+                lightning_port,
+            } => Box::new(
+                GatewayLdkClient::new(
+                    self.gateway_db.clone(),
+                    &self.data_dir,
+                    &bitcoind_rpc_url,
+                    network,
+                    lightning_port,
+                )
+                .await
+                .context("Failed to start LDK node")?,
+            ),
+            LightningMode::Fake => Box::new(GatewayFakeClient::new()),
+        })
     }
 }
@@ -0,0 +1,120 @@
+//! Ecash balance targets the gateway maintains per federation by moving funds
+//! between its federations and the on-chain wallet of its lightning node
+
+use std::time::Duration;
+
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+/// Interval in which the liquidity manager checks the ecash balances
+pub const LIQUIDITY_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Minimum time between two automatic actions for the same federation. Ecash
+/// from a peg-in only arrives once the deposit is confirmed, so without a
+/// cooldown the manager would peg in again while the first deposit is pending.
+pub const LIQUIDITY_ACTION_COOLDOWN: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityActionKind {
+    /// Funds were sent from the lightning node's wallet to the federation
+    PegIn,
+    /// Ecash was withdrawn to the lightning node's wallet
+    PegOut,
+}
+
+/// Bounds of the ecash balance of a federation. Once the balance leaves them,
+/// the liquidity manager rebalances it back to `target`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct LiquidityTarget {
+    /// Balance the manager rebalances towards
+    pub target: Amount,
+    /// Below this balance the manager pegs in from the node's wallet
+    pub min: Amount,
+    /// Above this balance the manager pegs out to the node's wallet
+    pub max: Amount,
+}
+
+impl LiquidityTarget {
+    /// The fees of a peg-in leave the balance slightly below `target`, so
+    /// `target` has to be above `min` for the peg-in to bring the balance
+    /// back within bounds.
+    pub fn new(target: Amount, min: Amount, max: Amount) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            min < target && target <= max,
+            "Liquidity target {target} has to be above the minimum {min} and at most the maximum {max}"
+        );
+
+        Ok(LiquidityTarget { target, min, max })
+    }
+
+    /// Returns the action that brings `balance` back to the target, or `None`
+    /// if the balance is within bounds. On-chain amounts are rounded down to
+    /// whole sats.
+    pub fn rebalance(&self, balance: Amount) -> Option<(LiquidityActionKind, bitcoin::Amount)> {
+        let (kind, difference) = if balance < self.min {
+            (LiquidityActionKind::PegIn, self.target - balance)
+        } else if self.max < balance {
+            (LiquidityActionKind::PegOut, balance - self.target)
+        } else {
+            return None;
+        };
+
+        let amount = bitcoin::Amount::from_sat(difference.msats / 1000);
+        (amount != bitcoin::Amount::ZERO).then_some((kind, amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{LiquidityActionKind, LiquidityTarget};
+
+    fn target() -> LiquidityTarget {
+        LiquidityTarget::new(
+            Amount::from_sats(500_000),
+            Amount::from_sats(100_000),
+            Amount::from_sats(1_000_000),
+        )
+        .expect("Valid target")
+    }
+
+    #[test]
+    fn rebalances_towards_target_outside_of_bounds() {
+        assert_eq!(target().rebalance(Amount::from_sats(100_000)), None);
+        assert_eq!(target().rebalance(Amount::from_sats(1_000_000)), None);
+
+        assert_eq!(
+            target().rebalance(Amount::from_sats(40_000)),
+            Some((
+                LiquidityActionKind::PegIn,
+                bitcoin::Amount::from_sat(460_000)
+            ))
+        );
+        assert_eq!(
+            target().rebalance(Amount::from_msats(1_200_000_999)),
+            Some((
+                LiquidityActionKind::PegOut,
+                bitcoin::Amount::from_sat(700_000)
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_target_outside_of_bounds() {
+        assert!(LiquidityTarget::new(
+            Amount::from_sats(50_000),
+            Amount::from_sats(100_000),
+            Amount::from_sats(1_000_000),
+        )
+        .is_err());
+        assert!(LiquidityTarget::new(
+            Amount::from_sats(100_000),
+            Amount::from_sats(100_000),
+            Amount::from_sats(1_000_000),
+        )
+        .is_err());
+    }
+}
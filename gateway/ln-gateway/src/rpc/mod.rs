@@ -6,12 +6,21 @@ use std::str::FromStr;
 
 use bitcoin29::{Address, Network};
 use fedimint_core::config::{ClientConfig, FederationId, JsonClientConfig};
+use fedimint_core::core::OperationId;
 use fedimint_core::{Amount, BitcoinAmountOrAll};
 use fedimint_ln_common::config::parse_routing_fees;
 use fedimint_ln_common::{route_hints, serde_option_routing_fees};
-use lightning_invoice::RoutingFees;
+use lightning_invoice::{Bolt11Invoice, RoutingFees};
 use serde::{Deserialize, Serialize};
 
+pub use crate::db::{
+    LiquidityActionEntry, LiquidityActionStatus, PaymentDirection, PaymentLogEntry, PaymentModule,
+    PaymentStatus,
+};
+use crate::fees::{FederationFees, FeePolicyConfig};
+use crate::lightning::ChannelLiquidity;
+use crate::liquidity::LiquidityTarget;
+
 pub const V1_API_ENDPOINT: &str = "v1";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -157,3 +166,191 @@ pub struct OpenChannelPayload {
     pub channel_size_sats: u64,
     pub push_amount_sats: u64,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SimulatePaymentPayload {
+    pub invoice: Bolt11Invoice,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFederationFeesPayload {
+    pub federation_id: FederationId,
+    /// Fees for paying invoices on behalf of the federation's users. Keeps
+    /// the current fees if not given.
+    pub outgoing_fees: Option<FederationRoutingFees>,
+    /// Fees for receiving payments into the federation. Keeps the current
+    /// fees if not given.
+    pub incoming_fees: Option<FederationRoutingFees>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFeePolicyPayload {
+    pub fee_policy: FeePolicyConfig,
+}
+
+/// Fees of one of the feds we are connected to
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FederationFeesInfo {
+    pub federation_id: FederationId,
+    /// Fees configured by the operator
+    pub configured_fees: FederationFees,
+    /// Fees announced to the federation after applying the fee policy
+    pub announced_fees: FederationFees,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct GatewayFees {
+    pub fee_policy: FeePolicyConfig,
+    /// Channel liquidity the fee policy is applied with, if the lightning node
+    /// reports it
+    pub liquidity: Option<ChannelLiquidity>,
+    pub federations: Vec<FederationFeesInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListPaymentsPayload {
+    /// Only list payments of this federation
+    pub federation_id: Option<FederationId>,
+    /// Only list payments started at or after this unix timestamp in seconds
+    pub start_time: Option<u64>,
+    /// Only list payments started before this unix timestamp in seconds
+    pub end_time: Option<u64>,
+    /// Only list payments that failed
+    pub failed_only: bool,
+    /// Maximum number of payments to list, most recent first
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaymentSummaryPayload {
+    /// Only summarize payments of this federation
+    pub federation_id: Option<FederationId>,
+    /// Only summarize payments started at or after this unix timestamp in
+    /// seconds
+    pub start_time: Option<u64>,
+    /// Only summarize payments started before this unix timestamp in seconds
+    pub end_time: Option<u64>,
+}
+
+/// A payment from the gateway's ledger
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PaymentLogInfo {
+    pub federation_id: FederationId,
+    pub operation_id: OperationId,
+    #[serde(flatten)]
+    pub entry: PaymentLogEntry,
+}
+
+/// Statistics of the payments of one direction
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PaymentDirectionSummary {
+    pub succeeded: u64,
+    pub failed: u64,
+    pub pending: u64,
+    /// Total lightning amount of the succeeded payments
+    pub volume: Amount,
+    /// Total fees earned with the succeeded payments
+    pub fees_earned: Amount,
+}
+
+impl Default for PaymentDirectionSummary {
+    fn default() -> Self {
+        PaymentDirectionSummary {
+            succeeded: 0,
+            failed: 0,
+            pending: 0,
+            volume: Amount::ZERO,
+            fees_earned: Amount::ZERO,
+        }
+    }
+}
+
+impl PaymentDirectionSummary {
+    fn add(&mut self, entry: &PaymentLogEntry) {
+        match entry.status {
+            PaymentStatus::Pending => self.pending += 1,
+            PaymentStatus::Failed { .. } => self.failed += 1,
+            PaymentStatus::Succeeded => {
+                self.succeeded += 1;
+                self.volume += entry.amount;
+                self.fees_earned += entry.fee;
+            }
+        }
+    }
+}
+
+/// Statistics of the payments of one of the feds we are connected to
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FederationPaymentSummary {
+    pub federation_id: FederationId,
+    pub outgoing: PaymentDirectionSummary,
+    pub incoming: PaymentDirectionSummary,
+}
+
+impl FederationPaymentSummary {
+    pub fn new(federation_id: FederationId) -> Self {
+        FederationPaymentSummary {
+            federation_id,
+            outgoing: PaymentDirectionSummary::default(),
+            incoming: PaymentDirectionSummary::default(),
+        }
+    }
+
+    pub fn add(&mut self, entry: &PaymentLogEntry) {
+        match entry.direction {
+            PaymentDirection::Outgoing => self.outgoing.add(entry),
+            PaymentDirection::Incoming => self.incoming.add(entry),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PaymentSummary {
+    pub federations: Vec<FederationPaymentSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetLiquidityTargetPayload {
+    pub federation_id: FederationId,
+    /// Stops managing the federation's liquidity if not given
+    pub target: Option<LiquidityTarget>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RebalancePayload {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LiquidityLogPayload {
+    /// Only list actions for this federation
+    pub federation_id: Option<FederationId>,
+    /// Maximum number of actions to list, most recent first
+    pub limit: Option<usize>,
+}
+
+/// An action from the liquidity manager's log
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LiquidityActionInfo {
+    pub federation_id: FederationId,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    #[serde(flatten)]
+    pub entry: LiquidityActionEntry,
+}
+
+/// Ecash liquidity of one of the feds we are connected to
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FederationLiquidityInfo {
+    pub federation_id: FederationId,
+    pub balance: Amount,
+    /// Target the liquidity manager maintains, if the federation is managed
+    pub target: Option<LiquidityTarget>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct GatewayLiquidity {
+    /// Liquidity of the lightning node's channels, if the node reports it
+    pub channel_liquidity: Option<ChannelLiquidity>,
+    pub federations: Vec<FederationLiquidityInfo>,
+}
@@ -8,8 +8,12 @@ use thiserror::Error;
 
 use super::{
     BackupPayload, BalancePayload, ConfigPayload, ConnectFedPayload, ConnectToPeerPayload,
-    DepositAddressPayload, FederationInfo, GatewayFedConfig, GatewayInfo, GetFundingAddressPayload,
-    LeaveFedPayload, OpenChannelPayload, RestorePayload, SetConfigurationPayload, WithdrawPayload,
+    DepositAddressPayload, FederationInfo, GatewayFedConfig, GatewayFees, GatewayInfo,
+    GatewayLiquidity, GetFundingAddressPayload, LeaveFedPayload, LiquidityActionInfo,
+    LiquidityLogPayload, ListPaymentsPayload, OpenChannelPayload, PaymentLogInfo, PaymentSummary,
+    PaymentSummaryPayload, RebalancePayload, RestorePayload, SetConfigurationPayload,
+    SetFederationFeesPayload, SetFeePolicyPayload, SetLiquidityTargetPayload,
+    SimulatePaymentPayload, WithdrawPayload,
 };
 
 pub struct GatewayRpcClient {
@@ -136,6 +140,95 @@ impl GatewayRpcClient {
         self.call_post(url, payload).await
     }
 
+    pub async fn simulate_payment(
+        &self,
+        payload: SimulatePaymentPayload,
+    ) -> GatewayRpcResult<String> {
+        let url = self
+            .base_url
+            .join("/simulate_payment")
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_fees(&self) -> GatewayRpcResult<GatewayFees> {
+        let url = self.base_url.join("/fees").expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_federation_fees(
+        &self,
+        payload: SetFederationFeesPayload,
+    ) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join("/set_federation_fees")
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn set_fee_policy(&self, payload: SetFeePolicyPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join("/set_fee_policy")
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_payments(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> GatewayRpcResult<Vec<PaymentLogInfo>> {
+        let url = self.base_url.join("/payments").expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn payment_summary(
+        &self,
+        payload: PaymentSummaryPayload,
+    ) -> GatewayRpcResult<PaymentSummary> {
+        let url = self
+            .base_url
+            .join("/payment_summary")
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_liquidity(&self) -> GatewayRpcResult<GatewayLiquidity> {
+        let url = self.base_url.join("/liquidity").expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_liquidity_target(
+        &self,
+        payload: SetLiquidityTargetPayload,
+    ) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join("/set_liquidity_target")
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn rebalance(
+        &self,
+        payload: RebalancePayload,
+    ) -> GatewayRpcResult<Option<LiquidityActionInfo>> {
+        let url = self.base_url.join("/rebalance").expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn liquidity_log(
+        &self,
+        payload: LiquidityLogPayload,
+    ) -> GatewayRpcResult<Vec<LiquidityActionInfo>> {
+        let url = self
+            .base_url
+            .join("/liquidity_log")
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
     async fn call<P: Serialize, T: DeserializeOwned>(
         &self,
         method: Method,
@@ -19,8 +19,10 @@ use tracing::{error, info, instrument};
 
 use super::{
     BackupPayload, BalancePayload, ConnectFedPayload, ConnectToPeerPayload, DepositAddressPayload,
-    GetFundingAddressPayload, InfoPayload, LeaveFedPayload, OpenChannelPayload, RestorePayload,
-    SetConfigurationPayload, WithdrawPayload, V1_API_ENDPOINT,
+    GetFundingAddressPayload, InfoPayload, LeaveFedPayload, LiquidityLogPayload,
+    ListPaymentsPayload, OpenChannelPayload, PaymentSummaryPayload, RebalancePayload,
+    RestorePayload, SetConfigurationPayload, SetFederationFeesPayload, SetFeePolicyPayload,
+    SetLiquidityTargetPayload, SimulatePaymentPayload, WithdrawPayload, V1_API_ENDPOINT,
 };
 use crate::rpc::ConfigPayload;
 use crate::{Gateway, GatewayError};
@@ -158,6 +160,16 @@ fn v1_routes(gateway: Gateway) -> Router {
         .route("/connect_to_peer", post(connect_to_peer))
         .route("/get_funding_address", post(get_funding_address))
         .route("/open_channel", post(open_channel))
+        .route("/simulate_payment", post(simulate_payment))
+        .route("/fees", get(fees))
+        .route("/set_federation_fees", post(set_federation_fees))
+        .route("/set_fee_policy", post(set_fee_policy))
+        .route("/payments", post(list_payments))
+        .route("/payment_summary", post(payment_summary))
+        .route("/liquidity", get(liquidity))
+        .route("/set_liquidity_target", post(set_liquidity_target))
+        .route("/rebalance", post(rebalance))
+        .route("/liquidity_log", post(liquidity_log))
         .layer(middleware::from_fn(auth_middleware));
 
     // Routes that are un-authenticated before gateway configuration, then become
@@ -342,6 +354,103 @@ async fn open_channel(
     Ok(Json(json!(())))
 }
 
+#[instrument(skip_all, err, fields(?payload))]
+async fn simulate_payment(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SimulatePaymentPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let preimage = gateway.handle_simulate_payment_msg(payload).await?;
+    Ok(Json(json!(preimage.encode_hex::<String>())))
+}
+
+/// Display the configured and announced fees of all federations
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn fees(Extension(gateway): Extension<Gateway>) -> Result<impl IntoResponse, GatewayError> {
+    let fees = gateway.handle_get_fees_msg().await?;
+    Ok(Json(json!(fees)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_federation_fees(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFederationFeesPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_federation_fees_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_fee_policy(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFeePolicyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_fee_policy_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+/// List the payments recorded in the gateway's ledger
+#[debug_handler]
+#[instrument(skip_all, err, fields(?payload))]
+async fn list_payments(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ListPaymentsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let payments = gateway.handle_list_payments_msg(payload).await?;
+    Ok(Json(json!(payments)))
+}
+
+/// Summarize the volume and fees of the payments recorded in the gateway's
+/// ledger
+#[debug_handler]
+#[instrument(skip_all, err, fields(?payload))]
+async fn payment_summary(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<PaymentSummaryPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let summary = gateway.handle_payment_summary_msg(payload).await?;
+    Ok(Json(json!(summary)))
+}
+
+/// Display the ecash balances and liquidity targets of all federations
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn liquidity(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let liquidity = gateway.handle_get_liquidity_msg().await?;
+    Ok(Json(json!(liquidity)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_liquidity_target(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetLiquidityTargetPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_liquidity_target_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn rebalance(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<RebalancePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let action = gateway.handle_rebalance_msg(payload).await?;
+    Ok(Json(json!(action)))
+}
+
+/// List the peg-ins and peg-outs of the liquidity manager
+#[debug_handler]
+#[instrument(skip_all, err, fields(?payload))]
+async fn liquidity_log(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<LiquidityLogPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let actions = gateway.handle_liquidity_log_msg(payload).await?;
+    Ok(Json(json!(actions)))
+}
+
 #[instrument(skip_all, err)]
 async fn get_gateway_id(
     Extension(gateway): Extension<Gateway>,
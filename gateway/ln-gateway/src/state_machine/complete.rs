@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use fedimint_client::sm::{State, StateTransition};
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
@@ -13,6 +13,7 @@ use thiserror::Error;
 use tracing::{debug, info, warn};
 
 use super::{GatewayClientContext, GatewayClientStateMachines};
+use crate::db::record_payment_outcome;
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Settle};
 use crate::gateway_lnrpc::InterceptHtlcResponse;
 
@@ -165,9 +166,17 @@ impl CompleteHtlcState {
         context: GatewayClientContext,
         common: GatewayCompleteCommon,
     ) -> Vec<StateTransition<GatewayCompleteStateMachine>> {
+        let outcome = self.outcome.clone();
         vec![StateTransition::new(
             Self::await_complete_htlc(context, common.clone(), self.outcome.clone()),
-            move |_dbtx, result, _| Box::pin(Self::transition_success(result, common.clone())),
+            move |dbtx, result, _| {
+                Box::pin(Self::transition_success(
+                    dbtx,
+                    result,
+                    common.clone(),
+                    outcome.clone(),
+                ))
+            },
         )]
     }
 
@@ -213,9 +222,18 @@ impl CompleteHtlcState {
     }
 
     async fn transition_success(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
         result: Result<(), CompleteHtlcError>,
         common: GatewayCompleteCommon,
+        outcome: HtlcOutcome,
     ) -> GatewayCompleteStateMachine {
+        let payment_outcome = match (&result, outcome) {
+            (Ok(()), HtlcOutcome::Success(_)) => Ok(()),
+            (Ok(()), HtlcOutcome::Failure(reason)) => Err(reason),
+            (Err(e), _) => Err(e.to_string()),
+        };
+        record_payment_outcome(&mut dbtx.module_tx(), common.operation_id, payment_outcome).await;
+
         match result {
             Ok(_) => GatewayCompleteStateMachine {
                 common,
@@ -13,7 +13,7 @@ use fedimint_client::derivable_secret::ChildId;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationLogEntry, OperationOutcomeState, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, State};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
@@ -50,6 +50,7 @@ use self::pay::{
     GatewayPayCommon, GatewayPayInvoice, GatewayPayStateMachine, GatewayPayStates,
     OutgoingPaymentError,
 };
+use crate::db::{record_payment_started, PaymentDirection, PaymentModule};
 use crate::gateway_lnrpc::InterceptHtlcRequest;
 use crate::state_machine::complete::{
     GatewayCompleteCommon, GatewayCompleteStates, WaitForPreimageState,
@@ -150,6 +151,32 @@ impl ClientModuleInit for GatewayClientInit {
             gateway: self.gateway.clone(),
         })
     }
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        match entry.try_meta::<GatewayMeta>() {
+            Ok(GatewayMeta::Pay) => OperationOutcomeState::classify(entry, |state| match state {
+                GatewayExtPayStates::Success { .. } => OperationOutcomeState::Success,
+                GatewayExtPayStates::Canceled { .. }
+                | GatewayExtPayStates::Fail { .. }
+                | GatewayExtPayStates::OfferDoesNotExist { .. } => OperationOutcomeState::Failure,
+                GatewayExtPayStates::Created | GatewayExtPayStates::Preimage { .. } => {
+                    OperationOutcomeState::Pending
+                }
+            }),
+            Ok(GatewayMeta::Receive) => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    GatewayExtReceiveStates::Preimage(_) => OperationOutcomeState::Success,
+                    GatewayExtReceiveStates::RefundSuccess { .. }
+                    | GatewayExtReceiveStates::RefundError { .. }
+                    | GatewayExtReceiveStates::FundingFailed { .. } => {
+                        OperationOutcomeState::Failure
+                    }
+                    GatewayExtReceiveStates::Funding => OperationOutcomeState::Pending,
+                })
+            }
+            Err(_) => OperationOutcomeState::unclassified(entry),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -425,7 +452,35 @@ impl GatewayClientModule {
         let tx = TransactionBuilder::new().with_output(self.client_ctx.make_client_output(output));
         let operation_meta_gen = |_: TransactionId, _: Vec<OutPoint>| GatewayMeta::Receive;
         self.client_ctx
-            .finalize_and_submit_transaction(operation_id, KIND.as_str(), operation_meta_gen, tx)
+            .module_autocommit_2(
+                |dbtx, _| {
+                    let tx = tx.clone();
+                    let htlc = htlc.clone();
+                    Box::pin(async move {
+                        dbtx.finalize_and_submit_transaction(
+                            operation_id,
+                            KIND.as_str(),
+                            operation_meta_gen,
+                            tx,
+                        )
+                        .await?;
+
+                        record_payment_started(
+                            &mut dbtx.module_dbtx(),
+                            operation_id,
+                            PaymentDirection::Incoming,
+                            PaymentModule::LnV1,
+                            htlc.payment_hash,
+                            htlc.incoming_amount_msat,
+                            htlc.incoming_amount_msat.saturating_sub(amount),
+                        )
+                        .await;
+
+                        Ok(())
+                    })
+                },
+                Some(100),
+            )
             .await?;
         debug!(?operation_id, "Submitted transaction for HTLC {htlc:?}");
         Ok(operation_id)
@@ -471,7 +526,7 @@ impl GatewayClientModule {
         let mut stream = self.notifier.subscribe(operation_id).await;
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, || {
             stream! {
 
                 yield GatewayExtReceiveStates::Funding;
@@ -558,6 +613,18 @@ impl GatewayClientModule {
                                             GatewayMeta::Pay,
                                         )
                                         .await;
+                                    // The fee is recorded once the state machine fetched the
+                                    // contract funded by the user
+                                    record_payment_started(
+                                        &mut dbtx.module_dbtx(),
+                                        operation_id,
+                                        PaymentDirection::Outgoing,
+                                        PaymentModule::LnV1,
+                                        payload.payment_data.payment_hash(),
+                                        payload.payment_data.amount().unwrap_or(Amount::ZERO),
+                                        Amount::ZERO,
+                                    )
+                                    .await;
                                 }
                                 Err(AddStateMachinesError::StateAlreadyExists) => {
                                     info!("State machine for operation {operation_id} already exists, will not add a new one")
@@ -588,7 +655,7 @@ impl GatewayClientModule {
         let operation = self.client_ctx.get_operation(operation_id).await?;
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, || {
             stream! {
                 yield GatewayExtPayStates::Created;
 
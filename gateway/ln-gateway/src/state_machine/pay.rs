@@ -28,7 +28,7 @@ use tokio_stream::StreamExt;
 use tracing::{debug, error, info, warn, Instrument};
 
 use super::{GatewayClientContext, GatewayClientStateMachines, GatewayExtReceiveStates};
-use crate::db::{FederationIdKey, PreimageAuthentication};
+use crate::db::{record_payment_contract_amount, record_payment_outcome, PreimageAuthentication};
 use crate::gateway_lnrpc::{PayInvoiceRequest, PayInvoiceResponse};
 use crate::lightning::LightningRpcError;
 use crate::state_machine::GatewayClientModule;
@@ -198,10 +198,29 @@ impl GatewayPayInvoice {
                 context.clone(),
                 common.clone(),
             ),
-            move |_dbtx, result, _old_state| Box::pin(futures::future::ready(result)),
+            move |dbtx, result, _old_state| {
+                Box::pin(Self::transition_fetch_parameters(dbtx, result))
+            },
         )]
     }
 
+    /// Records payments whose outgoing contract does not exist as failed, as
+    /// they have no contract that could be cancelled
+    async fn transition_fetch_parameters(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        result: GatewayPayStateMachine,
+    ) -> GatewayPayStateMachine {
+        if let GatewayPayStates::OfferDoesNotExist(contract_id) = &result.state {
+            record_payment_outcome(
+                &mut dbtx.module_tx(),
+                result.common.operation_id,
+                Err(format!("Outgoing contract {contract_id} does not exist")),
+            )
+            .await;
+        }
+        result
+    }
+
     async fn fetch_parameters_and_pay(
         global_context: DynGlobalClientContext,
         pay_invoice_payload: PayInvoicePayload,
@@ -344,16 +363,16 @@ impl GatewayPayInvoice {
                 });
             }
 
-            let mut gateway_dbtx = context.gateway.gateway_db.begin_transaction_nc().await;
-            let config = gateway_dbtx
-                .get_value(&FederationIdKey { id: federation_id })
+            let routing_fees = context
+                .gateway
+                .minimum_fees(federation_id)
                 .await
                 .ok_or(OutgoingPaymentError {
                     error_type: OutgoingPaymentErrorType::InvalidFederationConfiguration,
                     contract_id,
                     contract: Some(outgoing_contract_account.clone()),
-                })?;
-            let routing_fees = config.fees;
+                })?
+                .outgoing;
 
             let payment_parameters = Self::validate_outgoing_account(
                 &outgoing_contract_account,
@@ -713,6 +732,9 @@ impl GatewayPayClaimOutgoingContract {
 
         let out_points = global_context.claim_input(dbtx, client_input).await.1;
         debug!("Claimed outgoing contract {contract:?} with out points {out_points:?}");
+        record_payment_contract_amount(&mut dbtx.module_tx(), common.operation_id, contract.amount)
+            .await;
+        record_payment_outcome(&mut dbtx.module_tx(), common.operation_id, Ok(())).await;
         GatewayPayStateMachine {
             common,
             state: GatewayPayStates::Preimage(out_points, preimage),
@@ -887,6 +909,12 @@ impl GatewayPayCancelContract {
         error: OutgoingPaymentError,
     ) -> GatewayPayStateMachine {
         info!("Canceling outgoing contract {contract:?}");
+        record_payment_outcome(
+            &mut dbtx.module_tx(),
+            common.operation_id,
+            Err(error.to_string()),
+        )
+        .await;
         let cancel_signature = context.secp.sign_schnorr(
             &bitcoin29_to_bitcoin30_sha256_hash(contract.contract.cancellation_message()).into(),
             &bitcoin29_to_bitcoin30_keypair(context.redeem_key),
@@ -2,6 +2,7 @@
 //!
 //! This crate contains integration tests for the gateway API
 //! and business logic.
+use std::env;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -14,7 +15,10 @@ use fedimint_client::ClientHandleArc;
 use fedimint_core::bitcoin_migration::bitcoin30_to_bitcoin29_network;
 use fedimint_core::config::FederationId;
 use fedimint_core::core::{IntoDynInstance, OperationId};
-use fedimint_core::task::sleep_in_test;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::{block_in_place, sleep_in_test, TaskGroup};
 use fedimint_core::util::{retry, FibonacciBackoff, NextOrPending};
 use fedimint_core::{msats, sats, Amount, OutPoint, TransactionId};
 use fedimint_dummy_client::{DummyClientInit, DummyClientModule};
@@ -33,23 +37,37 @@ use fedimint_ln_common::contracts::outgoing::OutgoingContractAccount;
 use fedimint_ln_common::contracts::{EncryptedPreimage, FundedContract, Preimage, PreimageKey};
 use fedimint_ln_common::{LightningGateway, LightningInput, LightningOutput, PrunedInvoice};
 use fedimint_ln_server::LightningInit;
+use fedimint_lnv2_client::{ReceiveState, SendState};
+use fedimint_lnv2_common::config::FeeConsensus;
 use fedimint_logging::LOG_TEST;
 use fedimint_testing::btc::BitcoinTest;
 use fedimint_testing::db::BYTE_33;
+use fedimint_testing::envs::FM_TEST_BITCOIND_RPC_ENV;
 use fedimint_testing::federation::FederationTest;
-use fedimint_testing::fixtures::Fixtures;
+use fedimint_testing::fixtures::{test_dir, Fixtures};
 use fedimint_testing::gateway::{GatewayTest, LightningNodeType, DEFAULT_GATEWAY_PASSWORD};
 use fedimint_testing::ln::LightningTest;
 use fedimint_unknown_common::config::UnknownGenParams;
 use fedimint_unknown_server::UnknownInit;
-use futures::Future;
+use fedimint_wallet_client::WalletClientInit;
+use fedimint_wallet_common::config::WalletGenParams;
+use fedimint_wallet_server::WalletInit;
+use futures::{Future, StreamExt};
 use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription, Description, RoutingFees};
-use ln_gateway::gateway_lnrpc::GetNodeInfoResponse;
+use ln_gateway::fees::{FederationFees, FeePolicyConfig};
+use ln_gateway::gateway_lnrpc::intercept_htlc_response::{Action, Settle};
+use ln_gateway::gateway_lnrpc::{CreateInvoiceRequest, GetNodeInfoResponse, InterceptHtlcResponse};
+use ln_gateway::gateway_module_v2::GatewayClientModuleV2;
+use ln_gateway::lightning::ldk::GatewayLdkClient;
+use ln_gateway::lightning::ILnRpcClient;
+use ln_gateway::liquidity::{LiquidityActionKind, LiquidityTarget};
 use ln_gateway::rpc::rpc_client::{GatewayRpcClient, GatewayRpcError, GatewayRpcResult};
 use ln_gateway::rpc::rpc_server::hash_password;
 use ln_gateway::rpc::{
-    BalancePayload, ConnectFedPayload, FederationRoutingFees, LeaveFedPayload,
-    SetConfigurationPayload,
+    BalancePayload, ConnectFedPayload, FederationRoutingFees, LeaveFedPayload, LiquidityActionInfo,
+    LiquidityActionStatus, LiquidityLogPayload, ListPaymentsPayload, PaymentDirection,
+    PaymentLogInfo, PaymentModule, PaymentStatus, PaymentSummaryPayload, RebalancePayload,
+    SetConfigurationPayload, SetFederationFeesPayload, SetLiquidityTargetPayload,
 };
 use ln_gateway::state_machine::pay::{
     OutgoingContractError, OutgoingPaymentError, OutgoingPaymentErrorType,
@@ -553,6 +571,62 @@ async fn test_gateway_cannot_claim_invalid_preimage() -> anyhow::Result<()> {
     .await
 }
 
+/// Test helper function for paying a BOLT11 invoice that cannot be paid over
+/// lightning with a gateway specified by `gateway_id`, which refunds the user.
+async fn pay_unpayable_invoice(
+    invoice: Bolt11Invoice,
+    user_client: &ClientHandleArc,
+    gateway_client: &ClientHandleArc,
+    gateway_id: &PublicKey,
+) -> anyhow::Result<()> {
+    let lightning_module = user_client.get_first_module::<LightningClientModule>();
+
+    // User client pays test invoice
+    let OutgoingLightningPayment {
+        payment_type,
+        contract_id,
+        fee: _,
+    } = pay_invoice(&lightning_module, invoice.clone(), gateway_id).await?;
+    match payment_type {
+        PayType::Lightning(pay_op) => {
+            let mut pay_sub = lightning_module
+                .subscribe_ln_pay(pay_op)
+                .await?
+                .into_stream();
+            assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+            let funded = pay_sub.ok().await?;
+            assert_matches!(funded, LnPayState::Funded);
+
+            let payload = PayInvoicePayload {
+                federation_id: user_client.federation_id(),
+                contract_id,
+                // The fake lightning node only fails to pay invoices whose description was
+                // not pruned
+                payment_data: PaymentData::Invoice(invoice),
+                preimage_auth: Hash::hash(&[0; 32]),
+            };
+
+            let gw_pay_op = gateway_client
+                .get_first_module::<GatewayClientModule>()
+                .gateway_pay_bolt11_invoice(payload)
+                .await?;
+            let mut gw_pay_sub = gateway_client
+                .get_first_module::<GatewayClientModule>()
+                .gateway_subscribe_ln_pay(gw_pay_op)
+                .await?
+                .into_stream();
+            assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
+            assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Canceled { .. });
+
+            // Assert that the user receives a refund
+            assert_matches!(pay_sub.ok().await?, LnPayState::WaitingForRefund { .. });
+            assert_matches!(pay_sub.ok().await?, LnPayState::Refunded { .. });
+        }
+        _ => panic!("Expected Lightning payment!"),
+    }
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_client_pay_unpayable_invoice() -> anyhow::Result<()> {
     single_federation_test(
@@ -561,7 +635,6 @@ async fn test_gateway_client_pay_unpayable_invoice() -> anyhow::Result<()> {
             let gateway_client = gateway.remove_client_hack(&fed).await;
             // Print money for user client
             let dummy_module = user_client.get_first_module::<DummyClientModule>();
-            let lightning_module = user_client.get_first_module::<LightningClientModule>();
             let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
             dummy_module.receive_money(outpoint).await?;
             assert_eq!(user_client.get_balance().await, sats(1000));
@@ -569,49 +642,7 @@ async fn test_gateway_client_pay_unpayable_invoice() -> anyhow::Result<()> {
             // Create invoice that cannot be paid
             let invoice = other_lightning_client.unpayable_invoice(sats(250), None);
 
-            let gateway = lightning_module.select_gateway(&gateway_id).await;
-
-            // User client pays test invoice
-            let OutgoingLightningPayment {
-                payment_type,
-                contract_id,
-                fee: _,
-            } = pay_invoice(&lightning_module, invoice.clone(), &gateway_id).await?;
-            match payment_type {
-                PayType::Lightning(pay_op) => {
-                    let mut pay_sub = lightning_module
-                        .subscribe_ln_pay(pay_op)
-                        .await?
-                        .into_stream();
-                    assert_eq!(pay_sub.ok().await?, LnPayState::Created);
-                    let funded = pay_sub.ok().await?;
-                    assert_matches!(funded, LnPayState::Funded);
-
-                    let payload = PayInvoicePayload {
-                        federation_id: user_client.federation_id(),
-                        contract_id,
-                        payment_data: get_payment_data(gateway, invoice),
-                        preimage_auth: Hash::hash(&[0; 32]),
-                    };
-
-                    let gw_pay_op = gateway_client
-                        .get_first_module::<GatewayClientModule>()
-                        .gateway_pay_bolt11_invoice(payload)
-                        .await?;
-                    let mut gw_pay_sub = gateway_client
-                        .get_first_module::<GatewayClientModule>()
-                        .gateway_subscribe_ln_pay(gw_pay_op)
-                        .await?
-                        .into_stream();
-                    assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
-                    assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Canceled { .. });
-
-                    // Assert that the user receives a refund
-                    assert_matches!(pay_sub.ok().await?, LnPayState::WaitingForRefund { .. });
-                    assert_matches!(pay_sub.ok().await?, LnPayState::Refunded { .. });
-                }
-                _ => panic!("Expected Lightning payment!"),
-            }
+            pay_unpayable_invoice(invoice, &user_client, &gateway_client, &gateway_id).await?;
 
             Ok(())
         },
@@ -1009,6 +1040,109 @@ async fn test_gateway_cannot_pay_expired_invoice() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_ldk_node_opens_channel_and_intercepts_payments() -> anyhow::Result<()> {
+    if !Fixtures::is_real_test() {
+        return Ok(());
+    }
+
+    let fixtures = fixtures();
+    let bitcoin = fixtures.bitcoin();
+    let lnd = fixtures.lnd().await;
+    let GetNodeInfoResponse { pub_key, .. } = lnd.info().await?;
+    let lnd_public_key = PublicKey::from_slice(&pub_key)?;
+
+    let bitcoind_rpc_url = env::var(FM_TEST_BITCOIND_RPC_ENV)?.parse()?;
+    let (data_dir, _tmp_dir) = test_dir(&format!("gateway-ldk-{}", rand::random::<u64>()));
+    let lightning_port = block_in_place(|| fedimint_portalloc::port_alloc(1))?;
+    let ldk = GatewayLdkClient::new(
+        Database::new(MemDatabase::new(), ModuleDecoderRegistry::default()),
+        &data_dir,
+        &bitcoind_rpc_url,
+        Network::Regtest,
+        lightning_port,
+    )
+    .await?;
+
+    // Fund the LDK node and open a channel to LND, pushing half of the channel
+    // so LND can pay the LDK node
+    let funding_address = ldk.get_funding_address().await?.address;
+    bitcoin
+        .send_and_mine_block(
+            &bitcoin29::Address::from_str(&funding_address)?,
+            bitcoin29::Amount::from_sat(2_000_000),
+        )
+        .await;
+    ldk.connect_to_peer(lnd_public_key, lnd.listening_address())
+        .await?;
+    retry(
+        "open channel once the LDK wallet synced the funds",
+        FibonacciBackoff::default()
+            .with_min_delay(Duration::from_millis(500))
+            .with_max_delay(Duration::from_secs(5))
+            .with_max_times(20),
+        || async { Ok(ldk.open_channel(lnd_public_key, 1_000_000, 500_000).await?) },
+    )
+    .await?;
+    bitcoin.mine_blocks(10).await;
+    retry(
+        "wait for the channel to become usable",
+        FibonacciBackoff::default()
+            .with_min_delay(Duration::from_millis(500))
+            .with_max_delay(Duration::from_secs(5))
+            .with_max_times(20),
+        || async {
+            anyhow::ensure!(!ldk.routehints(1).await?.route_hints.is_empty());
+            Ok(())
+        },
+    )
+    .await?;
+
+    let mut task_group = TaskGroup::new();
+    let (mut htlc_stream, ldk) = Box::new(ldk).route_htlcs(&mut task_group).await?;
+
+    // LDK holds the payment to an invoice with an unknown preimage until the
+    // gateway settles it
+    let preimage = [42; 32];
+    let invoice = ldk
+        .create_invoice(CreateInvoiceRequest {
+            payment_hash: sha256(&preimage).into_inner().to_vec(),
+            amount_msat: 100_000,
+            expiry: 600,
+            description: "ldk gateway test".to_string(),
+        })
+        .await?
+        .invoice;
+    let invoice = Bolt11Invoice::from_str(&invoice)?;
+
+    let (payment, ()) = tokio::join!(
+        lnd.pay_private(invoice.try_into()?, 1000, Amount::from_sats(100)),
+        async {
+            let htlc = htlc_stream
+                .next()
+                .await
+                .expect("HTLC stream ended")
+                .expect("Failed to intercept HTLC");
+            assert_eq!(htlc.payment_hash, sha256(&preimage).into_inner().to_vec());
+            assert_eq!(htlc.incoming_amount_msat, 100_000);
+            ldk.complete_htlc(InterceptHtlcResponse {
+                action: Some(Action::Settle(Settle {
+                    preimage: preimage.to_vec(),
+                })),
+                incoming_chan_id: htlc.incoming_chan_id,
+                htlc_id: htlc.htlc_id,
+            })
+            .await
+            .expect("Failed to settle HTLC");
+        }
+    );
+    assert_eq!(payment?.preimage, preimage.to_vec());
+
+    task_group.shutdown_join_all(None).await?;
+
+    Ok(())
+}
+
 #[ignore] // TODO: This test should be refactored to a devimint test
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_filters_route_hints_by_inbound() -> anyhow::Result<()> {
@@ -1357,9 +1491,35 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
             .iter()
             .find(|f| f.federation_id == fed.id())
             .and_then(|f| f.routing_fees.clone()),
-        Some(federation_routing_fees)
+        Some(federation_routing_fees.clone())
     );
 
+    // Verify we can configure the incoming fees of a federation separately and
+    // the static default fee policy announces the configured fees
+    let incoming_fees = FederationRoutingFees::from_str("0,2000")?;
+    verify_gateway_rpc_success("set_federation_fees", || {
+        new_password_rpc_client.set_federation_fees(SetFederationFeesPayload {
+            federation_id: fed.id(),
+            outgoing_fees: None,
+            incoming_fees: Some(incoming_fees.clone()),
+        })
+    })
+    .await;
+    let gateway_fees =
+        verify_gateway_rpc_success("get_fees", || new_password_rpc_client.get_fees()).await;
+    assert_eq!(gateway_fees.fee_policy, FeePolicyConfig::Static);
+    let federation_fees = gateway_fees
+        .federations
+        .iter()
+        .find(|f| f.federation_id == fed.id())
+        .expect("Connected federation has fees");
+    let expected_fees = FederationFees {
+        outgoing: federation_routing_fees.into(),
+        incoming: incoming_fees.into(),
+    };
+    assert_eq!(federation_fees.configured_fees, expected_fees);
+    assert_eq!(federation_fees.announced_fees, expected_fees);
+
     Ok(())
 }
 
@@ -1664,6 +1824,397 @@ async fn test_gateway_executes_swaps_between_connected_federations() -> anyhow::
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_records_lnv1_payments_in_ledger() -> anyhow::Result<()> {
+    single_federation_test(
+        |gateway, other_lightning_client, fed, user_client, _| async move {
+            let gateway_id = gateway.gateway.gateway_id;
+            let rpc = gateway
+                .get_rpc()
+                .await
+                .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+
+            let fed_routing_fees = FederationRoutingFees::from_str("10,10000")?;
+            let set_configuration_payload = SetConfigurationPayload {
+                password: None,
+                num_route_hints: None,
+                routing_fees: None,
+                network: None,
+                per_federation_routing_fees: Some(vec![(fed.id(), fed_routing_fees.clone())]),
+            };
+            verify_gateway_rpc_success("set_configuration", || {
+                rpc.set_configuration(set_configuration_payload.clone())
+            })
+            .await;
+
+            // Print money for user client
+            let dummy_module = user_client.get_first_module::<DummyClientModule>();
+            let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+            dummy_module.receive_money(outpoint).await?;
+            let ln_module = user_client.get_first_module::<LightningClientModule>();
+            ln_module.update_gateway_cache().await?;
+
+            let gateway_client = gateway.select_client(fed.id()).await;
+
+            // The failed payment goes first, since the user client does not pay the same
+            // payment hash again once a payment for it succeeded
+            let unpayable_invoice = other_lightning_client.unpayable_invoice(sats(100), None);
+            pay_unpayable_invoice(
+                unpayable_invoice.clone(),
+                &user_client,
+                &gateway_client,
+                &gateway_id,
+            )
+            .await?;
+
+            let paid_invoice = other_lightning_client.invoice(sats(250), None).await?;
+            pay_valid_invoice(
+                paid_invoice.clone(),
+                &user_client,
+                &gateway_client,
+                &gateway_id,
+            )
+            .await?;
+
+            // The gateway receives a payment for the user and keeps the difference of the
+            // incoming and outgoing amount of the HTLC
+            let ln_gateway = ln_module.select_gateway(&gateway_id).await;
+            let desc = Description::new("description".to_string())?;
+            let (_invoice_op, received_invoice, _) = ln_module
+                .create_bolt11_invoice(
+                    sats(100),
+                    Bolt11InvoiceDescription::Direct(&desc),
+                    None,
+                    "test ledger",
+                    ln_gateway,
+                )
+                .await?;
+            let htlc = Htlc {
+                payment_hash: *received_invoice.payment_hash(),
+                incoming_amount_msat: sats(105),
+                outgoing_amount_msat: sats(100),
+                incoming_expiry: u32::MAX,
+                short_channel_id: 1,
+                incoming_chan_id: 2,
+                htlc_id: 1,
+            };
+            let intercept_op = gateway_client
+                .get_first_module::<GatewayClientModule>()
+                .gateway_handle_intercepted_htlc(htlc)
+                .await?;
+            let mut intercept_sub = gateway_client
+                .get_first_module::<GatewayClientModule>()
+                .gateway_subscribe_ln_receive(intercept_op)
+                .await?
+                .into_stream();
+            assert_eq!(intercept_sub.ok().await?, GatewayExtReceiveStates::Funding);
+            assert_matches!(
+                intercept_sub.ok().await?,
+                GatewayExtReceiveStates::Preimage { .. }
+            );
+
+            let payments = settled_payments(&rpc, fed.id(), 3).await?;
+            let routing_fee = msats(routing_fees_in_msats(&fed_routing_fees, &sats(250)));
+
+            let paid = find_payment(&payments, &paid_invoice, sats(250));
+            assert_eq!(paid.federation_id, fed.id());
+            assert_eq!(paid.entry.direction, PaymentDirection::Outgoing);
+            assert_eq!(paid.entry.module, PaymentModule::LnV1);
+            assert_eq!(paid.entry.amount, sats(250));
+            assert_eq!(paid.entry.fee, routing_fee);
+            assert_eq!(paid.entry.status, PaymentStatus::Succeeded);
+            assert!(paid.entry.completed_at.is_some());
+
+            let unpaid = find_payment(&payments, &unpayable_invoice, sats(100));
+            assert_eq!(unpaid.entry.direction, PaymentDirection::Outgoing);
+            assert_eq!(unpaid.entry.amount, sats(100));
+            assert_matches!(unpaid.entry.status, PaymentStatus::Failed { .. });
+
+            let received = find_payment(&payments, &received_invoice, sats(105));
+            assert_eq!(received.entry.direction, PaymentDirection::Incoming);
+            assert_eq!(received.entry.module, PaymentModule::LnV1);
+            assert_eq!(received.entry.amount, sats(105));
+            assert_eq!(received.entry.fee, sats(5));
+            assert_eq!(received.entry.status, PaymentStatus::Succeeded);
+
+            let failed = rpc
+                .list_payments(ListPaymentsPayload {
+                    federation_id: None,
+                    start_time: None,
+                    end_time: None,
+                    failed_only: true,
+                    limit: None,
+                })
+                .await?;
+            assert_eq!(failed, vec![unpaid.clone()]);
+
+            let limited = rpc
+                .list_payments(ListPaymentsPayload {
+                    federation_id: Some(fed.id()),
+                    start_time: None,
+                    end_time: None,
+                    failed_only: false,
+                    limit: Some(2),
+                })
+                .await?;
+            assert_eq!(limited.len(), 2);
+
+            let summary = rpc
+                .payment_summary(PaymentSummaryPayload {
+                    federation_id: Some(fed.id()),
+                    start_time: None,
+                    end_time: None,
+                })
+                .await?;
+            assert_eq!(summary.federations.len(), 1);
+            let summary = &summary.federations[0];
+            assert_eq!(summary.federation_id, fed.id());
+            assert_eq!(
+                (summary.outgoing.succeeded, summary.outgoing.failed),
+                (1, 1)
+            );
+            assert_eq!(summary.outgoing.volume, sats(250));
+            assert_eq!(summary.outgoing.fees_earned, routing_fee);
+            assert_eq!(
+                (summary.incoming.succeeded, summary.incoming.failed),
+                (1, 0)
+            );
+            assert_eq!(summary.incoming.volume, sats(105));
+            assert_eq!(summary.incoming.fees_earned, sats(5));
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+fn lnv2_fixtures() -> Fixtures {
+    let fixtures = Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default());
+    let bitcoin_server = fixtures.bitcoin_server();
+    let fixtures = fixtures.with_module(
+        fedimint_lnv2_client::LightningClientInit,
+        fedimint_lnv2_server::LightningInit,
+        fedimint_lnv2_common::config::LightningGenParams::regtest(bitcoin_server.clone()),
+    );
+
+    // The gateway still requires the legacy lightning module to connect to a
+    // federation
+    fixtures.with_module(
+        LightningClientInit,
+        LightningInit,
+        LightningGenParams::regtest(bitcoin_server),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_rebalances_liquidity() -> anyhow::Result<()> {
+    let fixtures = Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default());
+    let bitcoin_server = fixtures.bitcoin_server();
+    let wallet_client = WalletClientInit::new(fixtures.bitcoin_client());
+    let fixtures = fixtures
+        .with_module(
+            wallet_client,
+            WalletInit,
+            WalletGenParams::regtest(bitcoin_server.clone()),
+        )
+        .with_module(
+            LightningClientInit,
+            LightningInit,
+            LightningGenParams::regtest(bitcoin_server),
+        );
+    let fed = fixtures.new_default_fed().await;
+    let mut gateway = fixtures
+        .new_gateway(
+            fixtures.lnd().await,
+            0,
+            Some(DEFAULT_GATEWAY_PASSWORD.to_string()),
+        )
+        .await;
+    gateway.connect_fed(&fed).await;
+    let rpc = gateway
+        .get_rpc()
+        .await
+        .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+    send_msats_to_gateway(&gateway, fed.id(), 1_000_000).await;
+
+    let set_target = |target: u64, min: u64, max: u64| {
+        rpc.set_liquidity_target(SetLiquidityTargetPayload {
+            federation_id: fed.id(),
+            target: Some(
+                LiquidityTarget::new(sats(target), sats(min), sats(max))
+                    .expect("Valid liquidity target"),
+            ),
+        })
+    };
+    let rebalance = || {
+        rpc.rebalance(RebalancePayload {
+            federation_id: fed.id(),
+        })
+    };
+
+    // The balance is within bounds, so there is nothing to do
+    set_target(500, 100, 2_000).await?;
+    assert_eq!(rebalance().await?, None);
+
+    // The balance exceeds the maximum, so the manager pegs out to the node wallet.
+    // The federation's wallet holds no funds to pay out, so the peg-out fails.
+    set_target(200, 100, 500).await?;
+    let funding_address = gateway
+        .gateway
+        .handle_get_funding_address_msg()
+        .await?
+        .to_string();
+    let peg_out = rebalance().await?.expect("Balance is out of bounds");
+    assert_eq!(peg_out.entry.kind, LiquidityActionKind::PegOut);
+    assert_eq!(peg_out.entry.amount, sats(800));
+    assert_eq!(peg_out.entry.balance, sats(1_000));
+    assert_eq!(peg_out.entry.address, Some(funding_address));
+    assert_matches!(peg_out.entry.status, LiquidityActionStatus::Failed { .. });
+
+    // The balance is below the minimum, so the manager pegs in from the node
+    // wallet, which the fake lightning node cannot send from
+    set_target(5_000, 2_000, 10_000).await?;
+    let peg_in = rebalance().await?.expect("Balance is out of bounds");
+    assert_eq!(peg_in.entry.kind, LiquidityActionKind::PegIn);
+    assert_eq!(peg_in.entry.amount, sats(4_000));
+    assert_eq!(peg_in.entry.balance, sats(1_000));
+    assert!(peg_in.entry.address.is_some());
+    assert_matches!(peg_in.entry.status, LiquidityActionStatus::Failed { .. });
+
+    // Actions started within the same second are logged separately
+    let log: Vec<LiquidityActionInfo> = rpc
+        .liquidity_log(LiquidityLogPayload {
+            federation_id: Some(fed.id()),
+            limit: None,
+        })
+        .await?;
+    assert_eq!(log, vec![peg_in, peg_out]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_records_lnv2_payments_in_ledger() -> anyhow::Result<()> {
+    let fixtures = lnv2_fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let mut gateway = fixtures
+        .new_gateway(
+            fixtures.lnd().await,
+            0,
+            Some(DEFAULT_GATEWAY_PASSWORD.to_string()),
+        )
+        .await;
+    gateway.connect_fed(&fed).await;
+    let gateway_api = gateway.gateway.versioned_api.clone();
+    let rpc = gateway
+        .get_rpc()
+        .await
+        .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+    let other_lightning_client = fixtures.cln().await;
+
+    send_msats_to_gateway(&gateway, fed.id(), 1_000_000).await;
+
+    // Print money for user client
+    let user_client = fed.new_client().await;
+    let dummy_module = user_client.get_first_module::<DummyClientModule>();
+    let (_, outpoint) = dummy_module.print_money(sats(10_000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+    let ln_module = user_client.get_first_module::<fedimint_lnv2_client::LightningClientModule>();
+
+    // The user pays an external invoice
+    let paid_invoice = other_lightning_client.invoice(sats(1000), None).await?;
+    let send_op = ln_module
+        .send(gateway_api.clone(), paid_invoice.clone())
+        .await?;
+    let mut send_sub = ln_module.subscribe_send(send_op).await?.into_stream();
+    assert_eq!(send_sub.ok().await?, SendState::Funding);
+    assert_eq!(send_sub.ok().await?, SendState::Funded);
+    assert_matches!(send_sub.ok().await?, SendState::Success(..));
+    // The user also pays the federation's fee for the contract output
+    let send_fee =
+        sats(10_000 - 1000) - user_client.get_balance().await - FeeConsensus::default().output;
+
+    // The user pays an invoice the gateway cannot pay and is refunded
+    let unpayable_invoice = other_lightning_client.unpayable_invoice(sats(100), None);
+    let send_op = ln_module
+        .send(gateway_api.clone(), unpayable_invoice.clone())
+        .await?;
+    let mut send_sub = ln_module.subscribe_send(send_op).await?.into_stream();
+    assert_eq!(send_sub.ok().await?, SendState::Funding);
+    assert_eq!(send_sub.ok().await?, SendState::Funded);
+    assert_eq!(send_sub.ok().await?, SendState::Refunding);
+    assert_eq!(send_sub.ok().await?, SendState::Refunded);
+
+    // The user receives a payment the gateway relays from lightning
+    let balance_before_receive = user_client.get_balance().await;
+    let (received_invoice, receive_op) = ln_module.receive(gateway_api, sats(500)).await?;
+    let (payload, gateway_client) = gateway
+        .gateway
+        .get_payload_and_client_v2(
+            received_invoice.payment_hash().into_inner(),
+            received_invoice
+                .amount_milli_satoshis()
+                .expect("Invoice has amount"),
+        )
+        .await?;
+    gateway_client
+        .get_first_module::<GatewayClientModuleV2>()
+        .relay_incoming_htlc(2, 1, payload)
+        .await?;
+    let mut receive_sub = ln_module.subscribe_receive(receive_op).await?.into_stream();
+    assert_eq!(receive_sub.ok().await?, ReceiveState::Pending);
+    assert_eq!(receive_sub.ok().await?, ReceiveState::Claiming);
+    assert_eq!(receive_sub.ok().await?, ReceiveState::Claimed);
+    // The user also pays the federation's fee for claiming the contract
+    let receive_fee = sats(500)
+        - (user_client.get_balance().await - balance_before_receive)
+        - FeeConsensus::default().input;
+
+    let payments = settled_payments(&rpc, fed.id(), 3).await?;
+
+    let paid = find_payment(&payments, &paid_invoice, sats(1000));
+    assert_eq!(paid.entry.direction, PaymentDirection::Outgoing);
+    assert_eq!(paid.entry.module, PaymentModule::LnV2);
+    assert_eq!(paid.entry.amount, sats(1000));
+    assert_eq!(paid.entry.fee, send_fee);
+    assert_eq!(paid.entry.status, PaymentStatus::Succeeded);
+
+    let unpaid = find_payment(&payments, &unpayable_invoice, sats(100));
+    assert_eq!(unpaid.entry.module, PaymentModule::LnV2);
+    assert_matches!(unpaid.entry.status, PaymentStatus::Failed { .. });
+
+    let received = find_payment(&payments, &received_invoice, sats(500));
+    assert_eq!(received.entry.direction, PaymentDirection::Incoming);
+    assert_eq!(received.entry.module, PaymentModule::LnV2);
+    assert_eq!(received.entry.amount, sats(500));
+    assert_eq!(received.entry.fee, receive_fee);
+    assert_eq!(received.entry.status, PaymentStatus::Succeeded);
+
+    let summary = rpc
+        .payment_summary(PaymentSummaryPayload {
+            federation_id: None,
+            start_time: None,
+            end_time: None,
+        })
+        .await?;
+    assert_eq!(summary.federations.len(), 1);
+    let summary = &summary.federations[0];
+    assert_eq!(
+        (summary.outgoing.succeeded, summary.outgoing.failed),
+        (1, 1)
+    );
+    assert_eq!(summary.outgoing.volume, sats(1000));
+    assert_eq!(summary.outgoing.fees_earned, send_fee);
+    assert_eq!(
+        (summary.incoming.succeeded, summary.incoming.failed),
+        (1, 0)
+    );
+    assert_eq!(summary.incoming.volume, sats(500));
+    assert_eq!(summary.incoming.fees_earned, receive_fee);
+
+    Ok(())
+}
+
 fn routing_fees_in_msats(routing_fees: &FederationRoutingFees, amount: &Amount) -> u64 {
     ((amount.msats * routing_fees.proportional_millionths as u64) / 1_000_000)
         + routing_fees.base_msat as u64
@@ -1684,6 +2235,58 @@ async fn reconnect_federation(rpc: &GatewayRpcClient, fed: &FederationTest) {
     .await;
 }
 
+/// Waits until the gateway's ledger lists `count` payments of the federation
+/// that are no longer pending.
+async fn settled_payments(
+    rpc: &GatewayRpcClient,
+    federation_id: FederationId,
+    count: usize,
+) -> anyhow::Result<Vec<PaymentLogInfo>> {
+    retry(
+        "settled payments in the ledger",
+        FibonacciBackoff::default()
+            .with_min_delay(Duration::from_millis(200))
+            .with_max_delay(Duration::from_secs(3))
+            .with_max_times(10),
+        || async {
+            let payments = rpc
+                .list_payments(ListPaymentsPayload {
+                    federation_id: Some(federation_id),
+                    start_time: None,
+                    end_time: None,
+                    failed_only: false,
+                    limit: None,
+                })
+                .await?;
+            anyhow::ensure!(
+                payments.len() == count
+                    && payments
+                        .iter()
+                        .all(|payment| payment.entry.status != PaymentStatus::Pending),
+                "Payments are not settled yet: {payments:?}"
+            );
+            Ok(payments)
+        },
+    )
+    .await
+}
+
+/// Finds the ledger entry of the payment for `invoice` with the given amount.
+/// The invoices of the fake lightning nodes share their payment hash, so the
+/// amount tells the payments apart.
+fn find_payment<'p>(
+    payments: &'p [PaymentLogInfo],
+    invoice: &Bolt11Invoice,
+    amount: Amount,
+) -> &'p PaymentLogInfo {
+    payments
+        .iter()
+        .find(|payment| {
+            payment.entry.payment_hash == *invoice.payment_hash() && payment.entry.amount == amount
+        })
+        .expect("Payment is in the ledger")
+}
+
 /// Verifies that a gateway RPC succeeds. If it fails, the status code of the
 /// RPC is printed.
 async fn verify_gateway_rpc_success<Fut, T>(name: &str, func: impl Fn() -> Fut) -> T
@@ -26,7 +26,10 @@ use fedimint_client::derivable_secret::ChildId;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{
+    OperationDirection, OperationLogEntry, OperationLogRecordDetails, OperationOutcomeState,
+    UpdateStreamOrOutcome,
+};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
@@ -360,6 +363,78 @@ impl ClientModuleInit for LightningClientInit {
 
         migrations
     }
+
+    fn operation_log_record_details(
+        &self,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails> {
+        Ok(match entry.try_meta::<LightningOperationMeta>()?.variant {
+            LightningOperationMetaVariant::Pay(pay) => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Outgoing),
+                amount: pay.invoice.amount_milli_satoshis().map(Amount::from_msats),
+                fee: Some(pay.fee),
+                invoice: Some(pay.invoice.to_string()),
+                txid: Some(pay.out_point.txid.to_string()),
+                ..Default::default()
+            },
+            LightningOperationMetaVariant::Receive { invoice, .. } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Incoming),
+                amount: invoice.amount_milli_satoshis().map(Amount::from_msats),
+                invoice: Some(invoice.to_string()),
+                ..Default::default()
+            },
+            LightningOperationMetaVariant::Claim { out_points } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Incoming),
+                txid: out_points
+                    .first()
+                    .map(|out_point| out_point.txid.to_string()),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        let Ok(meta) = entry.try_meta::<LightningOperationMeta>() else {
+            return OperationOutcomeState::unclassified(entry);
+        };
+
+        match meta.variant {
+            LightningOperationMetaVariant::Pay(LightningOperationMetaPay {
+                is_internal_payment: true,
+                ..
+            }) => OperationOutcomeState::classify(entry, |state| match state {
+                InternalPayState::Preimage(_) => OperationOutcomeState::Success,
+                InternalPayState::RefundSuccess { .. }
+                | InternalPayState::RefundError { .. }
+                | InternalPayState::FundingFailed { .. }
+                | InternalPayState::UnexpectedError(_) => OperationOutcomeState::Failure,
+                InternalPayState::Funding => OperationOutcomeState::Pending,
+            }),
+            LightningOperationMetaVariant::Pay(_) => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    LnPayState::Success { .. } => OperationOutcomeState::Success,
+                    LnPayState::Canceled
+                    | LnPayState::Refunded { .. }
+                    | LnPayState::UnexpectedError { .. } => OperationOutcomeState::Failure,
+                    LnPayState::Created
+                    | LnPayState::Funded
+                    | LnPayState::WaitingForRefund { .. }
+                    | LnPayState::AwaitingChange => OperationOutcomeState::Pending,
+                })
+            }
+            LightningOperationMetaVariant::Receive { .. }
+            | LightningOperationMetaVariant::Claim { .. } => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    LnReceiveState::Claimed => OperationOutcomeState::Success,
+                    LnReceiveState::Canceled { .. } => OperationOutcomeState::Failure,
+                    LnReceiveState::Created
+                    | LnReceiveState::WaitingForPayment { .. }
+                    | LnReceiveState::Funded
+                    | LnReceiveState::AwaitingFunds => OperationOutcomeState::Pending,
+                })
+            }
+        }
+    }
 }
 
 /// Client side lightning module
@@ -1150,7 +1225,7 @@ impl LightningClientModule {
         let mut stream = self.notifier.subscribe(operation_id).await;
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
                 yield InternalPayState::Funding;
 
@@ -1193,7 +1268,7 @@ impl LightningClientModule {
 
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
                 let self_ref = client_ctx.self_ref();
 
@@ -1476,7 +1551,7 @@ impl LightningClientModule {
 
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
                 yield LnReceiveState::AwaitingFunds;
 
@@ -1509,7 +1584,7 @@ impl LightningClientModule {
 
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
 
                 let self_ref = client_ctx.self_ref();
@@ -1911,3 +1986,200 @@ pub async fn get_invoice(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_client::module::init::ClientModuleInitRegistry;
+    use fedimint_client::oplog::{OperationLog, OperationLogFilter};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IRawDatabaseExt;
+    use secp256k1::SecretKey;
+
+    use super::*;
+
+    fn invoice(amount: Amount) -> Bolt11Invoice {
+        let ctx = Secp256k1::new();
+        let secret_key = SecretKey::new(&mut OsRng);
+        InvoiceBuilder::new(Currency::Regtest)
+            .description(String::new())
+            .payment_hash(sha256::Hash::hash(&[0; 32]))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(0)
+            .payment_secret(PaymentSecret([0; 32]))
+            .amount_milli_satoshis(amount.msats)
+            .build_signed(|m| ctx.sign_ecdsa_recoverable(m, &secret_key))
+            .expect("Invoice is valid")
+    }
+
+    fn pay_meta(amount: Amount, is_internal_payment: bool) -> LightningOperationMeta {
+        LightningOperationMeta {
+            variant: LightningOperationMetaVariant::Pay(LightningOperationMetaPay {
+                out_point: OutPoint {
+                    txid: TransactionId::all_zeros(),
+                    out_idx: 0,
+                },
+                invoice: invoice(amount),
+                fee: Amount::from_sats(1),
+                change: vec![],
+                is_internal_payment,
+                contract_id: ContractId::from_hash(sha256::Hash::hash(&[0; 32])),
+                gateway_id: None,
+            }),
+            extra_meta: serde_json::Value::Null,
+        }
+    }
+
+    fn receive_meta(amount: Amount) -> LightningOperationMeta {
+        LightningOperationMeta {
+            variant: LightningOperationMetaVariant::Receive {
+                out_point: OutPoint {
+                    txid: TransactionId::all_zeros(),
+                    out_idx: 0,
+                },
+                invoice: invoice(amount),
+                gateway_id: None,
+            },
+            extra_meta: serde_json::Value::Null,
+        }
+    }
+
+    fn operation_indices(operations: &[(impl Sized, OperationLogEntry)]) -> Vec<u8> {
+        operations
+            .iter()
+            .map(|(_, entry)| {
+                entry
+                    .meta::<LightningOperationMeta>()
+                    .extra_meta
+                    .as_u64()
+                    .expect("Index is set") as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filters_operations_by_amount_and_outcome() {
+        let mut module_inits = ClientModuleInitRegistry::new();
+        module_inits.attach(LightningClientInit);
+        let db = MemDatabase::new().into_database();
+        let op_log = OperationLog::new(db.clone(), module_inits);
+
+        let operations = [
+            pay_meta(Amount::from_sats(1_000), false),
+            pay_meta(Amount::from_sats(2_000), true),
+            receive_meta(Amount::from_sats(3_000)),
+            receive_meta(Amount::from_sats(4_000)),
+        ];
+        for (operation_idx, mut meta) in operations.into_iter().enumerate() {
+            meta.extra_meta = json!(operation_idx);
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx.to_ref_nc(),
+                    OperationId([operation_idx as u8; 32]),
+                    LightningCommonInit::KIND.as_str(),
+                    meta,
+                )
+                .await;
+            dbtx.commit_tx().await;
+        }
+
+        op_log
+            .set_operation_outcome(OperationId([0; 32]), &LnPayState::Canceled)
+            .await
+            .unwrap();
+        op_log
+            .set_operation_outcome(
+                OperationId([1; 32]),
+                &InternalPayState::Preimage(Preimage([0; 32])),
+            )
+            .await
+            .unwrap();
+        op_log
+            .set_operation_outcome(OperationId([2; 32]), &LnReceiveState::Claimed)
+            .await
+            .unwrap();
+
+        let by_outcome = |outcome_state| OperationLogFilter {
+            outcome_state: Some(outcome_state),
+            ..Default::default()
+        };
+        let failed = op_log
+            .list_operations_filtered(&by_outcome(OperationOutcomeState::Failure), 10, None)
+            .await;
+        assert_eq!(operation_indices(&failed), vec![0]);
+        let succeeded = op_log
+            .list_operations_filtered(&by_outcome(OperationOutcomeState::Success), 10, None)
+            .await;
+        assert_eq!(operation_indices(&succeeded), vec![2, 1]);
+        let pending = op_log
+            .list_operations_filtered(&by_outcome(OperationOutcomeState::Pending), 10, None)
+            .await;
+        assert_eq!(operation_indices(&pending), vec![3]);
+
+        let amount_range = OperationLogFilter {
+            min_amount: Some(Amount::from_sats(2_000)),
+            max_amount: Some(Amount::from_sats(3_000)),
+            ..Default::default()
+        };
+        let in_range = op_log
+            .list_operations_filtered(&amount_range, 10, None)
+            .await;
+        assert_eq!(operation_indices(&in_range), vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn exports_undecodable_operations_as_raw_data() {
+        let mut module_inits = ClientModuleInitRegistry::new();
+        module_inits.attach(LightningClientInit);
+        let db = MemDatabase::new().into_database();
+        let op_log = OperationLog::new(db.clone(), module_inits.clone());
+
+        let mut dbtx = db.begin_transaction().await;
+        op_log
+            .add_operation_log_entry(
+                &mut dbtx.to_ref_nc(),
+                OperationId([0; 32]),
+                LightningCommonInit::KIND.as_str(),
+                pay_meta(Amount::from_sats(1_000), false),
+            )
+            .await;
+        op_log
+            .add_operation_log_entry(
+                &mut dbtx.to_ref_nc(),
+                OperationId([1; 32]),
+                LightningCommonInit::KIND.as_str(),
+                json!({ "unknown_variant": {} }),
+            )
+            .await;
+        dbtx.commit_tx().await;
+
+        let records = op_log
+            .export_records(
+                FederationId::dummy(),
+                &module_inits,
+                &OperationLogFilter::default(),
+                10,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+
+        let (_, pay) = records
+            .iter()
+            .find(|(key, _)| key.operation_id == OperationId([0; 32]))
+            .expect("Pay operation was exported");
+        assert_eq!(pay.amount, Some(Amount::from_sats(1_000)));
+        assert_eq!(pay.raw_data, None);
+
+        let (_, unknown) = records
+            .iter()
+            .find(|(key, _)| key.operation_id == OperationId([1; 32]))
+            .expect("Undecodable operation was exported");
+        assert_eq!(unknown.amount, None);
+        assert_eq!(
+            unknown.raw_data.as_deref(),
+            Some(r#"{"meta":{"unknown_variant":{}},"outcome":null}"#)
+        );
+    }
+}
@@ -12,7 +12,10 @@ use fedimint_api_client::api::DynModuleApi;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{
+    OperationDirection, OperationLogEntry, OperationLogRecordDetails, OperationOutcomeState,
+    UpdateStreamOrOutcome,
+};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
@@ -220,6 +223,61 @@ impl ClientModuleInit for LightningClientInit {
             admin_auth: args.admin_auth().cloned(),
         })
     }
+
+    fn operation_log_record_details(
+        &self,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails> {
+        Ok(match entry.try_meta::<LightningOperationMeta>()? {
+            LightningOperationMeta::Send {
+                funding_txid,
+                contract,
+                invoice,
+                ..
+            } => {
+                let amount = invoice.amount_milli_satoshis().map(Amount::from_msats);
+                OperationLogRecordDetails {
+                    direction: Some(OperationDirection::Outgoing),
+                    amount,
+                    fee: amount.map(|amount| contract.amount.saturating_sub(amount)),
+                    invoice: Some(invoice.to_string()),
+                    txid: Some(funding_txid.to_string()),
+                    ..Default::default()
+                }
+            }
+            LightningOperationMeta::Receive { contract } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Incoming),
+                amount: Some(contract.commitment.amount),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        match entry.try_meta::<LightningOperationMeta>() {
+            Ok(LightningOperationMeta::Send { .. }) => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    SendState::Success(_) => OperationOutcomeState::Success,
+                    SendState::Refunded | SendState::FundingRejected | SendState::Failure => {
+                        OperationOutcomeState::Failure
+                    }
+                    SendState::Funding | SendState::Funded | SendState::Refunding => {
+                        OperationOutcomeState::Pending
+                    }
+                })
+            }
+            Ok(LightningOperationMeta::Receive { .. }) => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    ReceiveState::Claimed => OperationOutcomeState::Success,
+                    ReceiveState::Expired | ReceiveState::Failure => OperationOutcomeState::Failure,
+                    ReceiveState::Pending | ReceiveState::Claiming => {
+                        OperationOutcomeState::Pending
+                    }
+                })
+            }
+            Err(_) => OperationOutcomeState::unclassified(entry),
+        }
+    }
 }
 
 /// Client side lightning module
@@ -459,7 +517,7 @@ impl LightningClientModule {
         let client_ctx = self.client_ctx.clone();
         let module_api = self.module_api.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
                 loop {
                     if let Some(LightningClientStateMachines::Send(state)) = stream.next().await {
@@ -753,7 +811,7 @@ impl LightningClientModule {
         let mut stream = self.notifier.subscribe(operation_id).await;
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
                 loop {
                     if let Some(LightningClientStateMachines::Receive(state)) = stream.next().await {
@@ -7,7 +7,7 @@ use serde::Serialize;
 use strum_macros::EnumIter;
 
 use crate::backup::recovery::MintRecoveryState;
-use crate::SpendableNote;
+use crate::{NoteRebalancingConfig, NotesSelectorKind, SpendableNote};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -17,6 +17,8 @@ pub enum DbKeyPrefix {
     CancelledOOBSpend = 0x2b,
     RecoveryState = 0x2c,
     RecoveryFinalized = 0x2d,
+    DefaultNotesSelector = 0x2e,
+    NoteRebalancingConfig = 0x2f,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -96,3 +98,24 @@ impl_db_lookup!(
     key = CancelledOOBSpendKey,
     query_prefix = CancelledOOBSpendKeyPrefix,
 );
+
+/// Note selector used by [`crate::MintClientModule::spend_notes`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct DefaultNotesSelectorKey;
+
+impl_db_record!(
+    key = DefaultNotesSelectorKey,
+    value = NotesSelectorKind,
+    db_prefix = DbKeyPrefix::DefaultNotesSelector,
+);
+
+/// Configuration of the background note rebalancing, disabled if not present
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct NoteRebalancingConfigKey;
+
+impl_db_record!(
+    key = NoteRebalancingConfigKey,
+    value = NoteRebalancingConfig,
+    db_prefix = DbKeyPrefix::NoteRebalancingConfig,
+    notify_on_modify = true,
+);
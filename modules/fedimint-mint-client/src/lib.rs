@@ -4,10 +4,16 @@ pub mod backup;
 pub mod client_db;
 /// State machines for mint inputs
 mod input;
+/// E-cash operations across the federations of a multi-federation client
+mod multi_federation;
 /// State machines for out-of-band transmitted e-cash notes
 mod oob;
 /// State machines for mint outputs
 pub mod output;
+/// Background rebalancing of note denominations
+mod rebalance;
+/// Note selection strategies
+mod select;
 
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
@@ -28,7 +34,10 @@ use fedimint_client::module::init::{
     ClientModuleInit, ClientModuleInitArgs, ClientModuleRecoverArgs,
 };
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::{OperationLogEntry, UpdateStreamOrOutcome};
+use fedimint_client::oplog::{
+    OperationDirection, OperationLogEntry, OperationLogRecordDetails, OperationOutcomeState,
+    UpdateStreamOrOutcome,
+};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
@@ -67,20 +76,30 @@ use tracing::{debug, warn};
 
 use crate::backup::EcashBackup;
 use crate::client_db::{
-    CancelledOOBSpendKey, CancelledOOBSpendKeyPrefix, NextECashNoteIndexKey,
-    NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix,
+    CancelledOOBSpendKey, CancelledOOBSpendKeyPrefix, DefaultNotesSelectorKey,
+    NextECashNoteIndexKey, NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix,
+    NoteRebalancingConfigKey,
 };
 use crate::input::{
     MintInputCommon, MintInputStateCreated, MintInputStateMachine, MintInputStates,
 };
+pub use crate::multi_federation::MultiFederationMintExt;
 use crate::oob::{MintOOBStateMachine, MintOOBStates, MintOOBStatesCreated};
 use crate::output::{
     MintOutputCommon, MintOutputStateMachine, MintOutputStates, MintOutputStatesCreated,
     NoteIssuanceRequest,
 };
+pub use crate::rebalance::NoteRebalancingConfig;
+pub use crate::select::{
+    BuiltinNotesSelector, NotesSelectorKind, SelectNotesBalanced, SelectNotesWithMinimalChange,
+};
 
 const MINT_E_CASH_TYPE_CHILD_ID: ChildId = ChildId(0);
 
+/// Number of notes per denomination the client tries to hold when issuing new
+/// e-cash notes
+const TARGET_NOTES_PER_DENOMINATION: u16 = 2;
+
 pub const LOG_TARGET: &str = "client::module::mint";
 
 /// An encapsulation of [`FederationId`] and e-cash notes in the form of
@@ -399,6 +418,15 @@ pub enum MintOperationMetaVariant {
         requested_amount: Amount,
         oob_notes: OOBNotes,
     },
+    /// Reissuance of own notes to get closer to the target denomination
+    /// distribution, see [`MintClientModule::rebalance_notes`]
+    Rebalance {
+        txid: TransactionId,
+        out_point_indices: Vec<u64>,
+        /// Number of notes that were reissued
+        reissued_notes: usize,
+        fee: Amount,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -453,6 +481,18 @@ impl ModuleInit for MintClientInit {
                 }
                 DbKeyPrefix::RecoveryState => {}
                 DbKeyPrefix::RecoveryFinalized => {}
+                DbKeyPrefix::DefaultNotesSelector => {
+                    if let Some(kind) = dbtx.get_value(&DefaultNotesSelectorKey).await {
+                        mint_client_items
+                            .insert("DefaultNotesSelector".to_string(), Box::new(kind));
+                    }
+                }
+                DbKeyPrefix::NoteRebalancingConfig => {
+                    if let Some(config) = dbtx.get_value(&NoteRebalancingConfigKey).await {
+                        mint_client_items
+                            .insert("NoteRebalancingConfig".to_string(), Box::new(config));
+                    }
+                }
             }
         }
 
@@ -470,6 +510,11 @@ impl ClientModuleInit for MintClientInit {
     }
 
     async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
+        args.task_group().spawn_cancellable(
+            "mint note rebalancing",
+            rebalance::run_note_rebalancing(args.context()),
+        );
+
         Ok(MintClientModule {
             federation_id: *args.federation_id(),
             cfg: args.cfg().clone(),
@@ -487,6 +532,68 @@ impl ClientModuleInit for MintClientInit {
     ) -> anyhow::Result<()> {
         args.recover_from_history::<MintRecovery>(snapshot).await
     }
+
+    fn operation_log_record_details(
+        &self,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails> {
+        let meta = entry.try_meta::<MintOperationMeta>()?;
+        Ok(match meta.variant {
+            MintOperationMetaVariant::Reissuance { txid, .. } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Incoming),
+                amount: Some(meta.amount),
+                txid: txid.map(|txid| txid.to_string()),
+                ..Default::default()
+            },
+            MintOperationMetaVariant::SpendOOB { .. } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Outgoing),
+                amount: Some(meta.amount),
+                ..Default::default()
+            },
+            MintOperationMetaVariant::Rebalance { txid, fee, .. } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Internal),
+                amount: Some(meta.amount),
+                fee: Some(fee),
+                txid: Some(txid.to_string()),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        let Ok(meta) = entry.try_meta::<MintOperationMeta>() else {
+            return OperationOutcomeState::unclassified(entry);
+        };
+
+        match meta.variant {
+            MintOperationMetaVariant::Reissuance { .. } => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    ReissueExternalNotesState::Done => OperationOutcomeState::Success,
+                    ReissueExternalNotesState::Failed(_) => OperationOutcomeState::Failure,
+                    ReissueExternalNotesState::Created | ReissueExternalNotesState::Issuing => {
+                        OperationOutcomeState::Pending
+                    }
+                })
+            }
+            // The notes only count as spent if the recipient reissued them
+            MintOperationMetaVariant::SpendOOB { .. } => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    SpendOOBState::Success | SpendOOBState::UserCanceledFailure => {
+                        OperationOutcomeState::Success
+                    }
+                    SpendOOBState::UserCanceledSuccess | SpendOOBState::Refunded => {
+                        OperationOutcomeState::Failure
+                    }
+                    SpendOOBState::Created | SpendOOBState::UserCanceledProcessing => {
+                        OperationOutcomeState::Pending
+                    }
+                })
+            }
+            MintOperationMetaVariant::Rebalance { .. } => {
+                OperationOutcomeState::unclassified(entry)
+            }
+        }
+    }
 }
 
 /// The `MintClientModule` is responsible for handling e-cash minting
@@ -666,7 +773,8 @@ impl ClientModule for MintClientModule {
         amount: Amount,
     ) -> Vec<ClientOutput<MintOutput, MintClientStateMachines>> {
         // FIXME: don't hardcode notes per denomination
-        self.create_output(dbtx, operation_id, 2, amount).await
+        self.create_output(dbtx, operation_id, TARGET_NOTES_PER_DENOMINATION, amount)
+            .await
     }
 
     async fn await_primary_module_output(
@@ -1173,7 +1281,7 @@ impl MintClientModule {
 
         let client_ctx = self.client_ctx.clone();
 
-        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
             stream! {
                 yield ReissueExternalNotesState::Created;
 
@@ -1208,6 +1316,9 @@ impl MintClientModule {
     /// canceled by calling [`MintClientModule::try_cancel_spend_notes`] as long
     /// as the recipient hasn't reissued the e-cash notes themselves yet.
     ///
+    /// Notes are selected using the default selector, see
+    /// [`MintClientModule::set_default_notes_selector`].
+    ///
     /// The client will also automatically attempt to cancel the operation after
     /// `try_cancel_after` time has passed. This is a safety mechanism to avoid
     /// users forgetting about failed out-of-band transactions. The timeout
@@ -1221,8 +1332,9 @@ impl MintClientModule {
         include_invite: bool,
         extra_meta: M,
     ) -> anyhow::Result<(OperationId, OOBNotes)> {
+        let notes_selector = self.notes_selector(self.default_notes_selector().await);
         self.spend_notes_with_selector(
-            &SelectNotesWithAtleastAmount,
+            &notes_selector,
             min_amount,
             try_cancel_after,
             include_invite,
@@ -1231,6 +1343,39 @@ impl MintClientModule {
         .await
     }
 
+    /// Returns the note selector used by [`MintClientModule::spend_notes`],
+    /// which is [`NotesSelectorKind::MinimalChange`] unless changed.
+    pub async fn default_notes_selector(&self) -> NotesSelectorKind {
+        self.client_ctx
+            .module_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&DefaultNotesSelectorKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Persistently changes the note selector used by
+    /// [`MintClientModule::spend_notes`]
+    pub async fn set_default_notes_selector(&self, kind: NotesSelectorKind) {
+        let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
+        dbtx.insert_entry(&DefaultNotesSelectorKey, &kind).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Creates a built-in note selector of the given kind. The balanced
+    /// selector targets the same number of notes per denomination that is
+    /// used when issuing new notes.
+    pub fn notes_selector(&self, kind: NotesSelectorKind) -> BuiltinNotesSelector {
+        let target = self
+            .cfg
+            .tbs_pks
+            .tiers()
+            .map(|amount| (*amount, TARGET_NOTES_PER_DENOMINATION as usize))
+            .collect();
+        BuiltinNotesSelector::new(kind, target)
+    }
+
     /// Same as `spend_notes` but allows different to select notes to be used.
     pub async fn spend_notes_with_selector<M: Serialize + Send>(
         &self,
@@ -1357,9 +1502,9 @@ impl MintClientModule {
 
         let client_ctx = self.client_ctx.clone();
 
-        let global_db = self.client_ctx.global_db();
+        let operation_log = self.client_ctx.operation_log();
         Ok(
-            operation.outcome_or_updates(&global_db, operation_id, move || {
+            operation.outcome_or_updates(&operation_log, operation_id, move || {
                 stream! {
                     yield SpendOOBState::Created;
 
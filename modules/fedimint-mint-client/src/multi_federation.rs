@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
+use fedimint_client::multi_federation::MultiFederationClient;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::{apply, async_trait_maybe_send, Amount};
+use serde::Serialize;
+
+use crate::{MintClientModule, OOBNotes};
+
+/// E-cash operations of a [`MultiFederationClient`] that pick the federation
+/// to use on their own
+#[apply(async_trait_maybe_send!)]
+pub trait MultiFederationMintExt {
+    /// Spends e-cash of the given federation, see
+    /// [`MintClientModule::spend_notes`]
+    async fn spend_notes<M: Serialize + Send>(
+        &self,
+        federation_id: FederationId,
+        min_amount: Amount,
+        try_cancel_after: Duration,
+        include_invite: bool,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, OOBNotes)>;
+
+    /// Reissues `oob_notes` with the client of the federation they belong to,
+    /// see [`MintClientModule::reissue_external_notes`]
+    async fn reissue_external_notes<M: Serialize + Send>(
+        &self,
+        oob_notes: OOBNotes,
+        extra_meta: M,
+    ) -> anyhow::Result<(FederationId, OperationId)>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl MultiFederationMintExt for MultiFederationClient {
+    async fn spend_notes<M: Serialize + Send>(
+        &self,
+        federation_id: FederationId,
+        min_amount: Amount,
+        try_cancel_after: Duration,
+        include_invite: bool,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, OOBNotes)> {
+        let client = self
+            .get(&federation_id)
+            .with_context(|| format!("Federation {federation_id} was not joined"))?;
+
+        client
+            .get_first_module::<MintClientModule>()
+            .spend_notes(min_amount, try_cancel_after, include_invite, extra_meta)
+            .await
+    }
+
+    async fn reissue_external_notes<M: Serialize + Send>(
+        &self,
+        oob_notes: OOBNotes,
+        extra_meta: M,
+    ) -> anyhow::Result<(FederationId, OperationId)> {
+        let client = self.get_by_prefix(&oob_notes.federation_id_prefix())?;
+
+        let operation_id = client
+            .get_first_module::<MintClientModule>()
+            .reissue_external_notes(oob_notes, extra_meta)
+            .await?;
+
+        Ok((client.federation_id(), operation_id))
+    }
+}
@@ -0,0 +1,398 @@
+//! Background rebalancing of the denominations of held e-cash notes
+//!
+//! Spending and receiving e-cash over time leaves the wallet with many notes
+//! of some tiers and none of others. Once the number of notes held in excess
+//! of the target distribution crosses a threshold they get reissued to
+//! ourselves, filling up the missing tiers.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use fedimint_client::module::ClientContext;
+use fedimint_client::transaction::TransactionBuilder;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::CommonModuleInit;
+use fedimint_core::{runtime, Amount, TieredSummary};
+use fedimint_logging::LOG_CLIENT_MODULE_MINT;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::client_db::{NoteKey, NoteKeyPrefix, NoteRebalancingConfigKey};
+use crate::select::collect_shuffled_tiers;
+use crate::{
+    MintClientModule, MintCommonInit, MintOperationMeta, MintOperationMetaVariant,
+    TARGET_NOTES_PER_DENOMINATION,
+};
+
+/// Configuration of the background note rebalancing, which is disabled unless
+/// set with [`MintClientModule::set_note_rebalancing_config`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NoteRebalancingConfig {
+    /// Number of notes per denomination the wallet should hold, the target
+    /// distribution is derived from it like for newly issued notes
+    pub notes_per_denomination: u16,
+    /// Number of notes held in excess of the target distribution above which
+    /// they get reissued
+    pub max_surplus_notes: u16,
+    /// Maximum fee to pay for a single rebalancing transaction
+    pub max_fee: Amount,
+    /// Time between two checks of the wallet's notes
+    pub check_interval_secs: u64,
+}
+
+/// Maximum number of iterations when searching for the fee of a balanced
+/// rebalancing transaction
+const MAX_FEE_ITERATIONS: usize = 10;
+
+impl Default for NoteRebalancingConfig {
+    fn default() -> Self {
+        Self {
+            notes_per_denomination: TARGET_NOTES_PER_DENOMINATION,
+            max_surplus_notes: 20,
+            max_fee: Amount::from_sats(10),
+            check_interval_secs: 600,
+        }
+    }
+}
+
+impl MintClientModule {
+    /// Returns the current rebalancing configuration, `None` if rebalancing is
+    /// disabled
+    pub async fn note_rebalancing_config(&self) -> Option<NoteRebalancingConfig> {
+        self.client_ctx
+            .module_db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&NoteRebalancingConfigKey)
+            .await
+    }
+
+    /// Persistently enables background note rebalancing with the given
+    /// configuration, or disables it if `None`
+    pub async fn set_note_rebalancing_config(&self, config: Option<NoteRebalancingConfig>) {
+        let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
+        match config {
+            Some(config) => {
+                dbtx.insert_entry(&NoteRebalancingConfigKey, &config).await;
+            }
+            None => {
+                dbtx.remove_entry(&NoteRebalancingConfigKey).await;
+            }
+        }
+        dbtx.commit_tx().await;
+    }
+
+    /// Reissues the notes held in excess of the target distribution described
+    /// by `config` if there are more than `config.max_surplus_notes` of them
+    /// and the fees don't exceed `config.max_fee`.
+    ///
+    /// Returns the id of the started [`MintOperationMetaVariant::Rebalance`]
+    /// operation, or `None` if no rebalancing was necessary.
+    pub async fn rebalance_notes(
+        &self,
+        config: &NoteRebalancingConfig,
+    ) -> anyhow::Result<Option<OperationId>> {
+        let spend_fee = self.cfg.fee_consensus.note_spend_abs;
+        let issuance_fee = self.cfg.fee_consensus.note_issuance_abs;
+
+        self.client_ctx
+            .module_autocommit_2(
+                move |dbtx, _| {
+                    Box::pin(async move {
+                        let held = self.get_wallet_summary(&mut dbtx.module_dbtx()).await;
+                        let target = TieredSummary::represent_amount(
+                            held.total_amount(),
+                            &TieredSummary::default(),
+                            &self.cfg.tbs_pks,
+                            config.notes_per_denomination,
+                        );
+                        // Notes not worth their input fee would only be lost
+                        let surplus = surplus_notes(&held, &target, spend_fee);
+                        let num_inputs = surplus.count_items();
+                        if num_inputs <= usize::from(config.max_surplus_notes) {
+                            return Ok(None);
+                        }
+
+                        let input_amount = surplus.total_amount();
+                        let remaining = held_without(&held, &surplus);
+                        let Some(expected_fee) = balanced_fee(
+                            input_amount,
+                            spend_fee * num_inputs as u64,
+                            issuance_fee,
+                            |amount| {
+                                TieredSummary::represent_amount(
+                                    amount,
+                                    &remaining,
+                                    &self.cfg.tbs_pks,
+                                    config.notes_per_denomination,
+                                )
+                                .count_items()
+                            },
+                        ) else {
+                            debug!(
+                                target: LOG_CLIENT_MODULE_MINT,
+                                %input_amount,
+                                "Skipping note rebalancing, no balanced transaction found"
+                            );
+                            return Ok(None);
+                        };
+                        if config.max_fee < expected_fee {
+                            debug!(
+                                target: LOG_CLIENT_MODULE_MINT,
+                                fee = %expected_fee,
+                                max_fee = %config.max_fee,
+                                "Skipping note rebalancing, fees too high"
+                            );
+                            return Ok(None);
+                        }
+                        let output_amount = input_amount - expected_fee;
+
+                        let notes = {
+                            let mut module_dbtx = dbtx.module_dbtx();
+                            let mut tiers = collect_shuffled_tiers(
+                                module_dbtx
+                                    .find_by_prefix(&NoteKeyPrefix)
+                                    .await
+                                    .map(|(key, note)| (key.amount, note)),
+                            )
+                            .await;
+
+                            let mut notes = vec![];
+                            for (amount, count) in surplus.iter() {
+                                let tier = tiers.get_mut(&amount).expect("Surplus notes are held");
+                                notes.extend(tier.drain(..count).map(|note| (amount, note)));
+                            }
+                            for (amount, note) in &notes {
+                                module_dbtx
+                                    .remove_entry(&NoteKey {
+                                        amount: *amount,
+                                        nonce: note.nonce(),
+                                    })
+                                    .await;
+                            }
+                            notes.into_iter().collect()
+                        };
+
+                        let operation_id = OperationId::new_random();
+                        let inputs = self.create_input_from_notes(operation_id, notes).await?;
+                        let outputs = self
+                            .create_output(
+                                &mut dbtx.module_dbtx(),
+                                operation_id,
+                                config.notes_per_denomination,
+                                output_amount,
+                            )
+                            .await;
+                        let num_outputs = outputs.len() as u64;
+                        // The transaction has to be balanced already, otherwise finalizing it
+                        // would spend additional notes or issue change and change its fee
+                        let fee = input_amount
+                            - outputs.iter().map(|output| output.amount).sum::<Amount>();
+                        anyhow::ensure!(
+                            fee == spend_fee * num_inputs as u64 + issuance_fee * num_outputs,
+                            "Rebalancing transaction is not balanced"
+                        );
+                        let tx = TransactionBuilder::new()
+                            .with_inputs(self.client_ctx.map_dyn(inputs).collect())
+                            .with_outputs(self.client_ctx.map_dyn(outputs).collect());
+
+                        let (txid, _) = dbtx
+                            .finalize_and_submit_transaction(
+                                operation_id,
+                                MintCommonInit::KIND.as_str(),
+                                |txid, change| MintOperationMeta {
+                                    variant: MintOperationMetaVariant::Rebalance {
+                                        txid,
+                                        out_point_indices: (0..num_outputs)
+                                            .chain(change.iter().map(|out_point| out_point.out_idx))
+                                            .collect(),
+                                        reissued_notes: num_inputs,
+                                        fee,
+                                    },
+                                    amount: input_amount,
+                                    extra_meta: serde_json::Value::Null,
+                                },
+                                tx,
+                            )
+                            .await?;
+
+                        info!(
+                            target: LOG_CLIENT_MODULE_MINT,
+                            %txid,
+                            num_inputs,
+                            %fee,
+                            "Rebalancing e-cash notes"
+                        );
+
+                        Ok(Some(operation_id))
+                    })
+                },
+                Some(100),
+            )
+            .await
+    }
+}
+
+/// Periodically checks the wallet's notes and rebalances them according to
+/// the configuration stored in the database, runs until the client shuts down
+pub(crate) async fn run_note_rebalancing(client_ctx: ClientContext<MintClientModule>) {
+    let mut pending_operation = None;
+
+    loop {
+        let config = client_ctx
+            .module_db()
+            .wait_key_exists(&NoteRebalancingConfigKey)
+            .await;
+        runtime::sleep(Duration::from_secs(config.check_interval_secs)).await;
+
+        let module = client_ctx.self_ref();
+        // Rebalancing might have been reconfigured or disabled while sleeping
+        let Some(config) = module.note_rebalancing_config().await else {
+            continue;
+        };
+
+        // Wait for the reissued notes of the last rebalancing before looking
+        // at the wallet again
+        if let Some(operation_id) = pending_operation {
+            if client_ctx.has_active_states(operation_id).await {
+                continue;
+            }
+        }
+
+        match module.rebalance_notes(&config).await {
+            Ok(Some(operation_id)) => pending_operation = Some(operation_id),
+            Ok(None) => {}
+            Err(e) => {
+                warn!(target: LOG_CLIENT_MODULE_MINT, err = %e, "Note rebalancing failed");
+            }
+        }
+    }
+}
+
+/// Returns the fee of a transaction spending `input_amount` that has to pay
+/// `input_fee` and `issuance_fee` per output note, where `num_outputs` returns
+/// the number of notes an amount is issued in. The fee depends on the number
+/// of outputs which in turn depends on the fee, so this searches for a fee for
+/// which the transaction is balanced. Returns `None` if there is none.
+fn balanced_fee(
+    input_amount: Amount,
+    input_fee: Amount,
+    issuance_fee: Amount,
+    num_outputs: impl Fn(Amount) -> usize,
+) -> Option<Amount> {
+    let mut fee = input_fee;
+    // The number of outputs only changes by a few notes between iterations, so
+    // this converges quickly unless it oscillates
+    for _ in 0..MAX_FEE_ITERATIONS {
+        if input_amount <= fee {
+            return None;
+        }
+        let next_fee = input_fee + issuance_fee * num_outputs(input_amount - fee) as u64;
+        if next_fee == fee {
+            return Some(fee);
+        }
+        fee = next_fee;
+    }
+    None
+}
+
+/// Returns the number of notes of each tier held in excess of `target`,
+/// ignoring tiers not worth more than `min_amount`
+fn surplus_notes(
+    held: &TieredSummary,
+    target: &TieredSummary,
+    min_amount: Amount,
+) -> TieredSummary {
+    let target = target.iter().collect::<BTreeMap<_, _>>();
+    let mut surplus = TieredSummary::default();
+    for (amount, count) in held.iter() {
+        let excess = count.saturating_sub(target.get(&amount).copied().unwrap_or_default());
+        if min_amount < amount && excess > 0 {
+            surplus.inc(amount, excess);
+        }
+    }
+    surplus
+}
+
+/// Returns the notes held after removing `removed`
+fn held_without(held: &TieredSummary, removed: &TieredSummary) -> TieredSummary {
+    let removed = removed.iter().collect::<BTreeMap<_, _>>();
+    let mut remaining = TieredSummary::default();
+    for (amount, count) in held.iter() {
+        remaining.inc(
+            amount,
+            count - removed.get(&amount).copied().unwrap_or_default(),
+        );
+    }
+    remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::{Amount, TieredSummary};
+
+    use super::{balanced_fee, held_without, surplus_notes};
+
+    fn summary(notes: &[(u64, usize)]) -> TieredSummary {
+        notes
+            .iter()
+            .map(|(msats, count)| (Amount::from_msats(*msats), *count))
+            .collect()
+    }
+
+    #[test]
+    fn surplus_notes_exceed_target() {
+        let held = summary(&[(1, 10), (2, 1), (4, 3), (8, 0)]);
+        let target = summary(&[(1, 2), (2, 2), (4, 2), (8, 1)]);
+
+        assert_eq!(
+            surplus_notes(&held, &target, Amount::ZERO),
+            summary(&[(1, 8), (4, 1)])
+        );
+        // Notes not worth more than their fee aren't reissued
+        assert_eq!(
+            surplus_notes(&held, &target, Amount::from_msats(1)),
+            summary(&[(4, 1)])
+        );
+    }
+
+    #[test]
+    fn held_without_removes_notes() {
+        let held = summary(&[(1, 10), (2, 1), (4, 3)]);
+        let removed = summary(&[(1, 8), (4, 1)]);
+
+        assert_eq!(
+            held_without(&held, &removed),
+            summary(&[(1, 2), (2, 1), (4, 2)])
+        );
+    }
+
+    #[test]
+    fn balanced_fee_pays_for_every_output() {
+        let input_fee = Amount::from_msats(30);
+        let issuance_fee = Amount::from_msats(10);
+        // One output note per started 100 msats
+        let num_outputs = |amount: Amount| ((amount.msats + 99) / 100) as usize;
+
+        let fee = balanced_fee(
+            Amount::from_msats(1_000),
+            input_fee,
+            issuance_fee,
+            num_outputs,
+        )
+        .expect("Balanced transaction exists");
+        assert_eq!(
+            fee,
+            input_fee + issuance_fee * num_outputs(Amount::from_msats(1_000) - fee) as u64
+        );
+        assert_eq!(fee, Amount::from_msats(120));
+
+        assert_eq!(
+            balanced_fee(Amount::from_msats(30), input_fee, issuance_fee, num_outputs),
+            None
+        );
+    }
+}
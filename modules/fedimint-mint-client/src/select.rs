@@ -0,0 +1,542 @@
+//! Additional [`NotesSelector`] strategies on top of the greedy
+//! [`SelectNotesWithAtleastAmount`] and [`SelectNotesWithExactAmount`].
+//!
+//! All selectors in this module shuffle the notes within each tier before
+//! picking from it. Notes of the same denomination are interchangeable, so
+//! this doesn't change the outcome, but it prevents the same notes from being
+//! picked again after e.g. a cancelled out-of-band spend, which would make two
+//! otherwise unrelated spends linkable.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::bail;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{apply, async_trait_maybe_send, Amount, TieredMulti, TieredSummary};
+use futures::StreamExt;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    select_notes_from_stream, InsufficientBalanceError, NotesSelector,
+    SelectNotesWithAtleastAmount, SelectNotesWithExactAmount,
+};
+
+/// Upper bound on the number of search steps [`SelectNotesWithMinimalChange`]
+/// takes before settling for the best selection found so far
+const MINIMAL_CHANGE_MAX_STEPS: usize = 100_000;
+
+/// Built-in note selection strategies, used to choose a selector from the CLI
+/// and to persist the default selector of a [`crate::MintClientModule`].
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NotesSelectorKind {
+    /// See [`SelectNotesWithAtleastAmount`]
+    AtLeast,
+    /// See [`SelectNotesWithExactAmount`]
+    Exact,
+    /// See [`SelectNotesWithMinimalChange`], the default since avoiding change
+    /// and picking random notes makes spends harder to link
+    #[default]
+    MinimalChange,
+    /// See [`SelectNotesBalanced`]
+    Balanced,
+}
+
+impl NotesSelectorKind {
+    pub const ALL: [NotesSelectorKind; 4] = [
+        NotesSelectorKind::AtLeast,
+        NotesSelectorKind::Exact,
+        NotesSelectorKind::MinimalChange,
+        NotesSelectorKind::Balanced,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            NotesSelectorKind::AtLeast => "at-least",
+            NotesSelectorKind::Exact => "exact",
+            NotesSelectorKind::MinimalChange => "minimal-change",
+            NotesSelectorKind::Balanced => "balanced",
+        }
+    }
+}
+
+impl Display for NotesSelectorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NotesSelectorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match NotesSelectorKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+        {
+            Some(kind) => Ok(kind),
+            None => bail!(
+                "Unknown notes selector '{s}', expected one of: {}",
+                NotesSelectorKind::ALL
+                    .map(NotesSelectorKind::as_str)
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// One of the built-in selectors, created from a [`NotesSelectorKind`] by
+/// [`crate::MintClientModule::notes_selector`]
+pub enum BuiltinNotesSelector {
+    AtLeast(SelectNotesWithAtleastAmount),
+    Exact(SelectNotesWithExactAmount),
+    MinimalChange(SelectNotesWithMinimalChange),
+    Balanced(SelectNotesBalanced),
+}
+
+impl BuiltinNotesSelector {
+    /// Creates the selector of the given `kind`, `target` is only used by
+    /// [`SelectNotesBalanced`]
+    pub fn new(kind: NotesSelectorKind, target: TieredSummary) -> Self {
+        match kind {
+            NotesSelectorKind::AtLeast => {
+                BuiltinNotesSelector::AtLeast(SelectNotesWithAtleastAmount)
+            }
+            NotesSelectorKind::Exact => BuiltinNotesSelector::Exact(SelectNotesWithExactAmount),
+            NotesSelectorKind::MinimalChange => {
+                BuiltinNotesSelector::MinimalChange(SelectNotesWithMinimalChange)
+            }
+            NotesSelectorKind::Balanced => {
+                BuiltinNotesSelector::Balanced(SelectNotesBalanced { target })
+            }
+        }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<Note: Send> NotesSelector<Note> for BuiltinNotesSelector {
+    async fn select_notes(
+        &self,
+        #[cfg(not(target_family = "wasm"))] stream: impl futures::Stream<Item = (Amount, Note)> + Send,
+        #[cfg(target_family = "wasm")] stream: impl futures::Stream<Item = (Amount, Note)>,
+        requested_amount: Amount,
+        fee_per_note_input: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        match self {
+            BuiltinNotesSelector::AtLeast(selector) => {
+                selector
+                    .select_notes(stream, requested_amount, fee_per_note_input)
+                    .await
+            }
+            BuiltinNotesSelector::Exact(selector) => {
+                selector
+                    .select_notes(stream, requested_amount, fee_per_note_input)
+                    .await
+            }
+            BuiltinNotesSelector::MinimalChange(selector) => {
+                selector
+                    .select_notes(stream, requested_amount, fee_per_note_input)
+                    .await
+            }
+            BuiltinNotesSelector::Balanced(selector) => {
+                selector
+                    .select_notes(stream, requested_amount, fee_per_note_input)
+                    .await
+            }
+        }
+    }
+}
+
+/// Select notes with total amount of *at least* `request_amount` such that the
+/// amount exceeding `request_amount` (and thus the change that has to be
+/// reissued) is as small as possible, using as few notes as possible among
+/// selections with the same change.
+///
+/// For large wallets the search is bounded, in which case the best selection
+/// found so far is returned.
+pub struct SelectNotesWithMinimalChange;
+
+#[apply(async_trait_maybe_send!)]
+impl<Note: Send> NotesSelector<Note> for SelectNotesWithMinimalChange {
+    async fn select_notes(
+        &self,
+        #[cfg(not(target_family = "wasm"))] stream: impl futures::Stream<Item = (Amount, Note)> + Send,
+        #[cfg(target_family = "wasm")] stream: impl futures::Stream<Item = (Amount, Note)>,
+        requested_amount: Amount,
+        fee_per_note_input: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        if requested_amount == Amount::ZERO {
+            return Ok(TieredMulti::default());
+        }
+        let mut tiers = collect_shuffled_tiers(stream).await;
+
+        // Notes that don't cover their own fee are never worth spending
+        let spendable = tiers
+            .iter()
+            .rev()
+            .filter(|(amount, _)| **amount > fee_per_note_input)
+            .map(|(amount, notes)| ((*amount - fee_per_note_input).msats, notes.len()))
+            .collect::<Vec<_>>();
+
+        let counts = minimal_change_counts(&spendable, requested_amount.msats).ok_or(
+            InsufficientBalanceError {
+                requested_amount,
+                total_amount: Amount::from_msats(
+                    spendable.iter().map(|(v, n)| v * *n as u64).sum(),
+                ),
+            },
+        )?;
+
+        let selected_tiers = tiers
+            .keys()
+            .rev()
+            .filter(|amount| **amount > fee_per_note_input)
+            .copied()
+            .collect::<Vec<_>>();
+        let selected = selected_tiers
+            .into_iter()
+            .zip(counts)
+            .flat_map(|(amount, count)| {
+                let notes = tiers.get_mut(&amount).expect("Tier exists");
+                notes
+                    .drain(..count)
+                    .map(move |note| (amount, note))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Ok(selected)
+    }
+}
+
+/// Select notes with total amount of *at least* `request_amount`, preferring
+/// notes of tiers the wallet holds more of than `target`. This keeps the
+/// wallet close to its target denomination distribution, so future spends can
+/// be made without change and without reissuing.
+///
+/// Surplus notes are taken from the highest tiers first as long as they don't
+/// exceed the requested amount, any remaining amount is then selected like
+/// [`SelectNotesWithAtleastAmount`] does. This can result in more notes being
+/// spent than with the other selectors.
+pub struct SelectNotesBalanced {
+    /// Number of notes per tier the wallet should ideally hold
+    pub target: TieredSummary,
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<Note: Send> NotesSelector<Note> for SelectNotesBalanced {
+    async fn select_notes(
+        &self,
+        #[cfg(not(target_family = "wasm"))] stream: impl futures::Stream<Item = (Amount, Note)> + Send,
+        #[cfg(target_family = "wasm")] stream: impl futures::Stream<Item = (Amount, Note)>,
+        requested_amount: Amount,
+        fee_per_note_input: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        let mut tiers = collect_shuffled_tiers(stream).await;
+        let target = self.target.iter().collect::<BTreeMap<_, _>>();
+
+        let mut selected = vec![];
+        let mut pending_amount = requested_amount;
+        for (amount, notes) in tiers.iter_mut().rev() {
+            if *amount <= fee_per_note_input {
+                continue;
+            }
+
+            let surplus = notes
+                .len()
+                .saturating_sub(target.get(amount).copied().unwrap_or_default());
+            let value = *amount - fee_per_note_input;
+            let count = surplus.min((pending_amount.msats / value.msats) as usize);
+
+            pending_amount -= value * count as u64;
+            selected.extend(notes.drain(..count).map(|note| (*amount, note)));
+        }
+
+        let remaining = tiers
+            .into_iter()
+            .rev()
+            .flat_map(|(amount, notes)| notes.into_iter().map(move |note| (amount, note)));
+        let rest = select_notes_from_stream(
+            futures::stream::iter(remaining),
+            pending_amount,
+            fee_per_note_input,
+        )
+        .await
+        .map_err(|e| InsufficientBalanceError {
+            requested_amount,
+            total_amount: e.total_amount + (requested_amount - pending_amount),
+        })?;
+
+        Ok(selected.into_iter().chain(rest.into_iter_items()).collect())
+    }
+}
+
+/// Collects the note stream into tiers, shuffling the notes of each tier
+pub(crate) async fn collect_shuffled_tiers<Note>(
+    stream: impl futures::Stream<Item = (Amount, Note)>,
+) -> BTreeMap<Amount, Vec<Note>> {
+    let mut tiers = stream
+        .fold(
+            BTreeMap::<Amount, Vec<Note>>::new(),
+            |mut tiers, (amount, note)| async move {
+                tiers.entry(amount).or_default().push(note);
+                tiers
+            },
+        )
+        .await;
+
+    let mut rng = rand::thread_rng();
+    for notes in tiers.values_mut() {
+        notes.shuffle(&mut rng);
+    }
+
+    tiers
+}
+
+/// Given `(value, available)` pairs sorted by descending value, returns how
+/// many notes to take of each so that their total value is at least `target`
+/// with minimal excess, or `None` if all of them together are not enough
+fn minimal_change_counts(tiers: &[(u64, usize)], target: u64) -> Option<Vec<usize>> {
+    struct Search<'a> {
+        tiers: &'a [(u64, usize)],
+        /// Total value of all notes in tiers `i..`
+        suffix_values: Vec<u64>,
+        counts: Vec<usize>,
+        /// `(change, number of notes, counts)` of the best selection found
+        best: Option<(u64, usize, Vec<usize>)>,
+        steps: usize,
+    }
+
+    impl<'a> Search<'a> {
+        fn run(&mut self, tier: usize, pending: u64, num_notes: usize) {
+            self.steps += 1;
+            if self.steps > MINIMAL_CHANGE_MAX_STEPS
+                || self.suffix_values[tier] < pending
+                || matches!(self.best, Some((0, ..)))
+            {
+                return;
+            }
+
+            let (value, available) = self.tiers[tier];
+            let max_count = available.min(pending.div_ceil(value) as usize);
+            // Trying larger counts first finds selections with few notes early
+            for count in (0..=max_count).rev() {
+                self.counts[tier] = count;
+                let covered = value * count as u64;
+                if pending <= covered {
+                    let candidate = (covered - pending, num_notes + count);
+                    let improves = match &self.best {
+                        Some((change, notes, _)) => candidate < (*change, *notes),
+                        None => true,
+                    };
+                    if improves {
+                        self.best = Some((candidate.0, candidate.1, self.counts.clone()));
+                    }
+                } else if tier + 1 < self.tiers.len() {
+                    self.run(tier + 1, pending - covered, num_notes + count);
+                }
+            }
+            self.counts[tier] = 0;
+        }
+    }
+
+    let mut suffix_values = vec![0u64; tiers.len() + 1];
+    for (idx, (value, available)) in tiers.iter().enumerate().rev() {
+        suffix_values[idx] = suffix_values[idx + 1] + value * *available as u64;
+    }
+    if suffix_values[0] < target {
+        return None;
+    }
+    if target == 0 {
+        return Some(vec![0; tiers.len()]);
+    }
+
+    let mut search = Search {
+        tiers,
+        suffix_values,
+        counts: vec![0; tiers.len()],
+        best: None,
+        steps: 0,
+    };
+    search.run(0, target, 0);
+
+    Some(
+        search
+            .best
+            .map(|(_, _, counts)| counts)
+            .unwrap_or_else(|| greedy_counts(tiers, target)),
+    )
+}
+
+/// Fallback in case the bounded search didn't find any selection: takes notes
+/// from the lowest tiers upwards until the target is covered, then drops notes
+/// starting from the highest tier as long as the target stays covered
+fn greedy_counts(tiers: &[(u64, usize)], target: u64) -> Vec<usize> {
+    let mut counts = vec![0; tiers.len()];
+    let mut covered = 0;
+    for (idx, (value, available)) in tiers.iter().enumerate().rev() {
+        if target <= covered {
+            break;
+        }
+        let count = (*available).min((target - covered).div_ceil(*value) as usize);
+        counts[idx] = count;
+        covered += value * count as u64;
+    }
+    for (idx, (value, _)) in tiers.iter().enumerate() {
+        while 0 < counts[idx] && target <= covered - value {
+            counts[idx] -= 1;
+            covered -= value;
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::{Amount, TieredMulti, TieredSummary};
+
+    use super::{
+        minimal_change_counts, NotesSelectorKind, SelectNotesBalanced, SelectNotesWithMinimalChange,
+    };
+    use crate::NotesSelector;
+
+    fn note_stream(notes: Vec<(u64, usize)>) -> impl futures::Stream<Item = (Amount, usize)> {
+        let mut notes = notes
+            .into_iter()
+            .flat_map(|(amount, number)| {
+                (0..number).map(move |idx| (Amount::from_sats(amount), idx))
+            })
+            .collect::<Vec<_>>();
+        notes.sort_by(|a, b| b.cmp(a));
+        futures::stream::iter(notes)
+    }
+
+    fn summary(notes: &TieredMulti<usize>) -> Vec<(u64, usize)> {
+        notes
+            .summary()
+            .iter()
+            .map(|(amount, count)| (amount.msats / 1000, count))
+            .collect()
+    }
+
+    #[test]
+    fn minimal_change_counts_finds_exact_combination() {
+        // Greedy would take 8 + 4 and fail to find an exact match
+        assert_eq!(
+            minimal_change_counts(&[(8, 1), (5, 2), (4, 1)], 10),
+            Some(vec![0, 2, 0])
+        );
+        assert_eq!(minimal_change_counts(&[(8, 1), (4, 1)], 13), None);
+        assert_eq!(
+            minimal_change_counts(&[(8, 1), (4, 1)], 0),
+            Some(vec![0, 0])
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn minimal_change_prefers_fewer_notes() {
+        let selected = SelectNotesWithMinimalChange
+            .select_notes(
+                note_stream(vec![(1, 10), (2, 10), (8, 1)]),
+                Amount::from_sats(8),
+                Amount::ZERO,
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary(&selected), vec![(8, 1)]);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn minimal_change_accounts_for_fees() {
+        let selected = SelectNotesWithMinimalChange
+            .select_notes(
+                note_stream(vec![(1, 10), (4, 2)]),
+                Amount::from_sats(6),
+                Amount::from_sats(1),
+            )
+            .await
+            .unwrap();
+        // Each 4 sat note is worth 3 sats after fees
+        assert_eq!(summary(&selected), vec![(4, 2)]);
+
+        let error = SelectNotesWithMinimalChange
+            .select_notes(
+                note_stream(vec![(1, 10), (4, 2)]),
+                Amount::from_sats(7),
+                Amount::from_sats(1),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Insufficient balance"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn balanced_spends_over_represented_tiers() {
+        let target = [(1, 2), (2, 2), (4, 2)]
+            .into_iter()
+            .map(|(amount, count)| (Amount::from_sats(amount), count))
+            .collect::<TieredSummary>();
+        let selector = SelectNotesBalanced { target };
+
+        // The 1 sat tier holds four surplus notes
+        let selected = selector
+            .select_notes(
+                note_stream(vec![(1, 6), (2, 2), (4, 2)]),
+                Amount::from_sats(4),
+                Amount::ZERO,
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary(&selected), vec![(1, 4)]);
+
+        // Without enough surplus the rest is selected greedily
+        let selected = selector
+            .select_notes(
+                note_stream(vec![(1, 3), (2, 2), (4, 2)]),
+                Amount::from_sats(5),
+                Amount::ZERO,
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary(&selected), vec![(1, 1), (4, 1)]);
+
+        let error = selector
+            .select_notes(
+                note_stream(vec![(1, 3), (2, 2)]),
+                Amount::from_sats(8),
+                Amount::ZERO,
+            )
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("only 7000 msat available"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn selectors_randomize_notes_within_tier() {
+        let mut seen = std::collections::BTreeSet::new();
+        for _ in 0..64 {
+            let selected = SelectNotesWithMinimalChange
+                .select_notes(
+                    note_stream(vec![(1, 16)]),
+                    Amount::from_sats(1),
+                    Amount::ZERO,
+                )
+                .await
+                .unwrap();
+            seen.extend(selected.into_iter_items().map(|(_, idx)| idx));
+        }
+        assert!(1 < seen.len());
+    }
+
+    #[test]
+    fn notes_selector_kind_roundtrip() {
+        for kind in NotesSelectorKind::ALL {
+            assert_eq!(kind.to_string().parse::<NotesSelectorKind>().unwrap(), kind);
+        }
+        assert!("greedy".parse::<NotesSelectorKind>().is_err());
+    }
+}
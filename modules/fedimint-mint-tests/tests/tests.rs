@@ -1,22 +1,38 @@
+use std::collections::BTreeSet;
 use std::io::Cursor;
+use std::sync::Arc;
 use std::time::Duration;
 
 use fedimint_client::backup::{ClientBackup, Metadata};
+use fedimint_client::module::init::ClientModuleInitRegistry;
+use fedimint_client::multi_federation::MultiFederationClient;
+use fedimint_client::oplog::{OperationDirection, OperationLogExportFormat, OperationLogFilter};
+use fedimint_client::policy::{SpendingPolicy, SpendingPolicyError};
+use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
 use fedimint_core::config::EmptyGenParams;
+use fedimint_core::core::{IntoDynInstance, OperationId};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
 use fedimint_core::task::sleep_in_test;
 use fedimint_core::util::NextOrPending;
-use fedimint_core::{sats, Amount};
+use fedimint_core::{sats, Amount, OutPoint};
+use fedimint_dummy_client::states::DummyStateMachine;
 use fedimint_dummy_client::{DummyClientInit, DummyClientModule};
 use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_common::DummyOutput;
 use fedimint_dummy_server::DummyInit;
 use fedimint_logging::LOG_TEST;
 use fedimint_mint_client::{
-    MintClientInit, MintClientModule, OOBNotes, ReissueExternalNotesState, SpendOOBState,
+    MintClientInit, MintClientModule, MintOperationMeta, MintOperationMetaVariant,
+    MultiFederationMintExt, NoteRebalancingConfig, NotesSelectorKind, OOBNotes,
+    ReissueExternalNotesState, SpendOOBState,
 };
 use fedimint_mint_common::config::{FeeConsensus, MintGenParams, MintGenParamsConsensus};
 use fedimint_mint_server::MintInit;
 use fedimint_testing::fixtures::{Fixtures, TIMEOUT};
 use futures::StreamExt;
+use rand::thread_rng;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
@@ -84,6 +100,70 @@ async fn sends_ecash_out_of_band() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn exports_operation_history() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let client1_dummy_module = client1.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = client1_dummy_module.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let client1_mint = client1.get_first_module::<MintClientModule>();
+    let client2_mint = client2.get_first_module::<MintClientModule>();
+    let (spend_op, notes) = client1_mint
+        .spend_notes(sats(750), TIMEOUT, false, ())
+        .await?;
+    let reissue_op = client2_mint.reissue_external_notes(notes, ()).await?;
+    let mut sub = client2_mint
+        .subscribe_reissue_external_notes(reissue_op)
+        .await?
+        .into_stream();
+    while let Some(state) = sub.next().await {
+        debug!(target: LOG_TEST, ?state, "Reissuing notes");
+    }
+
+    let mut csv = vec![];
+    let records = client2
+        .export_operation_log(
+            &OperationLogFilter::default(),
+            OperationLogExportFormat::Csv,
+            &mut csv,
+        )
+        .await?;
+    assert_eq!(records, 1);
+    let csv = String::from_utf8(csv)?;
+    let row = csv.lines().nth(1).expect("One record was exported");
+    assert!(row.starts_with(&reissue_op.to_string()));
+    assert!(row.contains(&format!(
+        ",mint,reissuance,success,incoming,{},",
+        sats(750).msats
+    )));
+
+    let mut module_inits = ClientModuleInitRegistry::new();
+    module_inits.attach(MintClientInit);
+    let mint_records = client1
+        .operation_log()
+        .export_records(
+            client1.federation_id(),
+            &module_inits,
+            &OperationLogFilter {
+                operation_module_kind: Some("mint".to_string()),
+                ..Default::default()
+            },
+            10,
+            None,
+        )
+        .await?;
+    assert_eq!(mint_records.len(), 1);
+    let (_, mint_record) = &mint_records[0];
+    assert_eq!(mint_record.operation_id, spend_op);
+    assert_eq!(mint_record.direction, Some(OperationDirection::Outgoing));
+    assert_eq!(mint_record.amount, Some(sats(750)));
+    assert_eq!(mint_record.raw_data, None);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 #[ignore] // TODO: flaky https://github.com/fedimint/fedimint/issues/4508
 async fn sends_ecash_oob_highly_parallel() -> anyhow::Result<()> {
@@ -262,6 +342,206 @@ async fn sends_ecash_out_of_band_cancel() -> anyhow::Result<()> {
     panic!("Did not receive refund in time");
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_ecash_with_default_notes_selector() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    let mint_module = client.get_first_module::<MintClientModule>();
+    assert_eq!(
+        mint_module.default_notes_selector().await,
+        NotesSelectorKind::MinimalChange
+    );
+    mint_module
+        .set_default_notes_selector(NotesSelectorKind::Balanced)
+        .await;
+    assert_eq!(
+        mint_module.default_notes_selector().await,
+        NotesSelectorKind::Balanced
+    );
+
+    let (_, notes) = mint_module
+        .spend_notes(sats(250), TIMEOUT, false, ())
+        .await?;
+    assert!(sats(250) <= notes.total_amount());
+    assert_eq!(
+        client.get_balance().await,
+        sats(1000) - notes.total_amount()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rebalances_notes() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    let mint_module = client.get_first_module::<MintClientModule>();
+    assert_eq!(mint_module.note_rebalancing_config().await, None);
+
+    // Notes get issued for two notes per denomination, so targeting four leaves
+    // the higher denominations in surplus
+    let config = NoteRebalancingConfig {
+        notes_per_denomination: 4,
+        max_surplus_notes: u16::MAX,
+        max_fee: EXPECTED_MAXIMUM_FEE,
+        check_interval_secs: 1,
+    };
+    assert_eq!(mint_module.rebalance_notes(&config).await?, None);
+
+    let config = NoteRebalancingConfig {
+        max_surplus_notes: 0,
+        ..config
+    };
+    let op = mint_module
+        .rebalance_notes(&config)
+        .await?
+        .expect("Notes are imbalanced");
+    let meta = client
+        .operation_log()
+        .get_operation(op)
+        .await
+        .expect("Operation exists")
+        .meta::<MintOperationMeta>();
+    let MintOperationMetaVariant::Rebalance {
+        txid,
+        out_point_indices,
+        fee,
+        ..
+    } = meta.variant
+    else {
+        panic!("Unexpected operation variant");
+    };
+    assert!(fee <= EXPECTED_MAXIMUM_FEE);
+    for out_idx in out_point_indices {
+        mint_module
+            .await_output_finalized(op, OutPoint { txid, out_idx })
+            .await?;
+    }
+    assert_eq!(client.get_balance().await, sats(1000) - fee);
+
+    // The background task rebalances once enabled
+    let config = NoteRebalancingConfig {
+        notes_per_denomination: 8,
+        ..config
+    };
+    mint_module.set_note_rebalancing_config(Some(config)).await;
+    assert_eq!(mint_module.note_rebalancing_config().await, Some(config));
+    for _ in 0..200 {
+        let rebalancings = client
+            .operation_log()
+            .list_operations(10, None)
+            .await
+            .into_iter()
+            .filter(|(_, entry)| {
+                entry.operation_module_kind() == "mint"
+                    && matches!(
+                        entry.meta::<MintOperationMeta>().variant,
+                        MintOperationMetaVariant::Rebalance { .. }
+                    )
+            })
+            .count();
+        if rebalancings == 2 {
+            return Ok(());
+        }
+        sleep_in_test("waiting for note rebalancing", Duration::from_millis(100)).await;
+    }
+
+    panic!("Notes were not rebalanced in time");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_federation_client_routes_notes() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed1 = fixtures.new_default_fed().await;
+    let fed2 = fixtures.new_default_fed().await;
+
+    let mut module_inits = ClientModuleInitRegistry::new();
+    module_inits.attach(MintClientInit);
+    module_inits.attach(DummyClientInit);
+    let db = Database::from(MemDatabase::new());
+    let root_secret = PlainRootSecretStrategy::to_root_secret(&PlainRootSecretStrategy::random(
+        &mut thread_rng(),
+    ));
+    let mut multi_client =
+        MultiFederationClient::open(db.clone(), module_inits.clone(), 0, root_secret.clone())
+            .await?;
+    let client1 = multi_client.join(&fed1.invite_code()).await?;
+    multi_client.join(&fed2.invite_code()).await?;
+    assert!(multi_client.join(&fed1.invite_code()).await.is_err());
+
+    let (op, outpoint) = client1
+        .get_first_module::<DummyClientModule>()
+        .print_money(sats(1000))
+        .await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    // Notes of the second federation are reissued by its client
+    let sender = fed2.new_client().await;
+    let (op, outpoint) = sender
+        .get_first_module::<DummyClientModule>()
+        .print_money(sats(500))
+        .await?;
+    sender.await_primary_module_output(op, outpoint).await?;
+    let (_, notes) = sender
+        .get_first_module::<MintClientModule>()
+        .spend_notes(sats(500), TIMEOUT, false, ())
+        .await?;
+    let (federation_id, op) = multi_client.reissue_external_notes(notes, ()).await?;
+    assert_eq!(federation_id, fed2.id());
+    let mut updates = multi_client
+        .get(&federation_id)
+        .expect("Federation was joined")
+        .get_first_module::<MintClientModule>()
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    while let Some(update) = updates.next().await {
+        if let ReissueExternalNotesState::Failed(e) = update {
+            panic!("Reissue failed: {e}");
+        }
+    }
+
+    // Spending picks the requested federation
+    let (_, notes) = multi_client
+        .spend_notes(fed1.id(), sats(100), TIMEOUT, false, ())
+        .await?;
+    assert_eq!(notes.federation_id_prefix(), fed1.id().to_prefix());
+
+    let balances = multi_client.balances().await;
+    assert_eq!(balances[&fed1.id()], sats(1000) - notes.total_amount());
+    assert!(balances[&fed2.id()] >= sats(500) - EXPECTED_MAXIMUM_FEE);
+    assert_eq!(
+        multi_client.total_balance().await,
+        balances.values().copied().sum()
+    );
+
+    let operations = multi_client.list_operations(10, None).await;
+    assert_eq!(operations.len(), 3);
+    assert!(operations
+        .windows(2)
+        .all(|ops| ops[1].1.creation_time <= ops[0].1.creation_time));
+    assert_eq!(operations[1].0, fed2.id());
+
+    // Reopening the database restores the clients of all joined federations
+    drop((client1, multi_client));
+    let multi_client = MultiFederationClient::open(db, module_inits, 0, root_secret).await?;
+    assert_eq!(
+        multi_client.federation_ids().collect::<Vec<_>>(),
+        balances.keys().copied().collect::<Vec<_>>()
+    );
+    assert_eq!(multi_client.balances().await, balances);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn error_zero_value_oob_spend() -> anyhow::Result<()> {
     // Print notes for client1
@@ -307,6 +587,63 @@ async fn error_zero_value_oob_receive() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn spending_policy_checks_module_kinds_of_outputs() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    let dummy_instance = client
+        .get_first_instance(&fedimint_dummy_common::KIND)
+        .expect("dummy module is configured");
+    // Sends funds through a dummy output while claiming to be a Lightning
+    // payment
+    let submit_ln_operation = || {
+        let output = ClientOutput {
+            output: DummyOutput {
+                amount: sats(100),
+                account: dummy_module.account(),
+            },
+            amount: sats(100),
+            state_machines: Arc::new(|_, _| Vec::<DummyStateMachine>::new()),
+        };
+        client.finalize_and_submit_transaction(
+            OperationId::new_random(),
+            "ln",
+            |_, _| (),
+            TransactionBuilder::new().with_output(output.into_dyn(dummy_instance)),
+        )
+    };
+
+    client
+        .set_spending_policy(Some(SpendingPolicy {
+            allowed_module_kinds: Some(BTreeSet::from(["ln".to_string()])),
+            ..Default::default()
+        }))
+        .await;
+    let error = submit_ln_operation()
+        .await
+        .expect_err("Dummy outputs are not allowed");
+    assert_eq!(
+        error.downcast_ref::<SpendingPolicyError>(),
+        Some(&SpendingPolicyError::ModuleKindNotAllowed {
+            module_kind: "dummy".to_string()
+        })
+    );
+
+    client
+        .set_spending_policy(Some(SpendingPolicy {
+            allowed_module_kinds: Some(BTreeSet::from(["dummy".to_string()])),
+            ..Default::default()
+        }))
+        .await;
+    submit_ln_operation().await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod fedimint_migration_tests {
     use std::collections::BTreeMap;
@@ -328,8 +665,9 @@ mod fedimint_migration_tests {
     use fedimint_mint_client::backup::recovery::{MintRecovery, MintRecoveryState};
     use fedimint_mint_client::backup::{EcashBackup, EcashBackupV0};
     use fedimint_mint_client::client_db::{
-        CancelledOOBSpendKey, CancelledOOBSpendKeyPrefix, NextECashNoteIndexKey,
-        NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix, RecoveryStateKey,
+        CancelledOOBSpendKey, CancelledOOBSpendKeyPrefix, DefaultNotesSelectorKey,
+        NextECashNoteIndexKey, NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix,
+        NoteRebalancingConfigKey, RecoveryStateKey,
     };
     use fedimint_mint_client::output::NoteIssuanceRequest;
     use fedimint_mint_client::{MintClientInit, MintClientModule, NoteIndex, SpendableNote};
@@ -661,6 +999,17 @@ mod fedimint_migration_tests {
                             );
                             info!("Validated RecoveryFinalized");
                         }
+                        fedimint_mint_client::client_db::DbKeyPrefix::DefaultNotesSelector => {
+                            // Not present in the v0 database, only check that it can be read
+                            let default_selector = dbtx.get_value(&DefaultNotesSelectorKey).await;
+                            info!(?default_selector, "Validated DefaultNotesSelector");
+                        }
+                        fedimint_mint_client::client_db::DbKeyPrefix::NoteRebalancingConfig => {
+                            // Not present in the v0 database, only check that it can be read
+                            let rebalancing_config =
+                                dbtx.get_value(&NoteRebalancingConfigKey).await;
+                            info!(?rebalancing_config, "Validated NoteRebalancingConfig");
+                        }
                     }
                 }
 
@@ -1,10 +1,12 @@
 use bitcoin::Address;
 use fedimint_api_client::api::{FederationApiExt, FederationResult, IModuleFederationApi};
-use fedimint_core::module::ApiRequestErased;
+use fedimint_core::module::{ApiAuth, ApiRequestErased};
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::{apply, async_trait_maybe_send};
-use fedimint_wallet_common::endpoint_constants::{BLOCK_COUNT_ENDPOINT, PEG_OUT_FEES_ENDPOINT};
-use fedimint_wallet_common::PegOutFees;
+use fedimint_wallet_common::endpoint_constants::{
+    BLOCK_COUNT_ENDPOINT, CONSENSUS_HISTORY_ENDPOINT, PEG_OUT_FEES_ENDPOINT, UTXO_STATS_ENDPOINT,
+};
+use fedimint_wallet_common::{ConsensusHistoryEntry, PegOutFees, UtxoStats};
 
 #[apply(async_trait_maybe_send!)]
 pub trait WalletFederationApi {
@@ -14,6 +16,12 @@ pub trait WalletFederationApi {
         address: &Address,
         amount: bitcoin::Amount,
     ) -> FederationResult<Option<PegOutFees>>;
+    async fn fetch_utxo_stats(&self, auth: ApiAuth) -> FederationResult<UtxoStats>;
+    async fn fetch_consensus_history(
+        &self,
+        before: Option<u64>,
+        limit: u32,
+    ) -> FederationResult<Vec<ConsensusHistoryEntry>>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -40,4 +48,21 @@ where
         )
         .await
     }
+
+    async fn fetch_utxo_stats(&self, auth: ApiAuth) -> FederationResult<UtxoStats> {
+        self.request_admin(UTXO_STATS_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn fetch_consensus_history(
+        &self,
+        before: Option<u64>,
+        limit: u32,
+    ) -> FederationResult<Vec<ConsensusHistoryEntry>> {
+        self.request_current_consensus(
+            CONSENSUS_HISTORY_ENDPOINT.to_string(),
+            ApiRequestErased::new((before, limit)),
+        )
+        .await
+    }
 }
@@ -25,8 +25,11 @@ use crate::{WalletClientContext, WalletClientStates};
 
 const TRANSACTION_STATUS_FETCH_INTERVAL: Duration = Duration::from_secs(1);
 
+/// Upper bound of the interval in which a watched deposit address is polled
+/// for further deposits
+const MAX_DEPOSIT_WATCH_INTERVAL: Duration = Duration::from_secs(60);
+
 // FIXME: deal with RBF
-// FIXME: deal with multiple deposits
 #[cfg_attr(doc, aquamarine::aquamarine)]
 /// The state machine driving forward a deposit (aka peg-in).
 ///
@@ -37,6 +40,16 @@ const TRANSACTION_STATUS_FETCH_INTERVAL: Duration = Duration::from_secs(1);
 ///     AwaitingConfirmations -- "Retransmit seen tx (planned)" --> AwaitingConfirmations
 ///     Created -- "No transactions seen for [time]" --> Timeout["Timed out"]
 /// ```
+///
+/// Once the first transaction is seen a second state machine is spawned that
+/// keeps watching the address until it expires and claims every further
+/// confirmed deposit to it.
+///
+/// ```mermaid
+/// graph LR
+///     Watching["Watching for deposits"] -- Unclaimed deposit confirmed --> Watching
+///     Watching -- "Address expired" --> Stopped["Watching stopped"]
+/// ```
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct DepositStateMachine {
     pub(crate) operation_id: OperationId,
@@ -59,8 +72,17 @@ impl State for DepositStateMachine {
                             context.clone(),
                             created_state.tweak_key,
                         ),
-                        |_db, (btc_tx, out_idx), old_state| {
-                            Box::pin(transition_tx_seen(old_state, btc_tx, out_idx))
+                        {
+                            let global_context = global_context.clone();
+                            move |dbtx, (btc_tx, out_idx), old_state| {
+                                Box::pin(transition_tx_seen(
+                                    dbtx,
+                                    global_context.clone(),
+                                    old_state,
+                                    btc_tx,
+                                    out_idx,
+                                ))
+                            }
                         },
                     ),
                     StateTransition::new(
@@ -93,6 +115,35 @@ impl State for DepositStateMachine {
             DepositStates::TimedOut(_) => {
                 vec![]
             }
+            DepositStates::WatchingForDeposits(watching_state) => {
+                let global_context = global_context.clone();
+                vec![
+                    StateTransition::new(
+                        await_unclaimed_deposit_confirmed(
+                            context.clone(),
+                            global_context.clone(),
+                            watching_state.clone(),
+                        ),
+                        move |dbtx, (btc_transaction, out_idx, txout_proof), old_state| {
+                            Box::pin(transition_unclaimed_deposit_confirmed(
+                                dbtx,
+                                global_context.clone(),
+                                old_state,
+                                btc_transaction,
+                                out_idx,
+                                txout_proof,
+                            ))
+                        },
+                    ),
+                    StateTransition::new(
+                        await_deposit_address_timeout(watching_state.timeout_at),
+                        |_db, (), old_state| Box::pin(transition_watching_stopped(old_state)),
+                    ),
+                ]
+            }
+            DepositStates::WatchingStopped(_) => {
+                vec![]
+            }
         }
     }
 
@@ -135,9 +186,10 @@ async fn await_created_btc_transaction_submitted(
             .await
         {
             Ok(received) => {
-                // TODO: fix
+                // Further transactions are picked up by the watcher spawned once the first one
+                // is seen
                 if received.len() > 1 {
-                    warn!("More than one transaction was sent to deposit address, only considering the first one");
+                    debug!("More than one transaction was sent to deposit address, only considering the first one");
                 }
 
                 if let Some(transaction) = received.into_iter().next() {
@@ -170,6 +222,8 @@ async fn await_created_btc_transaction_submitted(
 }
 
 async fn transition_tx_seen(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: DynGlobalClientContext,
     old_state: DepositStateMachine,
     btc_transaction: bitcoin::Transaction,
     out_idx: u32,
@@ -180,14 +234,34 @@ async fn transition_tx_seen(
     } = old_state;
 
     match old_state {
-        DepositStates::Created(created_state) => DepositStateMachine {
-            operation_id,
-            state: DepositStates::WaitingForConfirmations(WaitingForConfirmationsDepositState {
-                tweak_key: created_state.tweak_key,
-                btc_transaction,
-                out_idx,
-            }),
-        },
+        DepositStates::Created(created_state) => {
+            // The deposit we are about to claim is excluded from the watcher, it only
+            // picks up further deposits to the same address
+            let watcher = DepositStateMachine {
+                operation_id,
+                state: DepositStates::WatchingForDeposits(WatchingForDepositsState {
+                    tweak_key: created_state.tweak_key,
+                    timeout_at: created_state.timeout_at,
+                    claimed: vec![bitcoin::OutPoint::new(btc_transaction.txid(), out_idx)],
+                    claim: None,
+                }),
+            };
+            global_context
+                .add_state_machine(dbtx, WalletClientStates::Deposit(watcher))
+                .await
+                .expect("Watcher state is unique for the operation");
+
+            DepositStateMachine {
+                operation_id,
+                state: DepositStates::WaitingForConfirmations(
+                    WaitingForConfirmationsDepositState {
+                        tweak_key: created_state.tweak_key,
+                        btc_transaction,
+                        out_idx,
+                    },
+                ),
+            }
+        }
         state => panic!("Invalid previous state: {state:?}"),
     }
 }
@@ -296,11 +370,37 @@ async fn transition_btc_tx_confirmed(
         _ => panic!("Invalid previous state"),
     };
 
-    let pegin_proof = PegInProof::new(
-        txout_proof,
+    let claiming_state = claim_deposit(
+        dbtx,
+        &global_context,
+        awaiting_confirmation_state.tweak_key,
         awaiting_confirmation_state.btc_transaction,
         awaiting_confirmation_state.out_idx,
-        awaiting_confirmation_state.tweak_key.public_key(),
+        txout_proof,
+    )
+    .await;
+
+    DepositStateMachine {
+        operation_id: old_state.operation_id,
+        state: DepositStates::Claiming(claiming_state),
+    }
+}
+
+/// Claims the deposit output `out_idx` of `btc_transaction` in a new fedimint
+/// transaction
+async fn claim_deposit(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: &DynGlobalClientContext,
+    tweak_key: KeyPair,
+    btc_transaction: bitcoin::Transaction,
+    out_idx: u32,
+    txout_proof: TxOutProof,
+) -> ClaimingDepositState {
+    let pegin_proof = PegInProof::new(
+        txout_proof,
+        btc_transaction,
+        out_idx,
+        tweak_key.public_key(),
     )
     .expect("TODO: handle API returning faulty proofs");
 
@@ -310,18 +410,178 @@ async fn transition_btc_tx_confirmed(
 
     let client_input = ClientInput::<WalletInput, WalletClientStates> {
         input: wallet_input,
-        keys: vec![awaiting_confirmation_state.tweak_key],
+        keys: vec![tweak_key],
         amount,
         state_machines: Arc::new(|_, _| vec![]),
     };
 
     let (fm_txid, change) = global_context.claim_input(dbtx, client_input).await;
 
+    ClaimingDepositState {
+        transaction_id: fm_txid,
+        change,
+    }
+}
+
+/// Polls the deposit address until an output to it that wasn't claimed yet is
+/// confirmed according to the federation's consensus block count
+#[instrument(skip_all, level = "debug")]
+async fn await_unclaimed_deposit_confirmed(
+    context: WalletClientContext,
+    global_context: DynGlobalClientContext,
+    watching_state: WatchingForDepositsState,
+) -> (bitcoin::Transaction, u32, TxOutProof) {
+    let script = context
+        .wallet_descriptor
+        .tweak(&watching_state.tweak_key.public_key(), &context.secp)
+        .script_pubkey();
+
+    loop {
+        match context.rpc.watch_script_history(&script).await {
+            Ok(_) => break,
+            Err(e) => warn!("Error while registering deposit address to watch: {e}"),
+        }
+        sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
+    }
+
+    for attempt in 0u32.. {
+        sleep(cmp::min(
+            TRANSACTION_STATUS_FETCH_INTERVAL * attempt,
+            MAX_DEPOSIT_WATCH_INTERVAL,
+        ))
+        .await;
+
+        let consensus_block_count = match global_context
+            .module_api()
+            .fetch_consensus_block_count()
+            .await
+        {
+            Ok(consensus_block_count) => consensus_block_count,
+            Err(e) => {
+                warn!("Failed to fetch consensus block count from federation: {e}");
+                continue;
+            }
+        };
+
+        let received = match context.rpc.get_script_history(&script).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Error fetching transaction history for {script:?}: {e}");
+                continue;
+            }
+        };
+
+        for transaction in received {
+            let transaction = bitcoin30_to_bitcoin29_transaction(&transaction);
+            let txid = transaction.txid();
+
+            let Some(out_idx) = transaction
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(_, output)| {
+                    bitcoin29_to_bitcoin30_script(output.script_pubkey.clone()) == script
+                })
+                .map(|(idx, _)| idx as u32)
+                .find(|idx| {
+                    !watching_state
+                        .claimed
+                        .contains(&bitcoin::OutPoint::new(txid, *idx))
+                })
+            else {
+                continue;
+            };
+
+            let confirmation_block_count = match context
+                .rpc
+                .get_tx_block_height(&bitcoin29_to_bitcoin30_txid(txid))
+                .await
+            {
+                Ok(confirmation_height) => confirmation_height.map(|height| height + 1),
+                Err(e) => {
+                    warn!("Failed to fetch confirmation height: {e:?}");
+                    continue;
+                }
+            };
+
+            if !confirmation_block_count
+                .map(|confirmation_block_count| consensus_block_count >= confirmation_block_count)
+                .unwrap_or(false)
+            {
+                trace!(%txid, ?confirmation_block_count, consensus_block_count, "Further deposit not confirmed yet");
+                continue;
+            }
+
+            match context
+                .rpc
+                .get_txout_proof(bitcoin29_to_bitcoin30_txid(txid))
+                .await
+            {
+                Ok(txout_proof) => {
+                    debug!(%txid, out_idx, "Found further deposit to claim");
+                    return (transaction, out_idx, txout_proof);
+                }
+                Err(e) => {
+                    warn!("Failed to fetch transaction proof: {e:?}");
+                }
+            }
+        }
+    }
+
+    unreachable!()
+}
+
+async fn transition_unclaimed_deposit_confirmed(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: DynGlobalClientContext,
+    old_state: DepositStateMachine,
+    btc_transaction: bitcoin::Transaction,
+    out_idx: u32,
+    txout_proof: TxOutProof,
+) -> DepositStateMachine {
+    let watching_state = match old_state.state {
+        DepositStates::WatchingForDeposits(s) => s,
+        _ => panic!("Invalid previous state"),
+    };
+
+    let mut claimed = watching_state.claimed;
+    claimed.push(bitcoin::OutPoint::new(btc_transaction.txid(), out_idx));
+
+    let claiming_state = claim_deposit(
+        dbtx,
+        &global_context,
+        watching_state.tweak_key,
+        btc_transaction.clone(),
+        out_idx,
+        txout_proof,
+    )
+    .await;
+
     DepositStateMachine {
         operation_id: old_state.operation_id,
-        state: DepositStates::Claiming(ClaimingDepositState {
-            transaction_id: fm_txid,
-            change,
+        state: DepositStates::WatchingForDeposits(WatchingForDepositsState {
+            tweak_key: watching_state.tweak_key,
+            timeout_at: watching_state.timeout_at,
+            claimed,
+            claim: Some(Box::new(DepositClaim {
+                btc_transaction,
+                out_idx,
+                claiming_state,
+            })),
+        }),
+    }
+}
+
+async fn transition_watching_stopped(old_state: DepositStateMachine) -> DepositStateMachine {
+    let watching_state = match old_state.state {
+        DepositStates::WatchingForDeposits(s) => s,
+        _ => panic!("Invalid previous state"),
+    };
+
+    DepositStateMachine {
+        operation_id: old_state.operation_id,
+        state: DepositStates::WatchingStopped(WatchingStoppedDepositState {
+            claimed: watching_state.claimed,
         }),
     }
 }
@@ -332,6 +592,8 @@ pub enum DepositStates {
     WaitingForConfirmations(WaitingForConfirmationsDepositState),
     Claiming(ClaimingDepositState),
     TimedOut(TimedOutDepositState),
+    WatchingForDeposits(WatchingForDepositsState),
+    WatchingStopped(WatchingStoppedDepositState),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
@@ -362,3 +624,26 @@ pub struct ClaimingDepositState {
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct TimedOutDepositState {}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct WatchingForDepositsState {
+    pub(crate) tweak_key: KeyPair,
+    /// Time at which the deposit address stops being watched
+    pub(crate) timeout_at: SystemTime,
+    /// Deposit outputs that were already claimed
+    pub(crate) claimed: Vec<bitcoin::OutPoint>,
+    /// Deposit claimed when entering this state, if any
+    pub(crate) claim: Option<Box<DepositClaim>>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct DepositClaim {
+    pub(crate) btc_transaction: bitcoin::Transaction,
+    pub(crate) out_idx: u32,
+    pub(crate) claiming_state: ClaimingDepositState,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct WatchingStoppedDepositState {
+    pub(crate) claimed: Vec<bitcoin::OutPoint>,
+}
@@ -0,0 +1,741 @@
+//! Helpers for funding deposits from external wallets, e.g. hardware wallets,
+//! that need more than a bare deposit address.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use anyhow::{bail, ensure, Context};
+use bitcoin::hashes::Hash as _;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+use bitcoin::util::psbt::{Input, PartiallySignedTransaction};
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
+use bitcoin::{Address, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut};
+use fedimint_bitcoind::DynBitcoindRpc;
+use fedimint_core::bitcoin_migration::{
+    bitcoin29_to_bitcoin30_script, bitcoin30_to_bitcoin29_script,
+    bitcoin30_to_bitcoin29_secp256k1_public_key, bitcoin30_to_bitcoin29_transaction,
+};
+use fedimint_core::Feerate;
+use miniscript::bitcoin::hashes::Hash as _;
+use miniscript::descriptor::{DefiniteDescriptorKey, DescriptorPublicKey};
+use miniscript::psbt::PsbtInputExt;
+use miniscript::Descriptor;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// Number of consecutive unused addresses after which we stop scanning a
+/// descriptor for UTXOs
+const DESCRIPTOR_GAP_LIMIT: u32 = 20;
+
+/// Weight of the non-witness part of an input: outpoint, sequence and an empty
+/// `script_sig`
+const INPUT_BASE_WEIGHT: u64 = (32 + 4 + 4 + 1) * 4;
+
+/// Characters percent-encoded in BIP 21 parameter values, which are all but
+/// the unreserved characters of RFC 3986
+const BIP21_PARAM_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Creates a [BIP 21](https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki)
+/// URI for `address` that wallets can pre-fill the payment from
+pub fn bip21_uri(
+    address: &Address,
+    amount: Option<bitcoin::Amount>,
+    label: Option<&str>,
+) -> String {
+    let mut params = vec![];
+    if let Some(amount) = amount {
+        params.push(format!(
+            "amount={}",
+            amount.to_string_in(bitcoin::Denomination::Bitcoin)
+        ));
+    }
+    if let Some(label) = label {
+        params.push(format!(
+            "label={}",
+            utf8_percent_encode(label, BIP21_PARAM_VALUE)
+        ));
+    }
+
+    if params.is_empty() {
+        format!("bitcoin:{address}")
+    } else {
+        format!("bitcoin:{address}?{}", params.join("&"))
+    }
+}
+
+/// Source of the UTXOs funding a deposit PSBT
+#[derive(Debug, Clone)]
+pub enum DepositFunding {
+    /// Spend all of the given UTXOs, paying what is left after the deposit and
+    /// fees to `change_address`
+    Utxos {
+        utxos: Vec<ExternalUtxo>,
+        change_address: Address,
+    },
+    /// Select UTXOs from the addresses derived from the receive and change
+    /// descriptors of a wallet. Change is paid to the first unused address of
+    /// the `change` descriptor.
+    Descriptor {
+        receive: Box<Descriptor<DescriptorPublicKey>>,
+        change: Box<Descriptor<DescriptorPublicKey>>,
+    },
+}
+
+impl DepositFunding {
+    /// Parses an output descriptor of the external wallet, keys have to be
+    /// public. Descriptors with wildcard have to describe both the receive
+    /// and the change addresses, e.g.
+    /// `wpkh([d34db33f/84'/0'/0']xpub.../<0;1>/*)`.
+    pub fn from_descriptor_str(descriptor: &str) -> anyhow::Result<Self> {
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(descriptor)
+            .context("Invalid output descriptor")?;
+
+        if !descriptor.has_wildcard() {
+            return Ok(DepositFunding::Descriptor {
+                receive: Box::new(descriptor.clone()),
+                change: Box::new(descriptor),
+            });
+        }
+
+        ensure!(
+            descriptor.is_multipath(),
+            "The descriptor has to derive both receive and change addresses, e.g. .../<0;1>/*"
+        );
+        let Ok([receive, change]) = <[_; 2]>::try_from(
+            descriptor
+                .into_single_descriptors()
+                .context("Invalid multipath descriptor")?,
+        ) else {
+            bail!("The descriptor has to derive exactly two chains, receive and change addresses");
+        };
+
+        Ok(DepositFunding::Descriptor {
+            receive: Box::new(receive),
+            change: Box::new(change),
+        })
+    }
+}
+
+/// A UTXO owned by an external wallet
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExternalUtxo {
+    pub outpoint: bitcoin::OutPoint,
+    pub txout: TxOut,
+}
+
+/// A UTXO selected to fund a deposit together with what is needed to spend
+/// it
+#[derive(Debug, Clone)]
+struct FundingUtxo {
+    outpoint: bitcoin::OutPoint,
+    txout: TxOut,
+    /// Maximum weight of the `script_sig` and witness spending the UTXO
+    satisfaction_weight: u64,
+    psbt_input: Input,
+}
+
+/// UTXOs of an external wallet to fund deposits from, see
+/// [`FundingUtxos::deposit_psbt`]
+#[derive(Debug, Clone)]
+pub(crate) struct FundingUtxos {
+    utxos: Vec<FundingUtxo>,
+    /// Whether to only spend as many UTXOs as needed instead of all of them
+    select: bool,
+    change_script: Script,
+}
+
+impl FundingUtxos {
+    /// Fetches the UTXOs of `funding` and everything needed to spend them
+    pub(crate) async fn fetch(
+        rpc: &DynBitcoindRpc,
+        funding: DepositFunding,
+    ) -> anyhow::Result<Self> {
+        match funding {
+            DepositFunding::Utxos {
+                utxos,
+                change_address,
+            } => {
+                let mut funding_utxos = vec![];
+                for utxo in utxos {
+                    funding_utxos.push(fetch_external_utxo(rpc, utxo).await?);
+                }
+
+                Ok(FundingUtxos {
+                    utxos: funding_utxos,
+                    select: false,
+                    change_script: change_address.script_pubkey(),
+                })
+            }
+            DepositFunding::Descriptor { receive, change } => {
+                let (utxos, change_script) = scan_descriptors(rpc, &receive, &change).await?;
+
+                Ok(FundingUtxos {
+                    utxos,
+                    select: true,
+                    change_script,
+                })
+            }
+        }
+    }
+
+    /// Creates a PSBT paying `amount` to the deposit `address` that the
+    /// external wallet owning the UTXOs only needs to sign and broadcast
+    pub(crate) fn deposit_psbt(
+        &self,
+        address: &Address,
+        amount: bitcoin::Amount,
+        fee_rate: Feerate,
+    ) -> anyhow::Result<PartiallySignedTransaction> {
+        build_deposit_psbt(
+            address,
+            amount,
+            self.utxos.clone(),
+            self.select,
+            self.change_script.clone(),
+            fee_rate,
+        )
+    }
+}
+
+/// Fetches the transaction creating `utxo`, which signers need to spend
+/// non-taproot inputs
+async fn fetch_external_utxo(
+    rpc: &DynBitcoindRpc,
+    utxo: ExternalUtxo,
+) -> anyhow::Result<FundingUtxo> {
+    let script = &utxo.txout.script_pubkey;
+    let satisfaction_weight = estimate_satisfaction_weight(script).with_context(|| {
+        format!(
+            "Cannot estimate the size of spending {}, only P2PKH, P2WPKH and P2TR UTXOs are supported",
+            utxo.outpoint
+        )
+    })?;
+
+    let transaction = if script.is_v1_p2tr() {
+        None
+    } else {
+        let transaction = rpc
+            .get_script_history(&bitcoin29_to_bitcoin30_script(script.clone()))
+            .await?
+            .iter()
+            .map(bitcoin30_to_bitcoin29_transaction)
+            .find(|transaction| transaction.txid() == utxo.outpoint.txid)
+            .with_context(|| format!("Transaction of UTXO {} not found", utxo.outpoint))?;
+        ensure!(
+            transaction.output.get(utxo.outpoint.vout as usize) == Some(&utxo.txout),
+            "UTXO {} doesn't match its transaction",
+            utxo.outpoint
+        );
+        Some(transaction)
+    };
+
+    Ok(FundingUtxo {
+        outpoint: utxo.outpoint,
+        psbt_input: Input {
+            non_witness_utxo: transaction,
+            witness_utxo: script.is_witness_program().then(|| utxo.txout.clone()),
+            ..Default::default()
+        },
+        txout: utxo.txout,
+        satisfaction_weight,
+    })
+}
+
+/// Addresses derived from a descriptor and the transactions involving them
+struct DescriptorScan {
+    derived: BTreeMap<Script, Descriptor<DefiniteDescriptorKey>>,
+    transactions: Vec<Transaction>,
+    /// First script without any transactions
+    unused: Option<Script>,
+}
+
+/// Derives addresses from `descriptor` until [`DESCRIPTOR_GAP_LIMIT`]
+/// consecutive ones are unused
+async fn scan_descriptor(
+    rpc: &DynBitcoindRpc,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+) -> anyhow::Result<DescriptorScan> {
+    let mut scan = DescriptorScan {
+        derived: BTreeMap::new(),
+        transactions: vec![],
+        unused: None,
+    };
+    let mut gap = 0;
+
+    for index in 0.. {
+        let definite = descriptor
+            .at_derivation_index(index)
+            .context("Failed to derive from descriptor")?;
+        let script = bitcoin30_to_bitcoin29_script(&definite.script_pubkey());
+
+        let history = rpc.get_script_history(&definite.script_pubkey()).await?;
+        if history.is_empty() {
+            scan.unused.get_or_insert_with(|| script.clone());
+            gap += 1;
+        } else {
+            gap = 0;
+        }
+        scan.transactions
+            .extend(history.iter().map(bitcoin30_to_bitcoin29_transaction));
+        scan.derived.insert(script, definite);
+
+        // Descriptors without wildcard only have a single address
+        if DESCRIPTOR_GAP_LIMIT <= gap || !descriptor.has_wildcard() {
+            break;
+        }
+    }
+
+    Ok(scan)
+}
+
+/// Finds the UTXOs of the addresses derived from the `receive` and `change`
+/// descriptors and the first unused change script
+async fn scan_descriptors(
+    rpc: &DynBitcoindRpc,
+    receive: &Descriptor<DescriptorPublicKey>,
+    change: &Descriptor<DescriptorPublicKey>,
+) -> anyhow::Result<(Vec<FundingUtxo>, Script)> {
+    let change_scan = scan_descriptor(rpc, change).await?;
+    let change_script = match &change_scan.unused {
+        Some(script) => script.clone(),
+        // A descriptor without wildcard that was already used
+        None => change_scan
+            .derived
+            .keys()
+            .next()
+            .expect("derived at least once")
+            .clone(),
+    };
+
+    let mut scans = vec![change_scan];
+    if receive != change {
+        scans.push(scan_descriptor(rpc, receive).await?);
+    }
+
+    let mut derived = BTreeMap::new();
+    let mut transactions = BTreeMap::new();
+    for scan in scans {
+        derived.extend(scan.derived);
+        transactions.extend(
+            scan.transactions
+                .into_iter()
+                .map(|transaction| (transaction.txid(), transaction)),
+        );
+    }
+
+    let spent = transactions
+        .values()
+        .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+        .collect::<BTreeSet<_>>();
+
+    let mut utxos = vec![];
+    for tx in transactions.values() {
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let outpoint = bitcoin::OutPoint::new(tx.txid(), vout as u32);
+            let Some(definite) = derived.get(&txout.script_pubkey) else {
+                continue;
+            };
+            if spent.contains(&outpoint) {
+                continue;
+            }
+
+            utxos.push(FundingUtxo {
+                outpoint,
+                txout: txout.clone(),
+                satisfaction_weight: definite
+                    .max_weight_to_satisfy()
+                    .context("Descriptor is not satisfiable")?
+                    as u64,
+                psbt_input: descriptor_psbt_input(definite, tx, vout)?,
+            });
+        }
+    }
+
+    debug!(utxos = utxos.len(), "Scanned descriptors for deposit UTXOs");
+
+    Ok((utxos, change_script))
+}
+
+/// Creates the PSBT input for spending output `vout` of `transaction` with the
+/// keys of `descriptor`, including the key origins external signers need to
+/// find their keys
+fn descriptor_psbt_input(
+    descriptor: &Descriptor<DefiniteDescriptorKey>,
+    transaction: &Transaction,
+    vout: usize,
+) -> anyhow::Result<Input> {
+    let txout = transaction.output[vout].clone();
+    let segwit = descriptor.desc_type().segwit_version().is_some();
+
+    // miniscript works with bitcoin 0.30 PSBTs, so we convert the fields we need
+    let mut psbt_input = miniscript::bitcoin::psbt::Input::default();
+    psbt_input
+        .update_with_descriptor_unchecked(descriptor)
+        .context("Failed to derive keys from descriptor")?;
+
+    let bip32_derivation = psbt_input
+        .bip32_derivation
+        .into_iter()
+        .map(|(key, key_source)| {
+            Ok((
+                bitcoin30_to_bitcoin29_secp256k1_public_key(key),
+                convert_key_source(&key_source)?,
+            ))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let tap_key_origins = psbt_input
+        .tap_key_origins
+        .into_iter()
+        .map(|(key, (leaf_hashes, key_source))| {
+            Ok((
+                XOnlyPublicKey::from_slice(&key.serialize())?,
+                (
+                    leaf_hashes
+                        .into_iter()
+                        .map(|leaf_hash| TapLeafHash::from_inner(leaf_hash.to_byte_array()))
+                        .collect(),
+                    convert_key_source(&key_source)?,
+                ),
+            ))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let tap_scripts = psbt_input
+        .tap_scripts
+        .into_iter()
+        .map(|(control_block, (script, leaf_version))| {
+            Ok((
+                ControlBlock::from_slice(&control_block.serialize())?,
+                (
+                    bitcoin30_to_bitcoin29_script(&script),
+                    LeafVersion::from_consensus(leaf_version.to_consensus())?,
+                ),
+            ))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Input {
+        // Signers can only verify the amount of non-segwit inputs from the
+        // whole transaction, taproot signatures commit to the amounts instead
+        non_witness_utxo: (!txout.script_pubkey.is_v1_p2tr()).then(|| transaction.clone()),
+        witness_utxo: segwit.then_some(txout),
+        witness_script: psbt_input
+            .witness_script
+            .as_ref()
+            .map(bitcoin30_to_bitcoin29_script),
+        redeem_script: psbt_input
+            .redeem_script
+            .as_ref()
+            .map(bitcoin30_to_bitcoin29_script),
+        bip32_derivation,
+        tap_key_origins,
+        tap_scripts,
+        tap_internal_key: psbt_input
+            .tap_internal_key
+            .map(|key| XOnlyPublicKey::from_slice(&key.serialize()))
+            .transpose()?,
+        tap_merkle_root: psbt_input
+            .tap_merkle_root
+            .map(|root| TapBranchHash::from_inner(root.to_byte_array())),
+        ..Default::default()
+    })
+}
+
+/// Converts the key origin of a bitcoin 0.30 PSBT to bitcoin 0.29
+fn convert_key_source(
+    (fingerprint, path): &miniscript::bitcoin::bip32::KeySource,
+) -> anyhow::Result<(Fingerprint, DerivationPath)> {
+    Ok((
+        Fingerprint::from_str(&fingerprint.to_string())?,
+        DerivationPath::from_str(&path.to_string())?,
+    ))
+}
+
+/// Upper bound for the weight of the `script_sig` and witness spending a
+/// single-key output, `None` if we can't tell
+fn estimate_satisfaction_weight(script: &Script) -> Option<u64> {
+    if script.is_v0_p2wpkh() {
+        // item count, signature and public key
+        Some(1 + (1 + 73) + (1 + 33))
+    } else if script.is_v1_p2tr() {
+        // item count and a key path signature
+        Some(1 + (1 + 65))
+    } else if script.is_p2pkh() {
+        // signature and public key in the script_sig
+        Some((1 + 73 + 1 + 33) * 4)
+    } else {
+        None
+    }
+}
+
+fn output_weight(script: &Script) -> u64 {
+    (8 + 1 + script.len() as u64) * 4
+}
+
+/// Builds the deposit transaction, selecting the largest `utxos` until the
+/// deposit and fees are covered or spending all of them if `select` is false
+fn build_deposit_psbt(
+    address: &Address,
+    amount: bitcoin::Amount,
+    mut utxos: Vec<FundingUtxo>,
+    select: bool,
+    change_script: Script,
+    fee_rate: Feerate,
+) -> anyhow::Result<PartiallySignedTransaction> {
+    let deposit_script = address.script_pubkey();
+    ensure!(
+        deposit_script.dust_value() <= amount,
+        "Deposit amount is below the dust limit"
+    );
+
+    let mut total_weight = (4 + 1 + 1 + 4) * 4 + // version, input and output count, lock time
+        2 + // segwit marker and flag
+        output_weight(&deposit_script) +
+        output_weight(&change_script);
+
+    let mut selected: Vec<FundingUtxo> = vec![];
+    let mut selected_value = bitcoin::Amount::ZERO;
+
+    // Largest first to keep the transaction small
+    utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.txout.value));
+    for utxo in utxos {
+        if select
+            && amount + change_script.dust_value() + fee_rate.calculate_fee(total_weight)
+                <= selected_value
+        {
+            break;
+        }
+
+        total_weight += INPUT_BASE_WEIGHT + utxo.satisfaction_weight;
+        selected_value += bitcoin::Amount::from_sat(utxo.txout.value);
+        selected.push(utxo);
+    }
+
+    let fees = fee_rate.calculate_fee(total_weight);
+    let Some(change) = selected_value.checked_sub(amount + fees) else {
+        bail!("UTXOs worth {selected_value} can't pay for a deposit of {amount} and {fees} fees");
+    };
+
+    let mut output = vec![TxOut {
+        value: amount.to_sat(),
+        script_pubkey: deposit_script,
+    }];
+    // Change below the dust limit is left to the miners
+    if change_script.dust_value() <= change {
+        output.push(TxOut {
+            value: change.to_sat(),
+            script_pubkey: change_script,
+        });
+    }
+
+    let transaction = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: selected
+            .iter()
+            .map(|utxo| TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: Script::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: bitcoin::Witness::new(),
+            })
+            .collect(),
+        output,
+    };
+
+    let mut psbt =
+        PartiallySignedTransaction::from_unsigned_tx(transaction).expect("transaction is unsigned");
+    for (psbt_input, utxo) in psbt.inputs.iter_mut().zip(selected) {
+        *psbt_input = utxo.psbt_input;
+    }
+
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::psbt::Input;
+    use bitcoin::{
+        Address, Amount, Network, OutPoint, PackedLockTime, Script, Transaction, TxOut, Txid,
+    };
+    use fedimint_core::bitcoin_migration::bitcoin30_to_bitcoin29_script;
+    use fedimint_core::Feerate;
+    use miniscript::descriptor::DescriptorPublicKey;
+    use miniscript::Descriptor;
+
+    use super::{
+        bip21_uri, build_deposit_psbt, descriptor_psbt_input, DepositFunding, FundingUtxo,
+    };
+
+    const XPUB: &str = "[d34db33f/84'/0'/0']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    fn descriptor(descriptor: &str) -> Descriptor<DescriptorPublicKey> {
+        Descriptor::from_str(descriptor).expect("Descriptor is valid")
+    }
+
+    fn deposit_address() -> Address {
+        Address::p2wsh(&Script::from(vec![0x51]), Network::Regtest)
+    }
+
+    fn change_script() -> Script {
+        Address::p2wsh(&Script::from(vec![0x52]), Network::Regtest).script_pubkey()
+    }
+
+    fn utxo(vout: u32, sats: u64) -> FundingUtxo {
+        let txout = TxOut {
+            value: sats,
+            script_pubkey: change_script(),
+        };
+        FundingUtxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            txout: txout.clone(),
+            satisfaction_weight: 108,
+            psbt_input: Input {
+                witness_utxo: Some(txout),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn bip21_uri_should_encode_amount_and_label() {
+        let address = deposit_address();
+
+        assert_eq!(
+            bip21_uri(&address, None, None),
+            format!("bitcoin:{address}")
+        );
+        assert_eq!(
+            bip21_uri(
+                &address,
+                Some(Amount::from_sat(150_000)),
+                Some("Fedimint deposit")
+            ),
+            format!("bitcoin:{address}?amount=0.0015&label=Fedimint%20deposit")
+        );
+    }
+
+    #[test]
+    fn build_deposit_psbt_should_select_largest_utxos() {
+        let address = deposit_address();
+        let change_script = change_script();
+        let fee_rate = Feerate { sats_per_kvb: 2000 };
+
+        let psbt = build_deposit_psbt(
+            &address,
+            Amount::from_sat(60_000),
+            vec![utxo(0, 10_000), utxo(1, 50_000), utxo(2, 20_000)],
+            true,
+            change_script.clone(),
+            fee_rate,
+        )
+        .expect("UTXOs cover the deposit");
+
+        let tx = &psbt.unsigned_tx;
+        let spent = tx
+            .input
+            .iter()
+            .map(|input| input.previous_output.vout)
+            .collect::<Vec<_>>();
+        assert_eq!(spent, vec![1, 2]);
+        assert!(psbt.inputs.iter().all(|input| input.witness_utxo.is_some()));
+        assert_eq!(tx.output[0].script_pubkey, address.script_pubkey());
+        assert_eq!(tx.output[0].value, 60_000);
+        assert_eq!(tx.output[1].script_pubkey, change_script);
+        assert!(tx.output[1].value < 10_000);
+
+        // Without selection all UTXOs are spent, which still can't pay for more
+        // than they are worth
+        let psbt = build_deposit_psbt(
+            &address,
+            Amount::from_sat(60_000),
+            vec![utxo(0, 10_000), utxo(1, 50_000), utxo(2, 20_000)],
+            false,
+            change_script.clone(),
+            fee_rate,
+        )
+        .expect("UTXOs cover the deposit");
+        assert_eq!(psbt.unsigned_tx.input.len(), 3);
+
+        assert!(build_deposit_psbt(
+            &address,
+            Amount::from_sat(80_000),
+            vec![utxo(0, 10_000), utxo(1, 50_000), utxo(2, 20_000)],
+            false,
+            change_script,
+            fee_rate,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn descriptor_funding_should_split_receive_and_change() {
+        let DepositFunding::Descriptor { receive, change } =
+            DepositFunding::from_descriptor_str(&format!("wpkh({XPUB}/<0;1>/*)"))
+                .expect("Multipath descriptor is valid")
+        else {
+            panic!("Expected descriptor funding");
+        };
+        assert_eq!(*receive, descriptor(&format!("wpkh({XPUB}/0/*)")));
+        assert_eq!(*change, descriptor(&format!("wpkh({XPUB}/1/*)")));
+
+        // A single address is used for change as well
+        let DepositFunding::Descriptor { receive, change } =
+            DepositFunding::from_descriptor_str(&format!("wpkh({XPUB}/0/0)"))
+                .expect("Descriptor is valid")
+        else {
+            panic!("Expected descriptor funding");
+        };
+        assert_eq!(receive, change);
+
+        // Without change chain the change would go to a receive address
+        assert!(DepositFunding::from_descriptor_str(&format!("wpkh({XPUB}/0/*)")).is_err());
+    }
+
+    #[test]
+    fn descriptor_psbt_input_should_include_signing_data() {
+        for (descriptor, segwit) in [("pkh", false), ("wpkh", true), ("tr", true)] {
+            let DepositFunding::Descriptor { receive, .. } =
+                DepositFunding::from_descriptor_str(&format!("{descriptor}({XPUB}/<0;1>/*)"))
+                    .expect("Descriptor is valid")
+            else {
+                panic!("Expected descriptor funding");
+            };
+            let definite = receive.at_derivation_index(0).expect("Valid index");
+            let transaction = Transaction {
+                version: 2,
+                lock_time: PackedLockTime::ZERO,
+                input: vec![],
+                output: vec![TxOut {
+                    value: 10_000,
+                    script_pubkey: bitcoin30_to_bitcoin29_script(&definite.script_pubkey()),
+                }],
+            };
+
+            let input =
+                descriptor_psbt_input(&definite, &transaction, 0).expect("Keys can be derived");
+            assert_eq!(input.witness_utxo.is_some(), segwit, "{descriptor}");
+
+            if descriptor == "tr" {
+                // Taproot signatures commit to the amounts, so the previous
+                // transaction is not needed, but the taproot key origins are
+                assert_eq!(input.non_witness_utxo, None);
+                assert_eq!(input.tap_key_origins.len(), 1);
+                assert!(input.tap_internal_key.is_some());
+            } else {
+                assert_eq!(input.non_witness_utxo, Some(transaction.clone()));
+                assert_eq!(input.bip32_derivation.len(), 1);
+            }
+        }
+    }
+}
@@ -2,9 +2,10 @@ pub mod api;
 
 pub mod client_db;
 mod deposit;
+pub mod external_wallet;
 mod withdraw;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -18,7 +19,10 @@ use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{
+    OperationDirection, OperationLogEntry, OperationLogRecordDetails, OperationOutcomeState,
+    UpdateStreamOrOutcome,
+};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
@@ -37,7 +41,7 @@ use fedimint_core::module::{
     ApiVersion, CommonModuleInit, ModuleCommon, ModuleInit, MultiApiVersion,
 };
 use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
-use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint};
+use fedimint_core::{apply, async_trait_maybe_send, Amount, Feerate, OutPoint};
 use fedimint_wallet_common::config::{FeeConsensus, WalletClientConfig};
 use fedimint_wallet_common::tweakable::Tweakable;
 pub use fedimint_wallet_common::*;
@@ -49,11 +53,17 @@ use strum::IntoEnumIterator;
 
 use crate::api::WalletFederationApi;
 use crate::client_db::NextPegInTweakIndexKey;
-use crate::deposit::{CreatedDepositState, DepositStateMachine, DepositStates};
+use crate::deposit::{
+    CreatedDepositState, DepositStateMachine, DepositStates, WatchingForDepositsState,
+};
 use crate::withdraw::{CreatedWithdrawState, WithdrawStateMachine, WithdrawStates};
 
 const WALLET_TWEAK_CHILD_ID: ChildId = ChildId(0);
 
+/// Maximum number of times [`WalletClientModule::get_max_withdraw_amount`]
+/// re-estimates the fees before giving up
+const MAX_WITHDRAW_ALL_ROUNDS: usize = 5;
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct BitcoinTransactionData {
     /// The bitcoin transaction is saved as soon as we see it so the transaction
@@ -94,6 +104,26 @@ where
     }
 }
 
+/// Returns the next state of the deposit state machine that claims the first
+/// deposit to the address, buffering the states of the state machine watching
+/// for further deposits in `watcher_states`
+async fn next_main_deposit_state<S>(
+    stream: &mut S,
+    watcher_states: &mut VecDeque<DepositStates>,
+) -> Option<DepositStates>
+where
+    S: Stream<Item = WalletClientStates> + Unpin,
+{
+    loop {
+        match next_deposit_state(stream).await? {
+            state @ (DepositStates::WatchingForDeposits(_) | DepositStates::WatchingStopped(_)) => {
+                watcher_states.push_back(state);
+            }
+            state => return Some(state),
+        }
+    }
+}
+
 async fn next_withdraw_state<S>(stream: &mut S) -> Option<WithdrawStates>
 where
     S: Stream<Item = WalletClientStates> + Unpin,
@@ -177,6 +207,85 @@ impl ClientModuleInit for WalletClientInit {
             client_ctx: args.context(),
         })
     }
+
+    fn operation_log_record_details(
+        &self,
+        entry: &OperationLogEntry,
+    ) -> anyhow::Result<OperationLogRecordDetails> {
+        Ok(match entry.try_meta::<WalletOperationMeta>()?.variant {
+            WalletOperationMetaVariant::Deposit { address, .. } => {
+                let transaction = match entry.try_outcome::<DepositState>().transpose()? {
+                    Some(
+                        DepositState::WaitingForConfirmation(data)
+                        | DepositState::Confirmed(data)
+                        | DepositState::Claimed(data),
+                    ) => Some(data),
+                    _ => None,
+                };
+
+                OperationLogRecordDetails {
+                    direction: Some(OperationDirection::Incoming),
+                    amount: transaction.as_ref().and_then(|data| {
+                        data.btc_transaction
+                            .output
+                            .get(data.out_idx as usize)
+                            .map(|output| Amount::from_sats(output.value))
+                    }),
+                    address: Some(address.to_string()),
+                    txid: transaction.map(|data| data.btc_transaction.txid().to_string()),
+                    ..Default::default()
+                }
+            }
+            WalletOperationMetaVariant::Withdraw {
+                address,
+                amount,
+                fee,
+                ..
+            } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Outgoing),
+                amount: Some(Amount::from_sats(amount.to_sat())),
+                fee: Some(Amount::from_sats(fee.amount().to_sat())),
+                address: Some(address.to_string()),
+                txid: match entry.try_outcome::<WithdrawState>().transpose()? {
+                    Some(WithdrawState::Succeeded(txid)) => Some(txid.to_string()),
+                    _ => None,
+                },
+                ..Default::default()
+            },
+            WalletOperationMetaVariant::RbfWithdraw { rbf, .. } => OperationLogRecordDetails {
+                direction: Some(OperationDirection::Outgoing),
+                fee: Some(Amount::from_sats(rbf.fees.amount().to_sat())),
+                txid: Some(rbf.txid.to_string()),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn operation_outcome_state(&self, entry: &OperationLogEntry) -> OperationOutcomeState {
+        let Ok(meta) = entry.try_meta::<WalletOperationMeta>() else {
+            return OperationOutcomeState::unclassified(entry);
+        };
+
+        match meta.variant {
+            WalletOperationMetaVariant::Deposit { .. } => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    DepositState::Claimed(_) => OperationOutcomeState::Success,
+                    DepositState::Failed(_) => OperationOutcomeState::Failure,
+                    DepositState::WaitingForTransaction
+                    | DepositState::WaitingForConfirmation(_)
+                    | DepositState::Confirmed(_) => OperationOutcomeState::Pending,
+                })
+            }
+            WalletOperationMetaVariant::Withdraw { .. }
+            | WalletOperationMetaVariant::RbfWithdraw { .. } => {
+                OperationOutcomeState::classify(entry, |state| match state {
+                    WithdrawState::Succeeded(_) => OperationOutcomeState::Success,
+                    WithdrawState::Failed(_) => OperationOutcomeState::Failure,
+                    WithdrawState::Created => OperationOutcomeState::Pending,
+                })
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -330,6 +439,32 @@ impl WalletClientModule {
             .context("Federation didn't return peg-out fees")
     }
 
+    /// Fetches up to `limit` entries of the federation's consensus fee rate
+    /// and block count history, one entry per session, newest first. Pass the
+    /// `session_index` of the last returned entry as `before` to fetch the next
+    /// page.
+    pub async fn get_consensus_history(
+        &self,
+        before: Option<u64>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<ConsensusHistoryEntry>> {
+        Ok(self
+            .module_api
+            .fetch_consensus_history(before, limit)
+            .await?)
+    }
+
+    /// The fee rate the federation agreed on in its latest session
+    async fn consensus_fee_rate(&self) -> anyhow::Result<Feerate> {
+        Ok(self
+            .module_api
+            .fetch_consensus_history(None, 1)
+            .await?
+            .first()
+            .context("Bitcoin backend can't estimate fee rate and the federation has no fee rate")?
+            .fee_rate)
+    }
+
     pub async fn create_withdraw_output(
         &self,
         operation_id: OperationId,
@@ -389,17 +524,69 @@ impl WalletClientModule {
         valid_until: SystemTime,
         extra_meta: M,
     ) -> anyhow::Result<(OperationId, Address)> {
+        let (operation_id, address, ()) = self
+            .create_deposit_operation(valid_until, extra_meta, |_| Ok(()))
+            .await?;
+
+        Ok((operation_id, address))
+    }
+
+    /// Creates a deposit address like [`Self::get_deposit_address`] together
+    /// with an unsigned PSBT paying `amount` to it from `funding`, for
+    /// external wallets to sign and broadcast. The deposit operation is only
+    /// created if the PSBT could be created.
+    ///
+    /// If no `fee_rate` is given the rate estimated by the client's bitcoin
+    /// backend is used.
+    pub async fn get_deposit_address_with_psbt<M: Serialize + MaybeSend + MaybeSync>(
+        &self,
+        valid_until: SystemTime,
+        amount: bitcoin::Amount,
+        funding: external_wallet::DepositFunding,
+        fee_rate: Option<Feerate>,
+        extra_meta: M,
+    ) -> anyhow::Result<(
+        OperationId,
+        Address,
+        bitcoin::util::psbt::PartiallySignedTransaction,
+    )> {
+        let fee_rate = match fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => match self.rpc.get_fee_rate(CONFIRMATION_TARGET).await? {
+                Some(fee_rate) => fee_rate,
+                // Light clients need a few blocks before they can estimate fee rates
+                None => self.consensus_fee_rate().await?,
+            },
+        };
+        let funding_utxos = external_wallet::FundingUtxos::fetch(&self.rpc, funding).await?;
+
+        self.create_deposit_operation(valid_until, extra_meta, |address| {
+            funding_utxos.deposit_psbt(address, amount, fee_rate)
+        })
+        .await
+    }
+
+    /// Creates a deposit operation for a new address. The operation is only
+    /// committed if `prepare` succeeds for the address.
+    async fn create_deposit_operation<M: Serialize + MaybeSend + MaybeSync, T: MaybeSend>(
+        &self,
+        valid_until: SystemTime,
+        extra_meta: M,
+        prepare: impl Fn(&Address) -> anyhow::Result<T> + MaybeSend + MaybeSync,
+    ) -> anyhow::Result<(OperationId, Address, T)> {
         let extra_meta = serde_json::to_value(extra_meta).expect("extra meta is serializable");
 
-        let (operation_id, address) = self
+        let (operation_id, address, prepared) = self
             .client_ctx
             .module_autocommit(
                 |dbtx, _| {
                     let extra_meta_inner = extra_meta.clone();
+                    let prepare = &prepare;
                     Box::pin(async move {
                         let (operation_id, sm, address) = self
                             .get_deposit_address_inner(valid_until, &mut dbtx.module_dbtx())
                             .await;
+                        let prepared = prepare(&address)?;
 
                         // Begin watching the script address
                         self.rpc
@@ -424,7 +611,7 @@ impl WalletClientModule {
                         )
                         .await;
 
-                        Ok((operation_id, address))
+                        Ok((operation_id, address, prepared))
                     })
                 },
                 Some(100),
@@ -438,13 +625,13 @@ impl WalletClientModule {
                 AutocommitError::ClosureError { error, .. } => error,
             })?;
 
-        Ok((operation_id, address))
+        Ok((operation_id, address, prepared))
     }
 
-    pub async fn subscribe_deposit_updates(
+    async fn get_deposit_operation(
         &self,
         operation_id: OperationId,
-    ) -> anyhow::Result<UpdateStreamOrOutcome<DepositState>> {
+    ) -> anyhow::Result<OperationLogEntry> {
         let operation_log_entry = self
             .client_ctx
             .get_operation(operation_id)
@@ -464,15 +651,30 @@ impl WalletClientModule {
             bail!("Operation is not a deposit operation");
         }
 
+        Ok(operation_log_entry)
+    }
+
+    /// Subscribes to the updates of a deposit operation. After the first
+    /// deposit to the address was claimed every further deposit is reported
+    /// as [`DepositState::Confirmed`] followed by [`DepositState::Claimed`] or
+    /// [`DepositState::Failed`]. The updates end once the address isn't
+    /// watched anymore.
+    pub async fn subscribe_deposit_updates(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<DepositState>> {
+        let operation_log_entry = self.get_deposit_operation(operation_id).await?;
+
         let mut operation_stream = self.notifier.subscribe(operation_id).await;
         let tx_subscriber = self.client_ctx.transaction_updates(operation_id).await;
 
         let client_ctx = self.client_ctx.clone();
         Ok(
-            operation_log_entry.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+            operation_log_entry.outcome_or_updates(&self.client_ctx.operation_log(), operation_id, move || {
                 stream! {
+                    let mut watcher_states = VecDeque::new();
 
-                    match next_deposit_state(&mut operation_stream).await {
+                    match next_main_deposit_state(&mut operation_stream, &mut watcher_states).await {
                         Some(DepositStates::Created(_)) => {
                             yield DepositState::WaitingForTransaction;
                         },
@@ -482,7 +684,7 @@ impl WalletClientModule {
                         None => return,
                     }
 
-                    let tx_data = match next_deposit_state(&mut operation_stream).await {
+                    let tx_data = match next_main_deposit_state(&mut operation_stream, &mut watcher_states).await {
                         Some(DepositStates::WaitingForConfirmations(inner)) => {
                             let tx_data = BitcoinTransactionData { btc_transaction: inner.btc_transaction, out_idx: inner.out_idx };
                             yield DepositState::WaitingForConfirmation(tx_data.clone());
@@ -498,7 +700,7 @@ impl WalletClientModule {
                         None => return,
                     };
 
-                    let claiming = match next_deposit_state(&mut operation_stream).await {
+                    let claiming = match next_main_deposit_state(&mut operation_stream, &mut watcher_states).await {
                         Some(DepositStates::Claiming(claiming)) => claiming,
                         Some(s) => {
                             panic!("Unexpected state {s:?}")
@@ -517,12 +719,166 @@ impl WalletClientModule {
                         .await
                         .expect("Cannot fail if tx was accepted and federation is honest");
 
-                    yield DepositState::Claimed(tx_data.clone());
+                    yield DepositState::Claimed(tx_data);
+
+                    // The watcher is spawned together with the transition to waiting for
+                    // confirmations, if we haven't seen it by now the operation predates it
+                    if watcher_states.is_empty() {
+                        return;
+                    }
+
+                    loop {
+                        let watcher_state = match watcher_states.pop_front() {
+                            Some(state) => state,
+                            None => match next_deposit_state(&mut operation_stream).await {
+                                Some(state) => state,
+                                None => return,
+                            },
+                        };
+
+                        let claim = match watcher_state {
+                            DepositStates::WatchingForDeposits(WatchingForDepositsState { claim: Some(claim), .. }) => claim,
+                            DepositStates::WatchingForDeposits(_) => continue,
+                            DepositStates::WatchingStopped(_) => {
+                                // A rescan might have started watching the address again
+                                let still_watched = client_ctx
+                                    .get_own_active_states()
+                                    .await
+                                    .into_iter()
+                                    .any(|(state, _)| matches!(
+                                        state,
+                                        WalletClientStates::Deposit(DepositStateMachine {
+                                            operation_id: state_operation_id,
+                                            state: DepositStates::WatchingForDeposits(_),
+                                        }) if state_operation_id == operation_id
+                                    ));
+                                if still_watched {
+                                    continue;
+                                }
+                                return;
+                            },
+                            s => panic!("Unexpected state {s:?}"),
+                        };
+
+                        let tx_data = BitcoinTransactionData { btc_transaction: claim.btc_transaction, out_idx: claim.out_idx };
+                        yield DepositState::Confirmed(tx_data.clone());
+
+                        if let Err(e) = client_ctx
+                            .transaction_updates(operation_id)
+                            .await
+                            .await_tx_accepted(claim.claiming_state.transaction_id)
+                            .await
+                        {
+                            yield DepositState::Failed(format!("Failed to claim: {e:?}"));
+                            continue;
+                        }
+
+                        client_ctx.await_primary_module_outputs(operation_id, claim.claiming_state.change)
+                            .await
+                            .expect("Cannot fail if tx was accepted and federation is honest");
+
+                        yield DepositState::Claimed(tx_data);
+                    }
                 }
             }),
         )
     }
 
+    /// Watches the address of a past deposit operation again until
+    /// `valid_until`, claiming every confirmed deposit to it that wasn't
+    /// claimed yet, e.g. because it was sent after the address expired.
+    ///
+    /// Claims are reported by [`Self::subscribe_deposit_updates`] unless the
+    /// operation's outcome was already recorded, which happens once the
+    /// address stopped being watched.
+    pub async fn rescan_deposit_address(
+        &self,
+        operation_id: OperationId,
+        valid_until: SystemTime,
+    ) -> anyhow::Result<()> {
+        self.get_deposit_operation(operation_id).await?;
+
+        let active_states = self
+            .client_ctx
+            .get_own_active_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| state);
+        let inactive_states = self
+            .client_ctx
+            .get_own_inactive_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| state);
+
+        let mut tweak_key = None;
+        let mut claimed = vec![];
+        for (active, state) in active_states
+            .map(|state| (true, state))
+            .chain(inactive_states.map(|state| (false, state)))
+        {
+            let WalletClientStates::Deposit(DepositStateMachine {
+                operation_id: state_operation_id,
+                state,
+            }) = state
+            else {
+                continue;
+            };
+            if state_operation_id != operation_id {
+                continue;
+            }
+
+            match state {
+                DepositStates::Created(created) => {
+                    ensure!(!active, "Deposit address is still being watched");
+                    tweak_key = Some(created.tweak_key);
+                }
+                DepositStates::WaitingForConfirmations(waiting) => {
+                    claimed.push(bitcoin::OutPoint::new(
+                        waiting.btc_transaction.txid(),
+                        waiting.out_idx,
+                    ));
+                }
+                DepositStates::WatchingForDeposits(watching) => {
+                    ensure!(!active, "Deposit address is still being watched");
+                    claimed.extend(watching.claimed);
+                }
+                DepositStates::WatchingStopped(stopped) => {
+                    claimed.extend(stopped.claimed);
+                }
+                DepositStates::Claiming(_) | DepositStates::TimedOut(_) => {}
+            }
+        }
+
+        let tweak_key = tweak_key.context("Deposit state machine not found")?;
+        claimed.sort();
+        claimed.dedup();
+
+        let watcher_sm = WalletClientStates::Deposit(DepositStateMachine {
+            operation_id,
+            state: DepositStates::WatchingForDeposits(WatchingForDepositsState {
+                tweak_key,
+                timeout_at: valid_until,
+                claimed,
+                claim: None,
+            }),
+        });
+
+        self.client_ctx
+            .module_autocommit_2(
+                |dbtx, _| {
+                    let watcher_sm = watcher_sm.clone();
+                    Box::pin(async move {
+                        dbtx.add_state_machines(vec![self.client_ctx.make_dyn_state(watcher_sm)])
+                            .await?;
+                        Ok(())
+                    })
+                },
+                Some(100),
+            )
+            .await
+    }
+
     /// Attempt to withdraw a given `amount` of Bitcoin to a destination
     /// `address`. The caller has to supply the fee rate to be used which can be
     /// fetched using [`Self::get_withdraw_fees`] and should be
@@ -566,6 +922,94 @@ impl WalletClientModule {
         }
     }
 
+    /// Finds the largest amount that can be withdrawn to `address`, taking the
+    /// federation's peg-out fee, the on-chain fees and the fees for spending
+    /// the client's funds into account. Returns the amount together with the
+    /// fees to pass to [`Self::withdraw`].
+    ///
+    /// Like with [`Self::get_withdraw_fees`] the result may be outdated by the
+    /// time it is used, e.g. when the fee rate or the balance changes.
+    pub async fn get_max_withdraw_amount(
+        &self,
+        address: bitcoin::Address,
+    ) -> anyhow::Result<(bitcoin::Amount, PegOutFees)> {
+        check_address(&address, self.cfg.network)?;
+
+        let balance = bitcoin::Amount::from_sat(self.client_ctx.get_balance().await.msats / 1000);
+        let peg_out_fee = self.cfg.fee_consensus.peg_out_abs;
+        let mut fees = self.get_withdraw_fees(address.clone(), balance).await?;
+
+        // The on-chain fees only depend on the amount through the UTXOs and change
+        // of the peg-out transaction, so they converge after a few rounds
+        for _ in 0..MAX_WITHDRAW_ALL_ROUNDS {
+            // Upper bound if spending the client's funds was free
+            let max_amount = bitcoin::Amount::from_sat(
+                balance
+                    .to_sat()
+                    .saturating_sub(fees.amount().to_sat())
+                    .saturating_sub((peg_out_fee.msats + 999) / 1000),
+            );
+
+            // Binary search for the largest amount the client can actually fund
+            let mut fundable = bitcoin::Amount::ZERO;
+            let mut unfundable = max_amount + bitcoin::Amount::from_sat(1);
+            while fundable + bitcoin::Amount::from_sat(1) < unfundable {
+                let amount = if unfundable == max_amount + bitcoin::Amount::from_sat(1) {
+                    // Usually the upper bound is fundable, so check it first
+                    max_amount
+                } else {
+                    bitcoin::Amount::from_sat((fundable.to_sat() + unfundable.to_sat()) / 2)
+                };
+
+                let output = self
+                    .create_withdraw_output(
+                        OperationId::new_random(),
+                        address.clone(),
+                        amount,
+                        fees,
+                    )
+                    .await?;
+                let tx_builder = TransactionBuilder::new()
+                    .with_output(self.client_ctx.make_client_output(output));
+
+                if self.client_ctx.can_fund_transaction(tx_builder).await {
+                    fundable = amount;
+                } else {
+                    unfundable = amount;
+                }
+            }
+
+            ensure!(
+                address.script_pubkey().dust_value() <= fundable,
+                "Balance of {balance} is not enough to withdraw after fees"
+            );
+
+            // The federation requires the fees of the actual peg-out transaction, which
+            // may spend fewer UTXOs for a smaller amount. Cheaper fees keep the amount
+            // fundable, at worst leaving a few sats of change.
+            let new_fees = self.get_withdraw_fees(address.clone(), fundable).await?;
+            if new_fees.amount() <= fees.amount() {
+                return Ok((fundable, new_fees));
+            }
+            fees = new_fees;
+        }
+
+        bail!("Withdraw fees did not settle, try again later")
+    }
+
+    /// Withdraws the whole balance of the client to `address`, see
+    /// [`Self::get_max_withdraw_amount`]
+    pub async fn withdraw_all<M: Serialize + MaybeSend + MaybeSync>(
+        &self,
+        address: bitcoin::Address,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, bitcoin::Amount, PegOutFees)> {
+        let (amount, fees) = self.get_max_withdraw_amount(address.clone()).await?;
+        let operation_id = self.withdraw(address, amount, fees, extra_meta).await?;
+
+        Ok((operation_id, amount, fees))
+    }
+
     /// Attempt to increase the fee of a onchain withdraw transaction using
     /// replace by fee (RBF).
     /// This can prevent transactions from getting stuck
@@ -627,8 +1071,10 @@ impl WalletClientModule {
         let mut operation_stream = self.notifier.subscribe(operation_id).await;
         let client_ctx = self.client_ctx.clone();
 
-        Ok(
-            operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+        Ok(operation.outcome_or_updates(
+            &self.client_ctx.operation_log(),
+            operation_id,
+            move || {
                 stream! {
                     match next_withdraw_state(&mut operation_stream).await {
                         Some(WithdrawStates::Created(_)) => {
@@ -662,8 +1108,8 @@ impl WalletClientModule {
                         None => {},
                     }
                 }
-            }),
-        )
+            },
+        ))
     }
 }
 
@@ -729,3 +1175,146 @@ impl State for WalletClientStates {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use fedimint_client::module::init::ClientModuleInitRegistry;
+    use fedimint_client::oplog::{OperationLog, OperationLogFilter};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IRawDatabaseExt;
+
+    use super::*;
+
+    fn address() -> bitcoin::Address {
+        bitcoin::Address::p2wsh(&bitcoin::Script::new(), Network::Regtest)
+    }
+
+    fn deposit_meta() -> WalletOperationMeta {
+        WalletOperationMeta {
+            variant: WalletOperationMetaVariant::Deposit {
+                address: address(),
+                expires_at: fedimint_core::time::now(),
+            },
+            extra_meta: serde_json::Value::Null,
+        }
+    }
+
+    fn withdraw_meta(amount: bitcoin::Amount) -> WalletOperationMeta {
+        WalletOperationMeta {
+            variant: WalletOperationMetaVariant::Withdraw {
+                address: address(),
+                amount,
+                fee: PegOutFees::new(1000, 500),
+                change: vec![],
+            },
+            extra_meta: serde_json::Value::Null,
+        }
+    }
+
+    fn deposit_transaction(amount: bitcoin::Amount) -> BitcoinTransactionData {
+        BitcoinTransactionData {
+            btc_transaction: bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::PackedLockTime::ZERO,
+                input: vec![],
+                output: vec![bitcoin::TxOut {
+                    value: amount.to_sat(),
+                    script_pubkey: address().script_pubkey(),
+                }],
+            },
+            out_idx: 0,
+        }
+    }
+
+    fn operation_indices(operations: &[(impl Sized, OperationLogEntry)]) -> Vec<u8> {
+        operations
+            .iter()
+            .map(|(_, entry)| {
+                entry
+                    .meta::<WalletOperationMeta>()
+                    .extra_meta
+                    .as_u64()
+                    .expect("Index is set") as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filters_operations_by_amount_and_outcome() {
+        let mut module_inits = ClientModuleInitRegistry::new();
+        module_inits.attach(WalletClientInit::default());
+        let db = MemDatabase::new().into_database();
+        let op_log = OperationLog::new(db.clone(), module_inits);
+
+        let operations = [
+            deposit_meta(),
+            deposit_meta(),
+            withdraw_meta(bitcoin::Amount::from_sat(3_000)),
+            withdraw_meta(bitcoin::Amount::from_sat(4_000)),
+        ];
+        for (operation_idx, mut meta) in operations.into_iter().enumerate() {
+            meta.extra_meta = serde_json::json!(operation_idx);
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx.to_ref_nc(),
+                    OperationId([operation_idx as u8; 32]),
+                    WalletCommonInit::KIND.as_str(),
+                    meta,
+                )
+                .await;
+            dbtx.commit_tx().await;
+        }
+
+        // The amount of a deposit is only known once its transaction was seen
+        op_log
+            .set_operation_outcome(
+                OperationId([0; 32]),
+                &DepositState::Claimed(deposit_transaction(bitcoin::Amount::from_sat(2_000))),
+            )
+            .await
+            .unwrap();
+        op_log
+            .set_operation_outcome(
+                OperationId([2; 32]),
+                &WithdrawState::Failed("Insufficient funds".to_string()),
+            )
+            .await
+            .unwrap();
+        op_log
+            .set_operation_outcome(
+                OperationId([3; 32]),
+                &WithdrawState::Succeeded(bitcoin::Txid::all_zeros()),
+            )
+            .await
+            .unwrap();
+
+        let by_outcome = |outcome_state| OperationLogFilter {
+            outcome_state: Some(outcome_state),
+            ..Default::default()
+        };
+        let failed = op_log
+            .list_operations_filtered(&by_outcome(OperationOutcomeState::Failure), 10, None)
+            .await;
+        assert_eq!(operation_indices(&failed), vec![2]);
+        let succeeded = op_log
+            .list_operations_filtered(&by_outcome(OperationOutcomeState::Success), 10, None)
+            .await;
+        assert_eq!(operation_indices(&succeeded), vec![3, 0]);
+        let pending = op_log
+            .list_operations_filtered(&by_outcome(OperationOutcomeState::Pending), 10, None)
+            .await;
+        assert_eq!(operation_indices(&pending), vec![1]);
+
+        let amount_range = OperationLogFilter {
+            min_amount: Some(Amount::from_sats(2_000)),
+            max_amount: Some(Amount::from_sats(3_000)),
+            ..Default::default()
+        };
+        let in_range = op_log
+            .list_operations_filtered(&amount_range, 10, None)
+            .await;
+        assert_eq!(operation_indices(&in_range), vec![2, 0]);
+    }
+}
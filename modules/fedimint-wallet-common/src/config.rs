@@ -1,24 +1,39 @@
 use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
+use anyhow::ensure;
 use bitcoin::Network;
+use fedimint_core::config::{
+    ModuleInitParams, ServerModuleConsensusConfig, TypedServerModuleConfig,
+    TypedServerModuleConsensusConfig,
+};
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::envs::BitcoinRpcConfig;
 use fedimint_core::module::__reexports::serde_json;
+use fedimint_core::module::{CommonModuleInit, ModuleConsensusVersion};
 use fedimint_core::util::SafeUrl;
-use fedimint_core::{plugin_types_trait_impl_config, Feerate, PeerId};
-use miniscript::descriptor::{Wpkh, Wsh};
+use fedimint_core::{Feerate, PeerId};
+use miniscript::descriptor::{TapTree, Tr, Wpkh, Wsh};
+use miniscript::{Miniscript, Tap, Terminal};
 use secp256k1::SecretKey;
 use serde::{Deserialize, Serialize};
 
 use crate::envs::FM_PORT_ESPLORA_ENV;
 use crate::keys::CompressedPublicKey;
-use crate::{PegInDescriptor, WalletCommonInit};
+use crate::{supports_cpfp, supports_peg_out_batching, PegInDescriptor, WalletCommonInit};
 
 /// Helps against dust attacks where an attacker deposits UTXOs that, with
 /// higher fee levels, cannot be spent profitably.
 const DEFAULT_DEPOSIT_FEE_SATS: u64 = 1000;
 
+/// Internal key of Taproot peg-in descriptors that nobody knows the secret key
+/// of, which makes the multisig script the only way to spend (see
+/// [BIP 341](https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs)).
+const UNSPENDABLE_INTERNAL_KEY: &str =
+    "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WalletGenParams {
     pub local: WalletGenParamsLocal,
@@ -41,6 +56,10 @@ impl WalletGenParams {
                     .expect("Failed to parse default esplora server"),
                 },
                 fee_consensus: Default::default(),
+                peg_out_batching: None,
+                peg_in_descriptor_kind: Default::default(),
+                utxo_consolidation: None,
+                cpfp: None,
             },
         }
     }
@@ -62,6 +81,31 @@ pub struct WalletGenParamsConsensus {
     ///
     /// Deposit fees in particular are a protection against dust attacks.
     pub fee_consensus: FeeConsensus,
+    /// See [`WalletConfigConsensus::peg_out_batching`].
+    #[serde(default)]
+    pub peg_out_batching: Option<PegOutBatchingConfig>,
+    /// Script type of the federation's peg-in addresses
+    #[serde(default)]
+    pub peg_in_descriptor_kind: PegInDescriptorKind,
+    /// See [`WalletConfigConsensus::utxo_consolidation`].
+    #[serde(default)]
+    pub utxo_consolidation: Option<UtxoConsolidationConfig>,
+    /// See [`WalletConfigConsensus::cpfp`].
+    #[serde(default)]
+    pub cpfp: Option<CpfpConfig>,
+}
+
+/// Script type used for the peg-in descriptor
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PegInDescriptorKind {
+    /// `wsh(sortedmulti(...))`, or `wpkh(...)` for a single guardian
+    #[default]
+    Wsh,
+    /// `tr(...)` with an unspendable internal key and a single `multi_a(...)`
+    /// script leaf. The internal key can be replaced by an aggregated key of
+    /// the guardians (MuSig2/FROST) to allow key path spends in the future.
+    Taproot,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -83,6 +127,11 @@ pub struct WalletConfigPrivate {
     pub peg_in_key: SecretKey,
 }
 
+/// Configs of federations created before consensus version 2.1 are encoded as
+/// [`LegacyWalletConfigConsensus`] and later fields are only present from the
+/// version that introduced them on, the version of the erased config selects
+/// the layout when decoding it (see
+/// [`WalletConfigConsensus::from_erased`]).
 #[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
 pub struct WalletConfigConsensus {
     /// Bitcoin network (e.g. testnet, bitcoin)
@@ -107,6 +156,83 @@ pub struct WalletConfigConsensus {
     /// **This is only used by the client, the RPC used by the server is defined
     /// in [`WalletConfigLocal`].**
     pub client_default_bitcoin_rpc: BitcoinRpcConfig,
+    /// If set, peg-outs are queued and combined into a single bitcoin
+    /// transaction instead of creating one transaction each
+    #[serde(default)]
+    pub peg_out_batching: Option<PegOutBatchingConfig>,
+    /// If set, guardians sweep small UTXOs of the federation wallet into a
+    /// single one while on-chain fees are low
+    #[serde(default)]
+    pub utxo_consolidation: Option<UtxoConsolidationConfig>,
+    /// If set, guardians bump the fee of stuck peg-out transactions by
+    /// spending their change in a child transaction (CPFP)
+    #[serde(default)]
+    pub cpfp: Option<CpfpConfig>,
+}
+
+/// Layout of [`WalletConfigConsensus`] in configs of federations created
+/// before consensus version 2.1
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct LegacyWalletConfigConsensus {
+    pub network: Network,
+    pub peg_in_descriptor: PegInDescriptor,
+    pub peer_peg_in_keys: BTreeMap<PeerId, CompressedPublicKey>,
+    pub finality_delay: u32,
+    pub default_fee: Feerate,
+    pub fee_consensus: FeeConsensus,
+    pub client_default_bitcoin_rpc: BitcoinRpcConfig,
+}
+
+impl From<LegacyWalletConfigConsensus> for WalletConfigConsensus {
+    fn from(legacy: LegacyWalletConfigConsensus) -> Self {
+        Self {
+            network: legacy.network,
+            peg_in_descriptor: legacy.peg_in_descriptor,
+            peer_peg_in_keys: legacy.peer_peg_in_keys,
+            finality_delay: legacy.finality_delay,
+            default_fee: legacy.default_fee,
+            fee_consensus: legacy.fee_consensus,
+            client_default_bitcoin_rpc: legacy.client_default_bitcoin_rpc,
+            peg_out_batching: None,
+            utxo_consolidation: None,
+            cpfp: None,
+        }
+    }
+}
+
+/// Determines when queued peg-outs get combined into a batch transaction
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PegOutBatchingConfig {
+    /// Number of consensus blocks the oldest queued peg-out waits for others
+    /// to join its batch
+    pub window: u32,
+    /// Number of queued peg-outs that cause a batch to be created right away
+    pub max_batch_size: u32,
+}
+
+/// Determines when and how many UTXOs of the federation wallet get
+/// consolidated
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct UtxoConsolidationConfig {
+    /// Highest fee rate at which guardians vote to consolidate UTXOs
+    pub max_fee_rate: Feerate,
+    /// Number of spendable UTXOs above which the smallest ones get
+    /// consolidated
+    pub min_utxos: u32,
+    /// Maximum number of UTXOs swept by a single consolidation transaction
+    pub max_inputs: u32,
+}
+
+/// Limits the fees the federation wallet spends on bumping stuck peg-out
+/// transactions with CPFP
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct CpfpConfig {
+    /// Highest effective fee rate of a bumped peg-out transaction and its
+    /// child
+    pub max_fee_rate: Feerate,
+    /// Highest fee paid by a single child transaction
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub max_fee: bitcoin::Amount,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
@@ -161,8 +287,14 @@ impl WalletConfig {
         bitcoin_rpc: BitcoinRpcConfig,
         client_default_bitcoin_rpc: BitcoinRpcConfig,
         fee_consensus: FeeConsensus,
+        peg_out_batching: Option<PegOutBatchingConfig>,
+        peg_in_descriptor_kind: PegInDescriptorKind,
+        utxo_consolidation: Option<UtxoConsolidationConfig>,
+        cpfp: Option<CpfpConfig>,
     ) -> Self {
-        let peg_in_descriptor = if pubkeys.len() == 1 {
+        let peg_in_descriptor = if peg_in_descriptor_kind == PegInDescriptorKind::Taproot {
+            taproot_peg_in_descriptor(threshold, pubkeys.values().copied().collect())
+        } else if pubkeys.len() == 1 {
             PegInDescriptor::Wpkh(
                 Wpkh::new(
                     *pubkeys
@@ -189,11 +321,34 @@ impl WalletConfig {
                 default_fee: Feerate { sats_per_kvb: 1000 },
                 fee_consensus,
                 client_default_bitcoin_rpc,
+                peg_out_batching,
+                utxo_consolidation,
+                cpfp,
             },
         }
     }
 }
 
+/// Creates a Taproot descriptor that can only be spent by `threshold` of the
+/// `pubkeys` signing for its `multi_a` script leaf
+pub fn taproot_peg_in_descriptor(
+    threshold: usize,
+    mut pubkeys: Vec<CompressedPublicKey>,
+) -> PegInDescriptor {
+    // There is no `sortedmulti_a`, so we sort the keys ourselves
+    pubkeys.sort();
+
+    let internal_key =
+        CompressedPublicKey::from_str(UNSPENDABLE_INTERNAL_KEY).expect("is a valid public key");
+    let multisig = Miniscript::<_, Tap>::from_ast(Terminal::MultiA(threshold, pubkeys))
+        .expect("threshold is valid for the number of keys");
+
+    PegInDescriptor::Tr(
+        Tr::new(internal_key, Some(TapTree::Leaf(Arc::new(multisig))))
+            .expect("tree has a single leaf"),
+    )
+}
+
 impl WalletClientConfig {
     pub fn new(
         peg_in_descriptor: PegInDescriptor,
@@ -211,14 +366,224 @@ impl WalletClientConfig {
     }
 }
 
-plugin_types_trait_impl_config!(
-    WalletCommonInit,
-    WalletGenParams,
-    WalletGenParamsLocal,
-    WalletGenParamsConsensus,
-    WalletConfig,
-    WalletConfigLocal,
-    WalletConfigPrivate,
-    WalletConfigConsensus,
-    WalletClientConfig
-);
+// Implemented by hand instead of with `plugin_types_trait_impl_config!` to
+// decode configs of older federations with their legacy layout
+impl ModuleInitParams for WalletGenParams {
+    type Local = WalletGenParamsLocal;
+    type Consensus = WalletGenParamsConsensus;
+
+    fn from_parts(local: Self::Local, consensus: Self::Consensus) -> Self {
+        Self { local, consensus }
+    }
+
+    fn to_parts(self) -> (Self::Local, Self::Consensus) {
+        (self.local, self.consensus)
+    }
+}
+
+impl TypedServerModuleConsensusConfig for WalletConfigConsensus {
+    fn kind(&self) -> ModuleKind {
+        WalletCommonInit::KIND
+    }
+
+    fn version(&self) -> ModuleConsensusVersion {
+        WalletCommonInit::CONSENSUS_VERSION
+    }
+
+    fn from_erased(erased: &ServerModuleConsensusConfig) -> anyhow::Result<Self> {
+        let reader = &mut &erased.config[..];
+        let modules = Default::default();
+        let mut config: Self =
+            LegacyWalletConfigConsensus::consensus_decode(reader, &modules)?.into();
+        // Peg-out batching and UTXO consolidation were added to the config
+        // together
+        if supports_peg_out_batching(erased.version) {
+            config.peg_out_batching = Decodable::consensus_decode(reader, &modules)?;
+            config.utxo_consolidation = Decodable::consensus_decode(reader, &modules)?;
+        }
+        if supports_cpfp(erased.version) {
+            config.cpfp = Decodable::consensus_decode(reader, &modules)?;
+        }
+        ensure!(
+            reader.is_empty(),
+            "Trailing bytes after wallet consensus config"
+        );
+
+        Ok(config)
+    }
+}
+
+impl TypedServerModuleConfig for WalletConfig {
+    type Local = WalletConfigLocal;
+    type Private = WalletConfigPrivate;
+    type Consensus = WalletConfigConsensus;
+
+    fn from_parts(local: Self::Local, private: Self::Private, consensus: Self::Consensus) -> Self {
+        Self {
+            local,
+            private,
+            consensus,
+        }
+    }
+
+    fn to_parts(self) -> (ModuleKind, Self::Local, Self::Private, Self::Consensus) {
+        (
+            WalletCommonInit::KIND,
+            self.local,
+            self.private,
+            self.consensus,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin::Network;
+    use fedimint_core::config::{ServerModuleConsensusConfig, TypedServerModuleConsensusConfig};
+    use fedimint_core::encoding::Encodable;
+    use fedimint_core::envs::BitcoinRpcConfig;
+    use fedimint_core::module::ModuleConsensusVersion;
+    use fedimint_core::util::SafeUrl;
+    use fedimint_core::{Feerate, PeerId};
+    use secp256k1::SecretKey;
+
+    use super::{
+        CpfpConfig, FeeConsensus, LegacyWalletConfigConsensus, PegInDescriptorKind,
+        PegOutBatchingConfig, UtxoConsolidationConfig, WalletConfig, WalletConfigConsensus,
+    };
+    use crate::keys::CompressedPublicKey;
+
+    fn consensus_config(
+        peg_out_batching: Option<PegOutBatchingConfig>,
+        utxo_consolidation: Option<UtxoConsolidationConfig>,
+        cpfp: Option<CpfpConfig>,
+    ) -> WalletConfigConsensus {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = CompressedPublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let rpc = BitcoinRpcConfig {
+            kind: "esplora".to_string(),
+            url: SafeUrl::parse("http://127.0.0.1:50002").unwrap(),
+        };
+
+        WalletConfig::new(
+            BTreeMap::from([(PeerId::from(0), pk)]),
+            sk,
+            1,
+            Network::Regtest,
+            10,
+            rpc.clone(),
+            rpc,
+            FeeConsensus::default(),
+            peg_out_batching,
+            PegInDescriptorKind::Wsh,
+            utxo_consolidation,
+            cpfp,
+        )
+        .consensus
+    }
+
+    fn erased(config: Vec<u8>, version: ModuleConsensusVersion) -> ServerModuleConsensusConfig {
+        ServerModuleConsensusConfig {
+            kind: crate::KIND,
+            version,
+            config,
+        }
+    }
+
+    #[test_log::test]
+    fn decodes_legacy_consensus_config() {
+        let config = consensus_config(None, None, None);
+        let legacy = LegacyWalletConfigConsensus {
+            network: config.network,
+            peg_in_descriptor: config.peg_in_descriptor.clone(),
+            peer_peg_in_keys: config.peer_peg_in_keys.clone(),
+            finality_delay: config.finality_delay,
+            default_fee: config.default_fee,
+            fee_consensus: config.fee_consensus,
+            client_default_bitcoin_rpc: config.client_default_bitcoin_rpc.clone(),
+        }
+        .consensus_encode_to_vec();
+
+        let decoded = WalletConfigConsensus::from_erased(&erased(
+            legacy.clone(),
+            ModuleConsensusVersion::new(2, 0),
+        ))
+        .unwrap();
+        assert_eq!(decoded.peg_in_descriptor, config.peg_in_descriptor);
+        assert_eq!(decoded.default_fee, Feerate { sats_per_kvb: 1000 });
+        assert_eq!(decoded.peg_out_batching, None);
+        assert_eq!(decoded.utxo_consolidation, None);
+
+        // The layout is selected by the version, not by the remaining bytes
+        assert!(WalletConfigConsensus::from_erased(&erased(legacy, config.version())).is_err());
+    }
+
+    #[test_log::test]
+    fn roundtrips_consensus_config_with_later_fields() {
+        let batching = PegOutBatchingConfig {
+            window: 6,
+            max_batch_size: 10,
+        };
+        let config = consensus_config(Some(batching), None, None);
+
+        let encoded = config.consensus_encode_to_vec();
+        let decoded =
+            WalletConfigConsensus::from_erased(&erased(encoded.clone(), config.version())).unwrap();
+        assert_eq!(decoded.peg_out_batching, Some(batching));
+        assert_eq!(decoded.utxo_consolidation, None);
+        assert_eq!(decoded.consensus_encode_to_vec(), encoded);
+
+        let mut trailing = encoded;
+        trailing.push(0);
+        assert!(WalletConfigConsensus::from_erased(&erased(trailing, config.version())).is_err());
+    }
+
+    #[test_log::test]
+    fn roundtrips_consensus_config_with_utxo_consolidation() {
+        let consolidation = UtxoConsolidationConfig {
+            max_fee_rate: Feerate { sats_per_kvb: 2000 },
+            min_utxos: 50,
+            max_inputs: 20,
+        };
+        let config = consensus_config(None, Some(consolidation), None);
+
+        let encoded = config.consensus_encode_to_vec();
+        let decoded =
+            WalletConfigConsensus::from_erased(&erased(encoded.clone(), config.version())).unwrap();
+        assert_eq!(decoded.peg_out_batching, None);
+        assert_eq!(decoded.utxo_consolidation, Some(consolidation));
+        assert_eq!(decoded.consensus_encode_to_vec(), encoded);
+    }
+
+    #[test_log::test]
+    fn roundtrips_consensus_config_with_cpfp() {
+        let cpfp = CpfpConfig {
+            max_fee_rate: Feerate {
+                sats_per_kvb: 50_000,
+            },
+            max_fee: bitcoin::Amount::from_sat(100_000),
+        };
+        let config = consensus_config(None, None, Some(cpfp));
+
+        let encoded = config.consensus_encode_to_vec();
+        let decoded =
+            WalletConfigConsensus::from_erased(&erased(encoded.clone(), config.version())).unwrap();
+        assert_eq!(decoded.cpfp, Some(cpfp));
+        assert_eq!(decoded.consensus_encode_to_vec(), encoded);
+
+        // Configs of federations created with version 2.1 end before the CPFP
+        // limits
+        let without_cpfp = consensus_config(None, None, None).consensus_encode_to_vec();
+        let v2_1 = ModuleConsensusVersion::new(2, 1);
+        let decoded = WalletConfigConsensus::from_erased(&erased(
+            without_cpfp[..without_cpfp.len() - 1].to_vec(),
+            v2_1,
+        ))
+        .unwrap();
+        assert_eq!(decoded.cpfp, None);
+        assert!(WalletConfigConsensus::from_erased(&erased(encoded, v2_1)).is_err());
+    }
+}
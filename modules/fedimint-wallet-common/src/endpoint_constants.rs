@@ -1,3 +1,5 @@
 pub const BLOCK_COUNT_ENDPOINT: &str = "block_count";
 pub const PEG_OUT_FEES_ENDPOINT: &str = "peg_out_fees";
 pub const BLOCK_COUNT_LOCAL_ENDPOINT: &str = "block_count_local";
+pub const UTXO_STATS_ENDPOINT: &str = "utxo_stats";
+pub const CONSENSUS_HISTORY_ENDPOINT: &str = "consensus_history";
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::hash::Hasher;
 
 use bitcoin::util::psbt::raw::ProprietaryKey;
@@ -6,7 +7,9 @@ use config::WalletClientConfig;
 use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
-use fedimint_core::{extensible_associated_module_type, plugin_types_trait_impl_common, Feerate};
+use fedimint_core::{
+    extensible_associated_module_type, plugin_types_trait_impl_common, Feerate, PeerId,
+};
 use impl_tools::autoimpl;
 use miniscript::Descriptor;
 use serde::{Deserialize, Serialize};
@@ -24,7 +27,51 @@ pub mod tweakable;
 pub mod txoproof;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("wallet");
-pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 0);
+pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 2);
+
+/// First consensus version supporting Taproot peg-in descriptors. Federations
+/// created with an earlier version keep using their `wsh` descriptor.
+pub const TAPROOT_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 1);
+
+/// Returns whether a federation running `version` may use a Taproot peg-in
+/// descriptor
+pub fn supports_taproot(version: ModuleConsensusVersion) -> bool {
+    version_at_least(version, TAPROOT_CONSENSUS_VERSION)
+}
+
+/// First consensus version whose config can enable peg-out batching.
+/// Federations created with an earlier version create one transaction per
+/// peg-out.
+pub const PEG_OUT_BATCHING_CONSENSUS_VERSION: ModuleConsensusVersion =
+    ModuleConsensusVersion::new(2, 1);
+
+/// Returns whether a federation running `version` may batch peg-outs
+pub fn supports_peg_out_batching(version: ModuleConsensusVersion) -> bool {
+    version_at_least(version, PEG_OUT_BATCHING_CONSENSUS_VERSION)
+}
+
+/// First consensus version whose config can enable UTXO consolidation
+pub const UTXO_CONSOLIDATION_CONSENSUS_VERSION: ModuleConsensusVersion =
+    ModuleConsensusVersion::new(2, 1);
+
+/// Returns whether a federation running `version` may consolidate UTXOs
+pub fn supports_utxo_consolidation(version: ModuleConsensusVersion) -> bool {
+    version_at_least(version, UTXO_CONSOLIDATION_CONSENSUS_VERSION)
+}
+
+/// First consensus version whose guardians vote on CPFP fee rates. Peers of
+/// earlier versions can't process [`WalletConsensusItem::CpfpFeerate`] items.
+pub const CPFP_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 2);
+
+/// Returns whether a federation running `version` may bump the fees of stuck
+/// peg-out transactions with CPFP
+pub fn supports_cpfp(version: ModuleConsensusVersion) -> bool {
+    version_at_least(version, CPFP_CONSENSUS_VERSION)
+}
+
+fn version_at_least(version: ModuleConsensusVersion, min: ModuleConsensusVersion) -> bool {
+    (version.major, version.minor) >= (min.major, min.minor)
+}
 
 pub const CONFIRMATION_TARGET: u16 = 10;
 
@@ -38,6 +85,15 @@ pub enum WalletConsensusItem {
                       * * verification logic */
     Feerate(Feerate),
     PegOutSignature(PegOutSignatureItem),
+    /// Vote to bump the fees of a stuck `PendingTransaction` by spending its
+    /// change in a child transaction (CPFP)
+    CpfpFeerate(CpfpFeerateItem),
+    /// Like `PegOutSignature`, but for transactions spending Taproot peg-in
+    /// outputs which need Schnorr signatures
+    TaprootPegOutSignature(TaprootPegOutSignatureItem),
+    /// Vote to sweep the smallest UTXOs of the federation wallet into a single
+    /// one at the given fee rate
+    ConsolidationFeerate(Feerate),
     #[encodable_default]
     Default {
         variant: u64,
@@ -61,6 +117,27 @@ impl std::fmt::Display for WalletConsensusItem {
             WalletConsensusItem::PegOutSignature(sig) => {
                 write!(f, "Wallet PegOut signature for Bitcoin TxId {}", sig.txid)
             }
+            WalletConsensusItem::CpfpFeerate(cpfp) => {
+                write!(
+                    f,
+                    "Wallet CPFP fee rate with sats per kvb {} for Bitcoin TxId {}",
+                    cpfp.fee_rate.sats_per_kvb, cpfp.txid
+                )
+            }
+            WalletConsensusItem::TaprootPegOutSignature(sig) => {
+                write!(
+                    f,
+                    "Wallet Taproot PegOut signature for Bitcoin TxId {}",
+                    sig.txid
+                )
+            }
+            WalletConsensusItem::ConsolidationFeerate(feerate) => {
+                write!(
+                    f,
+                    "Wallet UTXO consolidation fee rate with sats per kvb {}",
+                    feerate.sats_per_kvb
+                )
+            }
             WalletConsensusItem::Default { variant, .. } => {
                 write!(f, "Unknown Wallet CI variant={variant}")
             }
@@ -74,6 +151,20 @@ pub struct PegOutSignatureItem {
     pub signature: Vec<secp256k1::ecdsa::Signature>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct TaprootPegOutSignatureItem {
+    pub txid: Txid,
+    pub signature: Vec<secp256k1::schnorr::Signature>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct CpfpFeerateItem {
+    /// Bitcoin tx id of the stuck transaction
+    pub txid: Txid,
+    /// Fee rate the transaction and its child should pay together
+    pub fee_rate: Feerate,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
 pub struct SpendableUTXO {
     #[serde(with = "::fedimint_core::encoding::as_hex")]
@@ -82,6 +173,53 @@ pub struct SpendableUTXO {
     pub amount: bitcoin::Amount,
 }
 
+/// Statistics about the spendable UTXOs of the federation wallet
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct UtxoStats {
+    /// Number of spendable UTXOs
+    pub count: u64,
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub total_amount: bitcoin::Amount,
+    #[serde(default, with = "bitcoin::util::amount::serde::as_sat::opt")]
+    pub smallest_amount: Option<bitcoin::Amount>,
+    #[serde(default, with = "bitcoin::util::amount::serde::as_sat::opt")]
+    pub largest_amount: Option<bitcoin::Amount>,
+    /// Number of UTXOs that would be swept by the next consolidation
+    /// transaction, zero if consolidation is disabled or not due
+    pub consolidation_candidates: u64,
+    /// Bitcoin tx ids of consolidation transactions that didn't confirm yet
+    pub pending_consolidations: Vec<Txid>,
+    /// Set while the queued peg-outs can't be combined into a batch
+    /// transaction
+    #[serde(default)]
+    pub peg_out_batch_failure: Option<PegOutBatchFailure>,
+}
+
+/// Repeated failure to create a peg-out batch transaction, e.g. because the
+/// wallet doesn't hold enough funds to pay all queued peg-outs at once
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PegOutBatchFailure {
+    /// Number of consecutive failed attempts
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+}
+
+/// Maximum number of entries returned by a single consensus history request
+pub const CONSENSUS_HISTORY_MAX_LIMIT: u32 = 1000;
+
+/// Consensus fee rate and block count of the federation wallet at the end of
+/// a session, together with the votes of the individual peers so that outliers
+/// can be spotted
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize, Encodable, Decodable)]
+pub struct ConsensusHistoryEntry {
+    pub session_index: u64,
+    pub block_count: u32,
+    pub fee_rate: Feerate,
+    pub block_count_votes: BTreeMap<PeerId, u32>,
+    pub fee_rate_votes: BTreeMap<PeerId, Feerate>,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct PegOutFees {
     pub fee_rate: Feerate,
@@ -302,6 +440,10 @@ pub enum WalletOutputError {
     BelowMinRelayFee,
     #[error("The wallet output version is not supported by this federation")]
     UnknownOutputVariant(#[from] UnknownWalletOutputVariantError),
+    #[error("RBF is not supported for batched peg-out transactions")]
+    RbfOfBatchedPegOut,
+    #[error("RBF is not supported for transactions bumped with CPFP")]
+    RbfOfCpfpTransaction,
 }
 
 #[derive(Debug, Error)]
@@ -5,7 +5,10 @@ use secp256k1::ecdsa::Signature;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
-use crate::{PendingTransaction, SpendableUTXO, UnsignedTransaction, WalletOutputOutcome};
+use crate::{
+    ConsensusHistoryEntry, CpfpTransaction, PegOutBatch, PegOutBatchFailure, PendingTransaction,
+    QueuedPegOut, SpendableUTXO, UnsignedTransaction, UtxoConsolidation, WalletOutputOutcome,
+};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -19,6 +22,18 @@ pub enum DbKeyPrefix {
     PegOutTxSigCi = 0x36,
     PegOutBitcoinOutPoint = 0x37,
     PegOutNonce = 0x38,
+    QueuedPegOut = 0x39,
+    PegOutBatch = 0x3a,
+    PendingTransactionBlockCount = 0x3b,
+    CpfpVote = 0x3c,
+    CpfpTransaction = 0x3d,
+    CpfpParent = 0x3e,
+    TaprootPegOutTxSigCi = 0x3f,
+    ConsolidationVote = 0x40,
+    UtxoConsolidation = 0x41,
+    ConsensusHistory = 0x42,
+    PegOutBatchFailure = 0x43,
+    ReservedUtxo = 0x44,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -101,6 +116,24 @@ impl_db_lookup!(
     query_prefix = PegOutTxSignatureCIPrefix
 );
 
+/// Our Schnorr signatures for a peg-out transaction spending Taproot peg-in
+/// outputs
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct TaprootPegOutTxSignatureCI(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct TaprootPegOutTxSignatureCIPrefix;
+
+impl_db_record!(
+    key = TaprootPegOutTxSignatureCI,
+    value = Vec<secp256k1::schnorr::Signature>,
+    db_prefix = DbKeyPrefix::TaprootPegOutTxSigCi,
+);
+impl_db_lookup!(
+    key = TaprootPegOutTxSignatureCI,
+    query_prefix = TaprootPegOutTxSignatureCIPrefix
+);
+
 #[derive(Clone, Debug, Encodable, Decodable, Serialize)]
 pub struct PegOutBitcoinTransaction(pub fedimint_core::OutPoint);
 
@@ -154,3 +187,178 @@ impl_db_record!(
     value = u64,
     db_prefix = DbKeyPrefix::PegOutNonce
 );
+
+/// A peg-out waiting to be included in the next batch transaction
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct QueuedPegOutKey(pub fedimint_core::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct QueuedPegOutPrefix;
+
+impl_db_record!(
+    key = QueuedPegOutKey,
+    value = QueuedPegOut,
+    db_prefix = DbKeyPrefix::QueuedPegOut,
+);
+impl_db_lookup!(key = QueuedPegOutKey, query_prefix = QueuedPegOutPrefix);
+
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutBatchKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutBatchPrefix;
+
+impl_db_record!(
+    key = PegOutBatchKey,
+    value = PegOutBatch,
+    db_prefix = DbKeyPrefix::PegOutBatch,
+);
+impl_db_lookup!(key = PegOutBatchKey, query_prefix = PegOutBatchPrefix);
+
+/// Set while the queued peg-outs can't be combined into a batch transaction,
+/// removed by the next successful batch
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutBatchFailureKey;
+
+impl_db_record!(
+    key = PegOutBatchFailureKey,
+    value = PegOutBatchFailure,
+    db_prefix = DbKeyPrefix::PegOutBatchFailure,
+);
+
+/// UTXO funding the batch of the queued peg-outs, which other transactions
+/// must not spend
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct ReservedUTXOKey(pub bitcoin::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct ReservedUTXOPrefix;
+
+impl_db_record!(
+    key = ReservedUTXOKey,
+    value = (),
+    db_prefix = DbKeyPrefix::ReservedUtxo,
+);
+impl_db_lookup!(key = ReservedUTXOKey, query_prefix = ReservedUTXOPrefix);
+
+/// Consensus block count when a `PendingTransaction` was created
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PendingTransactionBlockCountKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PendingTransactionBlockCountPrefix;
+
+impl_db_record!(
+    key = PendingTransactionBlockCountKey,
+    value = u32,
+    db_prefix = DbKeyPrefix::PendingTransactionBlockCount,
+);
+impl_db_lookup!(
+    key = PendingTransactionBlockCountKey,
+    query_prefix = PendingTransactionBlockCountPrefix
+);
+
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct CpfpVoteKey(pub Txid, pub PeerId);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct CpfpVoteTxidPrefix(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct CpfpVotePrefix;
+
+impl_db_record!(
+    key = CpfpVoteKey,
+    value = fedimint_core::Feerate,
+    db_prefix = DbKeyPrefix::CpfpVote,
+);
+impl_db_lookup!(
+    key = CpfpVoteKey,
+    query_prefix = CpfpVoteTxidPrefix,
+    query_prefix = CpfpVotePrefix
+);
+
+/// Child transaction bumping the fees of a stuck transaction, keyed by its
+/// txid
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct CpfpTransactionKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct CpfpTransactionPrefix;
+
+impl_db_record!(
+    key = CpfpTransactionKey,
+    value = CpfpTransaction,
+    db_prefix = DbKeyPrefix::CpfpTransaction,
+);
+impl_db_lookup!(
+    key = CpfpTransactionKey,
+    query_prefix = CpfpTransactionPrefix
+);
+
+/// Transaction whose change is spent by a CPFP child, mapping to the txid of
+/// the child
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct CpfpParentKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct CpfpParentPrefix;
+
+impl_db_record!(
+    key = CpfpParentKey,
+    value = Txid,
+    db_prefix = DbKeyPrefix::CpfpParent,
+);
+impl_db_lookup!(key = CpfpParentKey, query_prefix = CpfpParentPrefix);
+
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct ConsolidationVoteKey(pub PeerId);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct ConsolidationVotePrefix;
+
+impl_db_record!(
+    key = ConsolidationVoteKey,
+    value = fedimint_core::Feerate,
+    db_prefix = DbKeyPrefix::ConsolidationVote,
+);
+impl_db_lookup!(
+    key = ConsolidationVoteKey,
+    query_prefix = ConsolidationVotePrefix
+);
+
+/// Transaction sweeping UTXOs of the federation wallet that didn't confirm
+/// yet, keyed by its txid
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct UtxoConsolidationKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct UtxoConsolidationPrefix;
+
+impl_db_record!(
+    key = UtxoConsolidationKey,
+    value = UtxoConsolidation,
+    db_prefix = DbKeyPrefix::UtxoConsolidation,
+);
+impl_db_lookup!(
+    key = UtxoConsolidationKey,
+    query_prefix = UtxoConsolidationPrefix
+);
+
+/// Consensus fee rate and block count history, keyed by the index of the
+/// session at whose end the entry was recorded
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct ConsensusHistoryKey(pub u64);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct ConsensusHistoryPrefix;
+
+impl_db_record!(
+    key = ConsensusHistoryKey,
+    value = ConsensusHistoryEntry,
+    db_prefix = DbKeyPrefix::ConsensusHistory,
+);
+impl_db_lookup!(
+    key = ConsensusHistoryKey,
+    query_prefix = ConsensusHistoryPrefix
+);
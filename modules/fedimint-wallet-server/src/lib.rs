@@ -5,21 +5,27 @@ use std::convert::Infallible;
 #[cfg(not(target_family = "wasm"))]
 use std::time::Duration;
 
-use anyhow::{bail, format_err, Context};
+use anyhow::{bail, ensure, format_err, Context};
 use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine, Hmac, HmacEngine};
 use bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
 use bitcoin::secp256k1::{All, Secp256k1, Verification};
 use bitcoin::util::psbt::{Input, PartiallySignedTransaction};
-use bitcoin::util::sighash::SighashCache;
+use bitcoin::util::schnorr::SchnorrSig;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
 use bitcoin::{
-    Address, BlockHash, EcdsaSig, EcdsaSighashType, Network, PackedLockTime, Script, Sequence,
-    Transaction, TxIn, TxOut, Txid,
+    Address, BlockHash, EcdsaSig, EcdsaSighashType, KeyPair, Network, PackedLockTime,
+    SchnorrSighashType, Script, Sequence, Transaction, TxIn, TxOut, Txid, XOnlyPublicKey,
 };
 use common::config::WalletConfigConsensus;
 use common::{
-    proprietary_tweak_key, PegOutFees, PegOutSignatureItem, ProcessPegOutSigError, SpendableUTXO,
-    WalletCommonInit, WalletConsensusItem, WalletCreationError, WalletInput, WalletModuleTypes,
-    WalletOutput, WalletOutputOutcome, CONFIRMATION_TARGET,
+    proprietary_tweak_key, supports_cpfp, supports_peg_out_batching, supports_taproot,
+    supports_utxo_consolidation, CpfpFeerateItem, PegOutBatchFailure, PegOutFees,
+    PegOutSignatureItem, ProcessPegOutSigError, SpendableUTXO, TaprootPegOutSignatureItem,
+    UtxoStats, WalletCommonInit, WalletConsensusItem, WalletCreationError, WalletInput,
+    WalletModuleTypes, WalletOutput, WalletOutputOutcome, CONFIRMATION_TARGET,
+    CPFP_CONSENSUS_VERSION, PEG_OUT_BATCHING_CONSENSUS_VERSION, TAPROOT_CONSENSUS_VERSION,
+    UTXO_CONSOLIDATION_CONSENSUS_VERSION,
 };
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
 use fedimint_core::bitcoin_migration::{
@@ -49,20 +55,26 @@ use fedimint_core::task::sleep;
 use fedimint_core::task::{TaskGroup, TaskHandle};
 use fedimint_core::time::now;
 use fedimint_core::{
-    apply, async_trait_maybe_send, push_db_key_items, push_db_pair_items, Feerate, NumPeersExt,
-    OutPoint, PeerId, ServerModule,
+    apply, async_trait_maybe_send, push_db_key_items, push_db_pair_items, weight_to_vbytes,
+    Feerate, NumPeersExt, OutPoint, PeerId, ServerModule,
 };
 use fedimint_logging::LOG_MODULE_WALLET;
+use fedimint_server::check_auth;
 use fedimint_server::config::distributedgen::PeerHandleOps;
 pub use fedimint_wallet_common as common;
-use fedimint_wallet_common::config::{WalletClientConfig, WalletConfig, WalletGenParams};
+use fedimint_wallet_common::config::{
+    PegOutBatchingConfig, UtxoConsolidationConfig, WalletClientConfig, WalletConfig,
+    WalletGenParams,
+};
 use fedimint_wallet_common::endpoint_constants::{
-    BLOCK_COUNT_ENDPOINT, BLOCK_COUNT_LOCAL_ENDPOINT, PEG_OUT_FEES_ENDPOINT,
+    BLOCK_COUNT_ENDPOINT, BLOCK_COUNT_LOCAL_ENDPOINT, CONSENSUS_HISTORY_ENDPOINT,
+    PEG_OUT_FEES_ENDPOINT, UTXO_STATS_ENDPOINT,
 };
 use fedimint_wallet_common::keys::CompressedPublicKey;
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::{
-    Rbf, WalletInputError, WalletOutputError, WalletOutputV0, MODULE_CONSENSUS_VERSION,
+    ConsensusHistoryEntry, PegOut, Rbf, WalletInputError, WalletOutputError, WalletOutputV0,
+    CONSENSUS_HISTORY_MAX_LIMIT, MODULE_CONSENSUS_VERSION,
 };
 use futures::StreamExt;
 use hex::ToHex;
@@ -77,19 +89,33 @@ use secp256k1::{Message, Scalar};
 use serde::Serialize;
 use strum::IntoEnumIterator;
 use tokio::sync::watch;
-use tracing::{debug, info, instrument, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn};
 
 use crate::db::{
-    BlockCountVoteKey, BlockCountVotePrefix, BlockHashKey, BlockHashKeyPrefix, DbKeyPrefix,
-    FeeRateVoteKey, FeeRateVotePrefix, PegOutBitcoinTransaction, PegOutBitcoinTransactionPrefix,
-    PegOutNonceKey, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix, PendingTransactionKey,
-    PendingTransactionPrefixKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
-    UnsignedTransactionPrefixKey,
+    BlockCountVoteKey, BlockCountVotePrefix, BlockHashKey, BlockHashKeyPrefix, ConsensusHistoryKey,
+    ConsensusHistoryPrefix, ConsolidationVoteKey, ConsolidationVotePrefix, CpfpParentKey,
+    CpfpParentPrefix, CpfpTransactionKey, CpfpTransactionPrefix, CpfpVoteKey, CpfpVotePrefix,
+    CpfpVoteTxidPrefix, DbKeyPrefix, FeeRateVoteKey, FeeRateVotePrefix, PegOutBatchFailureKey,
+    PegOutBatchKey, PegOutBatchPrefix, PegOutBitcoinTransaction, PegOutBitcoinTransactionPrefix,
+    PegOutNonceKey, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix,
+    PendingTransactionBlockCountKey, PendingTransactionBlockCountPrefix, PendingTransactionKey,
+    PendingTransactionPrefixKey, QueuedPegOutKey, QueuedPegOutPrefix, ReservedUTXOKey,
+    ReservedUTXOPrefix, TaprootPegOutTxSignatureCI, TaprootPegOutTxSignatureCIPrefix, UTXOKey,
+    UTXOPrefixKey, UnsignedTransactionKey, UnsignedTransactionPrefixKey, UtxoConsolidationKey,
+    UtxoConsolidationPrefix,
 };
 use crate::metrics::WALLET_BLOCK_COUNT;
 
 mod metrics;
 
+/// Number of consensus blocks a `PendingTransaction` needs to stay unconfirmed
+/// before guardians vote to bump its fees with CPFP
+const CPFP_MIN_PENDING_BLOCKS: u32 = 6;
+
+/// Number of consecutive failed attempts to create a peg-out batch after which
+/// the queued peg-outs are paid individually instead
+const MAX_PEG_OUT_BATCH_ATTEMPTS: u32 = 3;
+
 #[derive(Debug, Clone)]
 pub struct WalletInit;
 
@@ -131,6 +157,16 @@ impl ModuleInit for WalletInit {
                         "Peg Out Transaction Signatures"
                     );
                 }
+                DbKeyPrefix::TaprootPegOutTxSigCi => {
+                    push_db_pair_items!(
+                        dbtx,
+                        TaprootPegOutTxSignatureCIPrefix,
+                        TaprootPegOutTxSignatureCI,
+                        Vec<secp256k1::schnorr::Signature>,
+                        wallet,
+                        "Taproot Peg Out Transaction Signatures"
+                    );
+                }
                 DbKeyPrefix::PendingTransaction => {
                     push_db_pair_items!(
                         dbtx,
@@ -188,6 +224,110 @@ impl ModuleInit for WalletInit {
                         "Fee Rate Votes"
                     );
                 }
+                DbKeyPrefix::QueuedPegOut => {
+                    push_db_pair_items!(
+                        dbtx,
+                        QueuedPegOutPrefix,
+                        QueuedPegOutKey,
+                        QueuedPegOut,
+                        wallet,
+                        "Queued Peg Outs"
+                    );
+                }
+                DbKeyPrefix::PegOutBatch => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutBatchPrefix,
+                        PegOutBatchKey,
+                        PegOutBatch,
+                        wallet,
+                        "Peg Out Batches"
+                    );
+                }
+                DbKeyPrefix::PendingTransactionBlockCount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PendingTransactionBlockCountPrefix,
+                        PendingTransactionBlockCountKey,
+                        u32,
+                        wallet,
+                        "Pending Transaction Block Counts"
+                    );
+                }
+                DbKeyPrefix::CpfpVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        CpfpVotePrefix,
+                        CpfpVoteKey,
+                        Feerate,
+                        wallet,
+                        "CPFP Votes"
+                    );
+                }
+                DbKeyPrefix::CpfpTransaction => {
+                    push_db_pair_items!(
+                        dbtx,
+                        CpfpTransactionPrefix,
+                        CpfpTransactionKey,
+                        CpfpTransaction,
+                        wallet,
+                        "CPFP Transactions"
+                    );
+                }
+                DbKeyPrefix::CpfpParent => {
+                    push_db_pair_items!(
+                        dbtx,
+                        CpfpParentPrefix,
+                        CpfpParentKey,
+                        Txid,
+                        wallet,
+                        "CPFP Parents"
+                    );
+                }
+                DbKeyPrefix::ConsolidationVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsolidationVotePrefix,
+                        ConsolidationVoteKey,
+                        Feerate,
+                        wallet,
+                        "UTXO Consolidation Votes"
+                    );
+                }
+                DbKeyPrefix::UtxoConsolidation => {
+                    push_db_pair_items!(
+                        dbtx,
+                        UtxoConsolidationPrefix,
+                        UtxoConsolidationKey,
+                        UtxoConsolidation,
+                        wallet,
+                        "UTXO Consolidations"
+                    );
+                }
+                DbKeyPrefix::ConsensusHistory => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusHistoryPrefix,
+                        ConsensusHistoryKey,
+                        ConsensusHistoryEntry,
+                        wallet,
+                        "Consensus History"
+                    );
+                }
+                DbKeyPrefix::PegOutBatchFailure => {
+                    if let Some(failure) = dbtx.get_value(&PegOutBatchFailureKey).await {
+                        wallet.insert("Peg Out Batch Failure".to_string(), Box::new(failure));
+                    }
+                }
+                DbKeyPrefix::ReservedUtxo => {
+                    push_db_key_items!(
+                        dbtx,
+                        ReservedUTXOPrefix,
+                        ReservedUTXOKey,
+                        wallet,
+                        "Reserved UTXOs"
+                    );
+                }
             }
         }
 
@@ -200,7 +340,12 @@ impl ServerModuleInit for WalletInit {
     type Params = WalletGenParams;
 
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[MODULE_CONSENSUS_VERSION]
+        const VERSIONS: [ModuleConsensusVersion; 3] = [
+            ModuleConsensusVersion::new(2, 0),
+            ModuleConsensusVersion::new(2, 1),
+            MODULE_CONSENSUS_VERSION,
+        ];
+        &VERSIONS
     }
 
     fn supported_api_versions(&self) -> SupportedModuleApiVersions {
@@ -231,6 +376,7 @@ impl ServerModuleInit for WalletInit {
 
         Ok(Wallet::new(
             args.cfg().to_typed()?,
+            args.cfg().consensus.version,
             args.db().clone(),
             &mut args.task_group().clone(),
             args.our_peer_id(),
@@ -267,6 +413,10 @@ impl ServerModuleInit for WalletInit {
                     params.local.bitcoin_rpc.clone(),
                     params.consensus.client_default_bitcoin_rpc.clone(),
                     params.consensus.fee_consensus,
+                    params.consensus.peg_out_batching,
+                    params.consensus.peg_in_descriptor_kind,
+                    params.consensus.utxo_consolidation,
+                    params.consensus.cpfp,
                 );
                 (*id, cfg)
             })
@@ -313,13 +463,53 @@ impl ServerModuleInit for WalletInit {
             params.local.bitcoin_rpc.clone(),
             params.consensus.client_default_bitcoin_rpc.clone(),
             params.consensus.fee_consensus,
+            params.consensus.peg_out_batching,
+            params.consensus.peg_in_descriptor_kind,
+            params.consensus.utxo_consolidation,
+            params.consensus.cpfp,
         );
 
         Ok(wallet_cfg.to_erased())
     }
 
     fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        let version = config.consensus.version;
         let config = config.to_typed::<WalletConfig>()?;
+
+        if matches!(config.consensus.peg_in_descriptor, Descriptor::Tr(_))
+            && !supports_taproot(version)
+        {
+            bail!(
+                "Taproot peg-in descriptors require wallet consensus version {}.{}",
+                TAPROOT_CONSENSUS_VERSION.major,
+                TAPROOT_CONSENSUS_VERSION.minor
+            );
+        }
+
+        if config.consensus.peg_out_batching.is_some() && !supports_peg_out_batching(version) {
+            bail!(
+                "Peg-out batching requires wallet consensus version {}.{}",
+                PEG_OUT_BATCHING_CONSENSUS_VERSION.major,
+                PEG_OUT_BATCHING_CONSENSUS_VERSION.minor
+            );
+        }
+
+        if config.consensus.utxo_consolidation.is_some() && !supports_utxo_consolidation(version) {
+            bail!(
+                "UTXO consolidation requires wallet consensus version {}.{}",
+                UTXO_CONSOLIDATION_CONSENSUS_VERSION.major,
+                UTXO_CONSOLIDATION_CONSENSUS_VERSION.minor
+            );
+        }
+
+        if config.consensus.cpfp.is_some() && !supports_cpfp(version) {
+            bail!(
+                "CPFP requires wallet consensus version {}.{}",
+                CPFP_CONSENSUS_VERSION.major,
+                CPFP_CONSENSUS_VERSION.minor
+            );
+        }
+
         let pubkey = secp256k1::PublicKey::from_secret_key_global(&config.private.peg_in_key);
 
         if config
@@ -371,6 +561,19 @@ impl ServerModule for Wallet {
             .collect::<Vec<WalletConsensusItem>>()
             .await;
 
+        items.extend(
+            dbtx.find_by_prefix(&TaprootPegOutTxSignatureCIPrefix)
+                .await
+                .map(|(key, val)| {
+                    WalletConsensusItem::TaprootPegOutSignature(TaprootPegOutSignatureItem {
+                        txid: key.0,
+                        signature: val,
+                    })
+                })
+                .collect::<Vec<WalletConsensusItem>>()
+                .await,
+        );
+
         // If we are unable to get a block count from the node we skip adding a block
         // count vote to consensus items.
         //
@@ -401,6 +604,23 @@ impl ServerModule for Wallet {
 
         items.push(WalletConsensusItem::Feerate(fee_rate_proposal));
 
+        if supports_cpfp(self.consensus_version) {
+            items.extend(
+                self.cpfp_proposals(dbtx, fee_rate_proposal)
+                    .await
+                    .into_iter()
+                    .map(WalletConsensusItem::CpfpFeerate),
+            );
+        }
+
+        if supports_utxo_consolidation(self.consensus_version) {
+            items.extend(
+                self.consolidation_proposal(dbtx, fee_rate_proposal)
+                    .await
+                    .map(WalletConsensusItem::ConsolidationFeerate),
+            );
+        }
+
         items
     }
 
@@ -467,6 +687,11 @@ impl ServerModule for Wallet {
                     }
                     _ => {}
                 }
+
+                if let Some(new_consensus_block_count) = new_consensus_block_count {
+                    self.flush_expired_peg_out_queue(dbtx, new_consensus_block_count)
+                        .await;
+                }
             }
             WalletConsensusItem::Feerate(feerate) => {
                 if Some(feerate) == dbtx.insert_entry(&FeeRateVoteKey(peer_id), &feerate).await {
@@ -474,33 +699,135 @@ impl ServerModule for Wallet {
                 }
             }
             WalletConsensusItem::PegOutSignature(peg_out_signature) => {
-                let txid = peg_out_signature.txid;
+                self.process_peg_out_signature(dbtx, peg_out_signature.txid, |psbt| {
+                    self.sign_peg_out_psbt(psbt, &peer_id, &peg_out_signature)
+                })
+                .await?;
+            }
+            WalletConsensusItem::TaprootPegOutSignature(peg_out_signature) => {
+                self.process_peg_out_signature(dbtx, peg_out_signature.txid, |psbt| {
+                    self.sign_taproot_peg_out_psbt(psbt, &peer_id, &peg_out_signature)
+                })
+                .await?;
+            }
+            WalletConsensusItem::CpfpFeerate(cpfp) => {
+                ensure!(
+                    supports_cpfp(self.consensus_version),
+                    "CPFP requires wallet consensus version {}.{}",
+                    CPFP_CONSENSUS_VERSION.major,
+                    CPFP_CONSENSUS_VERSION.minor
+                );
+
+                let config = self.cfg.consensus.cpfp.context("CPFP is disabled")?;
+
+                ensure!(
+                    cpfp.fee_rate <= config.max_fee_rate,
+                    "CPFP fee rate exceeds the configured maximum"
+                );
+
+                let parent = self
+                    .cpfp_candidates(dbtx)
+                    .await
+                    .remove(&cpfp.txid)
+                    .context("Transaction can't be bumped with CPFP")?;
+
+                ensure!(
+                    parent.fees.fee_rate < cpfp.fee_rate,
+                    "CPFP fee rate doesn't increase the fee rate"
+                );
+                // The fee of the child doesn't depend on the tweak of its change
+                ensure!(
+                    self.create_cpfp_child_tx(cpfp.txid, &parent, cpfp.fee_rate, &[0; 33])?
+                        .fees
+                        .amount()
+                        <= config.max_fee,
+                    "CPFP fee exceeds the configured maximum"
+                );
 
-                if dbtx.get_value(&PendingTransactionKey(txid)).await.is_some() {
-                    bail!("Already received a threshold of valid signatures");
+                if Some(cpfp.fee_rate)
+                    == dbtx
+                        .insert_entry(&CpfpVoteKey(cpfp.txid, peer_id), &cpfp.fee_rate)
+                        .await
+                {
+                    bail!("CPFP vote is redundant");
                 }
 
-                let mut unsigned = dbtx
-                    .get_value(&UnsignedTransactionKey(txid))
+                let mut votes = dbtx
+                    .find_by_prefix(&CpfpVoteTxidPrefix(cpfp.txid))
                     .await
-                    .context("Unsigned transaction does not exist")?;
+                    .map(|(.., fee_rate)| fee_rate)
+                    .collect::<Vec<_>>()
+                    .await;
+
+                let threshold = self.cfg.consensus.peer_peg_in_keys.threshold();
+                if threshold <= votes.len() {
+                    // The highest fee rate a threshold of guardians voted for
+                    votes.sort_unstable_by(|a, b| b.cmp(a));
+                    let fee_rate = votes[threshold - 1];
+
+                    if let Err(error) = self
+                        .bump_fee_with_cpfp(dbtx, cpfp.txid, parent, fee_rate)
+                        .await
+                    {
+                        warn!(target: LOG_MODULE_WALLET, txid = %cpfp.txid, %error, "Unable to bump fees with CPFP");
+                    }
+                }
+            }
+            WalletConsensusItem::ConsolidationFeerate(fee_rate) => {
+                ensure!(
+                    supports_utxo_consolidation(self.consensus_version),
+                    "UTXO consolidation requires wallet consensus version {}.{}",
+                    UTXO_CONSOLIDATION_CONSENSUS_VERSION.major,
+                    UTXO_CONSOLIDATION_CONSENSUS_VERSION.minor
+                );
+
+                let consolidation = self
+                    .cfg
+                    .consensus
+                    .utxo_consolidation
+                    .context("UTXO consolidation is disabled")?;
+
+                ensure!(
+                    fee_rate <= consolidation.max_fee_rate,
+                    "Consolidation fee rate exceeds the configured maximum"
+                );
+                ensure!(
+                    !self.consolidation_in_progress(dbtx).await,
+                    "UTXO consolidation is already in progress"
+                );
+                ensure!(
+                    !self
+                        .consolidation_candidates(dbtx, consolidation)
+                        .await
+                        .is_empty(),
+                    "UTXO consolidation is not due"
+                );
 
-                self.sign_peg_out_psbt(&mut unsigned.psbt, &peer_id, &peg_out_signature)
-                    .context("Peg out signature is invalid")?;
+                if Some(fee_rate)
+                    == dbtx
+                        .insert_entry(&ConsolidationVoteKey(peer_id), &fee_rate)
+                        .await
+                {
+                    bail!("Consolidation vote is redundant");
+                }
 
-                dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
+                let mut votes = dbtx
+                    .find_by_prefix(&ConsolidationVotePrefix)
+                    .await
+                    .map(|(.., fee_rate)| fee_rate)
+                    .collect::<Vec<_>>()
                     .await;
 
-                if let Ok(pending_tx) = self.finalize_peg_out_psbt(unsigned) {
-                    // We were able to finalize the transaction, so we will delete the
-                    // PSBT and instead keep the extracted tx for periodic transmission
-                    // as well as to accept the change into our wallet eventually once
-                    // it confirms.
-                    dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
-                        .await;
+                let threshold = self.cfg.consensus.peer_peg_in_keys.threshold();
+                if threshold <= votes.len() {
+                    // The highest fee rate a threshold of guardians voted for
+                    votes.sort_unstable_by(|a, b| b.cmp(a));
+                    let fee_rate = votes[threshold - 1];
 
-                    dbtx.remove_entry(&PegOutTxSignatureCI(txid)).await;
-                    dbtx.remove_entry(&UnsignedTransactionKey(txid)).await;
+                    if let Err(error) = self.consolidate_utxos(dbtx, consolidation, fee_rate).await
+                    {
+                        warn!(target: LOG_MODULE_WALLET, %error, "Unable to consolidate UTXOs");
+                    }
                 }
             }
             WalletConsensusItem::Default { variant, .. } => {
@@ -511,6 +838,10 @@ impl ServerModule for Wallet {
         Ok(())
     }
 
+    async fn end_session<'a, 'b>(&'a self, dbtx: &mut DatabaseTransaction<'b>, session_index: u64) {
+        self.record_consensus_history(dbtx, session_index).await;
+    }
+
     async fn process_input<'a, 'b, 'c>(
         &'a self,
         dbtx: &mut DatabaseTransaction<'c>,
@@ -558,60 +889,24 @@ impl ServerModule for Wallet {
     ) -> Result<TransactionItemAmount, WalletOutputError> {
         let output = output.ensure_v0_ref()?;
 
+        if let (WalletOutputV0::PegOut(peg_out), Some(batching)) =
+            (output, self.cfg.consensus.peg_out_batching)
+        {
+            return self
+                .queue_peg_out(dbtx, output, peg_out, out_point, batching)
+                .await;
+        }
+
         let change_tweak = self.consensus_nonce(dbtx).await;
 
-        let mut tx = self.create_peg_out_tx(dbtx, output, &change_tweak).await?;
+        let tx = self.create_peg_out_tx(dbtx, output, &change_tweak).await?;
 
         let fee_rate = self.consensus_fee_rate(dbtx).await;
 
         self.offline_wallet()
             .validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)?;
 
-        self.offline_wallet().sign_psbt(&mut tx.psbt);
-
-        let txid = tx.psbt.unsigned_tx.txid();
-
-        info!(
-            %txid,
-            "Signing peg out",
-        );
-
-        let sigs = tx
-            .psbt
-            .inputs
-            .iter_mut()
-            .map(|input| {
-                assert_eq!(
-                    input.partial_sigs.len(),
-                    1,
-                    "There was already more than one (our) or no signatures in input"
-                );
-
-                // TODO: don't put sig into PSBT in the first place
-                // We actually take out our own signature so everyone finalizes the tx in the
-                // same epoch.
-                let sig = std::mem::take(&mut input.partial_sigs)
-                    .into_values()
-                    .next()
-                    .expect("asserted previously");
-
-                // We drop SIGHASH_ALL, because we always use that and it is only present in the
-                // PSBT for compatibility with other tools.
-                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
-                    .expect("we serialized it ourselves that way")
-            })
-            .collect::<Vec<_>>();
-
-        // Delete used UTXOs
-        for input in tx.psbt.unsigned_tx.input.iter() {
-            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
-        }
-
-        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
-            .await;
-
-        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
-            .await;
+        let txid = self.sign_peg_out_tx(dbtx, tx).await;
 
         dbtx.insert_new_entry(
             &PegOutBitcoinTransaction(out_point),
@@ -665,6 +960,16 @@ impl ServerModule for Wallet {
                 },
             )
             .await;
+        audit
+            .add_items(dbtx, module_instance_id, &QueuedPegOutPrefix, |_, v| {
+                (v.peg_out.amount + v.peg_out.fees.amount()).to_sat() as i64 * -1000
+            })
+            .await;
+        audit
+            .add_items(dbtx, module_instance_id, &CpfpTransactionPrefix, |_, v| {
+                v.fee.to_sat() as i64 * -1000
+            })
+            .await;
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -694,11 +999,14 @@ impl ServerModule for Wallet {
                     // Since we are only calculating the tx size we can use an arbitrary dummy nonce.
                     let dummy_tweak = [0; 33];
 
+                    // UTXOs are only reserved for queued peg-outs, which are
+                    // validated against all UTXOs as well
+
                     let tx = module.offline_wallet().create_tx(
                         bitcoin::Amount::from_sat(sats),
                         address.script_pubkey(),
                         vec![],
-                        module.available_utxos(&mut context.dbtx().into_nc()).await,
+                        module.wallet_utxos(&mut context.dbtx().into_nc()).await,
                         feerate,
                         &dummy_tweak,
                         None
@@ -714,6 +1022,24 @@ impl ServerModule for Wallet {
                     }
                 }
             },
+            api_endpoint! {
+                CONSENSUS_HISTORY_ENDPOINT,
+                ApiVersion::new(0, 0),
+                async |module: &Wallet, context, params: (Option<u64>, u32)| -> Vec<ConsensusHistoryEntry> {
+                    let (before, limit) = params;
+
+                    Ok(module.consensus_history(&mut context.dbtx().into_nc(), before, limit).await)
+                }
+            },
+            api_endpoint! {
+                UTXO_STATS_ENDPOINT,
+                ApiVersion::new(0, 0),
+                async |module: &Wallet, context, _params: ()| -> UtxoStats {
+                    check_auth(context)?;
+
+                    Ok(module.utxo_stats(&mut context.dbtx().into_nc()).await)
+                }
+            },
         ]
     }
 }
@@ -764,21 +1090,29 @@ pub struct Wallet {
     block_count_rx: watch::Receiver<Option<u32>>,
     /// Fee rate updated periodically by a background task
     fee_rate_rx: watch::Receiver<Feerate>,
+    /// Consensus version the federation was created with, determines which
+    /// consensus items the peers can process
+    consensus_version: ModuleConsensusVersion,
 }
 
 impl Wallet {
     pub async fn new(
         cfg: WalletConfig,
+        consensus_version: ModuleConsensusVersion,
         db: Database,
         task_group: &mut TaskGroup,
         our_peer_id: PeerId,
     ) -> anyhow::Result<Wallet> {
         let btc_rpc = create_bitcoind(&cfg.local.bitcoin_rpc, task_group.make_handle())?;
-        Ok(Self::new_with_bitcoind(cfg, db, btc_rpc, task_group, our_peer_id).await?)
+        Ok(
+            Self::new_with_bitcoind(cfg, consensus_version, db, btc_rpc, task_group, our_peer_id)
+                .await?,
+        )
     }
 
     pub async fn new_with_bitcoind(
         cfg: WalletConfig,
+        consensus_version: ModuleConsensusVersion,
         db: Database,
         bitcoind: DynBitcoindRpc,
         task_group: &mut TaskGroup,
@@ -812,6 +1146,7 @@ impl Wallet {
             our_peer_id,
             block_count_rx,
             fee_rate_rx,
+            consensus_version,
         };
 
         Ok(wallet)
@@ -883,6 +1218,109 @@ impl Wallet {
         Ok(())
     }
 
+    /// Try to attach Schnorr signatures to a pending peg-out tx spending
+    /// Taproot peg-in outputs.
+    fn sign_taproot_peg_out_psbt(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        peer: &PeerId,
+        signature: &TaprootPegOutSignatureItem,
+    ) -> Result<(), ProcessPegOutSigError> {
+        let peer_key = self
+            .cfg
+            .consensus
+            .peer_peg_in_keys
+            .get(peer)
+            .expect("always called with valid peer id");
+
+        if psbt.inputs.len() != signature.signature.len() {
+            return Err(ProcessPegOutSigError::WrongSignatureCount(
+                psbt.inputs.len(),
+                signature.signature.len(),
+            ));
+        }
+
+        let prevouts = prevouts(psbt);
+        let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
+        for (idx, (input, signature)) in psbt
+            .inputs
+            .iter_mut()
+            .zip(signature.signature.iter())
+            .enumerate()
+        {
+            let (leaf_hash, tx_hash) =
+                taproot_script_spend_sighash(&mut tx_hasher, idx, &prevouts, input)
+                    .ok_or(ProcessPegOutSigError::SighashError)?;
+
+            let tweak = input
+                .proprietary
+                .get(&proprietary_tweak_key())
+                .expect("we saved it with a tweak");
+
+            let (tweaked_peer_key, _) = peer_key.tweak(tweak, &self.secp).key.x_only_public_key();
+            self.secp
+                .verify_schnorr(signature, &tx_hash, &tweaked_peer_key)
+                .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+            if input
+                .tap_script_sigs
+                .insert(
+                    (tweaked_peer_key, leaf_hash),
+                    SchnorrSig {
+                        sig: *signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    },
+                )
+                .is_some()
+            {
+                // Should never happen since peers only sign a PSBT once
+                return Err(ProcessPegOutSigError::DuplicateSignature);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the signatures of a peer to a peg-out tx using `sign` and
+    /// finalizes the tx once a threshold of peers signed it
+    async fn process_peg_out_signature(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        txid: Txid,
+        sign: impl FnOnce(&mut PartiallySignedTransaction) -> Result<(), ProcessPegOutSigError>,
+    ) -> anyhow::Result<()> {
+        if dbtx.get_value(&PendingTransactionKey(txid)).await.is_some() {
+            bail!("Already received a threshold of valid signatures");
+        }
+
+        let mut unsigned = dbtx
+            .get_value(&UnsignedTransactionKey(txid))
+            .await
+            .context("Unsigned transaction does not exist")?;
+
+        sign(&mut unsigned.psbt).context("Peg out signature is invalid")?;
+
+        dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
+            .await;
+
+        if let Ok(pending_tx) = self.finalize_peg_out_psbt(unsigned) {
+            // We were able to finalize the transaction, so we will delete the
+            // PSBT and instead keep the extracted tx for periodic transmission
+            // as well as to accept the change into our wallet eventually once
+            // it confirms.
+            dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
+                .await;
+            let block_count = self.consensus_block_count(dbtx).await.unwrap_or(0);
+            dbtx.insert_new_entry(&PendingTransactionBlockCountKey(txid), &block_count)
+                .await;
+
+            dbtx.remove_entry(&PegOutTxSignatureCI(txid)).await;
+            dbtx.remove_entry(&TaprootPegOutTxSignatureCI(txid)).await;
+            dbtx.remove_entry(&UnsignedTransactionKey(txid)).await;
+        }
+
+        Ok(())
+    }
+
     fn finalize_peg_out_psbt(
         &self,
         mut unsigned: UnsignedTransaction,
@@ -919,18 +1357,640 @@ impl Wallet {
         })
     }
 
-    async fn get_block_count(&self) -> anyhow::Result<u32> {
-        self.block_count_rx
-            .borrow()
-            .ok_or_else(|| format_err!("Block count not available yet"))
-    }
-
-    pub fn get_fee_rate_opt(&self) -> Feerate {
-        *self.fee_rate_rx.borrow()
-    }
+    /// Signs a peg-out transaction, marks its inputs as spent and stores it
+    /// until the federation collected enough signatures to broadcast it
+    async fn sign_peg_out_tx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        mut tx: UnsignedTransaction,
+    ) -> Txid {
+        self.offline_wallet().sign_psbt(&mut tx.psbt);
 
-    pub async fn consensus_block_count(&self, dbtx: &mut DatabaseTransaction<'_>) -> Option<u32> {
-        let peer_count = self.cfg.consensus.peer_peg_in_keys.total();
+        let txid = tx.psbt.unsigned_tx.txid();
+
+        info!(
+            %txid,
+            "Signing peg out",
+        );
+
+        // TODO: don't put sig into PSBT in the first place
+        // We actually take out our own signature so everyone finalizes the tx in the
+        // same epoch.
+        if matches!(self.cfg.consensus.peg_in_descriptor, Descriptor::Tr(_)) {
+            let sigs = tx
+                .psbt
+                .inputs
+                .iter_mut()
+                .map(|input| {
+                    assert_eq!(
+                        input.tap_script_sigs.len(),
+                        1,
+                        "There was already more than one (our) or no signatures in input"
+                    );
+
+                    std::mem::take(&mut input.tap_script_sigs)
+                        .into_values()
+                        .next()
+                        .expect("asserted previously")
+                        .sig
+                })
+                .collect::<Vec<_>>();
+
+            dbtx.insert_new_entry(&TaprootPegOutTxSignatureCI(txid), &sigs)
+                .await;
+        } else {
+            let sigs = tx
+                .psbt
+                .inputs
+                .iter_mut()
+                .map(|input| {
+                    assert_eq!(
+                        input.partial_sigs.len(),
+                        1,
+                        "There was already more than one (our) or no signatures in input"
+                    );
+
+                    let sig = std::mem::take(&mut input.partial_sigs)
+                        .into_values()
+                        .next()
+                        .expect("asserted previously");
+
+                    // We drop SIGHASH_ALL, because we always use that and it is only present in
+                    // the PSBT for compatibility with other tools.
+                    secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
+                        .expect("we serialized it ourselves that way")
+                })
+                .collect::<Vec<_>>();
+
+            dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
+                .await;
+        }
+
+        // Delete used UTXOs
+        for input in tx.psbt.unsigned_tx.input.iter() {
+            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
+            dbtx.remove_entry(&ReservedUTXOKey(input.previous_output))
+                .await;
+        }
+
+        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
+            .await;
+
+        txid
+    }
+
+    /// Validates a peg-out and queues it for the next batch transaction
+    async fn queue_peg_out(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        output: &WalletOutputV0,
+        peg_out: &PegOut,
+        out_point: OutPoint,
+        batching: PegOutBatchingConfig,
+    ) -> Result<TransactionItemAmount, WalletOutputError> {
+        // The user pays the fees of a standalone transaction, so the peg-out is
+        // validated as one. Like the fees quoted to the user it may spend the
+        // UTXOs reserved for the batch it joins. Since we are only checking the
+        // tx we can use an arbitrary dummy nonce.
+        let tx = self.offline_wallet().create_tx(
+            peg_out.amount,
+            peg_out.recipient.script_pubkey(),
+            vec![],
+            self.wallet_utxos(dbtx).await,
+            peg_out.fees.fee_rate,
+            &[0; 33],
+            None,
+        )?;
+        let fee_rate = self.consensus_fee_rate(dbtx).await;
+        self.offline_wallet()
+            .validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)?;
+
+        let queued = QueuedPegOut {
+            peg_out: peg_out.clone(),
+            block_count: self.consensus_block_count(dbtx).await.unwrap_or(0),
+        };
+
+        // Make sure the batch including this peg-out can still be funded
+        let mut queue = self.queued_peg_outs(dbtx).await;
+        queue.push((QueuedPegOutKey(out_point), queued.clone()));
+        self.reserve_batch_utxos(dbtx, &queue).await?;
+
+        debug!(target: LOG_MODULE_WALLET, %out_point, queued = queue.len(), "Queueing peg out");
+        dbtx.insert_new_entry(&QueuedPegOutKey(out_point), &queued)
+            .await;
+
+        if batching.max_batch_size as usize <= queue.len() {
+            self.flush_peg_out_queue(dbtx).await;
+        }
+
+        let amount: fedimint_core::Amount = output.amount().into();
+        let fee = self.cfg.consensus.fee_consensus.peg_out_abs;
+        calculate_pegout_metrics(dbtx, amount, fee);
+        Ok(TransactionItemAmount { amount, fee })
+    }
+
+    async fn queued_peg_outs(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Vec<(QueuedPegOutKey, QueuedPegOut)> {
+        dbtx.find_by_prefix(&QueuedPegOutPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    /// Reserves the UTXOs funding the batch of all `queue`d peg-outs in place
+    /// of the previous reservation, so no other transaction can spend them
+    /// before the batch is created
+    async fn reserve_batch_utxos(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        queue: &[(QueuedPegOutKey, QueuedPegOut)],
+    ) -> Result<(), WalletOutputError> {
+        let selected_utxos = if queue.is_empty() {
+            vec![]
+        } else {
+            self.create_batch_tx(dbtx, queue, &[0; 33])
+                .await?
+                .selected_utxos
+        };
+
+        dbtx.remove_by_prefix(&ReservedUTXOPrefix).await;
+        for (key, _) in selected_utxos {
+            dbtx.insert_new_entry(&ReservedUTXOKey(key.0), &()).await;
+        }
+
+        Ok(())
+    }
+
+    /// Creates the transaction paying all `queue`d peg-outs at the lowest fee
+    /// rate any of them paid for, which may spend reserved UTXOs
+    async fn create_batch_tx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        queue: &[(QueuedPegOutKey, QueuedPegOut)],
+        change_tweak: &[u8; 33],
+    ) -> Result<UnsignedTransaction, WalletOutputError> {
+        let fee_rate = queue
+            .iter()
+            .map(|(_, queued)| queued.peg_out.fees.fee_rate)
+            .min()
+            .expect("Queue is not empty");
+
+        self.offline_wallet().create_tx_with_outputs(
+            queue
+                .iter()
+                .map(|(_, queued)| {
+                    (
+                        queued.peg_out.recipient.script_pubkey(),
+                        queued.peg_out.amount,
+                    )
+                })
+                .collect(),
+            vec![],
+            self.wallet_utxos(dbtx).await,
+            fee_rate,
+            change_tweak,
+            None,
+        )
+    }
+
+    /// Creates a batch if the oldest queued peg-out waited for the configured
+    /// window
+    async fn flush_expired_peg_out_queue(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        consensus_block_count: u32,
+    ) {
+        let Some(batching) = self.cfg.consensus.peg_out_batching else {
+            return;
+        };
+
+        let expired = self
+            .queued_peg_outs(dbtx)
+            .await
+            .iter()
+            .any(|(_, queued)| queued.block_count + batching.window <= consensus_block_count);
+
+        if expired {
+            self.flush_peg_out_queue(dbtx).await;
+        }
+    }
+
+    /// Combines all queued peg-outs into a single transaction and resolves
+    /// their outcomes to its txid
+    ///
+    /// The UTXOs reserved when the peg-outs were queued always fund the batch.
+    /// Should it fail anyway the failure is recorded and reported by the
+    /// `utxo_stats` endpoint. After [`MAX_PEG_OUT_BATCH_ATTEMPTS`] failed
+    /// attempts every queued peg-out the wallet can still fund is paid in its
+    /// own transaction, so one unfundable peg-out can't block the others.
+    async fn flush_peg_out_queue(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let queue = self.queued_peg_outs(dbtx).await;
+        if queue.is_empty() {
+            return;
+        }
+
+        let change_tweak = self.consensus_nonce(dbtx).await;
+        let error = match self.create_batch_tx(dbtx, &queue, &change_tweak).await {
+            Ok(tx) => {
+                self.finalize_peg_out_batch(dbtx, queue, tx).await;
+                dbtx.remove_by_prefix(&ReservedUTXOPrefix).await;
+                dbtx.remove_entry(&PegOutBatchFailureKey).await;
+                return;
+            }
+            Err(error) => error,
+        };
+
+        let attempts = dbtx
+            .get_value(&PegOutBatchFailureKey)
+            .await
+            .map_or(0, |failure| failure.attempts)
+            + 1;
+        error!(
+            target: LOG_MODULE_WALLET,
+            %error,
+            attempts,
+            queued = queue.len(),
+            "Unable to create peg-out batch"
+        );
+        dbtx.insert_entry(
+            &PegOutBatchFailureKey,
+            &PegOutBatchFailure {
+                attempts,
+                error: error.to_string(),
+            },
+        )
+        .await;
+
+        if attempts < MAX_PEG_OUT_BATCH_ATTEMPTS {
+            return;
+        }
+
+        for entry in queue {
+            let change_tweak = self.consensus_nonce(dbtx).await;
+            match self
+                .create_batch_tx(dbtx, std::slice::from_ref(&entry), &change_tweak)
+                .await
+            {
+                Ok(tx) => self.finalize_peg_out_batch(dbtx, vec![entry], tx).await,
+                Err(error) => {
+                    error!(target: LOG_MODULE_WALLET, %error, out_point = %entry.0 .0, "Unable to pay queued peg-out");
+                }
+            }
+        }
+
+        let queue = self.queued_peg_outs(dbtx).await;
+        if let Err(error) = self.reserve_batch_utxos(dbtx, &queue).await {
+            error!(target: LOG_MODULE_WALLET, %error, queued = queue.len(), "Unable to reserve UTXOs for queued peg-outs");
+        }
+
+        if queue.is_empty() {
+            dbtx.remove_entry(&PegOutBatchFailureKey).await;
+        }
+    }
+
+    /// Signs the transaction paying the `queue`d peg-outs, removes them from
+    /// the queue and resolves their outcomes to its txid
+    async fn finalize_peg_out_batch(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        queue: Vec<(QueuedPegOutKey, QueuedPegOut)>,
+        tx: UnsignedTransaction,
+    ) {
+        let fees = tx.fees;
+        let fee_shares = split_batch_fees(
+            fees,
+            &queue
+                .iter()
+                .map(|(_, queued)| output_weight(&queued.peg_out.recipient.script_pubkey()))
+                .collect::<Vec<_>>(),
+        );
+        let txid = self.sign_peg_out_tx(dbtx, tx).await;
+
+        let mut peg_outs = vec![];
+        for ((key, queued), fee_share) in queue.into_iter().zip(fee_shares) {
+            dbtx.remove_entry(&key).await;
+            dbtx.insert_new_entry(
+                &PegOutBitcoinTransaction(key.0),
+                &WalletOutputOutcome::new_v0(txid),
+            )
+            .await;
+
+            peg_outs.push(BatchedPegOut {
+                out_point: key.0,
+                recipient: queued.peg_out.recipient,
+                amount: queued.peg_out.amount,
+                fee_paid: queued.peg_out.fees.amount(),
+                fee_share,
+            });
+        }
+
+        info!(
+            target: LOG_MODULE_WALLET,
+            %txid,
+            peg_outs = peg_outs.len(),
+            fees_sats = fees.amount().to_sat(),
+            "Created peg-out batch"
+        );
+        dbtx.insert_new_entry(&PegOutBatchKey(txid), &PegOutBatch { peg_outs, fees })
+            .await;
+    }
+
+    /// Pending transactions that can be bumped with CPFP, which excludes
+    /// transactions involved in RBF or CPFP
+    async fn cpfp_candidates(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> BTreeMap<Txid, PendingTransaction> {
+        let mut pending_transactions = dbtx
+            .find_by_prefix(&PendingTransactionPrefixKey)
+            .await
+            .map(|(key, transaction)| (key.0, transaction))
+            .collect::<BTreeMap<Txid, PendingTransaction>>()
+            .await;
+
+        let rbf_txids = pending_transactions
+            .values()
+            .filter_map(|tx| tx.rbf.as_ref().map(|rbf| rbf.txid))
+            .collect::<BTreeSet<_>>();
+        let cpfp_txids = dbtx
+            .find_by_prefix(&CpfpTransactionPrefix)
+            .await
+            .flat_map(|(key, cpfp)| futures::stream::iter([key.0, cpfp.parent]))
+            .collect::<BTreeSet<_>>()
+            .await;
+
+        pending_transactions.retain(|txid, tx| {
+            tx.rbf.is_none() && !rbf_txids.contains(txid) && !cpfp_txids.contains(txid)
+        });
+        pending_transactions
+    }
+
+    /// Votes to bump the fees of transactions that have been pending for a
+    /// while and pay less than our current `fee_rate` estimate
+    async fn cpfp_proposals(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        fee_rate: Feerate,
+    ) -> Vec<CpfpFeerateItem> {
+        let Some(config) = self.cfg.consensus.cpfp else {
+            return vec![];
+        };
+        let Some(block_count) = self.consensus_block_count(dbtx).await else {
+            return vec![];
+        };
+        let fee_rate = fee_rate.min(config.max_fee_rate);
+
+        let mut proposals = vec![];
+        for (txid, tx) in self.cpfp_candidates(dbtx).await {
+            // Transactions from before we tracked this are old enough
+            let pending_since = dbtx
+                .get_value(&PendingTransactionBlockCountKey(txid))
+                .await
+                .unwrap_or(0);
+
+            if fee_rate <= tx.fees.fee_rate
+                || block_count < pending_since + CPFP_MIN_PENDING_BLOCKS
+                || dbtx.get_value(&CpfpVoteKey(txid, self.our_peer_id)).await == Some(fee_rate)
+                || self
+                    .create_cpfp_child_tx(txid, &tx, fee_rate, &[0; 33])
+                    .map_or(true, |child| config.max_fee < child.fees.amount())
+            {
+                continue;
+            }
+
+            proposals.push(CpfpFeerateItem { txid, fee_rate });
+        }
+        proposals
+    }
+
+    /// Spends the change of `parent` in a child transaction paying enough fees
+    /// for both to reach `fee_rate`. The fees are paid by the federation.
+    async fn bump_fee_with_cpfp(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        parent_txid: Txid,
+        mut parent: PendingTransaction,
+        fee_rate: Feerate,
+    ) -> anyhow::Result<()> {
+        let change_tweak = self.consensus_nonce(dbtx).await;
+        let mut tx = self.create_cpfp_child_tx(parent_txid, &parent, fee_rate, &change_tweak)?;
+        let fee = tx.fees.amount();
+
+        // The child takes over the change of the parent in the audit, while the
+        // fees show up in its `CpfpTransaction`
+        tx.change = parent.change;
+        parent.change = bitcoin::Amount::ZERO;
+        dbtx.insert_entry(&PendingTransactionKey(parent_txid), &parent)
+            .await;
+
+        let txid = self.sign_peg_out_tx(dbtx, tx).await;
+
+        info!(
+            target: LOG_MODULE_WALLET,
+            %parent_txid,
+            %txid,
+            fee_sats = fee.to_sat(),
+            fee_rate = fee_rate.sats_per_kvb,
+            "Bumping fees with CPFP"
+        );
+        dbtx.insert_new_entry(
+            &CpfpTransactionKey(txid),
+            &CpfpTransaction {
+                parent: parent_txid,
+                fee,
+            },
+        )
+        .await;
+        dbtx.insert_new_entry(&CpfpParentKey(parent_txid), &txid)
+            .await;
+        dbtx.remove_by_prefix(&CpfpVoteTxidPrefix(parent_txid))
+            .await;
+
+        Ok(())
+    }
+
+    /// Creates the child transaction spending the change of `parent` that
+    /// bumps the fee rate of both to `fee_rate`
+    fn create_cpfp_child_tx(
+        &self,
+        parent_txid: Txid,
+        parent: &PendingTransaction,
+        fee_rate: Feerate,
+        change_tweak: &[u8; 33],
+    ) -> anyhow::Result<UnsignedTransaction> {
+        let change_script = bitcoin30_to_bitcoin29_script(
+            &self
+                .cfg
+                .consensus
+                .peg_in_descriptor
+                .tweak(&parent.tweak, &self.secp)
+                .script_pubkey(),
+        );
+        let (vout, change) = parent
+            .tx
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, output)| output.script_pubkey == change_script)
+            .context("Transaction has no change output")?;
+
+        Ok(self.offline_wallet().create_cpfp_tx(
+            (
+                UTXOKey(bitcoin::OutPoint {
+                    txid: parent_txid,
+                    vout: vout as u32,
+                }),
+                SpendableUTXO {
+                    tweak: parent.tweak,
+                    amount: bitcoin::Amount::from_sat(change.value),
+                },
+            ),
+            parent.fees,
+            fee_rate,
+            change_tweak,
+        )?)
+    }
+
+    async fn consolidation_in_progress(&self, dbtx: &mut DatabaseTransaction<'_>) -> bool {
+        dbtx.find_by_prefix(&UtxoConsolidationPrefix)
+            .await
+            .next()
+            .await
+            .is_some()
+    }
+
+    /// The smallest spendable UTXOs that the next consolidation transaction
+    /// sweeps, empty if the wallet doesn't hold enough UTXOs to consolidate
+    async fn consolidation_candidates(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        consolidation: UtxoConsolidationConfig,
+    ) -> Vec<(UTXOKey, SpendableUTXO)> {
+        let mut utxos = self.available_utxos(dbtx).await;
+        if utxos.len() <= consolidation.min_utxos as usize || consolidation.max_inputs < 2 {
+            return vec![];
+        }
+
+        // Ensure deterministic ordering of UTXOs for all peers
+        utxos.sort_by(|(a_key, a), (b_key, b)| {
+            a.amount.cmp(&b.amount).then_with(|| a_key.0.cmp(&b_key.0))
+        });
+        utxos.truncate(consolidation.max_inputs as usize);
+        utxos
+    }
+
+    /// Votes to consolidate UTXOs if fees are low and no other consolidation
+    /// transaction is waiting to confirm
+    async fn consolidation_proposal(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        fee_rate: Feerate,
+    ) -> Option<Feerate> {
+        let consolidation = self.cfg.consensus.utxo_consolidation?;
+
+        if consolidation.max_fee_rate < fee_rate
+            || consolidation.max_fee_rate < self.consensus_fee_rate(dbtx).await
+            || self.consolidation_in_progress(dbtx).await
+            || self
+                .consolidation_candidates(dbtx, consolidation)
+                .await
+                .is_empty()
+            || dbtx
+                .get_value(&ConsolidationVoteKey(self.our_peer_id))
+                .await
+                == Some(fee_rate)
+        {
+            return None;
+        }
+
+        Some(fee_rate)
+    }
+
+    /// Sweeps the consolidation candidates into a single UTXO paying
+    /// `fee_rate`. The fees are paid by the federation.
+    async fn consolidate_utxos(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        consolidation: UtxoConsolidationConfig,
+        fee_rate: Feerate,
+    ) -> anyhow::Result<()> {
+        dbtx.remove_by_prefix(&ConsolidationVotePrefix).await;
+
+        let utxos = self.consolidation_candidates(dbtx, consolidation).await;
+        ensure!(!utxos.is_empty(), "Not enough UTXOs to consolidate");
+
+        let change_tweak = self.consensus_nonce(dbtx).await;
+        let tx = self
+            .offline_wallet()
+            .create_consolidation_tx(utxos, fee_rate, &change_tweak)?;
+        let inputs = tx.selected_utxos.len() as u64;
+        let fee = tx.fees.amount();
+
+        let txid = self.sign_peg_out_tx(dbtx, tx).await;
+
+        info!(
+            target: LOG_MODULE_WALLET,
+            %txid,
+            inputs,
+            fee_sats = fee.to_sat(),
+            fee_rate = fee_rate.sats_per_kvb,
+            "Consolidating UTXOs"
+        );
+        dbtx.insert_new_entry(
+            &UtxoConsolidationKey(txid),
+            &UtxoConsolidation { inputs, fee },
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn utxo_stats(&self, dbtx: &mut DatabaseTransaction<'_>) -> UtxoStats {
+        let amounts = self
+            .wallet_utxos(dbtx)
+            .await
+            .into_iter()
+            .map(|(_, utxo)| utxo.amount)
+            .collect::<Vec<_>>();
+
+        let consolidation_candidates = match self.cfg.consensus.utxo_consolidation {
+            Some(consolidation) => self
+                .consolidation_candidates(dbtx, consolidation)
+                .await
+                .len() as u64,
+            None => 0,
+        };
+
+        UtxoStats {
+            count: amounts.len() as u64,
+            total_amount: amounts.iter().copied().sum(),
+            smallest_amount: amounts.iter().min().copied(),
+            largest_amount: amounts.iter().max().copied(),
+            consolidation_candidates,
+            pending_consolidations: dbtx
+                .find_by_prefix(&UtxoConsolidationPrefix)
+                .await
+                .map(|(key, _)| key.0)
+                .collect::<Vec<_>>()
+                .await,
+            peg_out_batch_failure: dbtx.get_value(&PegOutBatchFailureKey).await,
+        }
+    }
+
+    async fn get_block_count(&self) -> anyhow::Result<u32> {
+        self.block_count_rx
+            .borrow()
+            .ok_or_else(|| format_err!("Block count not available yet"))
+    }
+
+    pub fn get_fee_rate_opt(&self) -> Feerate {
+        *self.fee_rate_rx.borrow()
+    }
+
+    pub async fn consensus_block_count(&self, dbtx: &mut DatabaseTransaction<'_>) -> Option<u32> {
+        let peer_count = self.cfg.consensus.peer_peg_in_keys.total();
 
         let mut counts = dbtx
             .find_by_prefix(&BlockCountVotePrefix)
@@ -970,6 +2030,58 @@ impl Wallet {
         rates[peer_count / 2]
     }
 
+    /// Records the consensus fee rate and block count at the end of the
+    /// session together with the peer votes they were derived from
+    async fn record_consensus_history(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        session_index: u64,
+    ) {
+        let Some(block_count) = self.consensus_block_count(dbtx).await else {
+            return;
+        };
+
+        let entry = ConsensusHistoryEntry {
+            session_index,
+            block_count,
+            fee_rate: self.consensus_fee_rate(dbtx).await,
+            block_count_votes: dbtx
+                .find_by_prefix(&BlockCountVotePrefix)
+                .await
+                .map(|(key, count)| (key.0, count))
+                .collect::<BTreeMap<_, _>>()
+                .await,
+            fee_rate_votes: dbtx
+                .find_by_prefix(&FeeRateVotePrefix)
+                .await
+                .map(|(key, rate)| (key.0, rate))
+                .collect::<BTreeMap<_, _>>()
+                .await,
+        };
+
+        dbtx.insert_entry(&ConsensusHistoryKey(session_index), &entry)
+            .await;
+    }
+
+    /// Returns up to `limit` consensus history entries recorded before the
+    /// session `before`, newest first
+    pub async fn consensus_history(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        before: Option<u64>,
+        limit: u32,
+    ) -> Vec<ConsensusHistoryEntry> {
+        let limit = limit.min(CONSENSUS_HISTORY_MAX_LIMIT) as usize;
+
+        dbtx.find_by_prefix_sorted_descending(&ConsensusHistoryPrefix)
+            .await
+            .filter(|(key, _)| std::future::ready(before.is_none_or(|before| key.0 < before)))
+            .take(limit)
+            .map(|(.., entry)| entry)
+            .collect()
+            .await
+    }
+
     pub async fn consensus_nonce(&self, dbtx: &mut DatabaseTransaction<'_>) -> [u8; 33] {
         let nonce_idx = dbtx.get_value(&PegOutNonceKey).await.unwrap_or(0);
         dbtx.insert_entry(&PegOutNonceKey, &(nonce_idx + 1)).await;
@@ -1055,6 +2167,13 @@ impl Wallet {
     ) {
         self.remove_rbf_transactions(dbtx, pending_tx).await;
 
+        let txid = pending_tx.tx.txid();
+        dbtx.remove_entry(&CpfpTransactionKey(txid)).await;
+        if dbtx.remove_entry(&CpfpParentKey(txid)).await.is_some() {
+            // The change was already spent by a CPFP child, which accounts for it
+            return;
+        }
+
         let script_pk = bitcoin30_to_bitcoin29_script(
             &self
                 .cfg
@@ -1099,6 +2218,12 @@ impl Wallet {
             all_transactions.remove(&removed.tx.txid());
             dbtx.remove_entry(&PendingTransactionKey(removed.tx.txid()))
                 .await;
+            dbtx.remove_entry(&PendingTransactionBlockCountKey(removed.tx.txid()))
+                .await;
+            dbtx.remove_by_prefix(&CpfpVoteTxidPrefix(removed.tx.txid()))
+                .await;
+            dbtx.remove_entry(&UtxoConsolidationKey(removed.tx.txid()))
+                .await;
 
             // Search for tx that this `removed` has as RBF
             if let Some(rbf) = &removed.rbf {
@@ -1143,6 +2268,19 @@ impl Wallet {
                 None,
             ),
             WalletOutputV0::Rbf(rbf) => {
+                if dbtx.get_value(&PegOutBatchKey(rbf.txid)).await.is_some() {
+                    return Err(WalletOutputError::RbfOfBatchedPegOut);
+                }
+
+                if dbtx.get_value(&CpfpParentKey(rbf.txid)).await.is_some()
+                    || dbtx
+                        .get_value(&CpfpTransactionKey(rbf.txid))
+                        .await
+                        .is_some()
+                {
+                    return Err(WalletOutputError::RbfOfCpfpTransaction);
+                }
+
                 let tx = dbtx
                     .get_value(&PendingTransactionKey(rbf.txid))
                     .await
@@ -1161,7 +2299,9 @@ impl Wallet {
         }
     }
 
-    async fn available_utxos(
+    /// All UTXOs of the federation wallet, including the ones reserved for the
+    /// queued peg-outs
+    async fn wallet_utxos(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
     ) -> Vec<(UTXOKey, SpendableUTXO)> {
@@ -1171,9 +2311,29 @@ impl Wallet {
             .await
     }
 
+    /// UTXOs of the federation wallet that aren't reserved for the queued
+    /// peg-outs
+    async fn available_utxos(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Vec<(UTXOKey, SpendableUTXO)> {
+        let reserved = dbtx
+            .find_by_prefix(&ReservedUTXOPrefix)
+            .await
+            .map(|(key, ())| key.0)
+            .collect::<BTreeSet<_>>()
+            .await;
+
+        self.wallet_utxos(dbtx)
+            .await
+            .into_iter()
+            .filter(|(key, _)| !reserved.contains(&key.0))
+            .collect()
+    }
+
     pub async fn get_wallet_value(&self, dbtx: &mut DatabaseTransaction<'_>) -> bitcoin::Amount {
         let sat_sum = self
-            .available_utxos(dbtx)
+            .wallet_utxos(dbtx)
             .await
             .into_iter()
             .map(|(_, utxo)| utxo.amount.to_sat())
@@ -1285,12 +2445,20 @@ pub async fn run_broadcast_pending_tx(db: Database, rpc: DynBitcoindRpc, tg_hand
 }
 
 pub async fn broadcast_pending_tx(mut dbtx: DatabaseTransaction<'_>, rpc: &DynBitcoindRpc) {
-    let pending_tx: Vec<PendingTransaction> = dbtx
+    let mut pending_tx: Vec<PendingTransaction> = dbtx
         .find_by_prefix(&PendingTransactionPrefixKey)
         .await
         .map(|(_, val)| val)
         .collect::<Vec<_>>()
         .await;
+    let cpfp_txids: BTreeSet<Txid> = dbtx
+        .find_by_prefix(&CpfpTransactionPrefix)
+        .await
+        .map(|(key, _)| key.0)
+        .collect()
+        .await;
+    // CPFP children can only be broadcast after their parents
+    pending_tx.sort_by_key(|tx| cpfp_txids.contains(&tx.tx.txid()));
     let rbf_txids: BTreeSet<Txid> = pending_tx
         .iter()
         .filter_map(|tx| tx.rbf.clone().map(|rbf| rbf.txid))
@@ -1389,12 +2557,41 @@ impl<'a> StatelessWallet<'a> {
         &self,
         peg_out_amount: bitcoin::Amount,
         destination: Script,
+        included_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        fee_rate: Feerate,
+        change_tweak: &[u8; 33],
+        rbf: Option<Rbf>,
+    ) -> Result<UnsignedTransaction, WalletOutputError> {
+        self.create_tx_with_outputs(
+            vec![(destination, peg_out_amount)],
+            included_utxos,
+            remaining_utxos,
+            fee_rate,
+            change_tweak,
+            rbf,
+        )
+    }
+
+    /// Like [`Self::create_tx`], but paying each of the `peg_outs` in its own
+    /// output. The resulting [`UnsignedTransaction`] contains the destination
+    /// of the first peg-out, or the change script if there is none, and the
+    /// sum of all peg-out amounts.
+    fn create_tx_with_outputs(
+        &self,
+        peg_outs: Vec<(Script, bitcoin::Amount)>,
         mut included_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut fee_rate: Feerate,
         change_tweak: &[u8; 33],
         rbf: Option<Rbf>,
     ) -> Result<UnsignedTransaction, WalletOutputError> {
+        let change_script = self.derive_script(change_tweak);
+        let peg_out_amount = peg_outs
+            .iter()
+            .map(|(_, amount)| *amount)
+            .sum::<bitcoin::Amount>();
+
         // Add the rbf fees to the existing tx fees
         if let Some(rbf) = &rbf {
             fee_rate.sats_per_kvb += rbf.fees.fee_rate.sats_per_kvb;
@@ -1407,62 +2604,132 @@ impl<'a> StatelessWallet<'a> {
         // We then go on to calculate the base size of the transaction `total_weight`
         // and the maximum weight per added input which we will add every time
         // we select an input.
-        let change_script = self.derive_script(change_tweak);
-        let out_weight = (destination.len() * 4 + 1 + 32
+        let out_weight = peg_outs
+            .iter()
+            .map(|(script, _)| output_weight(script))
+            .sum::<u64>()
             // Add change script weight, it's very likely to be needed if not we just overpay in fees
-            + 1 // script len varint, 1 byte for all addresses we accept
-            + change_script.len() * 4 // script len
-            + 32) as u64; // value
+            + output_weight(&change_script);
         let mut total_weight = 16 + // version
             12 + // up to 2**16-1 inputs
             12 + // up to 2**16-1 outputs
             out_weight + // weight of all outputs
             16; // lock time
-                // https://github.com/fedimint/fedimint/issues/4590
-        #[allow(deprecated)]
-        let max_input_weight = (self
-            .descriptor
-            .max_satisfaction_weight()
-            .expect("is satisfyable") +
-            128 + // TxOutHash
-            16 + // TxOutIndex
-            16) as u64; // sequence
+        let max_input_weight = self.max_input_weight();
+
+        // Ensure deterministic ordering of UTXOs for all peers
+        included_utxos.sort_by_key(|(_, utxo)| utxo.amount);
+        remaining_utxos.sort_by_key(|(_, utxo)| utxo.amount);
+        included_utxos.extend(remaining_utxos);
+
+        // Finally we initialize our accumulator for selected input amounts
+        let mut total_selected_value = bitcoin::Amount::from_sat(0);
+        let mut selected_utxos: Vec<(UTXOKey, SpendableUTXO)> = vec![];
+        let mut fees = fee_rate.calculate_fee(total_weight);
+
+        while total_selected_value < peg_out_amount + change_script.dust_value() + fees {
+            match included_utxos.pop() {
+                Some((utxo_key, utxo)) => {
+                    total_selected_value += utxo.amount;
+                    total_weight += max_input_weight;
+                    fees = fee_rate.calculate_fee(total_weight);
+                    selected_utxos.push((utxo_key, utxo));
+                }
+                _ => return Err(WalletOutputError::NotEnoughSpendableUTXO), // Not enough UTXOs
+            }
+        }
+
+        Ok(self.build_tx(
+            peg_outs,
+            selected_utxos,
+            PegOutFees {
+                fee_rate,
+                total_weight,
+            },
+            change_tweak,
+            rbf,
+        ))
+    }
+
+    /// Creates a transaction sweeping all `utxos` into a single change output
+    /// paying `fee_rate`
+    fn create_consolidation_tx(
+        &self,
+        mut utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        fee_rate: Feerate,
+        change_tweak: &[u8; 33],
+    ) -> Result<UnsignedTransaction, WalletOutputError> {
+        let change_script = self.derive_script(change_tweak);
+
+        // Same weight estimation as in `create_tx_with_outputs` for a transaction
+        // with only the change output
+        let total_weight = 16
+            + 12
+            + 12
+            + output_weight(&change_script)
+            + 16
+            + utxos.len() as u64 * self.max_input_weight();
+        let fees = PegOutFees {
+            fee_rate,
+            total_weight,
+        };
+
+        let total_value = utxos
+            .iter()
+            .map(|(_, utxo)| utxo.amount)
+            .sum::<bitcoin::Amount>();
+        if total_value < change_script.dust_value() + fees.amount() {
+            return Err(WalletOutputError::NotEnoughSpendableUTXO);
+        }
 
         // Ensure deterministic ordering of UTXOs for all peers
-        included_utxos.sort_by_key(|(_, utxo)| utxo.amount);
-        remaining_utxos.sort_by_key(|(_, utxo)| utxo.amount);
-        included_utxos.extend(remaining_utxos);
+        utxos.sort_by_key(|(_, utxo)| utxo.amount);
 
-        // Finally we initialize our accumulator for selected input amounts
-        let mut total_selected_value = bitcoin::Amount::from_sat(0);
-        let mut selected_utxos: Vec<(UTXOKey, SpendableUTXO)> = vec![];
-        let mut fees = fee_rate.calculate_fee(total_weight);
+        Ok(self.build_tx(vec![], utxos, fees, change_tweak, None))
+    }
 
-        while total_selected_value < peg_out_amount + change_script.dust_value() + fees {
-            match included_utxos.pop() {
-                Some((utxo_key, utxo)) => {
-                    total_selected_value += utxo.amount;
-                    total_weight += max_input_weight;
-                    fees = fee_rate.calculate_fee(total_weight);
-                    selected_utxos.push((utxo_key, utxo));
-                }
-                _ => return Err(WalletOutputError::NotEnoughSpendableUTXO), // Not enough UTXOs
-            }
-        }
+    /// Assembles the transaction spending `selected_utxos` to the `peg_outs`,
+    /// paying the remaining amount after `fees` back to the federation as
+    /// change
+    fn build_tx(
+        &self,
+        peg_outs: Vec<(Script, bitcoin::Amount)>,
+        selected_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        fees: PegOutFees,
+        change_tweak: &[u8; 33],
+        rbf: Option<Rbf>,
+    ) -> UnsignedTransaction {
+        let change_script = self.derive_script(change_tweak);
+        let destination = peg_outs
+            .first()
+            .map_or_else(|| change_script.clone(), |(script, _)| script.clone());
+        let peg_out_amount = peg_outs
+            .iter()
+            .map(|(_, amount)| *amount)
+            .sum::<bitcoin::Amount>();
+        let total_selected_value = selected_utxos
+            .iter()
+            .map(|(_, utxo)| utxo.amount)
+            .sum::<bitcoin::Amount>();
+        let PegOutFees {
+            fee_rate,
+            total_weight,
+        } = fees;
 
         // We always pay ourselves change back to ensure that we don't lose anything due
         // to dust
-        let change = total_selected_value - fees - peg_out_amount;
-        let output: Vec<TxOut> = vec![
-            TxOut {
-                value: peg_out_amount.to_sat(),
-                script_pubkey: destination.clone(),
-            },
-            TxOut {
+        let change = total_selected_value - fees.amount() - peg_out_amount;
+        let output: Vec<TxOut> = peg_outs
+            .iter()
+            .map(|(script, amount)| TxOut {
+                value: amount.to_sat(),
+                script_pubkey: script.clone(),
+            })
+            .chain(std::iter::once(TxOut {
                 value: change.to_sat(),
                 script_pubkey: change_script,
-            },
-        ];
+            }))
+            .collect();
         let mut change_out = bitcoin::util::psbt::Output::default();
         change_out
             .proprietary
@@ -1470,10 +2737,11 @@ impl<'a> StatelessWallet<'a> {
 
         info!(
             inputs = selected_utxos.len(),
+            outputs = output.len(),
             input_sats = total_selected_value.to_sat(),
             peg_out_sats = peg_out_amount.to_sat(),
             ?total_weight,
-            fees_sats = fees.to_sat(),
+            fees_sats = fees.amount().to_sat(),
             fee_rate = fee_rate.sats_per_kvb,
             change_sats = change.to_sat(),
             "Creating peg-out tx",
@@ -1506,11 +2774,9 @@ impl<'a> StatelessWallet<'a> {
             inputs: selected_utxos
                 .iter()
                 .map(|(_utxo_key, utxo)| {
-                    let script_pubkey = self
-                        .descriptor
-                        .tweak(&utxo.tweak, self.secp)
-                        .script_pubkey();
-                    Input {
+                    let descriptor = self.descriptor.tweak(&utxo.tweak, self.secp);
+                    let script_pubkey = descriptor.script_pubkey();
+                    let mut input = Input {
                         non_witness_utxo: None,
                         witness_utxo: Some(TxOut {
                             value: utxo.amount.to_sat(),
@@ -1519,13 +2785,15 @@ impl<'a> StatelessWallet<'a> {
                         partial_sigs: Default::default(),
                         sighash_type: None,
                         redeem_script: None,
-                        witness_script: Some(bitcoin30_to_bitcoin29_script(
-                            &self
-                                .descriptor
-                                .tweak(&utxo.tweak, self.secp)
-                                .script_code()
-                                .expect("Failed to tweak descriptor"),
-                        )),
+                        // Taproot inputs have their script in `tap_scripts` instead
+                        witness_script: match descriptor {
+                            Descriptor::Tr(_) => None,
+                            _ => Some(bitcoin30_to_bitcoin29_script(
+                                &descriptor
+                                    .script_code()
+                                    .expect("Failed to tweak descriptor"),
+                            )),
+                        },
                         bip32_derivation: Default::default(),
                         final_script_sig: None,
                         final_script_witness: None,
@@ -1543,28 +2811,84 @@ impl<'a> StatelessWallet<'a> {
                         tap_internal_key: Default::default(),
                         tap_merkle_root: Default::default(),
                         unknown: Default::default(),
-                    }
+                    };
+                    add_taproot_spend_info(&mut input, &descriptor);
+                    input
                 })
                 .collect(),
-            outputs: vec![Default::default(), change_out],
+            outputs: vec![Default::default(); peg_outs.len()]
+                .into_iter()
+                .chain(std::iter::once(change_out))
+                .collect(),
         };
 
-        Ok(UnsignedTransaction {
+        UnsignedTransaction {
             psbt,
             signatures: vec![],
             change,
-            fees: PegOutFees {
-                fee_rate,
-                total_weight,
-            },
+            fees,
             destination,
             selected_utxos,
             peg_out_amount,
             rbf,
-        })
+        }
+    }
+
+    /// Creates a child transaction spending the change `utxo` of a stuck
+    /// parent, paying enough fees for both transactions to reach `fee_rate`
+    fn create_cpfp_tx(
+        &self,
+        utxo: (UTXOKey, SpendableUTXO),
+        parent_fees: PegOutFees,
+        fee_rate: Feerate,
+        change_tweak: &[u8; 33],
+    ) -> Result<UnsignedTransaction, WalletOutputError> {
+        // Same weight estimation as in `create_tx_with_outputs` for a transaction
+        // with a single input and only the change output
+        let child_weight = 16
+            + 12
+            + 12
+            + output_weight(&self.derive_script(change_tweak))
+            + 16
+            + self.max_input_weight();
+
+        let child_fee = fee_rate
+            .calculate_fee(parent_fees.total_weight + child_weight)
+            .checked_sub(parent_fees.amount())
+            .unwrap_or(bitcoin::Amount::ZERO)
+            .max(fee_rate.calculate_fee(child_weight));
+
+        // Fee rate of the child alone, rounded up to pay at least `child_fee`
+        let child_vbytes = weight_to_vbytes(child_weight);
+        let child_fee_rate = Feerate {
+            sats_per_kvb: (child_fee.to_sat() * 1000).div_ceil(child_vbytes),
+        };
+
+        self.create_tx_with_outputs(
+            vec![],
+            vec![utxo],
+            vec![],
+            child_fee_rate,
+            change_tweak,
+            None,
+        )
+    }
+
+    fn max_input_weight(&self) -> u64 {
+        // https://github.com/fedimint/fedimint/issues/4590
+        #[allow(deprecated)]
+        let max_input_weight = (self
+            .descriptor
+            .max_satisfaction_weight()
+            .expect("is satisfyable") +
+            128 + // TxOutHash
+            16 + // TxOutIndex
+            16) as u64; // sequence
+        max_input_weight
     }
 
     fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) {
+        let prevouts = prevouts(psbt);
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
 
         for (idx, (psbt_input, _tx_input)) in psbt
@@ -1582,6 +2906,22 @@ impl<'a> StatelessWallet<'a> {
                 self.secret_key.tweak(tweak, self.secp)
             };
 
+            if let Some((leaf_hash, tx_hash)) =
+                taproot_script_spend_sighash(&mut tx_hasher, idx, &prevouts, psbt_input)
+            {
+                let keypair = KeyPair::from_secret_key(self.secp, &tweaked_secret);
+                let signature = self.secp.sign_schnorr_no_aux_rand(&tx_hash, &keypair);
+
+                psbt_input.tap_script_sigs.insert(
+                    (keypair.x_only_public_key().0, leaf_hash),
+                    SchnorrSig {
+                        sig: signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    },
+                );
+                continue;
+            }
+
             let tx_hash = tx_hasher
                 .segwit_signature_hash(
                     idx,
@@ -1654,6 +2994,110 @@ impl<'a> StatelessWallet<'a> {
     }
 }
 
+/// Fills the PSBT fields needed to spend the script leaf of a (tweaked)
+/// Taproot peg-in `descriptor`, does nothing for other descriptor types
+fn add_taproot_spend_info(input: &mut Input, descriptor: &Descriptor<CompressedPublicKey>) {
+    let Descriptor::Tr(tr) = descriptor else {
+        return;
+    };
+    let spend_info = tr.spend_info();
+
+    input.tap_internal_key = Some(
+        XOnlyPublicKey::from_slice(&spend_info.internal_key().serialize()).expect("is a valid key"),
+    );
+    input.tap_merkle_root = spend_info.merkle_root().map(|root| {
+        TapBranchHash::from_inner(miniscript::bitcoin::hashes::Hash::to_byte_array(root))
+    });
+
+    for (_, script) in tr.iter_scripts() {
+        let script = script.encode();
+        let control_block = spend_info
+            .control_block(&(
+                script.clone(),
+                miniscript::bitcoin::taproot::LeafVersion::TapScript,
+            ))
+            .expect("script is part of the tree");
+
+        input.tap_scripts.insert(
+            ControlBlock::from_slice(&control_block.serialize()).expect("is a valid control block"),
+            (
+                bitcoin30_to_bitcoin29_script(&script),
+                LeafVersion::TapScript,
+            ),
+        );
+    }
+}
+
+/// Outputs spent by the inputs of `psbt`, which are committed to by Taproot
+/// sighashes
+fn prevouts(psbt: &PartiallySignedTransaction) -> Vec<TxOut> {
+    psbt.inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+        .collect()
+}
+
+/// Returns the leaf hash and sighash to sign for spending the script leaf of a
+/// Taproot input, or `None` if `input` doesn't spend a Taproot peg-in output
+fn taproot_script_spend_sighash(
+    tx_hasher: &mut SighashCache<&Transaction>,
+    idx: usize,
+    prevouts: &[TxOut],
+    input: &Input,
+) -> Option<(TapLeafHash, Message)> {
+    let (script, leaf_version) = input.tap_scripts.values().next()?;
+    let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+
+    let tx_hash = tx_hasher
+        .taproot_script_spend_signature_hash(
+            idx,
+            &Prevouts::All(prevouts),
+            leaf_hash,
+            SchnorrSighashType::Default,
+        )
+        .ok()?;
+
+    Some((
+        leaf_hash,
+        Message::from_slice(&tx_hash[..]).expect("sighash is 32 bytes"),
+    ))
+}
+
+/// Weight of a transaction output paying to `script`
+fn output_weight(script: &Script) -> u64 {
+    (1 // script len varint, 1 byte for all addresses we accept
+        + script.len() * 4 // script len
+        + 32) as u64 // value
+}
+
+/// Splits the fees of a batch transaction between its peg-outs, given the
+/// weight of their outputs. Every peg-out pays for its own output and an equal
+/// part of the rest of the transaction, the first peg-outs paying for any
+/// remainder so that the shares always add up to the fees of the transaction.
+fn split_batch_fees(fees: PegOutFees, output_weights: &[u64]) -> Vec<bitcoin::Amount> {
+    let peg_outs = output_weights.len() as u64;
+    let shared_weight = fees.total_weight - output_weights.iter().sum::<u64>();
+    let total_fee = fees.amount().to_sat();
+
+    let mut fee_shares = output_weights
+        .iter()
+        .map(|output_weight| {
+            let weight = output_weight + shared_weight / peg_outs;
+            total_fee * weight / fees.total_weight
+        })
+        .collect::<Vec<_>>();
+
+    let remainder = total_fee - fee_shares.iter().sum::<u64>();
+    for idx in 0..remainder {
+        fee_shares[(idx % peg_outs) as usize] += 1;
+    }
+
+    fee_shares
+        .into_iter()
+        .map(bitcoin::Amount::from_sat)
+        .collect()
+}
+
 pub fn nonce_from_idx(nonce_idx: u64) -> [u8; 33] {
     let mut nonce: [u8; 33] = [0; 33];
     // Make it look like a compressed pubkey, has to be either 0x02 or 0x03
@@ -1692,6 +3136,61 @@ impl Serialize for PendingTransaction {
     }
 }
 
+/// A peg-out accepted by the federation that is waiting to be combined with
+/// others into a batch transaction
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Encodable, Decodable)]
+pub struct QueuedPegOut {
+    pub peg_out: PegOut,
+    /// Consensus block count when the peg-out was queued
+    pub block_count: u32,
+}
+
+/// Record of the peg-outs paid by a batch transaction and how its fees were
+/// split between them
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Encodable, Decodable)]
+pub struct PegOutBatch {
+    pub peg_outs: Vec<BatchedPegOut>,
+    /// Fees of the batch transaction, which is built at the lowest fee rate
+    /// any of its peg-outs paid for
+    pub fees: PegOutFees,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Encodable, Decodable)]
+pub struct BatchedPegOut {
+    pub out_point: OutPoint,
+    pub recipient: Address,
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub amount: bitcoin::Amount,
+    /// Fees the user paid for a standalone peg-out transaction
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub fee_paid: bitcoin::Amount,
+    /// Part of the batch transaction's fees attributed to this peg-out, any
+    /// difference to `fee_paid` is settled through the federation's change
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub fee_share: bitcoin::Amount,
+}
+
+/// Child transaction spending the change of a stuck transaction to bump its
+/// fees (CPFP). The child's `change` is the one of the parent, so the audit
+/// shows the bumping costs separately as `fee`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Encodable, Decodable)]
+pub struct CpfpTransaction {
+    pub parent: Txid,
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub fee: bitcoin::Amount,
+}
+
+/// Transaction sweeping the smallest UTXOs of the federation wallet into a
+/// single one. Its fees are deducted from the change, so they show up in the
+/// audit as a smaller change amount.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Encodable, Decodable)]
+pub struct UtxoConsolidation {
+    /// Number of UTXOs swept
+    pub inputs: u64,
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub fee: bitcoin::Amount,
+}
+
 /// A PSBT that is awaiting enough signatures from the federation to becoming a
 /// `PendingTransaction`
 #[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable)]
@@ -1730,12 +3229,15 @@ mod tests {
     use bitcoin::Network::{Bitcoin, Testnet};
     use bitcoin::{Address, Amount, Network, OutPoint, Txid};
     use fedimint_core::{BitcoinHash, Feerate};
+    use fedimint_wallet_common::config::taproot_peg_in_descriptor;
     use fedimint_wallet_common::{PegOut, PegOutFees, Rbf, WalletOutputV0};
     use miniscript::descriptor::Wsh;
+    use miniscript9::psbt::PsbtExt;
 
     use crate::common::PegInDescriptor;
     use crate::{
-        CompressedPublicKey, OsRng, SpendableUTXO, StatelessWallet, UTXOKey, WalletOutputError,
+        output_weight, split_batch_fees, CompressedPublicKey, OsRng, SpendableUTXO,
+        StatelessWallet, UTXOKey, WalletOutputError,
     };
 
     #[test]
@@ -1837,6 +3339,273 @@ mod tests {
         assert_eq!(res, Err(WalletOutputError::WrongNetwork(Testnet, Bitcoin)));
     }
 
+    #[test]
+    fn create_tx_with_outputs_should_split_fees() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let descriptor = PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(
+                3,
+                (0..4)
+                    .map(|_| secp.generate_keypair(&mut OsRng))
+                    .map(|(_, key)| CompressedPublicKey { key })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &secret_key,
+            secp: &secp,
+        };
+
+        let spendable = SpendableUTXO {
+            tweak: [0; 33],
+            amount: Amount::from_sat(10_000),
+        };
+
+        let p2sh = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf")
+            .unwrap()
+            .script_pubkey();
+        let p2wsh =
+            Address::from_str("bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3")
+                .unwrap()
+                .script_pubkey();
+
+        let tx = wallet
+            .create_tx_with_outputs(
+                vec![
+                    (p2sh.clone(), Amount::from_sat(1000)),
+                    (p2wsh.clone(), Amount::from_sat(2000)),
+                ],
+                vec![],
+                vec![(UTXOKey(OutPoint::null()), spendable)],
+                Feerate { sats_per_kvb: 1000 },
+                &[0; 33],
+                None,
+            )
+            .expect("is ok");
+
+        // Both peg-outs and the change are paid in a single transaction
+        let outputs = &tx.psbt.unsigned_tx.output;
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].value, 1000);
+        assert_eq!(outputs[1].value, 2000);
+        assert_eq!(tx.psbt.outputs.len(), 3);
+        assert_eq!(tx.peg_out_amount, Amount::from_sat(3000));
+        assert_eq!(
+            tx.change + tx.fees.amount() + tx.peg_out_amount,
+            Amount::from_sat(10_000)
+        );
+
+        // The fee shares add up to the fees of the transaction, with the larger
+        // output paying more
+        let fee_shares = split_batch_fees(tx.fees, &[output_weight(&p2sh), output_weight(&p2wsh)]);
+        assert_eq!(fee_shares.iter().copied().sum::<Amount>(), tx.fees.amount());
+        assert!(fee_shares[0] < fee_shares[1]);
+    }
+
+    #[test]
+    fn create_cpfp_tx_should_reach_package_fee_rate() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let descriptor = PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(
+                3,
+                (0..4)
+                    .map(|_| secp.generate_keypair(&mut OsRng))
+                    .map(|(_, key)| CompressedPublicKey { key })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &secret_key,
+            secp: &secp,
+        };
+
+        let change = SpendableUTXO {
+            tweak: [0; 33],
+            amount: Amount::from_sat(10_000),
+        };
+        let parent_fees = PegOutFees::new(1000, 800);
+        let fee_rate = Feerate { sats_per_kvb: 5000 };
+
+        let tx = wallet
+            .create_cpfp_tx(
+                (UTXOKey(OutPoint::null()), change),
+                parent_fees,
+                fee_rate,
+                &[1; 33],
+            )
+            .expect("is ok");
+
+        // The child only spends the change of the parent back to the federation
+        assert_eq!(tx.psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(tx.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(tx.peg_out_amount, Amount::ZERO);
+        assert_eq!(tx.change + tx.fees.amount(), Amount::from_sat(10_000));
+
+        // Parent and child together pay at least the requested fee rate
+        assert!(
+            fee_rate.calculate_fee(parent_fees.total_weight + tx.fees.total_weight)
+                <= parent_fees.amount() + tx.fees.amount()
+        );
+    }
+
+    #[test]
+    fn create_consolidation_tx_should_sweep_all_utxos() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let descriptor = PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(
+                3,
+                (0..4)
+                    .map(|_| secp.generate_keypair(&mut OsRng))
+                    .map(|(_, key)| CompressedPublicKey { key })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &secret_key,
+            secp: &secp,
+        };
+
+        let utxos = (0..5u32)
+            .map(|vout| {
+                (
+                    UTXOKey(OutPoint {
+                        txid: Txid::all_zeros(),
+                        vout,
+                    }),
+                    SpendableUTXO {
+                        tweak: [0; 33],
+                        amount: Amount::from_sat(10_000 + u64::from(vout)),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let fee_rate = Feerate { sats_per_kvb: 1000 };
+
+        let tx = wallet
+            .create_consolidation_tx(utxos.clone(), fee_rate, &[1; 33])
+            .expect("is ok");
+
+        // All UTXOs are swept into a single change output
+        assert_eq!(tx.psbt.unsigned_tx.input.len(), 5);
+        assert_eq!(tx.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(tx.peg_out_amount, Amount::ZERO);
+        assert_eq!(tx.change + tx.fees.amount(), Amount::from_sat(50_010));
+
+        // Sweeping UTXOs that can't pay for their own inputs fails
+        let dust = utxos
+            .into_iter()
+            .map(|(key, utxo)| {
+                (
+                    key,
+                    SpendableUTXO {
+                        amount: Amount::from_sat(100),
+                        ..utxo
+                    },
+                )
+            })
+            .collect();
+        assert_eq!(
+            wallet.create_consolidation_tx(dust, fee_rate, &[1; 33]),
+            Err(WalletOutputError::NotEnoughSpendableUTXO)
+        );
+    }
+
+    #[test]
+    fn taproot_peg_out_should_be_finalized_with_threshold_signatures() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let keys = (0..4)
+            .map(|_| secp.generate_keypair(&mut OsRng))
+            .collect::<Vec<_>>();
+        let descriptor = taproot_peg_in_descriptor(
+            3,
+            keys.iter()
+                .map(|(_, key)| CompressedPublicKey { key: *key })
+                .collect(),
+        );
+
+        let spendable = SpendableUTXO {
+            tweak: [1; 33],
+            amount: Amount::from_sat(10_000),
+        };
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+
+        let mut tx = StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &keys[0].0,
+            secp: &secp,
+        }
+        .create_tx(
+            Amount::from_sat(1000),
+            recipient.script_pubkey(),
+            vec![],
+            vec![(UTXOKey(OutPoint::null()), spendable)],
+            Feerate { sats_per_kvb: 1000 },
+            &[2; 33],
+            None,
+        )
+        .expect("is ok");
+
+        for (secret_key, _) in &keys[..2] {
+            StatelessWallet {
+                descriptor: &descriptor,
+                secret_key,
+                secp: &secp,
+            }
+            .sign_psbt(&mut tx.psbt);
+        }
+        assert!(tx.psbt.clone().finalize_mut(&secp).is_err());
+
+        StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &keys[3].0,
+            secp: &secp,
+        }
+        .sign_psbt(&mut tx.psbt);
+        assert_eq!(tx.psbt.inputs[0].tap_script_sigs.len(), 3);
+
+        tx.psbt
+            .finalize_mut(&secp)
+            .expect("threshold of signatures");
+
+        // The fee estimation covers the actual size of the script path spend
+        let signed = tx.psbt.extract_tx();
+        assert!(signed.weight() as u64 <= tx.fees.total_weight);
+    }
+
+    #[test]
+    fn split_batch_fees_should_assign_remainder() {
+        // 301 sats split between three peg-outs with outputs of equal weight
+        let fees = PegOutFees::new(1000, 1204);
+        let fee_shares = split_batch_fees(fees, &[100, 100, 100]);
+        assert_eq!(
+            fee_shares,
+            vec![
+                Amount::from_sat(101),
+                Amount::from_sat(100),
+                Amount::from_sat(100)
+            ]
+        );
+    }
+
     fn rbf(sats_per_kvb: u64, total_weight: u64) -> WalletOutputV0 {
         WalletOutputV0::Rbf(Rbf {
             fees: PegOutFees::new(sats_per_kvb, total_weight),
@@ -3,31 +3,40 @@ use std::time::Duration;
 use anyhow::{bail, Context};
 use assert_matches::assert_matches;
 use bitcoin::secp256k1::rand::rngs::OsRng;
-use fedimint_bitcoind::DynBitcoindRpc;
+use fedimint_bitcoind::compact_filters::CompactFiltersFactory;
+use fedimint_bitcoind::{DynBitcoindRpc, IBitcoindRpcFactory};
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::ClientHandleArc;
 use fedimint_core::bitcoin_migration::{
-    bitcoin29_to_bitcoin30_network, bitcoin29_to_bitcoin30_txid, bitcoin30_to_bitcoin29_address,
-    bitcoin30_to_bitcoin29_amount, bitcoin30_to_bitcoin29_block_hash,
-    bitcoin30_to_bitcoin29_network, bitcoin30_to_bitcoin29_secp256k1_public_key,
+    bitcoin29_to_bitcoin30_network, bitcoin29_to_bitcoin30_script, bitcoin29_to_bitcoin30_txid,
+    bitcoin30_to_bitcoin29_address, bitcoin30_to_bitcoin29_amount,
+    bitcoin30_to_bitcoin29_block_hash, bitcoin30_to_bitcoin29_network,
+    bitcoin30_to_bitcoin29_secp256k1_public_key,
 };
 use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::{DatabaseTransaction, IRawDatabaseExt};
 use fedimint_core::envs::BitcoinRpcConfig;
-use fedimint_core::task::sleep_in_test;
-use fedimint_core::util::{BoxStream, NextOrPending};
+use fedimint_core::module::ModuleConsensusVersion;
+use fedimint_core::task::{sleep_in_test, TaskGroup};
+use fedimint_core::util::{BoxStream, NextOrPending, SafeUrl};
 use fedimint_core::{sats, time, Amount, Feerate, PeerId, ServerModule};
 use fedimint_dummy_client::DummyClientInit;
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyInit;
 use fedimint_testing::btc::BitcoinTest;
+use fedimint_testing::envs::FM_PORT_BTC_P2P_ENV;
 use fedimint_testing::fixtures::Fixtures;
 use fedimint_wallet_client::api::WalletFederationApi;
 use fedimint_wallet_client::{DepositState, WalletClientInit, WalletClientModule, WithdrawState};
-use fedimint_wallet_common::config::{WalletConfig, WalletGenParams};
+use fedimint_wallet_common::config::{
+    CpfpConfig, PegOutBatchingConfig, UtxoConsolidationConfig, WalletConfig, WalletGenParams,
+};
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::txoproof::PegInProof;
-use fedimint_wallet_common::{PegOutFees, Rbf};
+use fedimint_wallet_common::{
+    CpfpFeerateItem, PegOutFees, Rbf, WalletConsensusItem, CONFIRMATION_TARGET,
+    CPFP_CONSENSUS_VERSION, UTXO_CONSOLIDATION_CONSENSUS_VERSION,
+};
 use fedimint_wallet_server::WalletInit;
 use futures::stream::StreamExt;
 use tracing::info;
@@ -160,6 +169,113 @@ async fn sanity_check_bitcoin_blocks() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn consensus_history_is_paginated_newest_first() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    let dyn_bitcoin_rpc = fixtures.dyn_bitcoin_rpc();
+    let wallet = client.get_first_module::<WalletClientModule>();
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay + 3).await;
+    let block_count = dyn_bitcoin_rpc.get_block_count().await?;
+    let consensus_block_count =
+        await_consensus_to_catch_up(&client, block_count - finality_delay).await?;
+
+    // entries are only recorded at the end of a session
+    let history = loop {
+        let history = wallet.get_consensus_history(None, 1000).await?;
+        if history.len() >= 2 && u64::from(history[0].block_count) == consensus_block_count {
+            break history;
+        }
+        sleep_in_test(
+            "waiting for a session to record the consensus block count",
+            Duration::from_millis(100),
+        )
+        .await;
+    };
+    assert!(history
+        .windows(2)
+        .all(|pair| pair[0].session_index > pair[1].session_index
+            && pair[0].block_count >= pair[1].block_count));
+    assert!(!history[0].block_count_votes.is_empty());
+
+    let next_page = wallet
+        .get_consensus_history(Some(history[0].session_index), 1)
+        .await?;
+    assert_eq!(next_page, vec![history[1].clone()]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compact_filters_backend_finds_confirmed_transactions() -> anyhow::Result<()> {
+    if !Fixtures::is_real_test() {
+        info!("Skipping test compact_filters_backend_finds_confirmed_transactions, needs bitcoind");
+        return Ok(());
+    }
+
+    let fixtures = fixtures();
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    let dyn_bitcoin_rpc = fixtures.dyn_bitcoin_rpc();
+    let task_group = TaskGroup::new();
+    let url = SafeUrl::parse(&format!(
+        "bip157://127.0.0.1:{}?network=regtest",
+        std::env::var(FM_PORT_BTC_P2P_ENV).unwrap_or(String::from("18444"))
+    ))?;
+    let compact_filters_rpc =
+        CompactFiltersFactory.create_connection(&url, task_group.make_handle())?;
+
+    let address = bitcoin.get_new_address().await;
+    let script = bitcoin29_to_bitcoin30_script(address.script_pubkey());
+    compact_filters_rpc.watch_script_history(&script).await?;
+    let (proof, tx) = bitcoin
+        .send_and_mine_block(&address, bitcoin30_to_bitcoin29_amount(bsats(1000)))
+        .await;
+    let txid = bitcoin29_to_bitcoin30_txid(tx.txid());
+
+    let block_count = dyn_bitcoin_rpc.get_block_count().await?;
+    assert_eq!(compact_filters_rpc.get_block_count().await?, block_count);
+    assert_eq!(
+        compact_filters_rpc.get_block_hash(block_count - 1).await?,
+        dyn_bitcoin_rpc.get_block_hash(block_count - 1).await?
+    );
+
+    let history = compact_filters_rpc.get_script_history(&script).await?;
+    assert_eq!(
+        history.iter().map(|tx| tx.txid()).collect::<Vec<_>>(),
+        vec![txid]
+    );
+    assert_eq!(
+        compact_filters_rpc.get_tx_block_height(&txid).await?,
+        Some(block_count - 1)
+    );
+    assert_eq!(
+        compact_filters_rpc.get_txout_proof(txid).await?.block(),
+        proof.block()
+    );
+    // Each estimate downloads one of the last 6 blocks, there is no estimate
+    // until all of them were downloaded
+    for _ in 0..5 {
+        assert_eq!(
+            compact_filters_rpc
+                .get_fee_rate(CONFIRMATION_TARGET)
+                .await?,
+            None
+        );
+    }
+    assert!(compact_filters_rpc
+        .get_fee_rate(CONFIRMATION_TARGET)
+        .await?
+        .is_some());
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn on_chain_peg_in_and_peg_out_happy_case() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -220,6 +336,102 @@ async fn on_chain_peg_in_and_peg_out_happy_case() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn withdraw_all_empties_balance() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    let dyn_bitcoin_rpc = fixtures.dyn_bitcoin_rpc();
+    info!("Starting test withdraw_all_empties_balance");
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    await_consensus_to_catch_up(&client, 1).await?;
+
+    let mut balance_sub =
+        peg_in(&client, bitcoin.as_ref(), &dyn_bitcoin_rpc, finality_delay).await?;
+
+    let address = bitcoin.get_new_address().await;
+    let wallet_module = client.get_first_module::<WalletClientModule>();
+    let (op, amount, fees) = wallet_module.withdraw_all(address.clone(), ()).await?;
+    assert_eq!(
+        amount.to_sat(),
+        PEG_IN_AMOUNT_SATS - fees.amount().to_sat(),
+        "without e-cash fees the whole balance minus on-chain fees is withdrawn"
+    );
+    assert_eq!(client.get_balance().await, sats(0));
+    assert_eq!(balance_sub.ok().await?, sats(0));
+
+    let mut sub = wallet_module
+        .subscribe_withdraw_updates(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, WithdrawState::Created);
+    assert_matches!(sub.ok().await?, WithdrawState::Succeeded(_));
+
+    let received = bitcoin.mine_block_and_get_received(&address).await;
+    assert_eq!(received, amount.into());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peg_ins_to_the_same_address_are_all_claimed() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    info!("Starting test peg_ins_to_the_same_address_are_all_claimed");
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    await_consensus_to_catch_up(&client, 1).await?;
+
+    let wallet_module = client.get_first_module::<WalletClientModule>();
+    let peg_in_amount = bitcoin30_to_bitcoin29_amount(
+        bsats(PEG_IN_AMOUNT_SATS)
+            + bsats(wallet_module.get_fee_consensus().peg_in_abs.msats / 1000),
+    );
+    let (op, address) = wallet_module
+        .get_deposit_address(time::now() + PEG_IN_TIMEOUT, ())
+        .await?;
+    bitcoin.send_and_mine_block(&address, peg_in_amount).await;
+
+    let mut sub = wallet_module
+        .subscribe_deposit_updates(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, DepositState::WaitingForTransaction);
+    assert_matches!(sub.ok().await?, DepositState::WaitingForConfirmation(_));
+    bitcoin.mine_blocks(finality_delay).await;
+    assert_matches!(sub.ok().await?, DepositState::Confirmed(_));
+    assert_matches!(sub.ok().await?, DepositState::Claimed(_));
+    assert_eq!(client.get_balance().await, sats(PEG_IN_AMOUNT_SATS));
+
+    // A second deposit to the address is reported by the same subscription
+    let (_proof, tx) = bitcoin.send_and_mine_block(&address, peg_in_amount).await;
+    bitcoin.mine_blocks(finality_delay).await;
+    let DepositState::Confirmed(tx_data) = sub.ok().await? else {
+        panic!("Expected the second deposit to be confirmed");
+    };
+    assert_eq!(tx_data.btc_transaction.txid(), tx.txid());
+    assert_matches!(sub.ok().await?, DepositState::Claimed(_));
+
+    // The address is still watched, so the operation has no outcome yet
+    let outcome = client
+        .operation_log()
+        .get_operation(op)
+        .await
+        .context("Operation exists")?
+        .outcome::<DepositState>();
+    assert_eq!(outcome, None);
+    assert_eq!(client.get_balance().await, sats(2 * PEG_IN_AMOUNT_SATS));
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn peg_out_fail_refund() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -350,6 +562,89 @@ async fn peg_outs_support_rbf() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_peg_outs_are_paid_by_one_batch_transaction() -> anyhow::Result<()> {
+    let fixtures = Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default());
+    let mut wallet_params = WalletGenParams::regtest(fixtures.bitcoin_server());
+    // The window never expires, so only the second peg-out triggers the batch
+    wallet_params.consensus.peg_out_batching = Some(PegOutBatchingConfig {
+        window: 1000,
+        max_batch_size: 2,
+    });
+    let wallet_client = WalletClientInit::new(fixtures.bitcoin_client());
+    let fixtures = fixtures.with_module(wallet_client, WalletInit, wallet_params);
+    let fed = fixtures.new_default_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    let dyn_bitcoin_rpc = fixtures.dyn_bitcoin_rpc();
+    info!("Starting test queued_peg_outs_are_paid_by_one_batch_transaction");
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    await_consensus_to_catch_up(&client, 1).await?;
+
+    let mut balance_sub =
+        peg_in(&client, bitcoin.as_ref(), &dyn_bitcoin_rpc, finality_delay).await?;
+
+    let wallet_module = client.get_first_module::<WalletClientModule>();
+    let peg_out = bitcoin30_to_bitcoin29_amount(bsats(PEG_OUT_AMOUNT_SATS));
+    let address1 = bitcoin.get_new_address().await;
+    let fees1 = wallet_module
+        .get_withdraw_fees(address1.clone(), peg_out)
+        .await?;
+    let op1 = wallet_module
+        .withdraw(address1.clone(), peg_out, fees1, ())
+        .await?;
+    let balance_after_peg_out =
+        sats(PEG_IN_AMOUNT_SATS - PEG_OUT_AMOUNT_SATS - fees1.amount().to_sat());
+    assert_eq!(balance_sub.ok().await?, balance_after_peg_out);
+    let mut sub1 = wallet_module
+        .subscribe_withdraw_updates(op1)
+        .await?
+        .into_stream();
+    assert_eq!(sub1.ok().await?, WithdrawState::Created);
+
+    // The only UTXO of the federation is reserved for the queued peg-out, but
+    // it still funds the batch the second peg-out joins
+    let address2 = bitcoin.get_new_address().await;
+    let fees2 = wallet_module
+        .get_withdraw_fees(address2.clone(), peg_out)
+        .await?;
+    let op2 = wallet_module
+        .withdraw(address2.clone(), peg_out, fees2, ())
+        .await?;
+    let mut sub2 = wallet_module
+        .subscribe_withdraw_updates(op2)
+        .await?
+        .into_stream();
+    assert_eq!(sub2.ok().await?, WithdrawState::Created);
+    let balance_after_peg_outs = sats(
+        PEG_IN_AMOUNT_SATS
+            - 2 * PEG_OUT_AMOUNT_SATS
+            - fees1.amount().to_sat()
+            - fees2.amount().to_sat(),
+    );
+    assert_eq!(client.get_balance().await, balance_after_peg_outs);
+    assert_eq!(balance_sub.ok().await?, balance_after_peg_outs);
+
+    let txid = match sub1.ok().await? {
+        WithdrawState::Succeeded(txid) => txid,
+        other => panic!("Unexpected state: {other:?}"),
+    };
+    assert_eq!(sub2.ok().await?, WithdrawState::Succeeded(txid));
+
+    // The batch pays at most the fees of the two standalone transactions
+    let tx_fee = bitcoin.get_mempool_tx_fee(&txid).await;
+    assert!(tx_fee <= sats(fees1.amount().to_sat() + fees2.amount().to_sat()));
+
+    let received = bitcoin.mine_block_and_get_received(&address1).await;
+    assert_eq!(received, peg_out.into());
+    let received = bitcoin.mine_block_and_get_received(&address2).await;
+    assert_eq!(received, peg_out.into());
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn peg_outs_must_wait_for_available_utxos() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -489,6 +784,7 @@ async fn peg_ins_that_are_unconfirmed_are_rejected() -> anyhow::Result<()> {
 
     let mut wallet = fedimint_wallet_server::Wallet::new_with_bitcoind(
         wallet_server_cfg[0].to_typed()?,
+        wallet_server_cfg[0].consensus.version,
         db.clone(),
         dyn_bitcoin_rpc.clone(),
         &mut task_group,
@@ -600,6 +896,140 @@ async fn sync_wallet_to_block(
 
 const MINTS: usize = 5;
 
+#[tokio::test(flavor = "multi_thread")]
+async fn cpfp_votes_require_consensus_version() -> anyhow::Result<()> {
+    use bitcoin29::hashes::Hash as _;
+
+    let fixtures = fixtures();
+    let db = MemDatabase::new().into_database();
+    let mut task_group = fedimint_core::task::TaskGroup::new();
+    let (wallet_server_cfg, _) = build_wallet_server_configs(fixtures.bitcoin_server())?;
+
+    // A federation created before CPFP was introduced
+    let wallet = fedimint_wallet_server::Wallet::new_with_bitcoind(
+        wallet_server_cfg[0].to_typed()?,
+        ModuleConsensusVersion::new(2, 1),
+        db.clone(),
+        fixtures.dyn_bitcoin_rpc(),
+        &mut task_group,
+        PeerId::from(0),
+    )
+    .await?;
+
+    let item = WalletConsensusItem::CpfpFeerate(CpfpFeerateItem {
+        txid: bitcoin29::Txid::all_zeros(),
+        fee_rate: Feerate { sats_per_kvb: 2000 },
+    });
+    let mut dbtx = db.begin_transaction().await;
+    let error = wallet
+        .process_consensus_item(&mut dbtx.to_ref_nc(), item, PeerId::from(1))
+        .await
+        .expect_err("CPFP votes are rejected below the CPFP consensus version");
+    assert!(error
+        .to_string()
+        .contains("CPFP requires wallet consensus version"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cpfp_votes_are_limited_by_config() -> anyhow::Result<()> {
+    use bitcoin29::hashes::Hash as _;
+
+    let fixtures = fixtures();
+    let mut task_group = fedimint_core::task::TaskGroup::new();
+    let (wallet_server_cfg, _) = build_wallet_server_configs(fixtures.bitcoin_server())?;
+    let mut wallet_config: WalletConfig = wallet_server_cfg[0].to_typed()?;
+
+    let vote = |sats_per_kvb| {
+        WalletConsensusItem::CpfpFeerate(CpfpFeerateItem {
+            txid: bitcoin29::Txid::all_zeros(),
+            fee_rate: Feerate { sats_per_kvb },
+        })
+    };
+
+    for (cpfp, fee_rate, expected_error) in [
+        (None, 2000, "CPFP is disabled"),
+        (
+            Some(CpfpConfig {
+                max_fee_rate: Feerate { sats_per_kvb: 5000 },
+                max_fee: bitcoin29::Amount::from_sat(10_000),
+            }),
+            5001,
+            "CPFP fee rate exceeds the configured maximum",
+        ),
+    ] {
+        wallet_config.consensus.cpfp = cpfp;
+        let db = MemDatabase::new().into_database();
+        let wallet = fedimint_wallet_server::Wallet::new_with_bitcoind(
+            wallet_config.clone(),
+            CPFP_CONSENSUS_VERSION,
+            db.clone(),
+            fixtures.dyn_bitcoin_rpc(),
+            &mut task_group,
+            PeerId::from(0),
+        )
+        .await?;
+
+        let mut dbtx = db.begin_transaction().await;
+        let error = wallet
+            .process_consensus_item(&mut dbtx.to_ref_nc(), vote(fee_rate), PeerId::from(1))
+            .await
+            .expect_err("CPFP vote exceeds the configured limits");
+        assert!(error.to_string().contains(expected_error));
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn consolidation_votes_require_consensus_version_and_config() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let mut task_group = fedimint_core::task::TaskGroup::new();
+    let (wallet_server_cfg, _) = build_wallet_server_configs(fixtures.bitcoin_server())?;
+    let mut wallet_config: WalletConfig = wallet_server_cfg[0].to_typed()?;
+    let consolidation = UtxoConsolidationConfig {
+        max_fee_rate: Feerate { sats_per_kvb: 5000 },
+        min_utxos: 100,
+        max_inputs: 50,
+    };
+
+    for (utxo_consolidation, version, expected_error) in [
+        (
+            Some(consolidation),
+            ModuleConsensusVersion::new(2, 0),
+            "UTXO consolidation requires wallet consensus version",
+        ),
+        (
+            None,
+            UTXO_CONSOLIDATION_CONSENSUS_VERSION,
+            "UTXO consolidation is disabled",
+        ),
+    ] {
+        wallet_config.consensus.utxo_consolidation = utxo_consolidation;
+        let db = MemDatabase::new().into_database();
+        let wallet = fedimint_wallet_server::Wallet::new_with_bitcoind(
+            wallet_config.clone(),
+            version,
+            db.clone(),
+            fixtures.dyn_bitcoin_rpc(),
+            &mut task_group,
+            PeerId::from(0),
+        )
+        .await?;
+
+        let item = WalletConsensusItem::ConsolidationFeerate(Feerate { sats_per_kvb: 1000 });
+        let mut dbtx = db.begin_transaction().await;
+        let error = wallet
+            .process_consensus_item(&mut dbtx.to_ref_nc(), item, PeerId::from(1))
+            .await
+            .expect_err("Consolidation vote is rejected");
+        assert!(error.to_string().contains(expected_error));
+    }
+
+    Ok(())
+}
+
 // TODO: Something similar to this is needed in every module, maybe we can
 // remove some code duplication
 fn build_wallet_server_configs(
@@ -621,6 +1051,10 @@ fn build_wallet_server_configs(
                 finality_delay: 10,
                 client_default_bitcoin_rpc: bitcoin_rpc.clone(),
                 fee_consensus: Default::default(),
+                peg_out_batching: None,
+                peg_in_descriptor_kind: Default::default(),
+                utxo_consolidation: None,
+                cpfp: None,
             },
         })?,
     );
@@ -999,6 +1433,19 @@ mod fedimint_migration_tests {
                             );
                             info!("Validated FeeRateVote");
                         }
+                        // Prefixes added after the v0 snapshot, so there is no data to validate
+                        DbKeyPrefix::QueuedPegOut
+                        | DbKeyPrefix::PegOutBatch
+                        | DbKeyPrefix::PendingTransactionBlockCount
+                        | DbKeyPrefix::CpfpVote
+                        | DbKeyPrefix::CpfpTransaction
+                        | DbKeyPrefix::CpfpParent
+                        | DbKeyPrefix::TaprootPegOutTxSigCi
+                        | DbKeyPrefix::ConsolidationVote
+                        | DbKeyPrefix::UtxoConsolidation
+                        | DbKeyPrefix::ConsensusHistory
+                        | DbKeyPrefix::PegOutBatchFailure
+                        | DbKeyPrefix::ReservedUtxo => {}
                     }
                 }
                 Ok(())
@@ -6,6 +6,7 @@ alias bitcoin-cli="\$FM_BTC_CLIENT"
 alias fedimint-cli="\$FM_MINT_CLIENT"
 alias gateway-cln="\$FM_GWCLI_CLN"
 alias gateway-lnd="\$FM_GWCLI_LND"
+alias gateway-fake="\$FM_GWCLI_FAKE"
 alias fedimint-dbtool-fedimintd-0="env FM_DBTOOL_CONFIG_DIR=\$FM_DATA_DIR/fedimintd-0 FM_PASSWORD=pass \$FM_DB_TOOL --database \$FM_DATA_DIR/fedimintd-0/database"
 alias fedimint-dbtool-fedimintd-1="env FM_DBTOOL_CONFIG_DIR=\$FM_DATA_DIR/fedimintd-1 FM_PASSWORD=pass \$FM_DB_TOOL --database \$FM_DATA_DIR/fedimintd-1/database"
 alias fedimint-dbtool-fedimintd-2="env FM_DBTOOL_CONFIG_DIR=\$FM_DATA_DIR/fedimintd-2 FM_PASSWORD=pass \$FM_DB_TOOL --database \$FM_DATA_DIR/fedimintd-2/database"